@@ -0,0 +1,52 @@
+//! Round-trips StakePool, UserInfo and MasterStaking through their account layouts.
+//!
+//! The first byte selects the state type, the rest is used as account data.
+//! Any data that unpacks must survive pack -> unpack unchanged, and no input may panic.
+
+#![no_main]
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use libfuzzer_sys::fuzz_target;
+use stake_reward::{
+    solana_program::program_pack::Pack,
+    state::{MasterStaking, StakePool, UserInfo},
+};
+
+fuzz_target!(|data: &[u8]| {
+    let (selector, data) = match data.split_first() {
+        Some(v) => v,
+        None => return,
+    };
+
+    match selector % 3 {
+        0 => {
+            let stake_pool = match StakePool::unpack_unchecked(data) {
+                Ok(v) => v,
+                Err(_) => return,
+            };
+            let mut packed = vec![0; StakePool::LEN];
+            stake_pool.pack_into_slice(&mut packed);
+            let unpacked = StakePool::unpack_unchecked(&packed).expect("packed StakePool must unpack");
+            assert_eq!(format!("{:?}", stake_pool), format!("{:?}", unpacked));
+        }
+        1 => {
+            let user_info = match UserInfo::unpack_unchecked(data) {
+                Ok(v) => v,
+                Err(_) => return,
+            };
+            let mut packed = vec![0; UserInfo::LEN];
+            user_info.pack_into_slice(&mut packed);
+            let unpacked = UserInfo::unpack_unchecked(&packed).expect("packed UserInfo must unpack");
+            assert_eq!(format!("{:?}", user_info), format!("{:?}", unpacked));
+        }
+        _ => {
+            let master = match MasterStaking::try_from_slice(data) {
+                Ok(v) => v,
+                Err(_) => return,
+            };
+            let packed = master.try_to_vec().expect("MasterStaking must serialize");
+            let unpacked = MasterStaking::try_from_slice(&packed).expect("serialized MasterStaking must deserialize");
+            assert_eq!(master.pool_counter, unpacked.pool_counter);
+        }
+    }
+});
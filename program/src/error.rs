@@ -43,6 +43,156 @@ pub enum StakingError {
     PoolTokenAccountMissmatch,
     #[error("User Info missmatch")]
     UserInfoMissmatch,
+    #[error("Reward destination is not the associated token-account of the user")]
+    InvalidRewardDestination,
+    #[error("Withdraw fee is above the allowed maximum")]
+    WithdrawFeeTooHigh,
+    #[error("Whale reward rate must not exceed 10000 bps")]
+    InvalidWhaleRewardBps,
+    #[error("Bump seed is not canonical")]
+    InvalidBump,
+    #[error("Clock unix timestamp is not positive")]
+    InvalidClock,
+    #[error("Reward to claim is below the pool minimum")]
+    RewardBelowMinimum,
+    #[error("Destination UserInfo already holds a stake")]
+    PositionNotEmpty,
+    #[error("Signer is not the program upgrade authority")]
+    InvalidUpgradeAuthority,
+    #[error("Reward decimals of the pools differ")]
+    DecimalsMismatch,
+    #[error("Reward and staked token-accounts are the same account")]
+    VaultAliasing,
+    #[error("Pool count is zero, above the batch limit or does not match the accounts")]
+    InvalidPoolBatch,
+    #[error("Unknown reward lock policy")]
+    InvalidLockPolicy,
+    #[error("Unknown allowlist mode")]
+    InvalidAllowlistMode,
+    #[error("Invalid allowlist entry")]
+    InvalidAllowlistEntry,
+    #[error("Staker is not allowed in the pool")]
+    NotAllowlisted,
+    #[error("Pool still has stake or its reset grace period has not passed")]
+    PoolNotResettable,
+    #[error("Referral fee is above 100%")]
+    InvalidReferralFeeBps,
+    #[error("Referrer is already set")]
+    ReferrerAlreadySet,
+    #[error("Referrer token-account does not match UserInfo")]
+    ReferrerMismatch,
+    #[error("Staked token-account is not the staked vault of the pool")]
+    StakedVaultMismatch,
+    #[error("Native SOL reward is paid only by GetReward; claim it first")]
+    NativeRewardUnsupported,
+    #[error("Native SOL reward account can't cover the payout")]
+    InsufficientNativeReward,
+    #[error("Pool has no rate controller or another program was passed")]
+    RateControllerMismatch,
+    #[error("Rate controller returned no reward per block")]
+    InvalidRateControllerData,
+    #[error("Boost must be zero or between 1x and MAX_BOOST_BPS")]
+    InvalidBoostBps,
+    #[error("Reward per block is zero and the pool wasn't created with allow_zero_rate")]
+    ZeroRewardRate,
+    #[error("Reward token-account can't cover the claim")]
+    RewardVaultShortfall,
+    #[error("Reward campaign of the pool has ended")]
+    CampaignEnded,
+    #[error("Unstaked amount exceeds the total supply of the pool")]
+    TotalSupplyUnderflow,
+    #[error("Rewards are locked until reward_lock_finish")]
+    RewardLocked,
+    #[error("Unknown reward model")]
+    InvalidRewardModel,
+    #[error("Position is frozen by the pool owner")]
+    UserFrozen,
+    #[error("Reward period is still active")]
+    RewardPeriodActive,
+    #[error("Rewards duration must not be zero")]
+    InvalidRewardsDuration,
+    #[error("Unexpected number of accounts")]
+    UnexpectedAccountCount,
+    #[error("Pool has an unstake cooldown, use RequestUnstake")]
+    UnstakeCooldownRequired,
+    #[error("Unstake cooldown has not elapsed")]
+    UnstakeCooldownActive,
+    #[error("No unstake requested")]
+    NoPendingUnstake,
+    #[error("Requested unstake must be completed first")]
+    UnstakePending,
+    #[error("Unstake amount is zero or above the staked amount")]
+    InvalidUnstakeAmount,
+    #[error("Pool must be paused")]
+    PoolNotPaused,
+    #[error("Reward debt of the position was corrected recently")]
+    RewardDebtCorrectionTooSoon,
+    #[error("Reward debt exceeds the reward accrued by the position")]
+    InvalidRewardDebt,
+    #[error("Campaign is longer than MAX_CAMPAIGN_BLOCKS")]
+    CampaignWindowTooLong,
+    #[error("Account is not the token-account authority PDA")]
+    InvalidTokenAccountAuthority,
+    #[error("Reward token of the pool is not its stake token")]
+    CompoundMintMismatch,
+    #[error("Reward token-account doesn't cover the reward period")]
+    InsufficientRewardBalance,
+    #[error("Pool still has stake")]
+    PoolNotEmpty,
+    #[error("Reward campaign of the pool has not ended")]
+    CampaignNotEnded,
+    #[error("New pool owner must not be the default pubkey")]
+    InvalidNewOwner,
+    #[error("Signer is not the pending owner of the pool")]
+    NotPendingOwner,
+    #[error("Pool is paused, deposits are disabled")]
+    PoolPaused,
+    #[error("Position would stay below the minimum stake of the pool")]
+    BelowMinimumStake,
+    #[error("Stake would exceed the total staking cap of the pool")]
+    PoolCapExceeded,
+    #[error("Reward in another token than the staked one is paid only by GetReward and HarvestFor; claim it first")]
+    RewardMintUnsupported,
+    #[error("Fee token-account is not the fee recipient of the pool")]
+    FeeRecipientMismatch,
+    #[error("Early withdraw penalty must not exceed 10000 bps")]
+    PenaltyTooHigh,
+    #[error("Pool staker count is lower than its stakers")]
+    StakerCountUnderflow,
+    #[error("New end block must be after the current one")]
+    InvalidEndBlock,
+    #[error("Additional reward is not the reward per block over the added blocks")]
+    RewardAmountMismatch,
+    #[error("Reward per block spends the reward amount before the current block")]
+    RewardRateExhaustsPool,
+    #[error("Math operation overflowed")]
+    MathOverflow,
+    #[error("Reward campaign of the pool has not started")]
+    PoolNotStarted,
+    #[error("Signer is not the pool owner")]
+    Unauthorized,
+    #[error("Token program is not the one of the pool")]
+    TokenProgramMismatch,
+    #[error("Unable to deserialize RewardCheckpoints")]
+    InvalidRewardCheckpoints,
+    #[error("Pool start block is after end block")]
+    InvalidBlockRange,
+    #[error("Pool last reward block is after end block")]
+    LastRewardBlockAfterEnd,
+    #[error("Pool bonus window is outside of the pool blocks")]
+    InvalidBonusWindow,
+    #[error("Invalid precision factor")]
+    InvalidPrecisionFactor,
+    #[error("Reward amount does not cover reward per block over the pool blocks")]
+    InsufficientRewardAmount,
+    #[error("Account has the layout of an older program version")]
+    LegacyAccountLayout,
+    #[error("Amount to withdraw exceeds the staked amount")]
+    WithdrawAmountTooHigh,
+    #[error("Fee is above 10_000 bps")]
+    InvalidFeeBps,
+    #[error("Token-2022 mints and token-accounts with extensions are not supported")]
+    TokenExtensionsUnsupported,
 }
 
 impl PrintProgramError for StakingError {
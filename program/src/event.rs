@@ -0,0 +1,52 @@
+//! Events logged with `sol_log_data` for indexers
+
+use solana_program::{
+    log::sol_log_data,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use borsh::{
+    BorshSerialize,
+    BorshDeserialize,
+};
+
+/// Borsh-serialized event, the leading byte is the index of the variant
+#[derive(Debug, Clone, Copy, PartialEq, BorshSerialize, BorshDeserialize)]
+pub enum StakingEvent {
+    /// `user` is the pool owner, `amount` the funded reward
+    PoolInitialized {
+        pool_index: u64,
+        user: Pubkey,
+        amount: u64,
+        slot: u64,
+    },
+    /// `amount` staked, `user` is the owner of the token-account
+    Deposited {
+        pool_index: u64,
+        user: Pubkey,
+        amount: u64,
+        slot: u64,
+    },
+    /// `amount` unstaked, the withdraw fee included
+    Withdrawn {
+        pool_index: u64,
+        user: Pubkey,
+        amount: u64,
+        slot: u64,
+    },
+    /// `amount` of reward paid, the referrer share included
+    Harvested {
+        pool_index: u64,
+        user: Pubkey,
+        amount: u64,
+        slot: u64,
+    },
+}
+
+impl StakingEvent {
+    pub fn emit(&self) -> Result<(), ProgramError> {
+        sol_log_data(&[&self.try_to_vec()?]);
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 //! Instruction types
 
+use solana_program::pubkey::Pubkey;
 use borsh::{
     BorshSerialize,     
     BorshDeserialize,
@@ -20,23 +21,34 @@ pub enum StakingInstruction {
     /// 5. '[]' token mint
     /// 6. '[]' rent
     /// 7. '[]' system-program 
-    /// 8. '[]' token-program
+    /// 8. '[]' token-program owning the mint, SPL Token or SPL Token-2022
     /// 9. '[writable]' token-account with tokens for reward. Tokens will be relocated to the pool token-account
     /// 10. '[writable]' PDA authority for the token-account 
     /// 11. '[writable]' PDA token-account for the staked tokens
     /// 12. '[writable]' PDA token-account for the reward tokens
+    ///
+    /// Every later instruction of the pool takes the same token-program. Token-2022 mints and
+    /// token-accounts with extensions are not supported.
+    /// With `reward_starts_on_first_stake` the reward window is moved to start at the first deposit,
+    /// keeping its length of `end_block - start_block`.
+    /// A zero reward per block is rejected unless `allow_zero_rate` is set, e.g. for pools
+    /// funded later with FundNativeReward or SetRewardPerBlock.
+    /// The assigned pool index is set as u64 LE return data
     Initialize {
         reward_amount: u64,
         start_block: u64,
         end_block: u64,
+        reward_starts_on_first_stake: bool,
+        allow_zero_rate: bool,
     },
-    /// Deposit staked tokens and collect reward tokens (if any)
+    /// Deposit staked tokens and collect reward tokens (if any).
+    /// Only between `start_block` and `end_block`, unless the window starts on the first stake
     ///
     /// Accounts expected:
     ///
     /// 0. '[signer]' owner of the token-account with deposit
     /// 1. '[writable]' token-account with tokens for deposit. Tokens will be relocated to the PDA token-account
-    /// 2. '[]' token mint for staked token
+    /// 2. '[]' token mint for staked and reward token
     /// 3. '[writable]' PDA for state StakePool. Should be created prior to this instruction
     /// 4. '[]' PDA authority for the token-account. Should be created prior to this instruction
     /// 5. '[writable]' PDA token-account for staked tokens. Should be created prior to this instruction
@@ -47,10 +59,14 @@ pub enum StakingInstruction {
     /// 10. '[]' clock
     /// 11. '[]' system-program
     /// 12. '[]' token-program
+    /// 13. '[]' PDA for AllowlistEntry of the owner. Only for pools with an allowlist mode
     Deposit {
         amount: u64,
     },
-    /// Withdraw staked tokens and collect reward tokens 
+    /// Withdraw staked tokens and collect reward tokens.
+    /// The pool withdraw fee (if any) is kept from the withdrawn amount. Before `UserInfo::reward_lock_finish`
+    /// the reward isn't paid but kept in `UserInfo::reward_owed`, so a locked reward doesn't fail the
+    /// withdraw. Claims fail with `StakingError::RewardLocked` until the lock ends
     ///
     /// Accounts expected:
     ///
@@ -63,11 +79,16 @@ pub enum StakingInstruction {
     /// 6. '[writable]' PDA for state UserInfo. Should be created prior to this instruction
     /// 7. '[]' clock
     /// 8. '[]' token-program
+    /// 9. '[]' token mint
+    /// 10. '[writable]' token-account of the fee recipient. Only when the pool has one
     Withdraw {
         amount: u64,
     },
 
-    /// GetReward collect reward tokens 
+    /// GetReward collect reward tokens, only once `UserInfo::reward_lock_finish` has passed.
+    /// This is the harvest: the staked amount is untouched and `reward_debt` restarts from the new share
+    /// Also after `end_block`: accrual stops there, what accrued before stays claimable.
+    /// Sets `state::ClaimReceipt` return data
     ///
     /// Accounts expected:
     ///
@@ -80,9 +101,38 @@ pub enum StakingInstruction {
     /// 6. '[writable]' PDA for state UserInfo. Should be created prior to this instruction
     /// 7. '[]' clock
     /// 8. '[]' token-program
+    /// 9. '[]' token mint for reward token
+    /// 10. '[writable]' PDA native reward for pools with native SOL reward, the owner must then be writable;
+    ///     otherwise token-account of the owner for the reward, only when the reward mint isn't the token mint
+    /// 11. '[writable]' referrer token-account, only when UserInfo has a referrer. Account 10 when there is
+    ///     no reward token-account before it
     GetReward,
 
+    /// Collect reward tokens on behalf of a staker. Can be called by anyone,
+    /// rewards are sent to the associated token-account of `user` under the pool's token-program.
+    /// Settles like GetReward: owed reward is included, the reward lock applies and what the
+    /// vault can't cover stays owed
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[]' token-account for staked tokens owned by `user`
+    /// 1. '[writable]' associated token-account of `user` for reward tokens
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[]' PDA authority for the token-account. Should be created prior to this instruction
+    /// 4. '[]' PDA token-account for staked tokens. Should be created prior to this instruction
+    /// 5. '[writable]' PDA token-account for reward tokens. Should be created prior to this instruction
+    /// 6. '[writable]' PDA for state UserInfo. Should be created prior to this instruction
+    /// 7. '[]' clock
+    /// 8. '[]' token-program
+    /// 9. '[]' token mint for reward token
+    /// 10. '[writable]' referrer token-account. Only when UserInfo has a referrer
+    HarvestFor {
+        user: Pubkey,
+    },
+
     /// Withdraw staked tokens without caring about rewards 
+    /// The whole stake and any requested unstake are returned, pending and owed rewards are forfeited.
+    /// Works while the reward state of the pool is broken, no accrual is computed
     ///
     /// Accounts expected:
     ///
@@ -91,11 +141,13 @@ pub enum StakingInstruction {
     /// 2. '[]' PDA authority for the token-account. Should be created prior to this instruction
     /// 3. '[wirtable]' PDA token-account for staked tokens. Should be created prior to this instruction
     /// 4. '[writable]' PDA for state UserInfo. Should be created prior to this instruction 
-    /// 5. '[]' PDA for state StakePool. Should be created prior to this instruction
+    /// 5. '[writable]' PDA for state StakePool. Should be created prior to this instruction
     /// 6. '[]' token-program
+    /// 7. '[]' token mint
     EmergencyWithdraw,
 
-    /// Change time of end pool
+    /// Change time of end pool. The new end must be after the current one, blocks up to now
+    /// are accrued at the old window and the reward of the added blocks is moved to the pool
     ///
     /// Accounts expected:
     ///
@@ -106,10 +158,12 @@ pub enum StakingInstruction {
     /// 4. '[]' token-program
     /// 5. '[writable]' token-account with reward
     /// 6. '[writable]' PDA token-account for reward
+    /// 7. '[]' PDA token-account for staked tokens
     UpdateEndBlock {
         end_block: u64,
     },
-    /// Initialize a PDA for vec of pools
+    /// Initialize a PDA for vec of pools.
+    /// With the `upgrade-authority` feature the payer must be the program upgrade authority
     ///
     /// Accounts expected:
     ///
@@ -119,5 +173,709 @@ pub enum StakingInstruction {
     /// 3. '[]' this program
     /// 4. '[]' rent
     /// 5. '[]' system-program
+    /// 6. '[]' program data of this program. Only with the `upgrade-authority` feature
     CreateMasterAndAuthority,
+
+    /// Change the fee taken from withdrawn staked tokens.
+    /// The fee is moved to the reward token-account and shared by the remaining stakers,
+    /// or paid to the fee recipient set by SetFeeRecipient
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    UpdateWithdrawFee {
+        withdraw_fee_bps: u16,
+    },
+
+    /// Change the reduced reward rate for large positions.
+    /// The part of a position above `whale_threshold` earns `whale_reward_bps` of the normal rate.
+    /// Zero `whale_threshold` disables it
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    UpdateWhaleCurve {
+        whale_threshold: u64,
+        whale_reward_bps: u16,
+    },
+
+    /// Change the minimum reward paid by GetReward and HarvestFor.
+    /// Smaller claims are rejected and keep accruing. Zero disables it
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    UpdateMinClaimAmount {
+        min_claim_amount: u64,
+    },
+
+    /// Change the amount of reward tokens emitted per block.
+    /// Rewards up to the current block are accrued at the previous rate, then the end block
+    /// is moved to where the new rate spends the reward amount. A rate spending it by the
+    /// current block, or by the start block, is rejected
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[]' PDA token-account for staked tokens. Should be created prior to this instruction
+    /// 4. '[]' clock
+    SetRewardPerBlock {
+        reward_per_block: u64,
+    },
+
+    /// Update the pool and record `(slot, accrued_token_per_share, total_supply)`
+    /// into the pool ring buffer of checkpoints. Can be called by anyone
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[writable, signer]' payer. Pays for the checkpoints PDA on the first call
+    /// 1. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 2. '[]' PDA token-account for staked tokens. Should be created prior to this instruction
+    /// 3. '[writable]' PDA for RewardCheckpoints. Created on the first call
+    /// 4. '[]' clock
+    /// 5. '[]' rent
+    /// 6. '[]' system-program
+    Checkpoint,
+
+    /// Return the number of created pools (`MasterStaking::pool_counter`) as u64 LE return data.
+    /// Pool addresses are derived with `utils::state_pool_address` for indexes below it
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[]' PDA master-staking
+    GetPoolCount,
+
+    /// Move a staking position to another token-account without moving staked tokens.
+    /// Pending reward is paid to the current token-account first, so it fails while the reward
+    /// is under `UserInfo::reward_lock_finish`; UserInfo of the current token-account is closed
+    /// and its rent returned to the pool wallet
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' owner of the current token-account
+    /// 1. '[writable]' current token-account. Receives pending reward
+    /// 2. '[]' token-account `new_owner` of the same mint
+    /// 3. '[]' token mint
+    /// 4. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 5. '[]' PDA token-account authority. Should be created prior to this instruction
+    /// 6. '[]' PDA token-account for staked tokens. Should be created prior to this instruction
+    /// 7. '[writable]' PDA token-account for reward tokens. Should be created prior to this instruction
+    /// 8. '[writable]' PDA for UserInfo of the current token-account
+    /// 9. '[writable]' PDA for UserInfo of `new_owner`. Created if empty, must not hold a stake
+    /// 10. '[writable]' PDA wallet for create UserInfo
+    /// 11. '[]' rent
+    /// 12. '[]' clock
+    /// 13. '[]' system-program
+    /// 14. '[]' token-program
+    /// 15. '[]' PDA for AllowlistEntry of the owner of `new_owner`. Only for pools with an allowlist mode
+    TransferPosition {
+        new_owner: Pubkey,
+    },
+
+    /// Claim pending reward of the source pool and stake it into `target_pool` of the same mint.
+    /// Like Compound only once the source `UserInfo::reward_lock_finish` has passed.
+    /// Both pools are updated; pending reward of the target pool is paid to the token-account,
+    /// or kept in its `UserInfo::reward_owed` while the target position is locked
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' owner of the token-account
+    /// 1. '[writable]' token-account staked in both pools. Receives pending reward of the target pool
+    /// 2. '[]' token mint
+    /// 3. '[writable]' PDA for state StakePool to claim from
+    /// 4. '[]' PDA token-account authority. Should be created prior to this instruction
+    /// 5. '[]' PDA token-account for staked tokens of the source pool
+    /// 6. '[writable]' PDA token-account for reward tokens of the source pool
+    /// 7. '[writable]' PDA for UserInfo in the source pool
+    /// 8. '[writable]' PDA for state StakePool `target_pool`
+    /// 9. '[writable]' PDA token-account for staked tokens of the target pool
+    /// 10. '[writable]' PDA token-account for reward tokens of the target pool
+    /// 11. '[writable]' PDA for UserInfo in the target pool. Created if empty
+    /// 12. '[writable]' PDA wallet for create UserInfo of the target pool
+    /// 13. '[]' rent
+    /// 14. '[]' clock
+    /// 15. '[]' system-program
+    /// 16. '[]' token-program
+    /// 17. '[]' PDA for AllowlistEntry of the owner in the target pool. Only for target pools with an allowlist mode
+    CompoundInto {
+        target_pool: Pubkey,
+    },
+
+    /// Change the lifetime reward a single user can claim in the pool.
+    /// Claims over the cap are clamped to the remaining allowance. Zero disables it
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    UpdateMaxRewardPerUser {
+        max_reward_per_user: u64,
+    },
+
+    /// Intitializes `count` new pools with the same parameters, up to `MAX_POOLS_PER_BATCH`.
+    /// Pools get consecutive indexes from `MasterStaking::pool_counter`, each funded with `reward_amount`
+    ///
+    /// Accounts expected: 
+    ///
+    /// 0. '[writable, signer]' owner of the token-account with reward. Initializer
+    /// 1. '[writable]' PDA master-staking
+    /// 2. '[]' this program
+    /// 3. '[]' token mint
+    /// 4. '[]' rent
+    /// 5. '[]' system-program 
+    /// 6. '[]' token-program
+    /// 7. '[writable]' token-account with tokens for reward of all pools
+    /// 8. '[writable]' PDA authority for the token-account 
+    ///
+    /// Then for every pool, in the order of indexes:
+    ///
+    /// 0. '[writable]' PDA for state StakePool
+    /// 1. '[writable]' PDA for wallet stake pool
+    /// 2. '[writable]' PDA token-account for the staked tokens
+    /// 3. '[writable]' PDA token-account for the reward tokens
+    CreatePools {
+        count: u8,
+        reward_amount: u64,
+        start_block: u64,
+        end_block: u64,
+        reward_starts_on_first_stake: bool,
+        allow_zero_rate: bool,
+    },
+
+    /// Change the reward lock set on UserInfo by every deposit: `rewards_lock_duration` seconds,
+    /// moved per `lock_policy` for deposits during an active lock (0 Reset, 1 Extend, 2 KeepEarliest).
+    /// Zero `rewards_lock_duration` disables it
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    UpdateRewardLock {
+        rewards_lock_duration: u64,
+        lock_policy: u8,
+    },
+
+    /// Change who may stake in the pool: 0 anyone, 1 only users allowed by SetAllowlistEntry,
+    /// 2 anyone except users disallowed by it. With a mode set Deposit, TransferPosition and
+    /// CompoundInto take the AllowlistEntry PDA of the staker as an extra last account
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    UpdateAllowlistMode {
+        allowlist_mode: u8,
+    },
+
+    /// Allow or disallow `user`, the owner of staking token-accounts, in the pool
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[writable, signer]' pool owner. Pays for the entry on the first call
+    /// 1. '[]' token mint
+    /// 2. '[]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[writable]' PDA for AllowlistEntry of `user`
+    /// 4. '[]' rent
+    /// 5. '[]' system-program
+    SetAllowlistEntry {
+        user: Pubkey,
+        allowed: bool,
+    },
+
+    /// Rearm a finished pool for a new campaign, keeping its PDA and `pool_index`.
+    /// Only without stake and `POOL_RESET_GRACE_BLOCKS` after `end_block`. Reward tokens left in the
+    /// pool are reused, the owner tops them up to `new_reward_per_block * (new_end - new_start)`
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' mint of the reward token
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[]' clock
+    /// 4. '[]' token-program
+    /// 5. '[writable]' token-account with reward
+    /// 6. '[writable]' PDA token-account for reward
+    ResetPool {
+        new_start: u64,
+        new_end: u64,
+        new_reward_per_block: u64,
+    },
+
+    /// Set once the token-account of the referrer receiving `referral_fee_bps` of rewards
+    /// claimed by GetReward and HarvestFor. The referrer passes as their account 10
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' owner of the token-account
+    /// 1. '[]' token-account for staked tokens
+    /// 2. '[]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[writable]' PDA for UserInfo. Should be created prior to this instruction
+    SetReferrer {
+        referrer: Pubkey,
+    },
+
+    /// Change the share of claimed rewards paid to the referrer of a user
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    UpdateReferralFee {
+        referral_fee_bps: u16,
+    },
+
+    /// Fund the pool reward with `lamports` of native SOL. The first call switches a pool
+    /// created with zero `reward_amount` and without stake to native reward, with
+    /// `reward_per_block = lamports / (end_block - start_block)`; later calls top it up.
+    /// Native reward is paid to the owner wallet by GetReward (PDA native reward as account 10),
+    /// Deposit and Withdraw only settle it into `UserInfo::reward_owed`
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[writable, signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[writable]' PDA native reward. Created on the first call
+    /// 4. '[]' rent
+    /// 5. '[]' system-program
+    FundNativeReward {
+        lamports: u64,
+    },
+
+    /// Set or clear the program queried by PokePool for the reward per block
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    SetRateController {
+        rate_controller: Option<Pubkey>,
+    },
+
+    /// Settle accrual at the current rate, then take the reward per block returned by
+    /// the rate controller of the pool. Can be called by anyone
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 1. '[]' PDA token-account for staked tokens
+    /// 2. '[]' clock
+    /// 3. '[]' rate controller program
+    PokePool,
+
+    /// Set the reward share multiplier of a staker, e.g. for a lock tier or an NFT.
+    /// Zero removes the boost. Reward earned so far is kept for the next GetReward
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[]' PDA token-account for staked tokens
+    /// 4. '[]' clock
+    /// 5. '[]' token-account for staked tokens
+    /// 6. '[writable]' PDA for UserInfo. Should be created prior to this instruction
+    SetUserBoost {
+        boost_bps: u16,
+    },
+
+    /// Return the stake, claimable reward and reward unlock countdown of a user as Borsh
+    /// `state::UserRewardStatus` return data. Nothing is written, meant for simulation
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[]' PDA for state StakePool. Should be created prior to this instruction
+    /// 1. '[]' PDA token-account for staked tokens
+    /// 2. '[]' token-account for staked tokens
+    /// 3. '[]' PDA for UserInfo. Should be created prior to this instruction
+    /// 4. '[]' clock
+    GetUserInfo,
+
+    /// Run the accrual math of `update_pool` on synthetic input: `blocks` at `rate` reward per block
+    /// shared by `supply` staked tokens of the mint. Returns the resulting `accrued_token_per_share`
+    /// as u128 LE return data. Only with the `self-test` feature, meant for devnet and test builds
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[]' token mint
+    SelfTestAccrual {
+        blocks: u64,
+        supply: u64,
+        rate: u64,
+    },
+
+    /// Choose what GetReward does when the reward token-account can't cover the claim:
+    /// fail (default), or with `partial_claim_on_shortfall` pay what is there and keep
+    /// the rest in `UserInfo::reward_owed` for a claim after a top-up
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    UpdateShortfallPolicy {
+        partial_claim_on_shortfall: bool,
+    },
+
+    /// Withdraw the whole stake, collect reward tokens and close UserInfo in one go.
+    /// While the reward lock is active it fails, unless `forfeit_rewards` gives up the reward.
+    /// The pool withdraw fee (if any) is kept from the stake, rent of UserInfo goes back to the pool wallet
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' owner of the token-account
+    /// 1. '[writable]' token-account for staked tokens, receives the stake and the reward
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[]' PDA authority for the token-account. Should be created prior to this instruction
+    /// 4. '[writable]' PDA token-account for staked tokens. Should be created prior to this instruction
+    /// 5. '[writable]' PDA token-account for reward tokens. Should be created prior to this instruction
+    /// 6. '[writable]' PDA for state UserInfo. Should be created prior to this instruction
+    /// 7. '[writable]' PDA wallet stake pool
+    /// 8. '[]' clock
+    /// 9. '[]' token-program
+    /// 10. '[]' token mint
+    /// 11. '[writable]' token-account of the fee recipient. Only when the pool has one
+    /// 12. '[writable]' referrer token-account. Only when UserInfo has a referrer and a reward is paid.
+    ///     Account 11 without a fee recipient
+    ExitPool {
+        forfeit_rewards: bool,
+    },
+
+    /// Pay `simple_interest_bps` of the staked principal per block instead of the fixed
+    /// reward per block, zero switches back. Accrual up to now is settled at the previous rate.
+    /// The reward token-account has to be kept funded, the emission grows with the stake
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[]' PDA token-account for staked tokens
+    /// 4. '[]' clock
+    SetSimpleInterest {
+        simple_interest_bps: u16,
+    },
+
+    /// Freeze or unfreeze the position of the token-account `user`, e.g. for a legal hold.
+    /// A frozen position can't stake, unstake or claim, its rewards keep accruing
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[]' token-account `user`
+    /// 4. '[writable]' PDA for UserInfo. Should be created prior to this instruction
+    SetUserFrozen {
+        user: Pubkey,
+        frozen: bool,
+    },
+
+    /// Switch the pool to Synthetix accrual, `reward_rate` per second over periods of
+    /// `rewards_duration` seconds. Only allowed between periods, accrual up to now is settled first
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[]' PDA token-account for staked tokens
+    /// 4. '[]' clock
+    SetRewardsDuration {
+        rewards_duration: u64,
+    },
+
+    /// Set the cooldown in seconds between RequestUnstake and CompleteUnstake.
+    /// Non-zero replaces Withdraw and ExitPool by the two-step unstake
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    UpdateUnstakeCooldown {
+        unstake_cooldown: u64,
+    },
+
+    /// Take `amount` out of the stake, it stops earning at once and is paid by CompleteUnstake
+    /// after `StakePool::unstake_cooldown`. The reward earned so far goes to `UserInfo::reward_owed`,
+    /// the withdraw fee is charged now
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' owner of the token-account.
+    /// 1. '[]' token-account for staked tokens
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[]' PDA authority for the token-account. Should be created prior to this instruction
+    /// 4. '[writable]' PDA token-account for staked tokens. Should be created prior to this instruction
+    /// 5. '[writable]' PDA token-account for reward tokens. Should be created prior to this instruction
+    /// 6. '[writable]' PDA for state UserInfo. Should be created prior to this instruction
+    /// 7. '[]' clock
+    /// 8. '[]' token-program
+    /// 9. '[]' token mint
+    /// 10. '[writable]' token-account of the fee recipient. Only when the pool has one
+    RequestUnstake {
+        amount: u64,
+    },
+
+    /// Transfer the tokens of RequestUnstake once the cooldown elapsed
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' owner of the token-account.
+    /// 1. '[writable]' token-account for staked tokens
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[]' PDA authority for the token-account. Should be created prior to this instruction
+    /// 4. '[writable]' PDA token-account for staked tokens. Should be created prior to this instruction
+    /// 5. '[writable]' PDA for state UserInfo. Should be created prior to this instruction
+    /// 6. '[]' clock
+    /// 7. '[]' token-program
+    /// 8. '[]' token mint
+    CompleteUnstake,
+
+    /// Pause or unpause the pool. Deposit, Compound and CompoundInto into a paused pool fail,
+    /// withdrawals and claims keep working and rewards keep accruing
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    SetPause {
+        paused: bool,
+    },
+
+    /// Overwrite `UserInfo::reward_debt` of `user` to repair a position after an audited
+    /// accounting discrepancy. The pool must be paused, a position can be corrected once per
+    /// `state::REWARD_DEBT_CORRECTION_INTERVAL` slots
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[]' token-account of the user, must be `user`
+    /// 4. '[writable]' PDA for state UserInfo. Should be created prior to this instruction
+    /// 5. '[]' clock
+    SetUserRewardDebt {
+        user: Pubkey,
+        reward_debt: u128,
+    },
+
+    /// Return the earliest `UserInfo::reward_lock_finish` still in the future among the given
+    /// positions as u64 LE return data, 0 when none is locked. Nothing is written, meant for simulation
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[]' clock
+    /// 1. ..N '[]' PDAs for state UserInfo, at least one
+    GetNextUnlock,
+
+    /// Stake the pending reward into the same pool, the reward token must be the stake token.
+    /// Like GetReward only once `UserInfo::reward_lock_finish` has passed, the restaked amount
+    /// then locks like a deposit
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' owner of the token-account.
+    /// 1. '[]' token-account for staked tokens
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[]' PDA authority for the token-account. Should be created prior to this instruction
+    /// 4. '[writable]' PDA token-account for staked tokens. Should be created prior to this instruction
+    /// 5. '[writable]' PDA token-account for reward tokens. Should be created prior to this instruction
+    /// 6. '[writable]' PDA for state UserInfo. Should be created prior to this instruction
+    /// 7. '[]' clock
+    /// 8. '[]' token-program
+    /// 9. '[]' token mint
+    Compound,
+
+    /// Fund a new period of a `Synthetix` pool with `reward` tokens moved from the owner.
+    /// `reward_rate` spreads `reward`, plus what is left of a running period, over `rewards_duration`
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' owner of the token-account with reward. Pool owner
+    /// 1. '[]' mint of the reward token
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[]' clock
+    /// 4. '[]' token-program
+    /// 5. '[writable]' token-account with reward
+    /// 6. '[writable]' PDA token-account for reward
+    /// 7. '[]' PDA token-account for staked tokens
+    NotifyRewardAmount {
+        reward: u64,
+    },
+    /// Initialize a stake pool with an explicit reward per block and precision factor rank
+    /// instead of the ones Initialize derives from the reward amount and the mint decimals.
+    /// Positions of the pool must hold at least `min_stake` after every deposit.
+    /// `withdraw_fee_bps` and `fee_recipient` are checked as by UpdateWithdrawFee and SetFeeRecipient.
+    /// The assigned pool index is set as u64 LE return data
+    ///
+    /// Accounts expected: the same as Initialize, the token-account with reward holds the reward mint
+    ///
+    /// 13. '[]' mint of the reward token, only with `separate_reward_mint`. It must have the same decimals
+    ///     and belong to the token-program. Such a pool pays reward only with GetReward and HarvestFor,
+    ///     and takes no withdraw fee
+    InitializePool {
+        reward_amount: u64,
+        reward_per_block: u64,
+        start_block: u64,
+        end_block: u64,
+        precision_factor_rank: u8,
+        min_stake: u64,
+        separate_reward_mint: bool,
+        withdraw_fee_bps: u16,
+        fee_recipient: Option<Pubkey>,
+    },
+    /// Close a finished pool without stake. Reward tokens left in the pool go back to the owner,
+    /// so do staked tokens sent to the pool outside Deposit. The rent of the StakePool, the pool
+    /// wallet and both pool token-accounts goes to the owner.
+    /// Fails while a requested unstake is not completed
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[writable, signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[writable]' PDA wallet stake pool
+    /// 4. '[]' PDA authority for the token-account
+    /// 5. '[writable]' PDA token-account for staked tokens
+    /// 6. '[writable]' PDA token-account for reward tokens
+    /// 7. '[writable]' token-account of the owner for the reward tokens left, and for the staked
+    ///    tokens left when the reward mint is the token mint
+    /// 8. '[]' clock
+    /// 9. '[]' system-program
+    /// 10. '[]' token-program
+    /// 11. '[]' mint of the reward token, only for pools with a reward mint other than the token mint
+    /// 12. '[writable]' token-account of the owner for the staked tokens left, only for pools with a
+    ///    reward mint other than the token mint
+    ClosePool,
+    /// Propose `new_owner` as the owner of the pool, the ownership moves once it calls
+    /// AcceptPoolOwnership. A new proposal replaces the pending one
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    TransferPoolOwnership {
+        new_owner: Pubkey,
+    },
+    /// Become the owner of a pool as its pending owner
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pending owner of the pool
+    /// 1. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    AcceptPoolOwnership,
+    /// Change the cap of `total_supply`. Stakes past it fail, lowering it below the staked
+    /// supply only blocks new stake. Zero disables it
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    UpdateMaxTotalSupply {
+        max_total_supply: u64,
+    },
+    /// Pay the withdraw fee to the `fee_recipient` token-account instead of sharing it
+    /// between the remaining stakers. None shares it again
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    SetFeeRecipient {
+        fee_recipient: Option<Pubkey>,
+    },
+    /// Change the share of the stake kept when a position is withdrawn under its reward lock.
+    /// It falls linearly to zero at `UserInfo::reward_lock_finish` and is taken with the withdraw fee
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    UpdateEarlyWithdrawPenalty {
+        early_withdraw_penalty_bps: u16,
+    },
+    /// Move the end of the pool to `new_end_block`, funding it with `additional_reward`.
+    /// Fails unless it is `reward_per_block` over the added blocks, so the owner signs for
+    /// the exact amount moved from the reward token-account
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' owner of the token-account with reward. Pool owner
+    /// 1. '[]' mint of the reward token
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    /// 3. '[]' clock
+    /// 4. '[]' token-program
+    /// 5. '[writable]' token-account with reward
+    /// 6. '[writable]' PDA token-account for reward
+    /// 7. '[]' PDA token-account for staked tokens
+    ExtendRewardPeriod {
+        new_end_block: u64,
+        additional_reward: u64,
+    },
+    /// GetReward from `pool_count` pools in one transaction, up to `state::MAX_HARVESTS_PER_BATCH`.
+    /// Pools with native SOL reward are claimed with GetReward.
+    /// Sets the reward paid from all pools as u64 LE return data
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' owner of the token-accounts
+    /// 1. '[]' PDA authority for the token-account. Should be created prior to this instruction
+    /// 2. '[]' clock
+    /// 3. '[]' token-program
+    ///
+    /// Then for every pool:
+    ///
+    /// 0. '[writable]' token-account for staked tokens
+    /// 1. '[writable]' PDA for state StakePool
+    /// 2. '[writable]' PDA token-account for staked tokens
+    /// 3. '[writable]' PDA token-account for reward tokens
+    /// 4. '[writable]' PDA for state UserInfo
+    /// 5. '[]' token mint for reward token
+    /// 6. '[writable]' token-account of the owner for the reward. The token-account for staked
+    ///    tokens again when the reward mint is the token mint
+    /// 7. '[writable]' referrer token-account. The token-account of account 6 again when UserInfo
+    ///    has no referrer
+    HarvestMany {
+        pool_count: u8,
+    },
+    /// Return the yearly reward at the current rate as bps of the staked supply, as u64 LE
+    /// return data. Slots are taken as `state::SLOTS_PER_YEAR` a year. Nothing is written,
+    /// meant for simulation
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[]' PDA for state StakePool. Should be created prior to this instruction
+    /// 1. '[]' clock
+    GetPoolApr,
+    /// Take the precision factor rank of a pool created with a rank above
+    /// `state::MAX_PRECISION_FACTOR_RANK` down to it. Reward math fails for such a pool, its
+    /// stakers leave with EmergencyWithdraw before the owner migrates it without stake.
+    /// Nothing changes for a pool within the maximum
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. '[signer]' pool owner
+    /// 1. '[]' token mint
+    /// 2. '[writable]' PDA for state StakePool. Should be created prior to this instruction
+    MigratePrecisionFactor,
 }
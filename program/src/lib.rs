@@ -5,6 +5,8 @@ pub mod instruction;
 pub mod state;
 pub mod error;
 pub mod utils;
+pub mod reward_model;
+pub mod event;
 
 #[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
@@ -15,14 +17,16 @@ use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, pub
 
 
 pub const ADD_SEED_TOKEN_ACCOUNT_AUTHORITY: &str = "TOKEN_ACCOUNT_AUTHORITY_test_8"; 
-pub const BUMP_SEED_TOKEN_ACCOUNT_AUTHORITY: u8 = 255; 
  
 pub const ADD_SEED_MASTER_STAKING: &str = "MASTER_STAKING_test_8";  
-pub const BUMP_SEED_MASTER_STAKING: u8 = 254; 
 
 pub const ADD_SEED_STATE_POOL: &str = "STATE_POOL";
 pub const ADD_SEED_WALLET_POOL: &str = "WALLET_POOL"; // PDA with SOL for creating PDA UserInfo
 pub const ADD_SEED_STAKED: &str = "STAKED"; // PDA t-a with staked tokens. Reward tokens are kept in other PDA t-a
+pub const ADD_SEED_REWARD: &str = "REWARD"; // PDA t-a with reward tokens
+pub const ADD_SEED_CHECKPOINTS: &str = "CHECKPOINTS"; // PDA with ring buffer of reward checkpoints
+pub const ADD_SEED_ALLOWLIST: &str = "ALLOWLIST"; // PDA per pool and user with allowlist entry
+pub const ADD_SEED_NATIVE_REWARD: &str = "NATIVE_REWARD"; // PDA with lamports for pools with native SOL reward
 
 solana_program::declare_id!("EyJ4ZNzAK8HJJrRbTTE6x769RA2h95zj826194DxyEbw");
 
@@ -33,3 +37,20 @@ pub fn check_program_account(spl_token_program_id: &Pubkey) -> ProgramResult {
     }
     Ok(())
 }
+
+/// Program id of SPL Token-2022. spl-token-2022 needs a newer solana-program than this crate,
+/// its base instructions and account layouts are the ones of SPL Token, so they are built
+/// with spl-token and sent to this program id. Only mints and token-accounts without extensions
+/// have these layouts, the others are refused with `TokenExtensionsUnsupported`. That includes
+/// the associated token-accounts of Token-2022, they carry the ImmutableOwner extension
+pub mod spl_token_2022 {
+    solana_program::declare_id!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+}
+
+/// Checks that the supplied program ID is SPL Token or SPL Token-2022
+pub fn check_token_program_account(token_program_id: &Pubkey) -> ProgramResult {
+    if token_program_id != &spl_token::id() && token_program_id != &spl_token_2022::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
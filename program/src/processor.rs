@@ -7,9 +7,10 @@ use solana_program::{
     }, 
     program::{
         invoke_signed,
-        invoke,
+        set_return_data,
     },
     program_error::{
+        PrintProgramError,
         ProgramError,
     },
     program_option::COption,
@@ -32,30 +33,74 @@ use borsh::{
     BorshSerialize,
 };
 use crate::{
+    event::StakingEvent,
     state::{
         MasterStaking,
         StakePool,
         UserInfo,
+        UserRewardStatus,
+        ClaimReceipt,
+        CampaignState,
+        LockPolicy,
+        AllowlistEntry,
+        ALLOWLIST_ENTRY_LEN,
+        Checkpoint,
+        RewardCheckpoints,
         MASTER_STAKING_LEN,
+        MAX_POOLS_PER_BATCH,
+        MAX_HARVESTS_PER_BATCH,
+        MAX_PRECISION_FACTOR_RANK,
         USER_INFO_LEN,
+        REWARD_CHECKPOINTS_LEN,
     },
     utils::{
+        assert_pool_owner,
+        for_token_program,
+        validate_token_program,
         validate_pool_token_account,
         validate_stake_pool,
         validate_user_state,
         get_pending,
         get_reward_debt,
+        get_reward_debt_after_claim,
+        state_pool_address,
+        wallet_pool_address,
+        staked_address,
+        transfer_tokens,
+        now_secs,
+        expect_accounts,
+        validate_token_account_authority,
+        validate_reward_token_account,
+        validate_pool_reward_mint,
+        validate_master_staking,
+        validate_staker,
+        allowlist_entry_address,
+        required_reward_funding,
+        native_reward_address,
+        transfer_native_reward,
+        fetch_controller_rate,
+        reward_pda,
+        validate_reward_vault,
+        associated_token_address,
+        token_account_authority,
+        master_staking_pda,
+        precision_factor_rank_for_decimals,
+        unpack_token_account,
+        unpack_token_mint,
     },
     error::StakingError, 
     instruction::StakingInstruction,
     id as this_program_id,
+    check_token_program_account,
     ADD_SEED_TOKEN_ACCOUNT_AUTHORITY,
-    BUMP_SEED_TOKEN_ACCOUNT_AUTHORITY,
     ADD_SEED_MASTER_STAKING,
-    BUMP_SEED_MASTER_STAKING,
     ADD_SEED_STATE_POOL,
     ADD_SEED_WALLET_POOL,
     ADD_SEED_STAKED,
+    ADD_SEED_CHECKPOINTS,
+    ADD_SEED_ALLOWLIST,
+    ADD_SEED_NATIVE_REWARD,
+    ADD_SEED_REWARD,
 };
 
 /// Program state handler.
@@ -73,13 +118,23 @@ impl Processor {
                 reward_amount, 
                 start_block,
                 end_block,
+                reward_starts_on_first_stake,
+                allow_zero_rate,
             } => {
                 msg!("Instruction: Initialize stake pool");
                 Self::process_initialize(
                     accounts,
                     reward_amount,
+                    None,
                     start_block,
                     end_block,
+                    None,
+                    0,
+                    reward_starts_on_first_stake,
+                    allow_zero_rate,
+                    false,
+                    0,
+                    None,
                 )
             },
             StakingInstruction::Deposit {
@@ -107,6 +162,15 @@ impl Processor {
                     accounts,
                 )
             },
+            StakingInstruction::HarvestFor {
+                user,
+            } => {
+                msg!("Instruction: Harvest For");
+                Self::process_harvest_for(
+                    accounts,
+                    user,
+                )
+            },
             StakingInstruction::EmergencyWithdraw 
             => {
                 msg!("Instruction: Emergency Withdraw");
@@ -121,6 +185,7 @@ impl Processor {
                 Self::process_update_end_block(
                     accounts,
                     end_block,
+                    None,
                 )
             },
             StakingInstruction::CreateMasterAndAuthority{
@@ -130,15 +195,461 @@ impl Processor {
                     accounts,
                 )
             },
+            StakingInstruction::UpdateWithdrawFee {
+                withdraw_fee_bps,
+            } => {
+                msg!("Instruction: Update Withdraw Fee");
+                Self::process_update_withdraw_fee(
+                    accounts,
+                    withdraw_fee_bps,
+                )
+            },
+            StakingInstruction::UpdateWhaleCurve {
+                whale_threshold,
+                whale_reward_bps,
+            } => {
+                msg!("Instruction: Update Whale Curve");
+                Self::process_update_whale_curve(
+                    accounts,
+                    whale_threshold,
+                    whale_reward_bps,
+                )
+            },
+            StakingInstruction::UpdateMinClaimAmount {
+                min_claim_amount,
+            } => {
+                msg!("Instruction: Update Min Claim Amount");
+                Self::process_update_min_claim_amount(
+                    accounts,
+                    min_claim_amount,
+                )
+            },
+            StakingInstruction::SetRewardPerBlock {
+                reward_per_block,
+            } => {
+                msg!("Instruction: Set Reward Per Block");
+                Self::process_set_reward_per_block(
+                    accounts,
+                    reward_per_block,
+                )
+            },
+            StakingInstruction::Checkpoint
+            => {
+                msg!("Instruction: Checkpoint");
+                Self::process_checkpoint(
+                    accounts,
+                )
+            },
+            StakingInstruction::GetPoolCount
+            => {
+                msg!("Instruction: Get Pool Count");
+                Self::process_get_pool_count(
+                    accounts,
+                )
+            },
+            StakingInstruction::TransferPosition {
+                new_owner,
+            } => {
+                msg!("Instruction: Transfer Position");
+                Self::process_transfer_position(
+                    accounts,
+                    new_owner,
+                )
+            },
+            StakingInstruction::CompoundInto {
+                target_pool,
+            } => {
+                msg!("Instruction: Compound Into");
+                Self::process_compound_into(
+                    accounts,
+                    target_pool,
+                )
+            },
+            StakingInstruction::UpdateMaxRewardPerUser {
+                max_reward_per_user,
+            } => {
+                msg!("Instruction: Update Max Reward Per User");
+                Self::process_update_max_reward_per_user(
+                    accounts,
+                    max_reward_per_user,
+                )
+            },
+            StakingInstruction::UpdateRewardLock {
+                rewards_lock_duration,
+                lock_policy,
+            } => {
+                msg!("Instruction: Update Reward Lock");
+                Self::process_update_reward_lock(
+                    accounts,
+                    rewards_lock_duration,
+                    lock_policy,
+                )
+            },
+            StakingInstruction::UpdateAllowlistMode {
+                allowlist_mode,
+            } => {
+                msg!("Instruction: Update Allowlist Mode");
+                Self::process_update_allowlist_mode(
+                    accounts,
+                    allowlist_mode,
+                )
+            },
+            StakingInstruction::SetAllowlistEntry {
+                user,
+                allowed,
+            } => {
+                msg!("Instruction: Set Allowlist Entry");
+                Self::process_set_allowlist_entry(
+                    accounts,
+                    user,
+                    allowed,
+                )
+            },
+            StakingInstruction::ResetPool {
+                new_start,
+                new_end,
+                new_reward_per_block,
+            } => {
+                msg!("Instruction: Reset Pool");
+                Self::process_reset_pool(
+                    accounts,
+                    new_start,
+                    new_end,
+                    new_reward_per_block,
+                )
+            },
+            StakingInstruction::SetReferrer {
+                referrer,
+            } => {
+                msg!("Instruction: Set Referrer");
+                Self::process_set_referrer(
+                    accounts,
+                    referrer,
+                )
+            },
+            StakingInstruction::UpdateReferralFee {
+                referral_fee_bps,
+            } => {
+                msg!("Instruction: Update Referral Fee");
+                Self::process_update_referral_fee(
+                    accounts,
+                    referral_fee_bps,
+                )
+            },
+            StakingInstruction::FundNativeReward {
+                lamports,
+            } => {
+                msg!("Instruction: Fund Native Reward");
+                Self::process_fund_native_reward(
+                    accounts,
+                    lamports,
+                )
+            },
+            StakingInstruction::SetRateController {
+                rate_controller,
+            } => {
+                msg!("Instruction: Set Rate Controller");
+                Self::process_set_rate_controller(
+                    accounts,
+                    rate_controller,
+                )
+            },
+            StakingInstruction::PokePool => {
+                msg!("Instruction: Poke Pool");
+                Self::process_poke_pool(accounts)
+            },
+            StakingInstruction::SetUserBoost {
+                boost_bps,
+            } => {
+                msg!("Instruction: Set User Boost");
+                Self::process_set_user_boost(
+                    accounts,
+                    boost_bps,
+                )
+            },
+            StakingInstruction::GetUserInfo => {
+                msg!("Instruction: Get User Info");
+                Self::process_get_user_info(accounts)
+            },
+            StakingInstruction::SelfTestAccrual {
+                blocks,
+                supply,
+                rate,
+            } => {
+                msg!("Instruction: Self Test Accrual");
+                Self::process_self_test_accrual(
+                    accounts,
+                    blocks,
+                    supply,
+                    rate,
+                )
+            },
+            StakingInstruction::UpdateShortfallPolicy {
+                partial_claim_on_shortfall,
+            } => {
+                msg!("Instruction: Update Shortfall Policy");
+                Self::process_update_shortfall_policy(
+                    accounts,
+                    partial_claim_on_shortfall,
+                )
+            },
+            StakingInstruction::ExitPool {
+                forfeit_rewards,
+            } => {
+                msg!("Instruction: Exit Pool");
+                Self::process_exit_pool(
+                    accounts,
+                    forfeit_rewards,
+                )
+            },
+            StakingInstruction::SetSimpleInterest {
+                simple_interest_bps,
+            } => {
+                msg!("Instruction: Set Simple Interest");
+                Self::process_set_simple_interest(
+                    accounts,
+                    simple_interest_bps,
+                )
+            },
+            StakingInstruction::SetUserFrozen {
+                user,
+                frozen,
+            } => {
+                msg!("Instruction: Set User Frozen");
+                Self::process_set_user_frozen(
+                    accounts,
+                    user,
+                    frozen,
+                )
+            },
+            StakingInstruction::SetRewardsDuration {
+                rewards_duration,
+            } => {
+                msg!("Instruction: Set Rewards Duration");
+                Self::process_set_rewards_duration(
+                    accounts,
+                    rewards_duration,
+                )
+            },
+            StakingInstruction::UpdateUnstakeCooldown {
+                unstake_cooldown,
+            } => {
+                msg!("Instruction: Update Unstake Cooldown");
+                Self::process_update_unstake_cooldown(
+                    accounts,
+                    unstake_cooldown,
+                )
+            },
+            StakingInstruction::RequestUnstake {
+                amount,
+            } => {
+                msg!("Instruction: Request Unstake");
+                Self::process_request_unstake(
+                    accounts,
+                    amount,
+                )
+            },
+            StakingInstruction::CompleteUnstake => {
+                msg!("Instruction: Complete Unstake");
+                Self::process_complete_unstake(
+                    accounts,
+                )
+            },
+            StakingInstruction::SetPause {
+                paused,
+            } => {
+                msg!("Instruction: Set Pause");
+                Self::process_set_pause(
+                    accounts,
+                    paused,
+                )
+            },
+            StakingInstruction::SetUserRewardDebt {
+                user,
+                reward_debt,
+            } => {
+                msg!("Instruction: Set User Reward Debt");
+                Self::process_set_user_reward_debt(
+                    accounts,
+                    user,
+                    reward_debt,
+                )
+            },
+            StakingInstruction::GetNextUnlock => {
+                msg!("Instruction: Get Next Unlock");
+                Self::process_get_next_unlock(
+                    accounts,
+                )
+            },
+            StakingInstruction::Compound => {
+                msg!("Instruction: Compound");
+                Self::process_compound(
+                    accounts,
+                )
+            },
+            StakingInstruction::NotifyRewardAmount {
+                reward,
+            } => {
+                msg!("Instruction: Notify Reward Amount");
+                Self::process_notify_reward_amount(
+                    accounts,
+                    reward,
+                )
+            },
+            StakingInstruction::InitializePool {
+                reward_amount,
+                reward_per_block,
+                start_block,
+                end_block,
+                precision_factor_rank,
+                min_stake,
+                separate_reward_mint,
+                withdraw_fee_bps,
+                fee_recipient,
+            } => {
+                msg!("Instruction: Initialize Pool");
+                Self::process_initialize(
+                    accounts,
+                    reward_amount,
+                    Some(reward_per_block),
+                    start_block,
+                    end_block,
+                    Some(precision_factor_rank),
+                    min_stake,
+                    false,
+                    false,
+                    separate_reward_mint,
+                    withdraw_fee_bps,
+                    fee_recipient,
+                )
+            },
+            StakingInstruction::ClosePool => {
+                msg!("Instruction: Close Pool");
+                Self::process_close_pool(accounts)
+            },
+            StakingInstruction::TransferPoolOwnership {
+                new_owner,
+            } => {
+                msg!("Instruction: Transfer Pool Ownership");
+                Self::process_transfer_pool_ownership(
+                    accounts,
+                    new_owner,
+                )
+            },
+            StakingInstruction::AcceptPoolOwnership => {
+                msg!("Instruction: Accept Pool Ownership");
+                Self::process_accept_pool_ownership(accounts)
+            },
+            StakingInstruction::UpdateMaxTotalSupply {
+                max_total_supply,
+            } => {
+                msg!("Instruction: Update Max Total Supply");
+                Self::process_update_max_total_supply(
+                    accounts,
+                    max_total_supply,
+                )
+            },
+            StakingInstruction::SetFeeRecipient {
+                fee_recipient,
+            } => {
+                msg!("Instruction: Set Fee Recipient");
+                Self::process_set_fee_recipient(
+                    accounts,
+                    fee_recipient,
+                )
+            },
+            StakingInstruction::UpdateEarlyWithdrawPenalty {
+                early_withdraw_penalty_bps,
+            } => {
+                msg!("Instruction: Update Early Withdraw Penalty");
+                Self::process_update_early_withdraw_penalty(
+                    accounts,
+                    early_withdraw_penalty_bps,
+                )
+            },
+            StakingInstruction::ExtendRewardPeriod {
+                new_end_block,
+                additional_reward,
+            } => {
+                msg!("Instruction: Extend Reward Period");
+                Self::process_update_end_block(
+                    accounts,
+                    new_end_block,
+                    Some(additional_reward),
+                )
+            },
+            StakingInstruction::HarvestMany {
+                pool_count,
+            } => {
+                msg!("Instruction: Harvest Many");
+                Self::process_harvest_many(
+                    accounts,
+                    pool_count,
+                )
+            },
+            StakingInstruction::GetPoolApr => {
+                msg!("Instruction: Get Pool Apr");
+                Self::process_get_pool_apr(
+                    accounts,
+                )
+            },
+            StakingInstruction::CreatePools {
+                count,
+                reward_amount,
+                start_block,
+                end_block,
+                reward_starts_on_first_stake,
+                allow_zero_rate,
+            } => {
+                msg!("Instruction: Create Pools");
+                Self::process_create_pools(
+                    accounts,
+                    count,
+                    reward_amount,
+                    start_block,
+                    end_block,
+                    reward_starts_on_first_stake,
+                    allow_zero_rate,
+                )
+            },
+            StakingInstruction::MigratePrecisionFactor => {
+                msg!("Instruction: Migrate Precision Factor");
+                Self::process_migrate_precision_factor(
+                    accounts,
+                )
+            },
         }
     }
 
+    /// `reward_per_block` and `precision_factor_rank` are derived from `reward_amount`
+    /// and the mint decimals when not given. With `separate_reward_mint` the reward is paid
+    /// in the mint passed after the other accounts. Only InitializePool charges a withdraw fee
+    #[allow(clippy::too_many_arguments)]
     fn process_initialize(
         accounts: &[AccountInfo],
         reward_amount: u64,
+        reward_per_block: Option<u64>,
         start_block: u64,
         end_block: u64,
+        precision_factor_rank: Option<u8>,
+        min_stake: u64,
+        reward_starts_on_first_stake: bool,
+        allow_zero_rate: bool,
+        separate_reward_mint: bool,
+        withdraw_fee_bps: u16,
+        fee_recipient: Option<Pubkey>,
     ) -> ProgramResult {
+        expect_accounts(accounts, 13 + separate_reward_mint as usize)?;
+        if start_block >= end_block {
+            StakingError::InvalidBlockRange.print::<StakingError>();
+            return Err(StakingError::InvalidBlockRange.into());
+        }
+        if let Some(precision_factor_rank) = precision_factor_rank {
+            if precision_factor_rank > MAX_PRECISION_FACTOR_RANK {
+                StakingError::InvalidPrecisionFactor.print::<StakingError>();
+                return Err(StakingError::InvalidPrecisionFactor.into());
+            }
+        }
         let account_info_iter = &mut accounts.iter();
 
         let owner_account_info = next_account_info(account_info_iter)?; // 0
@@ -147,6 +658,8 @@ impl Processor {
         }
         
         let pda_master_staking_info = next_account_info(account_info_iter)?; // 1
+        validate_master_staking(pda_master_staking_info)?;
+
         let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
         let pda_wallet_for_create_user_info = next_account_info(account_info_iter)?; // 3
 
@@ -156,29 +669,55 @@ impl Processor {
         }
 
         let mint_info = next_account_info(account_info_iter)?; // 5
-        let mint = TokenMint::unpack_unchecked(&mint_info.data.borrow())?;
+        let mint = unpack_token_mint(&mint_info.data.borrow())?;
 
         let rent_info = next_account_info(account_info_iter)?; // 6
         let rent = &Rent::from_account_info(rent_info)?; 
 
         let system_program_info = next_account_info(account_info_iter)?; // 7
         let token_program_info = next_account_info(account_info_iter)?; // 8
+        check_token_program_account(token_program_info.key)?;
+        if mint_info.owner != token_program_info.key {
+            StakingError::TokenProgramMismatch.print::<StakingError>();
+            return Err(StakingError::TokenProgramMismatch.into());
+        }
         let token_account_info = next_account_info(account_info_iter)?; // 9
 
-        // TODO: Add validate for authority
         let pda_pool_token_account_authority_info = next_account_info(account_info_iter)?; // 10
+        validate_token_account_authority(pda_pool_token_account_authority_info)?;
         let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 11
         // TODO: Add validate for token-account
         let pda_pool_token_account_reward_info = next_account_info(account_info_iter)?; // 12
 
+        let reward_mint_info = if separate_reward_mint {
+            let reward_mint_info = next_account_info(account_info_iter)?; // 13
+            if reward_mint_info.owner != token_program_info.key {
+                StakingError::TokenProgramMismatch.print::<StakingError>();
+                return Err(StakingError::TokenProgramMismatch.into());
+            }
+            reward_mint_info
+        } else {
+            mint_info
+        };
+        // Principal and reward transfers share `reward_decimals`
+        if unpack_token_mint(&reward_mint_info.data.borrow())?.decimals != mint.decimals {
+            return Err(StakingError::DecimalsMismatch.into());
+        }
+
+        // Stake and reward share the mint, one account for both would pay rewards out of principal
+        if pda_pool_token_account_staked_info.key == pda_pool_token_account_reward_info.key {
+            StakingError::VaultAliasing.print::<StakingError>();
+            return Err(StakingError::VaultAliasing.into());
+        }
+
         let mut master_staking = MasterStaking::from_account_info(&pda_master_staking_info)?;
         let pool_index = master_staking.pool_counter;
 
         let minimum_balance_token_acc = rent.minimum_balance(TokenAccount::LEN);
 
-        let (_pda_token_account_staked_pubkey, bump_seed_pda_token_account_staked) = Pubkey::find_program_address(
-            &[&pool_index.to_le_bytes(), ADD_SEED_STAKED.as_bytes()],
-            &this_program_info.key,
+        let (_pda_token_account_staked_pubkey, bump_seed_pda_token_account_staked) = staked_address(
+            this_program_info.key,
+            pool_index,
         );
         let sign_seeds_pda_token_account_staked: &[&[_]] = 
             &[
@@ -193,18 +732,21 @@ impl Processor {
                 pda_pool_token_account_staked_info.key,
                 minimum_balance_token_acc,
                 TokenAccount::LEN as u64,
-                &spl_token::id(),
+                token_program_info.key,
             ),
             &[owner_account_info.clone(), pda_pool_token_account_staked_info.clone(), system_program_info.clone()],
             &[&sign_seeds_pda_token_account_staked],
         )?;
 
         invoke_signed(
-            &spl_token::instruction::initialize_account(
-                &spl_token::id(),
-                pda_pool_token_account_staked_info.key,
-                mint_info.key,
-                pda_pool_token_account_authority_info.key,
+            &for_token_program(
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(),
+                    pda_pool_token_account_staked_info.key,
+                    mint_info.key,
+                    pda_pool_token_account_authority_info.key,
+                )?,
+                token_program_info.key,
             )?,
             &[
             pda_pool_token_account_staked_info.clone(), 
@@ -216,13 +758,14 @@ impl Processor {
             &[&sign_seeds_pda_token_account_staked],
         )?;
 
-        let (_pda_token_account_pubkey, bump_seed_pda_token_account) = Pubkey::find_program_address(
-            &[&pool_index.to_le_bytes()],
-            &this_program_info.key
+        let (_pda_token_account_pubkey, bump_seed_pda_token_account) = reward_pda(
+            this_program_info.key,
+            pool_index,
         );
         let sign_seeds_pda_token_account: &[&[_]] = 
             &[
             &pool_index.to_le_bytes(),
+            ADD_SEED_REWARD.as_bytes(),
             &[bump_seed_pda_token_account],
             ];
 
@@ -232,22 +775,25 @@ impl Processor {
                 pda_pool_token_account_reward_info.key,
                 minimum_balance_token_acc,
                 TokenAccount::LEN as u64,
-                &spl_token::id(),
+                token_program_info.key,
             ),
             &[owner_account_info.clone(), pda_pool_token_account_reward_info.clone(), system_program_info.clone()],
             &[&sign_seeds_pda_token_account],
         )?;                                                             
 
         invoke_signed(
-            &spl_token::instruction::initialize_account(
-                &spl_token::id(),
-                pda_pool_token_account_reward_info.key,
-                mint_info.key,
-                pda_pool_token_account_authority_info.key,
+            &for_token_program(
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(),
+                    pda_pool_token_account_reward_info.key,
+                    reward_mint_info.key,
+                    pda_pool_token_account_authority_info.key,
+                )?,
+                token_program_info.key,
             )?,
             &[
             pda_pool_token_account_reward_info.clone(), 
-            mint_info.clone(), 
+            reward_mint_info.clone(), 
             pda_pool_token_account_authority_info.clone(),
             rent_info.clone(),
             token_program_info.clone(),
@@ -255,28 +801,22 @@ impl Processor {
             &[&sign_seeds_pda_token_account],
         )?;
 
-        invoke(
-            &spl_token::instruction::transfer(
-                &spl_token::id(),
-                token_account_info.key,
-                pda_pool_token_account_reward_info.key,
-                owner_account_info.key,
-                &[owner_account_info.key],
-                reward_amount,
-            )?,
-            &[
-            token_account_info.clone(), 
-            pda_pool_token_account_reward_info.clone(), 
-            owner_account_info.clone(),
-            token_program_info.clone(),
-            ],
+        transfer_tokens(
+            token_program_info,
+            token_account_info,
+            reward_mint_info,
+            pda_pool_token_account_reward_info,
+            owner_account_info,
+            &[],
+            reward_amount,
+            mint.decimals,
         )?;
 
         let min_balance_wallet_pool = rent.minimum_balance(USER_INFO_LEN) * 5; 
 
-        let (_pda_wallet_for_create_user_pubkey, bump_seed_wallet_for_create_user) = Pubkey::find_program_address(
-            &[&pool_index.to_le_bytes(), ADD_SEED_WALLET_POOL.as_bytes()],
-            &this_program_info.key,
+        let (_pda_wallet_for_create_user_pubkey, bump_seed_wallet_for_create_user) = wallet_pool_address(
+            this_program_info.key,
+            pool_index,
         );
         let sign_seeds_pda_wallet_pool: &[&[_]] = 
             &[
@@ -299,9 +839,9 @@ impl Processor {
 
         let min_balance_stake_pool = rent.minimum_balance(StakePool::LEN);
 
-        let (_pda_state_pool_pubkey, bump_seed_state_pool) = Pubkey::find_program_address(
-            &[&pool_index.to_le_bytes(), ADD_SEED_STATE_POOL.as_bytes()],
-            &this_program_info.key,
+        let (_pda_state_pool_pubkey, bump_seed_state_pool) = state_pool_address(
+            this_program_info.key,
+            pool_index,
         );
         let sign_seeds_pda_state_pool: &[&[_]] = 
             &[
@@ -322,20 +862,22 @@ impl Processor {
             &[&sign_seeds_pda_state_pool],
         )?;
 
-        assert!(mint.decimals < 21, "Token decimals must be inferior to 21");
-
-        let precision_factor_rank = 21_u8
-            .checked_sub(mint.decimals as u8)
-            .ok_or(StakingError::Overflow)?;
+        let precision_factor_rank = match precision_factor_rank {
+            Some(precision_factor_rank) => precision_factor_rank,
+            None => precision_factor_rank_for_decimals(mint.decimals)?,
+        };
 
-        let reward_per_block = reward_amount
-            .checked_div(
-                end_block
-                .checked_sub(start_block)
-                .ok_or(StakingError::Overflow)?)
-            .ok_or(StakingError::Overflow)?;
+        let reward_per_block = match reward_per_block {
+            Some(reward_per_block) => reward_per_block,
+            None => reward_amount
+                .checked_div(
+                    end_block
+                    .checked_sub(start_block)
+                    .ok_or(StakingError::Overflow)?)
+                .ok_or(StakingError::Overflow)?,
+        };
 
-        let stake_pool = StakePool {
+        let mut stake_pool = StakePool {
             pool_index,
             owner: *owner_account_info.key,
             mint: *mint_info.key,  
@@ -347,26 +889,132 @@ impl Processor {
             last_reward_block: 0,
             start_block,
             end_block,
-            reward_amount: 0, // TODO: delete this
+            reward_amount,
             reward_per_block,
             accrued_token_per_share: 0,
+            reward_decimals: mint.decimals,
+            withdraw_fee_bps: 0,
+            whale_threshold: 0,
+            whale_reward_bps: 0,
+            reward_starts_on_first_stake: reward_starts_on_first_stake as u8,
+            min_claim_amount: 0,
+            max_reward_per_user: 0,
+            total_supply: 0,
+            max_total_supply: 0,
+            rewards_lock_duration: 0,
+            lock_policy: 0,
+            allowlist_mode: 0,
+            referral_fee_bps: 0,
+            staked_vault: *pda_pool_token_account_staked_info.key,
+            reward_is_native: 0,
+            rate_controller: COption::None,
+            effective_total_supply: 0,
+            allow_zero_rate: allow_zero_rate as u8,
+            partial_claim_on_shortfall: 0,
+            reward_model: 0,
+            simple_interest_bps: 0,
+            rewards_duration: 0,
+            reward_rate: 0,
+            period_finish: 0,
+            last_update_time: 0,
+            unstake_cooldown: 0,
+            is_paused: 0,
+            pending_owner: COption::None,
+            min_stake,
+            reward_mint: *reward_mint_info.key,
+            fee_recipient: COption::None,
+            early_withdraw_penalty_bps: 0,
+            staker_count: 0,
+            token_program: *token_program_info.key,
+            pending_unstake_supply: 0,
         };
+        stake_pool.set_fee_recipient(fee_recipient.into())?;
+        stake_pool.set_withdraw_fee_bps(withdraw_fee_bps)?;
+        stake_pool.check_reward_rate()?;
+        stake_pool.check_campaign_window()?;
+
+        stake_pool.validate()?;
 
         StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())
             .expect("Failed to serialize StakePool");
 
-        // debug 
-        let stake_pool_unpacked = StakePool::unpack(&pda_stake_pool_info.data.borrow())
-            .expect("Failed to serialize StakePool");
-        println!("Stake Pool check {:#?}", stake_pool_unpacked);
-        //
+        #[cfg(feature = "debug-logs")]
+        msg!("StakePool after initialize is {:#?}", stake_pool);
 
         master_staking.increase_counter()?;
         master_staking.serialize(&mut &mut pda_master_staking_info.data.borrow_mut()[..])?;
+
+        set_return_data(&pool_index.to_le_bytes());
+
+        StakingEvent::PoolInitialized {
+            pool_index,
+            user: *owner_account_info.key,
+            amount: reward_amount,
+            slot: Clock::get()?.slot,
+        }.emit()?;
         
         Ok(())
     }
 
+    /// Runs Initialize for every group of pool accounts, the counter in master-staking
+    /// is read and increased by each of them
+    fn process_create_pools(
+        accounts: &[AccountInfo],
+        count: u8,
+        reward_amount: u64,
+        start_block: u64,
+        end_block: u64,
+        reward_starts_on_first_stake: bool,
+        allow_zero_rate: bool,
+    ) -> ProgramResult {
+        const SHARED_ACCOUNTS: usize = 9;
+        const POOL_ACCOUNTS: usize = 4;
+
+        if count == 0 ||
+           count > MAX_POOLS_PER_BATCH ||
+           accounts.len() != SHARED_ACCOUNTS + POOL_ACCOUNTS * count as usize {
+            StakingError::InvalidPoolBatch.print::<StakingError>();
+            return Err(StakingError::InvalidPoolBatch.into());
+        }
+
+        let (shared, pools) = accounts.split_at(SHARED_ACCOUNTS);
+
+        for pool in pools.chunks(POOL_ACCOUNTS) {
+            let initialize_accounts = [
+                shared[0].clone(), // owner
+                shared[1].clone(), // master-staking
+                pool[0].clone(),   // StakePool
+                pool[1].clone(),   // wallet
+                shared[2].clone(), // this program
+                shared[3].clone(), // mint
+                shared[4].clone(), // rent
+                shared[5].clone(), // system-program
+                shared[6].clone(), // token-program
+                shared[7].clone(), // token-account with reward
+                shared[8].clone(), // authority
+                pool[2].clone(),   // staked token-account
+                pool[3].clone(),   // reward token-account
+            ];
+
+            Self::process_initialize(
+                &initialize_accounts,
+                reward_amount,
+                None,
+                start_block,
+                end_block,
+                None,
+                0,
+                reward_starts_on_first_stake,
+                allow_zero_rate,
+                false,
+                0,
+                None,
+            )?;
+        }
+
+        Ok(())
+    }
+
     pub fn process_deposit(
         accounts: &[AccountInfo],
         amount: u64,
@@ -378,17 +1026,24 @@ impl Processor {
         let mint_info = next_account_info(account_info_iter)?; // 2
         
         let pda_stake_pool_info = next_account_info(account_info_iter)?; // 3
-        let mut stake_pool = StakePool::unpack(&pda_stake_pool_info.data.borrow_mut())
-            .expect("Failed to deserialie StakePool");
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
         let pool_index = stake_pool.pool_index;
+        stake_pool.check_not_paused()?;
 
         let pda_pool_token_account_authority_info = next_account_info(account_info_iter)?; // 4
         let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 5
         let pda_pool_token_account_reward_info = next_account_info(account_info_iter)?; // 6
         let pda_wallet_for_create_user_info = next_account_info(account_info_iter)?; // 7
         let pda_user_state_info = next_account_info(account_info_iter)?; // 8
-
-        let pda_pool_token_account_staked = TokenAccount::unpack( 
+        if stake_pool.reward_is_native == 0 {
+            validate_reward_vault(
+                &stake_pool,
+                pda_pool_token_account_reward_info,
+            )?;
+        }
+
+        let pda_pool_token_account_staked = unpack_token_account(
             &pda_pool_token_account_staked_info.data.borrow(),
         )?;
  
@@ -404,146 +1059,209 @@ impl Processor {
 
         let system_program_info = next_account_info(account_info_iter)?; // 11
         let token_program_info = next_account_info(account_info_iter)?; // 12
+        validate_token_program(&stake_pool, token_program_info)?;
+        let allowlist_entry_info = next_account_info(account_info_iter).ok(); // 13
+
+        // AllowlistEntry is only passed for pools with an allowlist mode
+        expect_accounts(accounts, 13 + stake_pool.uses_allowlist() as usize)?;
+        validate_staker(
+            &stake_pool,
+            pda_stake_pool_info.key,
+            owner_token_account_info.key,
+            allowlist_entry_info,
+        )?;
         
+        validate_user_state(
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
+        )?;
         if pda_user_state_info.data_is_empty() {
-            msg!("Creating account for UserInfo");
-
-            let (_pda_wallet_pubkey, bump_seed_wallet) = Pubkey::find_program_address(
-                &[&pool_index.to_le_bytes(), ADD_SEED_WALLET_POOL.as_bytes()],
-                &this_program_id(), 
-            );
-    
-            let (_pda_user_state_pubkey, bump_seed_user_state) = Pubkey::find_program_address(
-                &[pda_stake_pool_info.key.as_ref(), token_account_info.key.as_ref()],
-                &this_program_id(),
-            );
-            
-            let signers_seeds_pda_wallet: &[&[_]] = 
-                &[
-                &pool_index.to_le_bytes(),
-                ADD_SEED_WALLET_POOL.as_bytes(),
-                &[bump_seed_wallet],
-                ];
-            
-            let signers_seeds_pda_user_state: &[&[_]] = 
-                &[
-                pda_stake_pool_info.key.as_ref(),
-                token_account_info.key.as_ref(),
-                &[bump_seed_user_state],
-                ]; 
-            
-            let min_balance_user_info = rent.minimum_balance(USER_INFO_LEN);
-
-            invoke_signed( 
-                &system_instruction::create_account(
-                    pda_wallet_for_create_user_info.key, // account "from" for transfer instruction must not carry data
-                    pda_user_state_info.key,
-                    min_balance_user_info,
-                    USER_INFO_LEN as u64,
-                    &this_program_id(),
-                ),
-                &[pda_wallet_for_create_user_info.clone(), pda_user_state_info.clone(), system_program_info.clone()],
-                &[&signers_seeds_pda_wallet, &signers_seeds_pda_user_state],
+            Self::create_user_info(
+                pool_index,
+                pda_stake_pool_info,
+                token_account_info,
+                pda_wallet_for_create_user_info,
+                pda_user_state_info,
+                rent,
+                system_program_info,
             )?;
-    
-            let user_data = UserInfo {
-                token_account_id: *token_account_info.key, 
-                amount: 0,
-                reward_debt: 0,
-            };
-    
-            user_data.serialize(&mut &mut pda_user_state_info.data.borrow_mut()[..])?;
         } 
 
-        invoke(
-            &spl_token::instruction::transfer(
-                &spl_token::id(),
-                token_account_info.key,
-                pda_pool_token_account_staked_info.key,
-                owner_token_account_info.key,
-                &[owner_token_account_info.key],
-                amount,
-            )?, 
-            &[
-            token_account_info.clone(),
-            pda_pool_token_account_staked_info.clone(),
-            owner_token_account_info.clone(),
-            token_program_info.clone()
-            ],
-        )?;
+        stake_pool.start_on_first_stake(clock.slot)?;
+        stake_pool.check_active(clock)?;
 
-        // TODO: make transfer instruction after update_pool
-        // TODO: stakers++
         // TODO: add loop
+        // Accrual must see the staked supply before this deposit, otherwise the reward since
+        // last_reward_block is divided by tokens that earned nothing. Later stakes in the same
+        // slot skip accrual, so every staker of a slot gets reward_debt from the same share
         stake_pool.update_pool(
-            &pda_pool_token_account_staked,
+            pda_pool_token_account_staked_info,
             clock
-        )
-        .expect("Unable to update pool");  
+        )?;
 
-        let mut user_data = UserInfo::from_account_info(&pda_user_state_info)?; 
+        transfer_tokens(
+            token_program_info,
+            token_account_info,
+            mint_info,
+            pda_pool_token_account_staked_info,
+            owner_token_account_info,
+            &[],
+            amount,
+            stake_pool.reward_decimals,
+        )?;
+
+        let mut user_data = UserInfo::from_account_info(pda_user_state_info)?;
+        user_data.check_not_frozen()?;
+        stake_pool.check_min_stake(user_data.amount, amount)?;
         
         let current_amount = user_data.amount;
+        let effective_before = user_data.effective_balance();
+        user_data.accumulate_twab(now_secs(clock)?)?;
 
         user_data.amount = user_data
             .amount
             .checked_add(amount)
             .ok_or(StakingError::Overflow)?;
+        stake_pool.increase_total_supply(amount)?;
+        stake_pool.update_effective_total_supply(effective_before, user_data.effective_balance())?;
+        stake_pool.update_staker_count(current_amount, user_data.amount)?;
  
         if current_amount > 0 {
             let pending = get_pending(
-                current_amount,
+                effective_before,
                 stake_pool.accrued_token_per_share,
                 stake_pool.precision_factor_rank,
                 user_data.reward_debt,
-            )
-            .expect("Unable to get pending value");
+            )?;
+            let pending = stake_pool.apply_whale_curve(pending, current_amount)?;
+            let pending = user_data.cap_claim(pending, stake_pool.max_reward_per_user)?;
 
             // TODO: Check reward_amount > pending
-            if pending > 0 {
+            // Native SOL and a separate reward mint are paid only by GetReward
+            if stake_pool.reward_is_native != 0 || stake_pool.has_separate_reward_mint() {
+                user_data.owe_reward(pending)?;
+            } else if pending > 0 {
+                let (_, bump_seed_token_account_authority) = token_account_authority(&this_program_id());
                 let sign_seeds_pda_pool_token_account_authority: &[&[_]] = 
                     &[
                     ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(),
-                    &[BUMP_SEED_TOKEN_ACCOUNT_AUTHORITY],
+                    &[bump_seed_token_account_authority],
                     ];
 
-                invoke_signed(
-                    &spl_token::instruction::transfer(
-                        &spl_token::id(),
-                        pda_pool_token_account_reward_info.key,
-                        token_account_info.key,
-                        pda_pool_token_account_authority_info.key,
-                        &[pda_pool_token_account_authority_info.key],
-                        pending,
-                    )?, 
-                    &[
-                    pda_pool_token_account_reward_info.clone(),
-                    token_account_info.clone(),
-                    pda_pool_token_account_authority_info.clone(), 
-                    token_program_info.clone(),
-                    ],
-                    &[&sign_seeds_pda_pool_token_account_authority]
+                transfer_tokens(
+                    token_program_info,
+                    pda_pool_token_account_reward_info,
+                    mint_info,
+                    token_account_info,
+                    pda_pool_token_account_authority_info,
+                    &[sign_seeds_pda_pool_token_account_authority],
+                    pending,
+                    stake_pool.reward_decimals,
                 )?;
             }
         }
         user_data.set_reward_debt(
             get_reward_debt(
-                user_data.amount,
+                user_data.effective_balance(),
                 stake_pool.accrued_token_per_share,
-                stake_pool.precision_factor_rank,
             )?
         );
 
-        user_data.serialize(&mut &mut pda_user_state_info.data.borrow_mut()[..])?; 
+        user_data.set_reward_lock_finish(
+            now_secs(clock)?,
+            stake_pool.rewards_lock_duration,
+            LockPolicy::from_u8(stake_pool.lock_policy)?,
+        )?;
+
+        UserInfo::pack(user_data, &mut pda_user_state_info.data.borrow_mut())?; 
 
-        msg!("stake_pool after deposit is {:#?}", stake_pool);
+        #[cfg(feature = "debug-logs")]
+        msg!("StakePool after deposit is {:#?}\nUserInfo is {:#?}", stake_pool, user_data);
         StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
 
-        // debug
-        let unpacked_user_data = UserInfo::try_from_slice(&pda_user_state_info.data.borrow())?; 
-        msg!("unpacked_user_data is {:#?}", unpacked_user_data);
-        // 
+        StakingEvent::Deposited {
+            pool_index,
+            user: *owner_token_account_info.key,
+            amount,
+            slot: clock.slot,
+        }.emit()?;
+        
+        Ok(())
+    }
+
+    /// Create and zero-initialize the UserInfo PDA of `token_account_info`,
+    /// paid from the pool wallet PDA
+    fn create_user_info<'a>(
+        pool_index: u64,
+        pda_stake_pool_info: &AccountInfo<'a>,
+        token_account_info: &AccountInfo<'a>,
+        pda_wallet_for_create_user_info: &AccountInfo<'a>,
+        pda_user_state_info: &AccountInfo<'a>,
+        rent: &Rent,
+        system_program_info: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        msg!("Creating account for UserInfo");
+        // Withdraw reads the token-account of the position, one with extensions would trap the stake
+        unpack_token_account(&token_account_info.data.borrow())?;
+
+        let (_pda_wallet_pubkey, bump_seed_wallet) = wallet_pool_address(
+            &this_program_id(),
+            pool_index,
+        );
+
+        let (_pda_user_state_pubkey, bump_seed_user_state) = Pubkey::find_program_address(
+            &[pda_stake_pool_info.key.as_ref(), token_account_info.key.as_ref()],
+            &this_program_id(),
+        );
+        
+        let signers_seeds_pda_wallet: &[&[_]] = 
+            &[
+            &pool_index.to_le_bytes(),
+            ADD_SEED_WALLET_POOL.as_bytes(),
+            &[bump_seed_wallet],
+            ];
+        
+        let signers_seeds_pda_user_state: &[&[_]] = 
+            &[
+            pda_stake_pool_info.key.as_ref(),
+            token_account_info.key.as_ref(),
+            &[bump_seed_user_state],
+            ]; 
         
+        let min_balance_user_info = rent.minimum_balance(USER_INFO_LEN);
+
+        invoke_signed( 
+            &system_instruction::create_account(
+                pda_wallet_for_create_user_info.key, // account "from" for transfer instruction must not carry data
+                pda_user_state_info.key,
+                min_balance_user_info,
+                USER_INFO_LEN as u64,
+                &this_program_id(),
+            ),
+            &[pda_wallet_for_create_user_info.clone(), pda_user_state_info.clone(), system_program_info.clone()],
+            &[signers_seeds_pda_wallet, signers_seeds_pda_user_state],
+        )?;
+
+        let user_data = UserInfo {
+            token_account_id: *token_account_info.key, 
+            amount: 0,
+            reward_debt: 0,
+            total_claimed: 0,
+            reward_lock_finish: 0,
+            referrer: None,
+            reward_owed: 0,
+            boost_bps: 0,
+            frozen: false,
+            time_weighted_balance: 0,
+            twab_updated_at: 0,
+            pending_unstake: 0,
+            unstake_ready_at: 0,
+            reward_debt_corrected_at: 0,
+        };
+
+        UserInfo::pack(user_data, &mut pda_user_state_info.data.borrow_mut())?;
+
         Ok(())
     }
 
@@ -556,7 +1274,7 @@ impl Processor {
         let owner_info = next_account_info(account_info_iter)?; // 0
         let token_account_info = next_account_info(account_info_iter)?; // 1
 
-        let token_account = TokenAccount::unpack(
+        let token_account = unpack_token_account(
             &token_account_info.data.borrow(),
         )?;
 
@@ -577,118 +1295,177 @@ impl Processor {
         let clock = &Clock::from_account_info(clock_program_info)?;
 
         let token_program_info = next_account_info(account_info_iter)?; // 8
+        let mint_info = next_account_info(account_info_iter)?; // 9
 
-        let pda_pool_token_account_staked = TokenAccount::unpack( 
-            &pda_pool_token_account_staked_info.data.borrow(),
-        )?;
-
+        let (_, bump_seed_token_account_authority) = token_account_authority(&this_program_id());
         let sign_seeds_pda_pool_token_account_authority: &[&[_]] = 
             &[
             ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(),
-            &[BUMP_SEED_TOKEN_ACCOUNT_AUTHORITY],
+            &[bump_seed_token_account_authority],
             ];
 
-        let mut stake_pool = StakePool::unpack(&pda_stake_pool_info.data.borrow_mut())
-            .expect("Failed to deserialie StakePool");
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        validate_token_program(&stake_pool, token_program_info)?;
+        // Withdraw fee goes to the reward token-account
+        validate_reward_vault(
+            &stake_pool,
+            pda_pool_token_account_reward_info,
+        )?;
+        // Only tokens of the staked mint move here, a separate reward mint is paid by GetReward
+        if *mint_info.key != stake_pool.mint {
+            return Err(TokenError::MintMismatch.into());
+        }
+        expect_accounts(accounts, 10 + stake_pool.fee_recipient.is_some() as usize)?;
+        // Fee recipient token-account follows the other accounts only when the pool has one
+        let fee_destination_info = match stake_pool.fee_recipient {
+            COption::Some(fee_recipient) => {
+                let fee_recipient_info = next_account_info(account_info_iter)?; // 10
+                if *fee_recipient_info.key != fee_recipient {
+                    StakingError::FeeRecipientMismatch.print::<StakingError>();
+                    return Err(StakingError::FeeRecipientMismatch.into());
+                }
+                fee_recipient_info
+            },
+            COption::None => pda_pool_token_account_reward_info,
+        };
 
-        let mut user_data = UserInfo::from_account_info(&pda_user_state_info)?;
+        validate_user_state(
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
+        )?;
+        let mut user_data = UserInfo::from_account_info(pda_user_state_info)?;
+        user_data.check_not_frozen()?;
+        if amount > 0 {
+            stake_pool.check_instant_unstake_allowed()?;
+        }
         
-        assert!(user_data.amount >= amount, "Amount to withdraw too high");
+        if amount > user_data.amount {
+            StakingError::WithdrawAmountTooHigh.print::<StakingError>();
+            return Err(StakingError::WithdrawAmountTooHigh.into());
+        }
 
         stake_pool.update_pool(
-            &pda_pool_token_account_staked,
+            pda_pool_token_account_staked_info,
             &clock,
         )?;
 
         let current_amount = user_data.amount;
+        let effective_before = user_data.effective_balance();
+        let now = now_secs(clock)?;
+        let withdraw_fee = stake_pool.get_exit_fee(amount, user_data.reward_lock_finish, now)?;
+        user_data.accumulate_twab(now)?;
 
         if amount > 0 {
             user_data.amount = user_data
                 .amount
                 .checked_sub(amount)
                 .ok_or(StakingError::Overflow)?;
+            stake_pool.decrease_total_supply(amount)?;
+            stake_pool.update_effective_total_supply(effective_before, user_data.effective_balance())?;
+            stake_pool.update_staker_count(current_amount, user_data.amount)?;
 
-            invoke_signed(
-                &spl_token::instruction::transfer(
-                    &spl_token::id(),
-                    pda_pool_token_account_staked_info.key,
-                    token_account_info.key,
-                    pda_pool_token_account_authority_info.key,
-                    &[pda_pool_token_account_authority_info.key],
-                    amount,
-                )?,
-                &[
-                pda_pool_token_account_staked_info.clone(),
-                token_account_info.clone(),
-                pda_pool_token_account_authority_info.clone(),
-                token_program_info.clone(),
-                ],
-                &[&sign_seeds_pda_pool_token_account_authority]
+            let amount_to_transfer = amount
+                .checked_sub(withdraw_fee)
+                .ok_or(StakingError::Overflow)?;
+
+            transfer_tokens(
+                token_program_info,
+                pda_pool_token_account_staked_info,
+                mint_info,
+                token_account_info,
+                pda_pool_token_account_authority_info,
+                &[sign_seeds_pda_pool_token_account_authority],
+                amount_to_transfer,
+                stake_pool.reward_decimals,
+            )?;
+        }
+
+        // Without a fee recipient the withdraw fee is shared by the remaining stakers
+        if withdraw_fee > 0 {
+            transfer_tokens(
+                token_program_info,
+                pda_pool_token_account_staked_info,
+                mint_info,
+                fee_destination_info,
+                pda_pool_token_account_authority_info,
+                &[sign_seeds_pda_pool_token_account_authority],
+                withdraw_fee,
+                stake_pool.reward_decimals,
             )?;
         }
 
         let pending = get_pending(
-            current_amount,
+            effective_before,
             stake_pool.accrued_token_per_share,
             stake_pool.precision_factor_rank,
             user_data.reward_debt,
-        )
-        .expect("Unable to get pending value");
+        )?;
+        let pending = stake_pool.apply_whale_curve(pending, current_amount)?;
+        let pending = user_data.cap_claim(pending, stake_pool.max_reward_per_user)?;
         
         // TODO: add loop for reward tokens
-            if pending > 0 {
-                invoke_signed(
-                    &spl_token::instruction::transfer(
-                        &spl_token::id(),
-                        pda_pool_token_account_reward_info.key,
-                        token_account_info.key,
-                        pda_pool_token_account_authority_info.key,
-                        &[pda_pool_token_account_authority_info.key],
-                        pending,
-                    )?, 
-                    &[
-                    pda_pool_token_account_reward_info.clone(),
-                    token_account_info.clone(),
-                    pda_pool_token_account_authority_info.clone(), 
-                    token_program_info.clone(),
-                    ],
-                    &[&sign_seeds_pda_pool_token_account_authority]
-                )?;
-            }
+        // Native SOL and a separate reward mint are paid only by GetReward. A reward still
+        // under `reward_lock_finish` stays with the position until the lock ends
+        if stake_pool.reward_is_native != 0 || stake_pool.has_separate_reward_mint() || !user_data.can_claim(now) {
+            user_data.owe_reward(pending)?;
+        } else if pending > 0 {
+            transfer_tokens(
+                token_program_info,
+                pda_pool_token_account_reward_info,
+                mint_info,
+                token_account_info,
+                pda_pool_token_account_authority_info,
+                &[sign_seeds_pda_pool_token_account_authority],
+                pending,
+                stake_pool.reward_decimals,
+            )?;
+        }
 
-            user_data.set_reward_debt(
-                get_reward_debt(
-                    user_data.amount,
-                    stake_pool.accrued_token_per_share,
-                    stake_pool.precision_factor_rank,
-                )?
-            );
+        user_data.set_reward_debt(
+            get_reward_debt(
+                user_data.effective_balance(),
+                stake_pool.accrued_token_per_share,
+            )?
+        );
 
-        user_data.serialize(&mut &mut pda_user_state_info.data.borrow_mut()[..])?;
+        // The user's remaining stake is part of the remaining supply, so the debt above
+        // is taken before the fee is distributed
+        let remaining_staked_supply = stake_pool.effective_total_supply;
+        stake_pool.distribute_withdraw_fee(
+            withdraw_fee,
+            remaining_staked_supply,
+        )?;
+
+        UserInfo::pack(user_data, &mut pda_user_state_info.data.borrow_mut())?;
 
-        msg!("stake_pool after deposit is {:#?}", stake_pool);
+        #[cfg(feature = "debug-logs")]
+        msg!("StakePool after withdraw is {:#?}\nUserInfo is {:#?}", stake_pool, user_data);
         StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
 
-        // debug
-        let unpacked_user_data = UserInfo::try_from_slice(&pda_user_state_info.data.borrow())?; 
-        msg!("unpacked_user_data is {:#?}", unpacked_user_data);
-        // 
+        StakingEvent::Withdrawn {
+            pool_index: stake_pool.pool_index,
+            user: *owner_info.key,
+            amount,
+            slot: clock.slot,
+        }.emit()?;
 
         // TODO: Need to delete UserInfo, but it can't possible.
-        // TODO: stakers--; 
         
         Ok(())
     }
 
-    pub fn process_get_reward(
+    pub fn process_exit_pool(
         accounts: &[AccountInfo],
+        forfeit_rewards: bool,
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
 
         let owner_info = next_account_info(account_info_iter)?; // 0
         let token_account_info = next_account_info(account_info_iter)?; // 1
 
-        let token_account = TokenAccount::unpack(
+        let token_account = unpack_token_account(
             &token_account_info.data.borrow(),
         )?;
 
@@ -704,228 +1481,3010 @@ impl Processor {
         let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 4
         let pda_pool_token_account_reward_info = next_account_info(account_info_iter)?; // 5
         let pda_user_state_info = next_account_info(account_info_iter)?; // 6
+        let pda_wallet_for_create_user_info = next_account_info(account_info_iter)?; // 7
 
-        let clock_program_info = next_account_info(account_info_iter)?; // 7
+        let clock_program_info = next_account_info(account_info_iter)?; // 8
         let clock = &Clock::from_account_info(clock_program_info)?;
 
-        let token_program_info = next_account_info(account_info_iter)?; // 8
-
-        let pda_pool_token_account_staked = TokenAccount::unpack( 
-            &pda_pool_token_account_staked_info.data.borrow(),
-        )?;
+        let token_program_info = next_account_info(account_info_iter)?; // 9
+        let mint_info = next_account_info(account_info_iter)?; // 10
 
+        let (_, bump_seed_token_account_authority) = token_account_authority(&this_program_id());
         let sign_seeds_pda_pool_token_account_authority: &[&[_]] = 
             &[
             ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(),
-            &[BUMP_SEED_TOKEN_ACCOUNT_AUTHORITY],
+            &[bump_seed_token_account_authority],
             ];
 
-        let mut stake_pool = StakePool::unpack(&pda_stake_pool_info.data.borrow_mut())
-            .expect("Failed to deserialie StakePool");
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        validate_token_program(&stake_pool, token_program_info)?;
 
-        let mut user_data = UserInfo::from_account_info(&pda_user_state_info)?;
-        
-        // assert!(user_data.amount >= amount, "Amount to withdraw too high");
+        validate_reward_vault(
+            &stake_pool,
+            pda_pool_token_account_reward_info,
+        )?;
+        validate_user_state(
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
+        )?;
+
+        let (pda_wallet_pubkey, _bump_seed_wallet) = wallet_pool_address(
+            &this_program_id(),
+            stake_pool.pool_index,
+        );
+        if pda_wallet_pubkey != *pda_wallet_for_create_user_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        let mut user_data = UserInfo::from_account_info(pda_user_state_info)?;
+        user_data.check_not_frozen()?;
+        user_data.check_no_pending_unstake()?;
+        if user_data.amount > 0 {
+            stake_pool.check_instant_unstake_allowed()?;
+        }
 
         stake_pool.update_pool(
-            &pda_pool_token_account_staked,
-            &clock,
+            pda_pool_token_account_staked_info,
+            clock,
         )?;
 
         let pending = get_pending(
-            user_data.amount,
+            user_data.effective_balance(),
             stake_pool.accrued_token_per_share,
             stake_pool.precision_factor_rank,
             user_data.reward_debt,
-        )
-        .expect("Unable to get pending value");
-        
-        // TODO: add loop for reward tokens
-            if pending > 0 {
-                invoke_signed(
-                    &spl_token::instruction::transfer(
-                        &spl_token::id(),
-                        pda_pool_token_account_reward_info.key,
-                        token_account_info.key,
-                        pda_pool_token_account_authority_info.key,
-                        &[pda_pool_token_account_authority_info.key],
-                        pending,
-                    )?, 
-                    &[
-                    pda_pool_token_account_reward_info.clone(),
-                    token_account_info.clone(),
-                    pda_pool_token_account_authority_info.clone(), 
-                    token_program_info.clone(),
-                    ],
-                    &[&sign_seeds_pda_pool_token_account_authority]
+        )?;
+        let pending = stake_pool.apply_whale_curve(pending, user_data.amount)?;
+        let pending = user_data.cap_claim(pending, stake_pool.max_reward_per_user)?
+            .checked_add(user_data.take_reward_owed())
+            .ok_or(StakingError::Overflow)?;
+
+        // UserInfo is closed below, so a reward that can't be paid now is given up or the exit fails
+        let reward_payable = user_data.can_claim(now_secs(clock)?) &&
+            stake_pool.reward_is_native == 0 &&
+            !stake_pool.has_separate_reward_mint();
+        let pending = match (reward_payable, forfeit_rewards) {
+            (true, _) => pending,
+            (false, _) if pending == 0 => 0,
+            (false, true) => {
+                msg!("Forfeited reward: {}", pending);
+                0
+            },
+            (false, false) if stake_pool.reward_is_native != 0 => {
+                return Err(StakingError::NativeRewardUnsupported.into());
+            },
+            (false, false) if stake_pool.has_separate_reward_mint() => {
+                StakingError::RewardMintUnsupported.print::<StakingError>();
+                return Err(StakingError::RewardMintUnsupported.into());
+            },
+            (false, false) => {
+                StakingError::RewardLocked.print::<StakingError>();
+                return Err(StakingError::RewardLocked.into());
+            },
+        };
+
+        // Referrer token-account is only passed when a reward is paid. All the
+        // optional accounts are checked before the first transfer
+        let pays_referrer = user_data.referrer.is_some() && pending > 0;
+        expect_accounts(
+            accounts,
+            11 + stake_pool.fee_recipient.is_some() as usize + pays_referrer as usize,
+        )?;
+
+        let amount = user_data.amount;
+        let withdraw_fee = stake_pool.get_exit_fee(
+            amount,
+            user_data.reward_lock_finish,
+            now_secs(clock)?,
+        )?;
+        let amount_to_transfer = amount
+            .checked_sub(withdraw_fee)
+            .ok_or(StakingError::Overflow)?;
+
+        stake_pool.decrease_total_supply(amount)?;
+        stake_pool.update_effective_total_supply(user_data.effective_balance(), 0)?;
+        stake_pool.update_staker_count(amount, 0)?;
+
+        transfer_tokens(
+            token_program_info,
+            pda_pool_token_account_staked_info,
+            mint_info,
+            token_account_info,
+            pda_pool_token_account_authority_info,
+            &[sign_seeds_pda_pool_token_account_authority],
+            amount_to_transfer,
+            stake_pool.reward_decimals,
+        )?;
+
+        // Fee recipient token-account follows the other accounts only when the pool has one
+        let fee_destination_info = match stake_pool.fee_recipient {
+            COption::Some(fee_recipient) => {
+                let fee_recipient_info = next_account_info(account_info_iter)?; // 11
+                if *fee_recipient_info.key != fee_recipient {
+                    StakingError::FeeRecipientMismatch.print::<StakingError>();
+                    return Err(StakingError::FeeRecipientMismatch.into());
+                }
+                fee_recipient_info
+            },
+            COption::None => pda_pool_token_account_reward_info,
+        };
+
+        // Without a fee recipient the withdraw fee is shared by the remaining stakers
+        transfer_tokens(
+            token_program_info,
+            pda_pool_token_account_staked_info,
+            mint_info,
+            fee_destination_info,
+            pda_pool_token_account_authority_info,
+            &[sign_seeds_pda_pool_token_account_authority],
+            withdraw_fee,
+            stake_pool.reward_decimals,
+        )?;
+        let remaining_staked_supply = stake_pool.effective_total_supply;
+        stake_pool.distribute_withdraw_fee(
+            withdraw_fee,
+            remaining_staked_supply,
+        )?;
+
+        let user_reward = match user_data.referrer {
+            Some(referrer) if pending > 0 => {
+                let referrer_token_account_info = next_account_info(account_info_iter)?; // 11 or 12
+                if *referrer_token_account_info.key != referrer {
+                    return Err(StakingError::ReferrerMismatch.into());
+                }
+
+                let (user_reward, referrer_reward) = stake_pool.split_referral(pending)?;
+                transfer_tokens(
+                    token_program_info,
+                    pda_pool_token_account_reward_info,
+                    mint_info,
+                    referrer_token_account_info,
+                    pda_pool_token_account_authority_info,
+                    &[sign_seeds_pda_pool_token_account_authority],
+                    referrer_reward,
+                    stake_pool.reward_decimals,
                 )?;
-            }
 
-            user_data.set_reward_debt(
-                get_reward_debt(
-                    user_data.amount,
-                    stake_pool.accrued_token_per_share,
-                    stake_pool.precision_factor_rank,
-                )?
-            );
+                user_reward
+            },
+            _ => pending,
+        };
 
-        user_data.serialize(&mut &mut pda_user_state_info.data.borrow_mut()[..])?;
+        transfer_tokens(
+            token_program_info,
+            pda_pool_token_account_reward_info,
+            mint_info,
+            token_account_info,
+            pda_pool_token_account_authority_info,
+            &[sign_seeds_pda_pool_token_account_authority],
+            user_reward,
+            stake_pool.reward_decimals,
+        )?;
+
+        // Close UserInfo, rent goes back to the pool wallet
+        let user_state_lamports = pda_user_state_info.lamports();
+        **pda_wallet_for_create_user_info.lamports.borrow_mut() = pda_wallet_for_create_user_info
+            .lamports()
+            .checked_add(user_state_lamports)
+            .ok_or(StakingError::Overflow)?;
+        **pda_user_state_info.lamports.borrow_mut() = 0;
+        pda_user_state_info.data.borrow_mut().fill(0);
 
-        msg!("stake_pool after deposit is {:#?}", stake_pool);
         StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
 
-        // debug
-        let unpacked_user_data = UserInfo::try_from_slice(&pda_user_state_info.data.borrow())?; 
-        msg!("unpacked_user_data is {:#?}", unpacked_user_data);
-        // 
+        StakingEvent::Withdrawn {
+            pool_index: stake_pool.pool_index,
+            user: *owner_info.key,
+            amount,
+            slot: clock.slot,
+        }.emit()?;
+        StakingEvent::Harvested {
+            pool_index: stake_pool.pool_index,
+            user: *owner_info.key,
+            amount: pending,
+            slot: clock.slot,
+        }.emit()?;
 
-        // TODO: Need to delete UserInfo, but it can't possible.
-        // TODO: stakers--; 
-        
         Ok(())
     }
 
-    pub fn process_emergency_withdraw(
-        accounts: &[AccountInfo]
+    pub fn process_get_reward(
+        accounts: &[AccountInfo],
     ) -> ProgramResult {
+        let receipt = Self::get_reward(accounts)?;
+        set_return_data(&receipt.try_to_vec()?);
+
+        Ok(())
+    }
+
+    /// GetReward without the return data, HarvestMany sums the paid amounts of its pools
+    fn get_reward(
+        accounts: &[AccountInfo],
+    ) -> Result<ClaimReceipt, ProgramError> {
         let account_info_iter = &mut accounts.iter();
 
         let owner_info = next_account_info(account_info_iter)?; // 0
-        if !owner_info.is_signer {
-            return Err(ProgramError::MissingRequiredSignature);
-        }
-
         let token_account_info = next_account_info(account_info_iter)?; // 1
-        let token_account = TokenAccount::unpack(
+
+        let token_account = unpack_token_account(
             &token_account_info.data.borrow(),
         )?;
+
         if token_account.owner != *owner_info.key {
             return Err(TokenError::OwnerMismatch.into());
         }
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
 
-        let pda_pool_token_account_authority_info = next_account_info(account_info_iter)?; // 2
-        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 3
-        let pda_user_state_info = next_account_info(account_info_iter)?; // 4
-        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 5
-        let token_program_info = next_account_info(account_info_iter)?; // 6
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        let pda_pool_token_account_authority_info = next_account_info(account_info_iter)?; // 3
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 4
+        let pda_pool_token_account_reward_info = next_account_info(account_info_iter)?; // 5
+        let pda_user_state_info = next_account_info(account_info_iter)?; // 6
+
+        let clock_program_info = next_account_info(account_info_iter)?; // 7
+        let clock = &Clock::from_account_info(clock_program_info)?;
+
+        let token_program_info = next_account_info(account_info_iter)?; // 8
+        let mint_info = next_account_info(account_info_iter)?; // 9
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        validate_token_program(&stake_pool, token_program_info)?;
+        if stake_pool.reward_is_native == 0 {
+            validate_reward_vault(
+                &stake_pool,
+                pda_pool_token_account_reward_info,
+            )?;
+            if *mint_info.key != stake_pool.reward_mint {
+                return Err(TokenError::MintMismatch.into());
+            }
+        }
 
-        validate_pool_token_account(
-            &pda_pool_token_account_staked_info,
-        )?;
         validate_user_state(
-            &pda_user_state_info,
-            &pda_stake_pool_info,
-            &token_account_info,
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
         )?;
+        let mut user_data = UserInfo::from_account_info(pda_user_state_info)?;
+        user_data.check_not_frozen()?;
+
+        // Native reward PDA, or the reward token-account of a separate reward mint followed by
+        // the referrer token-account
+        let pays_referrer = stake_pool.reward_is_native == 0 && user_data.referrer.is_some();
+        expect_accounts(
+            accounts,
+            10 + (stake_pool.reward_is_native != 0) as usize
+               + stake_pool.has_separate_reward_mint() as usize
+               + pays_referrer as usize,
+        )?;
+        
+        // assert!(user_data.amount >= amount, "Amount to withdraw too high");
 
-        let mut user_data = UserInfo::from_account_info(&pda_user_state_info)?;
+        stake_pool.update_pool(
+            pda_pool_token_account_staked_info,
+            &clock,
+        )?;
 
-        let amount_to_transfer = user_data.amount;
+        let pending = Self::claimable_reward(&stake_pool, &mut user_data, clock)?;
+        let mut paid_amount = pending;
+        
+        // TODO: add loop for reward tokens
+            // Native SOL reward goes to the owner wallet, without referral split
+            if stake_pool.reward_is_native != 0 {
+                let pda_native_reward_info = next_account_info(account_info_iter)?; // 10
+                if *pda_native_reward_info.key != native_reward_address(&this_program_id(), stake_pool.pool_index).0 {
+                    return Err(ProgramError::InvalidSeeds);
+                }
+
+                transfer_native_reward(
+                    pda_native_reward_info,
+                    owner_info,
+                    pending,
+                )?;
+            } else {
+                // The staked token-account can't take another mint
+                let reward_token_account_info = if stake_pool.has_separate_reward_mint() {
+                    let reward_token_account_info = next_account_info(account_info_iter)?; // 10
+                    validate_reward_token_account(
+                        &stake_pool,
+                        reward_token_account_info,
+                        owner_info.key,
+                    )?;
+                    reward_token_account_info
+                } else {
+                    token_account_info
+                };
+                // Referrer token-account follows the other accounts only when UserInfo has one
+                let referrer_token_account_info = user_data.referrer
+                    .map(|_| next_account_info(account_info_iter)) // 10 or 11
+                    .transpose()?;
+
+                paid_amount = Self::pay_token_reward(
+                    &stake_pool,
+                    &mut user_data,
+                    pending,
+                    token_program_info,
+                    pda_pool_token_account_reward_info,
+                    mint_info,
+                    reward_token_account_info,
+                    pda_pool_token_account_authority_info,
+                    referrer_token_account_info,
+                )?;
+            }
 
-        // TODO: Stakers--;
-        if amount_to_transfer > 0 {
-            user_data.amount = user_data
-                .amount
-                .checked_sub(amount_to_transfer)
-                .ok_or(StakingError::Overflow)?;
+            user_data.set_reward_debt(
+                get_reward_debt_after_claim(
+                    user_data.effective_balance(),
+                    stake_pool.accrued_token_per_share,
+                    stake_pool.precision_factor_rank,
+                    user_data.reward_debt,
+                )?
+            );
 
-            let sign_seeds_pda_pool_token_account_authority: &[&[_]] = 
-                &[
-                ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(),
-                &[BUMP_SEED_TOKEN_ACCOUNT_AUTHORITY],
-                ];
+        UserInfo::pack(user_data, &mut pda_user_state_info.data.borrow_mut())?;
 
-            invoke_signed(
-                &spl_token::instruction::transfer(
-                    &spl_token::id(),
-                    pda_pool_token_account_staked_info.key,
-                    token_account_info.key,
-                    pda_pool_token_account_authority_info.key,
-                    &[pda_pool_token_account_authority_info.key],
-                    amount_to_transfer,
-                )?, 
-                &[
-                pda_pool_token_account_staked_info.clone(),
-                token_account_info.clone(),
-                pda_pool_token_account_authority_info.clone(), 
-                token_program_info.clone(),
-                ],
-                &[&sign_seeds_pda_pool_token_account_authority]
+        let receipt = ClaimReceipt {
+            paid_amount,
+            remaining_pending: user_data.reward_owed,
+            next_unlock: user_data.reward_lock_finish,
+        };
+
+        #[cfg(feature = "debug-logs")]
+        msg!("StakePool after get-reward is {:#?}\nUserInfo is {:#?}", stake_pool, user_data);
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        StakingEvent::Harvested {
+            pool_index: stake_pool.pool_index,
+            user: *owner_info.key,
+            amount: paid_amount,
+            slot: clock.slot,
+        }.emit()?;
+
+        // TODO: Need to delete UserInfo, but it can't possible.
+        // TODO: stakers--; 
+        
+        Ok(receipt)
+    }
+
+    /// Pending reward of the user after the whale curve and the per-user cap, plus the reward
+    /// owed by earlier partial payouts. Fails below the pool's minimum claim and while locked
+    fn claimable_reward(
+        stake_pool: &StakePool,
+        user_data: &mut UserInfo,
+        clock: &Clock,
+    ) -> Result<u64, ProgramError> {
+        let pending = get_pending(
+            user_data.effective_balance(),
+            stake_pool.accrued_token_per_share,
+            stake_pool.precision_factor_rank,
+            user_data.reward_debt,
+        )?;
+        let pending = stake_pool.apply_whale_curve(pending, user_data.amount)?;
+        let pending = user_data.cap_claim(pending, stake_pool.max_reward_per_user)?;
+        let pending = pending
+            .checked_add(user_data.take_reward_owed())
+            .ok_or(StakingError::Overflow)?;
+        stake_pool.check_claim_amount(pending)?;
+        if pending > 0 && !user_data.can_claim(now_secs(clock)?) {
+            StakingError::RewardLocked.print::<StakingError>();
+            return Err(StakingError::RewardLocked.into());
+        }
+
+        Ok(pending)
+    }
+
+    /// Pays `pending` out of the reward vault, the referrer share to `referrer_token_account_info`.
+    /// What the vault can't cover is owed to the user. Returns the amount paid
+    #[allow(clippy::too_many_arguments)]
+    fn pay_token_reward<'a>(
+        stake_pool: &StakePool,
+        user_data: &mut UserInfo,
+        pending: u64,
+        token_program_info: &AccountInfo<'a>,
+        pda_pool_token_account_reward_info: &AccountInfo<'a>,
+        mint_info: &AccountInfo<'a>,
+        reward_token_account_info: &AccountInfo<'a>,
+        pda_pool_token_account_authority_info: &AccountInfo<'a>,
+        referrer_token_account_info: Option<&AccountInfo<'a>>,
+    ) -> Result<u64, ProgramError> {
+        let (_, bump_seed_token_account_authority) = token_account_authority(&this_program_id());
+        let sign_seeds_pda_pool_token_account_authority: &[&[_]] = 
+            &[
+            ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(),
+            &[bump_seed_token_account_authority],
+            ];
+
+        let available = unpack_token_account(
+            &pda_pool_token_account_reward_info.data.borrow(),
+        )?.amount;
+        let (pending, shortfall) = stake_pool.cover_shortfall(pending, available)?;
+        user_data.owe_reward(shortfall)?;
+
+        let user_reward = match (user_data.referrer, referrer_token_account_info) {
+            (Some(referrer), Some(referrer_token_account_info)) => {
+                if *referrer_token_account_info.key != referrer {
+                    return Err(StakingError::ReferrerMismatch.into());
+                }
+
+                let (user_reward, referrer_reward) = stake_pool.split_referral(pending)?;
+                transfer_tokens(
+                    token_program_info,
+                    pda_pool_token_account_reward_info,
+                    mint_info,
+                    referrer_token_account_info,
+                    pda_pool_token_account_authority_info,
+                    &[sign_seeds_pda_pool_token_account_authority],
+                    referrer_reward,
+                    stake_pool.reward_decimals,
+                )?;
+
+                user_reward
+            },
+            (Some(_), None) => return Err(ProgramError::NotEnoughAccountKeys),
+            (None, _) => pending,
+        };
+
+        transfer_tokens(
+            token_program_info,
+            pda_pool_token_account_reward_info,
+            mint_info,
+            reward_token_account_info,
+            pda_pool_token_account_authority_info,
+            &[sign_seeds_pda_pool_token_account_authority],
+            user_reward,
+            stake_pool.reward_decimals,
+        )?;
+
+        Ok(pending)
+    }
+
+    /// Runs GetReward for every group of pool accounts of the same owner.
+    /// Sets the reward paid by all of them as u64 LE return data
+    fn process_harvest_many(
+        accounts: &[AccountInfo],
+        pool_count: u8,
+    ) -> ProgramResult {
+        const SHARED_ACCOUNTS: usize = 4;
+        const POOL_ACCOUNTS: usize = 8;
+
+        if pool_count == 0 || pool_count > MAX_HARVESTS_PER_BATCH {
+            StakingError::InvalidPoolBatch.print::<StakingError>();
+            return Err(StakingError::InvalidPoolBatch.into());
+        }
+        if accounts.len() != SHARED_ACCOUNTS + POOL_ACCOUNTS * pool_count as usize {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        let (shared, pools) = accounts.split_at(SHARED_ACCOUNTS);
+
+        let mut paid_amount: u64 = 0;
+        for pool in pools.chunks(POOL_ACCOUNTS) {
+            let mut get_reward_accounts = vec![
+                shared[0].clone(), // owner
+                pool[0].clone(),   // token-account for staked tokens
+                pool[1].clone(),   // StakePool
+                shared[1].clone(), // authority
+                pool[2].clone(),   // staked token-account
+                pool[3].clone(),   // reward token-account
+                pool[4].clone(),   // UserInfo
+                shared[2].clone(), // clock
+                shared[3].clone(), // token-program
+                pool[5].clone(),   // reward mint
+            ];
+            // Pools paying in the staked mint send the reward to the token-account for staked tokens
+            if pool[6].key != pool[0].key {
+                get_reward_accounts.push(pool[6].clone());
+            }
+            // The referrer slot is a placeholder for positions without a referrer
+            if UserInfo::from_account_info(&pool[4])?.referrer.is_some() {
+                get_reward_accounts.push(pool[7].clone());
+            }
+
+            let receipt = Self::get_reward(&get_reward_accounts)?;
+            paid_amount = paid_amount
+                .checked_add(receipt.paid_amount)
+                .ok_or(StakingError::Overflow)?;
+        }
+
+        msg!("Harvested: {}", paid_amount);
+        set_return_data(&paid_amount.to_le_bytes());
+
+        Ok(())
+    }
+
+    pub fn process_harvest_for(
+        accounts: &[AccountInfo],
+        user: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let token_account_info = next_account_info(account_info_iter)?; // 0
+        let token_account = unpack_token_account(
+            &token_account_info.data.borrow(),
+        )?;
+        if token_account.owner != user {
+            return Err(TokenError::OwnerMismatch.into());
+        }
+
+        let reward_token_account_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        let pda_pool_token_account_authority_info = next_account_info(account_info_iter)?; // 3
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 4
+        let pda_pool_token_account_reward_info = next_account_info(account_info_iter)?; // 5
+        let pda_user_state_info = next_account_info(account_info_iter)?; // 6
+
+        let clock_program_info = next_account_info(account_info_iter)?; // 7
+        let clock = &Clock::from_account_info(clock_program_info)?;
+
+        let token_program_info = next_account_info(account_info_iter)?; // 8
+        let mint_info = next_account_info(account_info_iter)?; // 9
+
+        validate_pool_token_account(
+            pda_pool_token_account_staked_info,
+        )?;
+        validate_pool_token_account(
+            pda_pool_token_account_reward_info,
+        )?;
+        validate_user_state(
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
+        )?;
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        validate_token_program(&stake_pool, token_program_info)?;
+        if stake_pool.reward_is_native != 0 {
+            return Err(StakingError::NativeRewardUnsupported.into());
+        }
+        validate_reward_vault(
+            &stake_pool,
+            pda_pool_token_account_reward_info,
+        )?;
+        if *mint_info.key != stake_pool.reward_mint {
+            return Err(TokenError::MintMismatch.into());
+        }
+        // Nobody signs for the user, so rewards may only go to the user's own associated token-account
+        let reward_destination = associated_token_address(
+            &user,
+            mint_info.key,
+            &stake_pool.token_program,
+        );
+        if *reward_token_account_info.key != reward_destination {
+            return Err(StakingError::InvalidRewardDestination.into());
+        }
+
+        let mut user_data = UserInfo::from_account_info(pda_user_state_info)?;
+        user_data.check_not_frozen()?;
+        expect_accounts(accounts, 10 + user_data.referrer.is_some() as usize)?;
+
+        stake_pool.update_pool(
+            pda_pool_token_account_staked_info,
+            clock,
+        )?;
+
+        let pending = Self::claimable_reward(&stake_pool, &mut user_data, clock)?;
+        // Referrer token-account follows the other accounts only when UserInfo has one
+        let referrer_token_account_info = user_data.referrer
+            .map(|_| next_account_info(account_info_iter)) // 10
+            .transpose()?;
+        let paid_amount = Self::pay_token_reward(
+            &stake_pool,
+            &mut user_data,
+            pending,
+            token_program_info,
+            pda_pool_token_account_reward_info,
+            mint_info,
+            reward_token_account_info,
+            pda_pool_token_account_authority_info,
+            referrer_token_account_info,
+        )?;
+
+        user_data.set_reward_debt(
+            get_reward_debt_after_claim(
+                user_data.effective_balance(),
+                stake_pool.accrued_token_per_share,
+                stake_pool.precision_factor_rank,
+                user_data.reward_debt,
+            )?
+        );
+
+        UserInfo::pack(user_data, &mut pda_user_state_info.data.borrow_mut())?;
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        StakingEvent::Harvested {
+            pool_index: stake_pool.pool_index,
+            user,
+            amount: paid_amount,
+            slot: clock.slot,
+        }.emit()?;
+
+        Ok(())
+    }
+
+    pub fn process_emergency_withdraw(
+        accounts: &[AccountInfo]
+    ) -> ProgramResult {
+        expect_accounts(accounts, 8)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let owner_info = next_account_info(account_info_iter)?; // 0
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let token_account_info = next_account_info(account_info_iter)?; // 1
+        let token_account = unpack_token_account(
+            &token_account_info.data.borrow(),
+        )?;
+        if token_account.owner != *owner_info.key {
+            return Err(TokenError::OwnerMismatch.into());
+        }
+
+        let pda_pool_token_account_authority_info = next_account_info(account_info_iter)?; // 2
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 3
+        let pda_user_state_info = next_account_info(account_info_iter)?; // 4
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 5
+        let token_program_info = next_account_info(account_info_iter)?; // 6
+        let mint_info = next_account_info(account_info_iter)?; // 7
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        validate_token_program(&stake_pool, token_program_info)?;
+        if stake_pool.mint != *mint_info.key {
+            return Err(TokenError::MintMismatch.into());
+        }
+
+        validate_pool_token_account(
+            &pda_pool_token_account_staked_info,
+        )?;
+        validate_user_state(
+            &pda_user_state_info,
+            &pda_stake_pool_info,
+            &token_account_info,
+        )?;
+
+        let mut user_data = UserInfo::from_account_info(&pda_user_state_info)?;
+        user_data.check_not_frozen()?;
+
+        if *pda_pool_token_account_staked_info.key != stake_pool.staked_vault {
+            StakingError::StakedVaultMismatch.print::<StakingError>();
+            return Err(StakingError::StakedVaultMismatch.into());
+        }
+
+        // No reward math and only saturating bookkeeping, so a pool whose rate, share or supply
+        // is broken can't keep the principal in. Pending and owed rewards are forfeited
+        let effective_before = user_data.effective_balance();
+        let amount_to_transfer = user_data.amount.saturating_add(user_data.pending_unstake);
+        stake_pool.total_supply = stake_pool.total_supply.saturating_sub(user_data.amount);
+        stake_pool.effective_total_supply = stake_pool.effective_total_supply.saturating_sub(effective_before);
+        stake_pool.staker_count = stake_pool.staker_count.saturating_sub((user_data.amount > 0) as u64);
+        stake_pool.pending_unstake_supply = stake_pool.pending_unstake_supply.saturating_sub(user_data.pending_unstake);
+        user_data.amount = 0;
+        user_data.pending_unstake = 0;
+        user_data.reward_debt = 0;
+        user_data.reward_owed = 0;
+
+        if amount_to_transfer > 0 {
+            let (_, bump_seed_token_account_authority) = token_account_authority(&this_program_id());
+            let sign_seeds_pda_pool_token_account_authority: &[&[_]] = 
+                &[
+                ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(),
+                &[bump_seed_token_account_authority],
+                ];
+
+            transfer_tokens(
+                token_program_info,
+                pda_pool_token_account_staked_info,
+                mint_info,
+                token_account_info,
+                pda_pool_token_account_authority_info,
+                &[sign_seeds_pda_pool_token_account_authority],
+                amount_to_transfer,
+                stake_pool.reward_decimals,
+            )?;
+        }
+
+        #[cfg(feature = "debug-logs")]
+        msg!("UserInfo after emergency-withdraw is {:#?}", user_data);
+        UserInfo::pack(user_data, &mut pda_user_state_info.data.borrow_mut())?;
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_reset_pool(
+        accounts: &[AccountInfo],
+        new_start: u64,
+        new_end: u64,
+        new_reward_per_block: u64,
+    ) -> ProgramResult {
+        expect_accounts(accounts, 7)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pool_owner_info = next_account_info(account_info_iter)?; // 0
+        if !pool_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mint_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+
+        let clock_info = next_account_info(account_info_iter)?; // 3
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let token_program_info = next_account_info(account_info_iter)?; // 4
+        let reward_token_account_info = next_account_info(account_info_iter)?; // 5
+        let pda_pool_token_account_reward_info = next_account_info(account_info_iter)?; // 6
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        validate_token_program(&stake_pool, token_program_info)?;
+
+        assert_pool_owner(&stake_pool, pool_owner_info)?;
+        validate_pool_reward_mint(
+            &stake_pool,
+            pool_owner_info.key,
+            mint_info.key,
+        )?;
+
+        validate_reward_vault(
+            &stake_pool,
+            pda_pool_token_account_reward_info,
+        )?;
+
+        let pda_pool_token_account_reward = unpack_token_account(
+            &pda_pool_token_account_reward_info.data.borrow(),
+        )?;
+
+        let reward_amount = required_reward_funding(
+            new_reward_per_block,
+            new_start,
+            new_end,
+            1,
+            0,
+            0,
+        )?;
+
+        // Nothing is staked, so the whole reward token-account is free for the new campaign
+        let to_transfer = reward_amount.saturating_sub(pda_pool_token_account_reward.amount);
+
+        transfer_tokens(
+            token_program_info,
+            reward_token_account_info,
+            mint_info,
+            pda_pool_token_account_reward_info,
+            pool_owner_info,
+            &[],
+            to_transfer,
+            stake_pool.reward_decimals,
+        )?;
+
+        stake_pool.reset(
+            clock.slot,
+            new_start,
+            new_end,
+            new_reward_per_block,
+            reward_amount,
+        )?;
+
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    /// Drains and closes the accounts of a finished pool without stake
+    pub fn process_close_pool(
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let pool_owner_info = next_account_info(account_info_iter)?; // 0
+        if !pool_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mint_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        let pda_wallet_for_create_user_info = next_account_info(account_info_iter)?; // 3
+        let pda_pool_token_account_authority_info = next_account_info(account_info_iter)?; // 4
+        validate_token_account_authority(pda_pool_token_account_authority_info)?;
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 5
+        let pda_pool_token_account_reward_info = next_account_info(account_info_iter)?; // 6
+        let reward_token_account_info = next_account_info(account_info_iter)?; // 7
+
+        let clock_info = next_account_info(account_info_iter)?; // 8
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let system_program_info = next_account_info(account_info_iter)?; // 9
+        let token_program_info = next_account_info(account_info_iter)?; // 10
+
+        let stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        validate_token_program(&stake_pool, token_program_info)?;
+
+        assert_pool_owner(&stake_pool, pool_owner_info)?;
+        validate_stake_pool(
+            &stake_pool,
+            pool_owner_info.key,
+            mint_info.key,
+        )?;
+
+        expect_accounts(accounts, 11 + 2 * stake_pool.has_separate_reward_mint() as usize)?;
+        let (reward_mint_info, staked_token_account_info) = if stake_pool.has_separate_reward_mint() {
+            (
+                next_account_info(account_info_iter)?, // 11
+                next_account_info(account_info_iter)?, // 12
+            )
+        } else {
+            (mint_info, reward_token_account_info)
+        };
+        if *reward_mint_info.key != stake_pool.reward_mint {
+            return Err(TokenError::MintMismatch.into());
+        }
+
+        if stake_pool.total_supply != 0 {
+            StakingError::PoolNotEmpty.print::<StakingError>();
+            return Err(StakingError::PoolNotEmpty.into());
+        }
+        if clock.slot <= stake_pool.end_block {
+            StakingError::CampaignNotEnded.print::<StakingError>();
+            return Err(StakingError::CampaignNotEnded.into());
+        }
+
+        if *pda_pool_token_account_staked_info.key != stake_pool.staked_vault {
+            StakingError::StakedVaultMismatch.print::<StakingError>();
+            return Err(StakingError::StakedVaultMismatch.into());
+        }
+        validate_reward_vault(
+            &stake_pool,
+            pda_pool_token_account_reward_info,
+        )?;
+
+        let (pda_wallet_pubkey, bump_seed_wallet) = wallet_pool_address(
+            &this_program_id(),
+            stake_pool.pool_index,
+        );
+        if pda_wallet_pubkey != *pda_wallet_for_create_user_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        let (_, bump_seed_token_account_authority) = token_account_authority(&this_program_id());
+        let sign_seeds_pda_pool_token_account_authority: &[&[_]] = 
+            &[
+            ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(),
+            &[bump_seed_token_account_authority],
+            ];
+
+        let pda_pool_token_account_reward = unpack_token_account(
+            &pda_pool_token_account_reward_info.data.borrow(),
+        )?;
+
+        transfer_tokens(
+            token_program_info,
+            pda_pool_token_account_reward_info,
+            reward_mint_info,
+            reward_token_account_info,
+            pda_pool_token_account_authority_info,
+            &[sign_seeds_pda_pool_token_account_authority],
+            pda_pool_token_account_reward.amount,
+            stake_pool.reward_decimals,
+        )?;
+
+        // Without stake, tokens of the staked token-account beyond the pending unstakes were sent
+        // to it directly. They would block close_account, so they go to the owner
+        let pda_pool_token_account_staked = unpack_token_account(
+            &pda_pool_token_account_staked_info.data.borrow(),
+        )?;
+        transfer_tokens(
+            token_program_info,
+            pda_pool_token_account_staked_info,
+            mint_info,
+            staked_token_account_info,
+            pda_pool_token_account_authority_info,
+            &[sign_seeds_pda_pool_token_account_authority],
+            pda_pool_token_account_staked.amount.saturating_sub(stake_pool.pending_unstake_supply),
+            stake_pool.reward_decimals,
+        )?;
+
+        // Tokens still in the staked token-account belong to pending unstakes, closing it fails then
+        for pda_pool_token_account_info in [pda_pool_token_account_reward_info, pda_pool_token_account_staked_info] {
+            invoke_signed(
+                &for_token_program(
+                    spl_token::instruction::close_account(
+                        &spl_token::id(),
+                        pda_pool_token_account_info.key,
+                        pool_owner_info.key,
+                        pda_pool_token_account_authority_info.key,
+                        &[pda_pool_token_account_authority_info.key],
+                    )?,
+                    token_program_info.key,
+                )?,
+                &[
+                pda_pool_token_account_info.clone(),
+                pool_owner_info.clone(),
+                pda_pool_token_account_authority_info.clone(),
+                token_program_info.clone(),
+                ],
+                &[sign_seeds_pda_pool_token_account_authority],
+            )?;
+        }
+
+        let sign_seeds_pda_wallet_pool: &[&[_]] = 
+            &[
+            &stake_pool.pool_index.to_le_bytes(),
+            ADD_SEED_WALLET_POOL.as_bytes(),
+            &[bump_seed_wallet],
+            ];
+
+        invoke_signed(
+            &system_instruction::transfer(
+                pda_wallet_for_create_user_info.key,
+                pool_owner_info.key,
+                pda_wallet_for_create_user_info.lamports(),
+            ),
+            &[pda_wallet_for_create_user_info.clone(), pool_owner_info.clone(), system_program_info.clone()],
+            &[sign_seeds_pda_wallet_pool],
+        )?;
+
+        let stake_pool_lamports = pda_stake_pool_info.lamports();
+        **pool_owner_info.lamports.borrow_mut() = pool_owner_info
+            .lamports()
+            .checked_add(stake_pool_lamports)
+            .ok_or(StakingError::Overflow)?;
+        **pda_stake_pool_info.lamports.borrow_mut() = 0;
+        pda_stake_pool_info.data.borrow_mut().fill(0);
+
+        Ok(())
+    }
+
+    pub fn process_fund_native_reward(
+        accounts: &[AccountInfo],
+        lamports: u64,
+    ) -> ProgramResult {
+        expect_accounts(accounts, 6)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pool_owner_info = next_account_info(account_info_iter)?; // 0
+        if !pool_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mint_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        let pda_native_reward_info = next_account_info(account_info_iter)?; // 3
+
+        let rent_info = next_account_info(account_info_iter)?; // 4
+        let rent = &Rent::from_account_info(rent_info)?;
+
+        let system_program_info = next_account_info(account_info_iter)?; // 5
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+
+        assert_pool_owner(&stake_pool, pool_owner_info)?;
+        validate_stake_pool(
+            &stake_pool,
+            pool_owner_info.key,
+            mint_info.key,
+        )?;
+
+        let pool_index = stake_pool.pool_index;
+        let (pda_native_reward_pubkey, bump_seed_native_reward) = native_reward_address(
+            &this_program_id(),
+            pool_index,
+        );
+        if pda_native_reward_pubkey != *pda_native_reward_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        if pda_native_reward_info.lamports() == 0 {
+            let sign_seeds_pda_native_reward: &[&[_]] = 
+                &[
+                &pool_index.to_le_bytes(),
+                ADD_SEED_NATIVE_REWARD.as_bytes(),
+                &[bump_seed_native_reward],
+                ];
+
+            let min_balance_native_reward = rent.minimum_balance(0);
+
+            invoke_signed(
+                &system_instruction::create_account(
+                    pool_owner_info.key,
+                    pda_native_reward_info.key,
+                    min_balance_native_reward
+                        .checked_add(lamports)
+                        .ok_or(StakingError::Overflow)?,
+                    0,
+                    &this_program_id(),
+                ),
+                &[pool_owner_info.clone(), pda_native_reward_info.clone(), system_program_info.clone()],
+                &[sign_seeds_pda_native_reward],
+            )?;
+        } else {
+            invoke_signed(
+                &system_instruction::transfer(
+                    pool_owner_info.key,
+                    pda_native_reward_info.key,
+                    lamports,
+                ),
+                &[pool_owner_info.clone(), pda_native_reward_info.clone(), system_program_info.clone()],
+                &[],
+            )?;
+        }
+
+        stake_pool.fund_native_reward(lamports)?;
+        stake_pool.validate()?;
+
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    /// `additional_reward`, when given, must be exactly the reward of the added blocks
+    pub fn process_update_end_block(
+        accounts: &[AccountInfo],
+        end_block: u64,
+        additional_reward: Option<u64>,
+    ) -> ProgramResult {
+        expect_accounts(accounts, 8)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pool_owner_info = next_account_info(account_info_iter)?; // 0
+        let mint_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        
+        let clock_info = next_account_info(account_info_iter)?; // 3
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let token_program_info = next_account_info(account_info_iter)?; // 4
+
+        let reward_token_account_info = next_account_info(account_info_iter)?; // 5
+        let pda_pool_token_account_reward_info = next_account_info(account_info_iter)?; // 6
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 7
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        validate_token_program(&stake_pool, token_program_info)?;
+        validate_reward_vault(
+            &stake_pool,
+            pda_pool_token_account_reward_info,
+        )?;
+
+        assert_pool_owner(&stake_pool, pool_owner_info)?;
+        validate_pool_reward_mint(
+            &stake_pool,
+            pool_owner_info.key,
+            mint_info.key,
+        )?;
+
+        let current_block = clock.slot;
+
+        if stake_pool.state(current_block) == CampaignState::Ended {
+            StakingError::CampaignEnded.print::<StakingError>();
+            return Err(StakingError::CampaignEnded.into());
+        }
+        if end_block <= stake_pool.end_block {
+            StakingError::InvalidEndBlock.print::<StakingError>();
+            return Err(StakingError::InvalidEndBlock.into());
+        }
+
+        let blocks_added = end_block - stake_pool.end_block;
+
+        // TODO: add loop for reward tokens
+            let to_transfer = blocks_added
+                .checked_mul(stake_pool.reward_per_block)
+                .ok_or(StakingError::Overflow)?;
+            if additional_reward.is_some_and(|additional_reward| additional_reward != to_transfer) {
+                StakingError::RewardAmountMismatch.print::<StakingError>();
+                return Err(StakingError::RewardAmountMismatch.into());
+            }
+
+            transfer_tokens(
+                token_program_info,
+                reward_token_account_info,
+                mint_info,
+                pda_pool_token_account_reward_info,
+                pool_owner_info,
+                &[],
+                to_transfer,
+                stake_pool.reward_decimals,
+            )?;
+
+        Self::settle_then(
+            &mut stake_pool,
+            clock,
+            pda_pool_token_account_staked_info,
+            |stake_pool| {
+                stake_pool.reward_amount = stake_pool.reward_amount
+                    .checked_add(to_transfer)
+                    .ok_or(StakingError::Overflow)?;
+                stake_pool.set_end_block(end_block);
+
+                stake_pool.check_campaign_window()
+            },
+        )?;
+
+        #[cfg(feature = "debug-logs")]
+        msg!("StakePool after update-end-block is {:#?}", stake_pool);
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_update_withdraw_fee(
+        accounts: &[AccountInfo],
+        withdraw_fee_bps: u16,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| stake_pool.set_withdraw_fee_bps(withdraw_fee_bps),
+        )
+    }
+
+    pub fn process_update_whale_curve(
+        accounts: &[AccountInfo],
+        whale_threshold: u64,
+        whale_reward_bps: u16,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| stake_pool.set_whale_curve(whale_threshold, whale_reward_bps),
+        )
+    }
+
+    pub fn process_update_min_claim_amount(
+        accounts: &[AccountInfo],
+        min_claim_amount: u64,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| stake_pool.set_min_claim_amount(min_claim_amount),
+        )
+    }
+
+    pub fn process_update_reward_lock(
+        accounts: &[AccountInfo],
+        rewards_lock_duration: u64,
+        lock_policy: u8,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| stake_pool.set_reward_lock(rewards_lock_duration, lock_policy),
+        )
+    }
+
+    pub fn process_update_allowlist_mode(
+        accounts: &[AccountInfo],
+        allowlist_mode: u8,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| stake_pool.set_allowlist_mode(allowlist_mode),
+        )
+    }
+
+    pub fn process_set_allowlist_entry(
+        accounts: &[AccountInfo],
+        user: Pubkey,
+        allowed: bool,
+    ) -> ProgramResult {
+        expect_accounts(accounts, 6)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pool_owner_info = next_account_info(account_info_iter)?; // 0
+        if !pool_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mint_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        let pda_allowlist_entry_info = next_account_info(account_info_iter)?; // 3
+
+        let rent_info = next_account_info(account_info_iter)?; // 4
+        let rent = &Rent::from_account_info(rent_info)?;
+
+        let system_program_info = next_account_info(account_info_iter)?; // 5
+
+        let stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+
+        assert_pool_owner(&stake_pool, pool_owner_info)?;
+        validate_stake_pool(
+            &stake_pool,
+            pool_owner_info.key,
+            mint_info.key,
+        )?;
+
+        let (pda_allowlist_entry_pubkey, bump_seed_allowlist_entry) = allowlist_entry_address(
+            &this_program_id(),
+            pda_stake_pool_info.key,
+            &user,
+        );
+        if pda_allowlist_entry_pubkey != *pda_allowlist_entry_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        if pda_allowlist_entry_info.data_is_empty() {
+            let sign_seeds_pda_allowlist_entry: &[&[_]] = 
+                &[
+                pda_stake_pool_info.key.as_ref(),
+                user.as_ref(),
+                ADD_SEED_ALLOWLIST.as_bytes(),
+                &[bump_seed_allowlist_entry],
+                ];
+
+            invoke_signed(
+                &system_instruction::create_account(
+                    pool_owner_info.key,
+                    pda_allowlist_entry_info.key,
+                    rent.minimum_balance(ALLOWLIST_ENTRY_LEN),
+                    ALLOWLIST_ENTRY_LEN as u64,
+                    &this_program_id(),
+                ),
+                &[pool_owner_info.clone(), pda_allowlist_entry_info.clone(), system_program_info.clone()],
+                &[sign_seeds_pda_allowlist_entry],
+            )?;
+        }
+
+        let allowlist_entry = AllowlistEntry {
+            allowed,
+        };
+
+        allowlist_entry.serialize(&mut &mut pda_allowlist_entry_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    pub fn process_update_referral_fee(
+        accounts: &[AccountInfo],
+        referral_fee_bps: u16,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| stake_pool.set_referral_fee_bps(referral_fee_bps),
+        )
+    }
+
+    pub fn process_set_rate_controller(
+        accounts: &[AccountInfo],
+        rate_controller: Option<Pubkey>,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| {
+                stake_pool.set_rate_controller(rate_controller.into());
+
+                Ok(())
+            },
+        )
+    }
+
+    pub fn process_set_fee_recipient(
+        accounts: &[AccountInfo],
+        fee_recipient: Option<Pubkey>,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| stake_pool.set_fee_recipient(fee_recipient.into()),
+        )
+    }
+
+    pub fn process_poke_pool(
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        expect_accounts(accounts, 4)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 0
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 1
+
+        let clock_info = next_account_info(account_info_iter)?; // 2
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let rate_controller_info = next_account_info(account_info_iter)?; // 3
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+
+        let reward_per_block = fetch_controller_rate(
+            &stake_pool,
+            pda_stake_pool_info,
+            rate_controller_info,
+        )?;
+        msg!("Rate controller reward per block: {}", reward_per_block);
+
+        Self::settle_then(
+            &mut stake_pool,
+            clock,
+            pda_pool_token_account_staked_info,
+            |stake_pool| {
+                stake_pool.set_reward_per_block(reward_per_block)
+            },
+        )?;
+
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_set_user_boost(
+        accounts: &[AccountInfo],
+        boost_bps: u16,
+    ) -> ProgramResult {
+        expect_accounts(accounts, 7)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pool_owner_info = next_account_info(account_info_iter)?; // 0
+        if !pool_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mint_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 3
+
+        let clock_info = next_account_info(account_info_iter)?; // 4
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let token_account_info = next_account_info(account_info_iter)?; // 5
+        let pda_user_state_info = next_account_info(account_info_iter)?; // 6
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+
+        assert_pool_owner(&stake_pool, pool_owner_info)?;
+        validate_stake_pool(
+            &stake_pool,
+            pool_owner_info.key,
+            mint_info.key,
+        )?;
+        validate_user_state(
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
+        )?;
+
+        let mut user_data = UserInfo::from_account_info(pda_user_state_info)?;
+
+        // Reward earned with the old boost is kept in `reward_owed` for the next GetReward
+        Self::settle_then(
+            &mut stake_pool,
+            clock,
+            pda_pool_token_account_staked_info,
+            |stake_pool| {
+                let pending = get_pending(
+                    user_data.effective_balance(),
+                    stake_pool.accrued_token_per_share,
+                    stake_pool.precision_factor_rank,
+                    user_data.reward_debt,
+                )?;
+                let pending = stake_pool.apply_whale_curve(pending, user_data.amount)?;
+                let pending = user_data.cap_claim(pending, stake_pool.max_reward_per_user)?;
+                user_data.owe_reward(pending)?;
+
+                let effective_before = user_data.effective_balance();
+                user_data.set_boost_bps(boost_bps)?;
+                stake_pool.update_effective_total_supply(effective_before, user_data.effective_balance())?;
+
+                user_data.set_reward_debt(
+                    get_reward_debt(
+                        user_data.effective_balance(),
+                        stake_pool.accrued_token_per_share,
+                    )?
+                );
+
+                Ok(())
+            },
+        )?;
+
+        UserInfo::pack(user_data, &mut pda_user_state_info.data.borrow_mut())?;
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_update_unstake_cooldown(
+        accounts: &[AccountInfo],
+        unstake_cooldown: u64,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| {
+                stake_pool.set_unstake_cooldown(unstake_cooldown);
+
+                Ok(())
+            },
+        )
+    }
+
+    pub fn process_request_unstake(
+        accounts: &[AccountInfo],
+        amount: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let owner_info = next_account_info(account_info_iter)?; // 0
+        let token_account_info = next_account_info(account_info_iter)?; // 1
+
+        let token_account = unpack_token_account(
+            &token_account_info.data.borrow(),
+        )?;
+
+        if token_account.owner != *owner_info.key {
+            return Err(TokenError::OwnerMismatch.into());
+        }
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        let pda_pool_token_account_authority_info = next_account_info(account_info_iter)?; // 3
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 4
+        let pda_pool_token_account_reward_info = next_account_info(account_info_iter)?; // 5
+        let pda_user_state_info = next_account_info(account_info_iter)?; // 6
+
+        let clock_program_info = next_account_info(account_info_iter)?; // 7
+        let clock = &Clock::from_account_info(clock_program_info)?;
+
+        let token_program_info = next_account_info(account_info_iter)?; // 8
+        let mint_info = next_account_info(account_info_iter)?; // 9
+
+        let (_, bump_seed_token_account_authority) = token_account_authority(&this_program_id());
+        let sign_seeds_pda_pool_token_account_authority: &[&[_]] = 
+            &[
+            ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(),
+            &[bump_seed_token_account_authority],
+            ];
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        validate_token_program(&stake_pool, token_program_info)?;
+        // Withdraw fee goes to the reward token-account
+        validate_reward_vault(
+            &stake_pool,
+            pda_pool_token_account_reward_info,
+        )?;
+        expect_accounts(accounts, 10 + stake_pool.fee_recipient.is_some() as usize)?;
+        // Fee recipient token-account follows the other accounts only when the pool has one
+        let fee_destination_info = match stake_pool.fee_recipient {
+            COption::Some(fee_recipient) => {
+                let fee_recipient_info = next_account_info(account_info_iter)?; // 10
+                if *fee_recipient_info.key != fee_recipient {
+                    StakingError::FeeRecipientMismatch.print::<StakingError>();
+                    return Err(StakingError::FeeRecipientMismatch.into());
+                }
+                fee_recipient_info
+            },
+            COption::None => pda_pool_token_account_reward_info,
+        };
+        validate_user_state(
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
+        )?;
+
+        let mut user_data = UserInfo::from_account_info(pda_user_state_info)?;
+        user_data.check_not_frozen()?;
+        if amount == 0 || amount > user_data.amount {
+            StakingError::InvalidUnstakeAmount.print::<StakingError>();
+            return Err(StakingError::InvalidUnstakeAmount.into());
+        }
+
+        stake_pool.update_pool(
+            pda_pool_token_account_staked_info,
+            clock,
+        )?;
+
+        let pending = get_pending(
+            user_data.effective_balance(),
+            stake_pool.accrued_token_per_share,
+            stake_pool.precision_factor_rank,
+            user_data.reward_debt,
+        )?;
+        let pending = stake_pool.apply_whale_curve(pending, user_data.amount)?;
+        let pending = user_data.cap_claim(pending, stake_pool.max_reward_per_user)?;
+        user_data.owe_reward(pending)?;
+
+        let now = now_secs(clock)?;
+        let effective_before = user_data.effective_balance();
+        let withdraw_fee = stake_pool.get_exit_fee(amount, user_data.reward_lock_finish, now)?;
+        user_data.accumulate_twab(now)?;
+
+        let amount_after_fee = amount
+            .checked_sub(withdraw_fee)
+            .ok_or(StakingError::Overflow)?;
+        let amount_before = user_data.amount;
+        user_data.request_unstake(amount, now, stake_pool.unstake_cooldown)?;
+        // The fee is already taken out of the queued tokens
+        user_data.pending_unstake = user_data.pending_unstake
+            .checked_sub(withdraw_fee)
+            .ok_or(StakingError::Overflow)?;
+        stake_pool.pending_unstake_supply = stake_pool.pending_unstake_supply
+            .checked_add(amount_after_fee)
+            .ok_or(StakingError::Overflow)?;
+        stake_pool.decrease_total_supply(amount)?;
+        stake_pool.update_effective_total_supply(effective_before, user_data.effective_balance())?;
+        stake_pool.update_staker_count(amount_before, user_data.amount)?;
+
+        user_data.set_reward_debt(
+            get_reward_debt(
+                user_data.effective_balance(),
+                stake_pool.accrued_token_per_share,
+            )?
+        );
+
+        // Without a fee recipient the withdraw fee is shared by the remaining stakers
+        if withdraw_fee > 0 {
+            transfer_tokens(
+                token_program_info,
+                pda_pool_token_account_staked_info,
+                mint_info,
+                fee_destination_info,
+                pda_pool_token_account_authority_info,
+                &[sign_seeds_pda_pool_token_account_authority],
+                withdraw_fee,
+                stake_pool.reward_decimals,
+            )?;
+        }
+        let remaining_staked_supply = stake_pool.effective_total_supply;
+        stake_pool.distribute_withdraw_fee(
+            withdraw_fee,
+            remaining_staked_supply,
+        )?;
+
+        msg!("Unstake of {} ready at {}", amount_after_fee, user_data.unstake_ready_at);
+
+        UserInfo::pack(user_data, &mut pda_user_state_info.data.borrow_mut())?;
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_complete_unstake(
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        expect_accounts(accounts, 9)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let owner_info = next_account_info(account_info_iter)?; // 0
+        let token_account_info = next_account_info(account_info_iter)?; // 1
+
+        let token_account = unpack_token_account(
+            &token_account_info.data.borrow(),
+        )?;
+
+        if token_account.owner != *owner_info.key {
+            return Err(TokenError::OwnerMismatch.into());
+        }
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        let pda_pool_token_account_authority_info = next_account_info(account_info_iter)?; // 3
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 4
+        let pda_user_state_info = next_account_info(account_info_iter)?; // 5
+
+        let clock_program_info = next_account_info(account_info_iter)?; // 6
+        let clock = &Clock::from_account_info(clock_program_info)?;
+
+        let token_program_info = next_account_info(account_info_iter)?; // 7
+        let mint_info = next_account_info(account_info_iter)?; // 8
+
+        let (_, bump_seed_token_account_authority) = token_account_authority(&this_program_id());
+        let sign_seeds_pda_pool_token_account_authority: &[&[_]] = 
+            &[
+            ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(),
+            &[bump_seed_token_account_authority],
+            ];
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        validate_token_program(&stake_pool, token_program_info)?;
+        if *pda_pool_token_account_staked_info.key != stake_pool.staked_vault {
+            StakingError::StakedVaultMismatch.print::<StakingError>();
+            return Err(StakingError::StakedVaultMismatch.into());
+        }
+        validate_user_state(
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
+        )?;
+
+        let mut user_data = UserInfo::from_account_info(pda_user_state_info)?;
+        user_data.check_not_frozen()?;
+
+        let amount = user_data.complete_unstake(now_secs(clock)?)?;
+        // Saturating, requests made before the pool tracked them aren't counted
+        stake_pool.pending_unstake_supply = stake_pool.pending_unstake_supply.saturating_sub(amount);
+        transfer_tokens(
+            token_program_info,
+            pda_pool_token_account_staked_info,
+            mint_info,
+            token_account_info,
+            pda_pool_token_account_authority_info,
+            &[sign_seeds_pda_pool_token_account_authority],
+            amount,
+            stake_pool.reward_decimals,
+        )?;
+
+        UserInfo::pack(user_data, &mut pda_user_state_info.data.borrow_mut())?;
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_update_shortfall_policy(
+        accounts: &[AccountInfo],
+        partial_claim_on_shortfall: bool,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| {
+                stake_pool.set_partial_claim_on_shortfall(partial_claim_on_shortfall);
+
+                Ok(())
+            },
+        )
+    }
+
+    pub fn process_set_user_frozen(
+        accounts: &[AccountInfo],
+        user: Pubkey,
+        frozen: bool,
+    ) -> ProgramResult {
+        expect_accounts(accounts, 5)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pool_owner_info = next_account_info(account_info_iter)?; // 0
+        if !pool_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mint_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        let token_account_info = next_account_info(account_info_iter)?; // 3
+        let pda_user_state_info = next_account_info(account_info_iter)?; // 4
+
+        if *token_account_info.key != user {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+
+        assert_pool_owner(&stake_pool, pool_owner_info)?;
+        validate_stake_pool(
+            &stake_pool,
+            pool_owner_info.key,
+            mint_info.key,
+        )?;
+        validate_user_state(
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
+        )?;
+
+        // Accrual doesn't depend on the flag, a frozen position keeps earning
+        let mut user_data = UserInfo::from_account_info(pda_user_state_info)?;
+        user_data.set_frozen(frozen);
+
+        UserInfo::pack(user_data, &mut pda_user_state_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_set_pause(
+        accounts: &[AccountInfo],
+        paused: bool,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| {
+                stake_pool.set_paused(paused);
+
+                Ok(())
+            },
+        )
+    }
+
+    pub fn process_transfer_pool_ownership(
+        accounts: &[AccountInfo],
+        new_owner: Pubkey,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| stake_pool.propose_owner(new_owner),
+        )
+    }
+
+    pub fn process_accept_pool_ownership(
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        expect_accounts(accounts, 2)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pending_owner_info = next_account_info(account_info_iter)?; // 0
+        if !pending_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 1
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+
+        stake_pool.accept_owner(pending_owner_info.key)?;
+
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_set_user_reward_debt(
+        accounts: &[AccountInfo],
+        user: Pubkey,
+        reward_debt: u128,
+    ) -> ProgramResult {
+        expect_accounts(accounts, 6)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pool_owner_info = next_account_info(account_info_iter)?; // 0
+        if !pool_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mint_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        let token_account_info = next_account_info(account_info_iter)?; // 3
+        let pda_user_state_info = next_account_info(account_info_iter)?; // 4
+
+        let clock_info = next_account_info(account_info_iter)?; // 5
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        if *token_account_info.key != user {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+
+        assert_pool_owner(&stake_pool, pool_owner_info)?;
+        validate_stake_pool(
+            &stake_pool,
+            pool_owner_info.key,
+            mint_info.key,
+        )?;
+        validate_user_state(
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
+        )?;
+
+        // Corrections are only made while the pool is halted for the incident
+        if stake_pool.is_paused == 0 {
+            StakingError::PoolNotPaused.print::<StakingError>();
+            return Err(StakingError::PoolNotPaused.into());
+        }
+
+        let mut user_data = UserInfo::from_account_info(pda_user_state_info)?;
+        let previous_reward_debt = user_data.reward_debt;
+        user_data.correct_reward_debt(
+            reward_debt,
+            stake_pool.accrued_token_per_share,
+            clock.slot,
+        )?;
+
+        msg!(
+            "Reward debt of {} in pool {} corrected from {} to {}",
+            user,
+            stake_pool.pool_index,
+            previous_reward_debt,
+            reward_debt,
+        );
+
+        UserInfo::pack(user_data, &mut pda_user_state_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_set_referrer(
+        accounts: &[AccountInfo],
+        referrer: Pubkey,
+    ) -> ProgramResult {
+        expect_accounts(accounts, 4)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let owner_info = next_account_info(account_info_iter)?; // 0
+        let token_account_info = next_account_info(account_info_iter)?; // 1
+
+        let token_account = unpack_token_account(
+            &token_account_info.data.borrow(),
+        )?;
+
+        if token_account.owner != *owner_info.key {
+            return Err(TokenError::OwnerMismatch.into());
+        }
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        let pda_user_state_info = next_account_info(account_info_iter)?; // 3
+
+        validate_user_state(
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
+        )?;
+
+        if referrer == *token_account_info.key {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let mut user_data = UserInfo::from_account_info(pda_user_state_info)?;
+        if user_data.referrer.is_some() {
+            return Err(StakingError::ReferrerAlreadySet.into());
+        }
+        user_data.referrer = Some(referrer);
+
+        UserInfo::pack(user_data, &mut pda_user_state_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_update_early_withdraw_penalty(
+        accounts: &[AccountInfo],
+        early_withdraw_penalty_bps: u16,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| stake_pool.set_early_withdraw_penalty_bps(early_withdraw_penalty_bps),
+        )
+    }
+
+    pub fn process_update_max_reward_per_user(
+        accounts: &[AccountInfo],
+        max_reward_per_user: u64,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| stake_pool.set_max_reward_per_user(max_reward_per_user),
+        )
+    }
+
+    pub fn process_update_max_total_supply(
+        accounts: &[AccountInfo],
+        max_total_supply: u64,
+    ) -> ProgramResult {
+        Self::update_pool_config(
+            accounts,
+            |stake_pool| {
+                stake_pool.set_max_total_supply(max_total_supply);
+
+                Ok(())
+            },
+        )
+    }
+
+    /// Every change of a parameter used by accrual (rate, bonus, precision, end block) goes
+    /// through here: blocks up to the current one are accrued with the old parameters first,
+    /// otherwise the change would reprice them retroactively
+    fn settle_then<F>(
+        stake_pool: &mut StakePool,
+        clock: &Clock,
+        pda_pool_token_account_staked_info: &AccountInfo,
+        update: F,
+    ) -> ProgramResult
+    where
+        F: FnOnce(&mut StakePool) -> ProgramResult,
+    {
+        validate_pool_token_account(
+            pda_pool_token_account_staked_info,
+        )?;
+
+        stake_pool.update_pool(
+            pda_pool_token_account_staked_info,
+            clock,
+        )?;
+
+        update(stake_pool)?;
+        stake_pool.validate()
+    }
+
+    /// Owner-only change of StakePool parameters that don't affect already accrued rewards
+    /// Unlike `update_pool_config`, the pool isn't validated before the update, a legacy rank
+    /// fails the validation
+    pub fn process_migrate_precision_factor(
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        expect_accounts(accounts, 3)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pool_owner_info = next_account_info(account_info_iter)?; // 0
+        if !pool_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mint_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+
+        assert_pool_owner(&stake_pool, pool_owner_info)?;
+        validate_stake_pool(
+            &stake_pool,
+            pool_owner_info.key,
+            mint_info.key,
+        )?;
+
+        stake_pool.migrate_precision_factor_rank()?;
+        stake_pool.validate()?;
+
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn update_pool_config<F>(
+        accounts: &[AccountInfo],
+        update: F,
+    ) -> ProgramResult
+    where
+        F: FnOnce(&mut StakePool) -> ProgramResult,
+    {
+        expect_accounts(accounts, 3)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pool_owner_info = next_account_info(account_info_iter)?; // 0
+        if !pool_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mint_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+
+        assert_pool_owner(&stake_pool, pool_owner_info)?;
+        validate_stake_pool(
+            &stake_pool,
+            pool_owner_info.key,
+            mint_info.key,
+        )?;
+
+        update(&mut stake_pool)?;
+        stake_pool.validate()?;
+
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_set_reward_per_block(
+        accounts: &[AccountInfo],
+        reward_per_block: u64,
+    ) -> ProgramResult {
+        expect_accounts(accounts, 5)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pool_owner_info = next_account_info(account_info_iter)?; // 0
+        if !pool_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mint_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 3
+
+        let clock_info = next_account_info(account_info_iter)?; // 4
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+
+        assert_pool_owner(&stake_pool, pool_owner_info)?;
+        validate_stake_pool(
+            &stake_pool,
+            pool_owner_info.key,
+            mint_info.key,
+        )?;
+
+        Self::settle_then(
+            &mut stake_pool,
+            clock,
+            pda_pool_token_account_staked_info,
+            |stake_pool| {
+                stake_pool.set_reward_per_block(reward_per_block)
+            },
+        )?;
+
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_set_simple_interest(
+        accounts: &[AccountInfo],
+        simple_interest_bps: u16,
+    ) -> ProgramResult {
+        expect_accounts(accounts, 5)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pool_owner_info = next_account_info(account_info_iter)?; // 0
+        if !pool_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mint_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 3
+
+        let clock_info = next_account_info(account_info_iter)?; // 4
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+
+        assert_pool_owner(&stake_pool, pool_owner_info)?;
+        validate_stake_pool(
+            &stake_pool,
+            pool_owner_info.key,
+            mint_info.key,
+        )?;
+
+        Self::settle_then(
+            &mut stake_pool,
+            clock,
+            pda_pool_token_account_staked_info,
+            |stake_pool| stake_pool.set_simple_interest_bps(simple_interest_bps),
+        )?;
+
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_notify_reward_amount(
+        accounts: &[AccountInfo],
+        reward: u64,
+    ) -> ProgramResult {
+        expect_accounts(accounts, 8)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pool_owner_info = next_account_info(account_info_iter)?; // 0
+        if !pool_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mint_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+
+        let clock_info = next_account_info(account_info_iter)?; // 3
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let token_program_info = next_account_info(account_info_iter)?; // 4
+
+        let reward_token_account_info = next_account_info(account_info_iter)?; // 5
+        let pda_pool_token_account_reward_info = next_account_info(account_info_iter)?; // 6
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 7
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        validate_token_program(&stake_pool, token_program_info)?;
+
+        assert_pool_owner(&stake_pool, pool_owner_info)?;
+        validate_pool_reward_mint(
+            &stake_pool,
+            pool_owner_info.key,
+            mint_info.key,
+        )?;
+        validate_reward_vault(
+            &stake_pool,
+            pda_pool_token_account_reward_info,
+        )?;
+
+        transfer_tokens(
+            token_program_info,
+            reward_token_account_info,
+            mint_info,
+            pda_pool_token_account_reward_info,
+            pool_owner_info,
+            &[],
+            reward,
+            stake_pool.reward_decimals,
+        )?;
+        let reward_balance = unpack_token_account(
+            &pda_pool_token_account_reward_info.data.borrow(),
+        )?.amount;
+
+        let now = now_secs(clock)?;
+        Self::settle_then(
+            &mut stake_pool,
+            clock,
+            pda_pool_token_account_staked_info,
+            |stake_pool| stake_pool.notify_reward_amount(reward, now, reward_balance),
+        )?;
+
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_set_rewards_duration(
+        accounts: &[AccountInfo],
+        rewards_duration: u64,
+    ) -> ProgramResult {
+        expect_accounts(accounts, 5)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pool_owner_info = next_account_info(account_info_iter)?; // 0
+        if !pool_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mint_info = next_account_info(account_info_iter)?; // 1
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 3
+
+        let clock_info = next_account_info(account_info_iter)?; // 4
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+
+        assert_pool_owner(&stake_pool, pool_owner_info)?;
+        validate_stake_pool(
+            &stake_pool,
+            pool_owner_info.key,
+            mint_info.key,
+        )?;
+
+        let now = now_secs(clock)?;
+        Self::settle_then(
+            &mut stake_pool,
+            clock,
+            pda_pool_token_account_staked_info,
+            |stake_pool| stake_pool.set_rewards_duration(rewards_duration, now),
+        )?;
+
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_checkpoint(
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        expect_accounts(accounts, 7)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let payer_info = next_account_info(account_info_iter)?; // 0
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 1
+        if *pda_stake_pool_info.owner != this_program_id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 2
+        let pda_checkpoints_info = next_account_info(account_info_iter)?; // 3
+
+        let clock_info = next_account_info(account_info_iter)?; // 4
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let rent_info = next_account_info(account_info_iter)?; // 5
+        let rent = &Rent::from_account_info(rent_info)?;
+
+        let system_program_info = next_account_info(account_info_iter)?; // 6
+
+        validate_pool_token_account(
+            pda_pool_token_account_staked_info,
+        )?;
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        let pool_index = stake_pool.pool_index;
+
+        let (pda_checkpoints_pubkey, bump_seed_checkpoints) = Pubkey::find_program_address(
+            &[&pool_index.to_le_bytes(), ADD_SEED_CHECKPOINTS.as_bytes()],
+            &this_program_id(),
+        );
+        if pda_checkpoints_pubkey != *pda_checkpoints_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        if pda_checkpoints_info.data_is_empty() {
+            if !payer_info.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+
+            let sign_seeds_pda_checkpoints: &[&[_]] = 
+                &[
+                &pool_index.to_le_bytes(),
+                ADD_SEED_CHECKPOINTS.as_bytes(),
+                &[bump_seed_checkpoints],
+                ];
+
+            invoke_signed(
+                &system_instruction::create_account(
+                    payer_info.key,
+                    pda_checkpoints_info.key,
+                    rent.minimum_balance(REWARD_CHECKPOINTS_LEN),
+                    REWARD_CHECKPOINTS_LEN as u64,
+                    &this_program_id(),
+                ),
+                &[payer_info.clone(), pda_checkpoints_info.clone(), system_program_info.clone()],
+                &[sign_seeds_pda_checkpoints],
+            )?;
+
+            RewardCheckpoints::new(pool_index)
+                .serialize(&mut &mut pda_checkpoints_info.data.borrow_mut()[..])?;
+        }
+
+        let pda_pool_token_account_staked = unpack_token_account(
+            &pda_pool_token_account_staked_info.data.borrow(),
+        )?;
+
+        stake_pool.update_pool(
+            pda_pool_token_account_staked_info,
+            clock,
+        )?;
+
+        let mut checkpoints = RewardCheckpoints::from_account_info(pda_checkpoints_info)?;
+        checkpoints.push(Checkpoint {
+            slot: clock.slot,
+            accrued_token_per_share: stake_pool.accrued_token_per_share,
+            total_supply: pda_pool_token_account_staked.amount,
+        });
+
+        checkpoints.serialize(&mut &mut pda_checkpoints_info.data.borrow_mut()[..])?;
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_get_pool_count(
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        expect_accounts(accounts, 1)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pda_master_staking_info = next_account_info(account_info_iter)?; // 0
+        validate_master_staking(pda_master_staking_info)?;
+
+        let master_staking = MasterStaking::from_account_info(pda_master_staking_info)?;
+
+        set_return_data(&master_staking.pool_counter.to_le_bytes());
+
+        Ok(())
+    }
+
+    pub fn process_get_next_unlock(
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let (clock_info, user_state_infos) = match accounts.split_first() {
+            Some((clock_info, user_state_infos)) if !user_state_infos.is_empty() => (clock_info, user_state_infos),
+            _ => return Err(ProgramError::NotEnoughAccountKeys),
+        };
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let positions = user_state_infos
+            .iter()
+            .map(|user_state_info| {
+                if *user_state_info.owner != this_program_id() {
+                    return Err(ProgramError::IncorrectProgramId);
+                }
+                UserInfo::from_account_info(user_state_info)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let next_unlock = UserInfo::next_unlock(&positions, now_secs(clock)?).unwrap_or(0);
+        set_return_data(&next_unlock.to_le_bytes());
+
+        Ok(())
+    }
+
+    pub fn process_get_pool_apr(
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        expect_accounts(accounts, 2)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 0
+        if *pda_stake_pool_info.owner != this_program_id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let clock_info = next_account_info(account_info_iter)?; // 1
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+
+        let apr_bps = stake_pool.get_apr_bps(clock)?;
+        msg!("Pool APR bps: {}", apr_bps);
+        set_return_data(&apr_bps.to_le_bytes());
+
+        Ok(())
+    }
+
+    pub fn process_get_user_info(
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        expect_accounts(accounts, 5)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 0
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 1
+        let token_account_info = next_account_info(account_info_iter)?; // 2
+        let pda_user_state_info = next_account_info(account_info_iter)?; // 3
+
+        let clock_info = next_account_info(account_info_iter)?; // 4
+        let clock = &Clock::from_account_info(clock_info)?;
+        let now = now_secs(clock)?;
+
+        let stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        if *pda_pool_token_account_staked_info.key != stake_pool.staked_vault {
+            StakingError::StakedVaultMismatch.print::<StakingError>();
+            return Err(StakingError::StakedVaultMismatch.into());
+        }
+
+        validate_user_state(
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
+        )?;
+
+        let user_data = UserInfo::from_account_info(pda_user_state_info)?;
+
+        let pending = stake_pool.pending_reward(
+            &user_data,
+            stake_pool.effective_total_supply,
+            clock,
+        )?
+            .checked_add(user_data.reward_owed)
+            .ok_or(StakingError::Overflow)?;
+
+        let status = UserRewardStatus {
+            amount: user_data.amount,
+            pending,
+            reward_lock_finish: user_data.reward_lock_finish,
+            seconds_until_unlock: user_data.seconds_until_unlock(now),
+            can_claim: user_data.can_claim(now) && pending > 0 && pending >= stake_pool.min_claim_amount,
+        };
+
+        set_return_data(&status.try_to_vec()?);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "self-test")]
+    pub fn process_self_test_accrual(
+        accounts: &[AccountInfo],
+        blocks: u64,
+        supply: u64,
+        rate: u64,
+    ) -> ProgramResult {
+        expect_accounts(accounts, 1)?;
+        let account_info_iter = &mut accounts.iter();
+
+        let mint_info = next_account_info(account_info_iter)?; // 0
+        let mint = TokenMint::unpack(&mint_info.data.borrow())?;
+
+        // Same rank as process_initialize gives pools of the mint
+        let precision_factor_rank = precision_factor_rank_for_decimals(mint.decimals)?;
+
+        let accrued_token_per_share = crate::utils::simulate_update(
+            0,
+            blocks as u128,
+            rate,
+            supply,
+            precision_factor_rank,
+        )?;
+        msg!("accrued_token_per_share: {}", accrued_token_per_share);
+
+        set_return_data(&accrued_token_per_share.to_le_bytes());
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "self-test"))]
+    pub fn process_self_test_accrual(
+        _accounts: &[AccountInfo],
+        _blocks: u64,
+        _supply: u64,
+        _rate: u64,
+    ) -> ProgramResult {
+        msg!("SelfTestAccrual needs the self-test feature");
+        Err(ProgramError::InvalidInstructionData)
+    }
+
+    pub fn process_transfer_position(
+        accounts: &[AccountInfo],
+        new_owner: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let owner_info = next_account_info(account_info_iter)?; // 0
+        let token_account_info = next_account_info(account_info_iter)?; // 1
+
+        let token_account = unpack_token_account(
+            &token_account_info.data.borrow(),
+        )?;
+
+        if token_account.owner != *owner_info.key {
+            return Err(TokenError::OwnerMismatch.into());
+        }
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let new_token_account_info = next_account_info(account_info_iter)?; // 2
+        if *new_token_account_info.key != new_owner || new_owner == *token_account_info.key {
+            return Err(ProgramError::InvalidArgument);
+        }
+        let new_token_account = unpack_token_account(
+            &new_token_account_info.data.borrow(),
+        )?;
+
+        let mint_info = next_account_info(account_info_iter)?; // 3
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 4
+        let pda_pool_token_account_authority_info = next_account_info(account_info_iter)?; // 5
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 6
+        let pda_pool_token_account_reward_info = next_account_info(account_info_iter)?; // 7
+        let pda_user_state_info = next_account_info(account_info_iter)?; // 8
+        let pda_new_user_state_info = next_account_info(account_info_iter)?; // 9
+        let pda_wallet_for_create_user_info = next_account_info(account_info_iter)?; // 10
+
+        let rent_info = next_account_info(account_info_iter)?; // 11
+        let rent = &Rent::from_account_info(rent_info)?;
+
+        let clock_program_info = next_account_info(account_info_iter)?; // 12
+        let clock = &Clock::from_account_info(clock_program_info)?;
+
+        let system_program_info = next_account_info(account_info_iter)?; // 13
+        let token_program_info = next_account_info(account_info_iter)?; // 14
+        let allowlist_entry_info = next_account_info(account_info_iter).ok(); // 15
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        validate_token_program(&stake_pool, token_program_info)?;
+        let pool_index = stake_pool.pool_index;
+
+        if new_token_account.mint != stake_pool.mint {
+            return Err(TokenError::MintMismatch.into());
+        }
+
+        // AllowlistEntry is only passed for pools with an allowlist mode
+        expect_accounts(accounts, 15 + stake_pool.uses_allowlist() as usize)?;
+        validate_staker(
+            &stake_pool,
+            pda_stake_pool_info.key,
+            &new_token_account.owner,
+            allowlist_entry_info,
+        )?;
+
+        validate_pool_token_account(
+            pda_pool_token_account_staked_info,
+        )?;
+        if *pda_pool_token_account_staked_info.key != stake_pool.staked_vault {
+            StakingError::StakedVaultMismatch.print::<StakingError>();
+            return Err(StakingError::StakedVaultMismatch.into());
+        }
+        validate_reward_vault(
+            &stake_pool,
+            pda_pool_token_account_reward_info,
+        )?;
+        validate_user_state(
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
+        )?;
+
+        let (pda_wallet_pubkey, _bump_seed_wallet) = wallet_pool_address(
+            &this_program_id(),
+            pool_index,
+        );
+        if pda_wallet_pubkey != *pda_wallet_for_create_user_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        if pda_new_user_state_info.data_is_empty() {
+            Self::create_user_info(
+                pool_index,
+                pda_stake_pool_info,
+                new_token_account_info,
+                pda_wallet_for_create_user_info,
+                pda_new_user_state_info,
+                rent,
+                system_program_info,
+            )?;
+        }
+        validate_user_state(
+            pda_new_user_state_info,
+            pda_stake_pool_info,
+            new_token_account_info,
+        )?;
+
+        let mut new_user_data = UserInfo::from_account_info(pda_new_user_state_info)?;
+        new_user_data.check_not_frozen()?;
+        if new_user_data.amount != 0 {
+            return Err(StakingError::PositionNotEmpty.into());
+        }
+
+        let (_, bump_seed_token_account_authority) = token_account_authority(&this_program_id());
+        let sign_seeds_pda_pool_token_account_authority: &[&[_]] = 
+            &[
+            ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(),
+            &[bump_seed_token_account_authority],
+            ];
+
+        let mut user_data = UserInfo::from_account_info(pda_user_state_info)?;
+        user_data.check_not_frozen()?;
+        user_data.check_no_pending_unstake()?;
+
+        stake_pool.update_pool(
+            pda_pool_token_account_staked_info,
+            clock,
+        )?;
+
+        let pending = get_pending(
+            user_data.effective_balance(),
+            stake_pool.accrued_token_per_share,
+            stake_pool.precision_factor_rank,
+            user_data.reward_debt,
+        )?;
+        let pending = stake_pool.apply_whale_curve(pending, user_data.amount)?;
+        let pending = user_data.cap_claim(pending, stake_pool.max_reward_per_user)?;
+        // UserInfo is closed below, native SOL reward must be claimed with GetReward beforehand
+        if stake_pool.reward_is_native != 0 && (pending > 0 || user_data.reward_owed > 0) {
+            return Err(StakingError::NativeRewardUnsupported.into());
+        }
+        if stake_pool.has_separate_reward_mint() && (pending > 0 || user_data.reward_owed > 0) {
+            StakingError::RewardMintUnsupported.print::<StakingError>();
+            return Err(StakingError::RewardMintUnsupported.into());
+        }
+        let pending = pending
+            .checked_add(user_data.take_reward_owed())
+            .ok_or(StakingError::Overflow)?;
+        let now = now_secs(clock)?;
+        if pending > 0 && !user_data.can_claim(now) {
+            StakingError::RewardLocked.print::<StakingError>();
+            return Err(StakingError::RewardLocked.into());
+        }
+
+        if pending > 0 {
+            transfer_tokens(
+                token_program_info,
+                pda_pool_token_account_reward_info,
+                mint_info,
+                token_account_info,
+                pda_pool_token_account_authority_info,
+                &[sign_seeds_pda_pool_token_account_authority],
+                pending,
+                stake_pool.reward_decimals,
             )?;
         }
 
-        //debug
-        msg!("user_data after emergency-withdraw is {:#?}", user_data);
-        //
-        user_data.serialize(&mut &mut pda_user_state_info.data.borrow_mut()[..])?;
+        user_data.accumulate_twab(now)?;
+        new_user_data.accumulate_twab(now)?;
+        new_user_data.time_weighted_balance = new_user_data
+            .time_weighted_balance
+            .checked_add(user_data.time_weighted_balance)
+            .ok_or(StakingError::Overflow)?;
+
+        new_user_data.amount = user_data.amount;
+        new_user_data.boost_bps = user_data.boost_bps;
+        new_user_data.total_claimed = new_user_data
+            .total_claimed
+            .checked_add(user_data.total_claimed)
+            .ok_or(StakingError::Overflow)?;
+        new_user_data.reward_lock_finish = new_user_data
+            .reward_lock_finish
+            .max(user_data.reward_lock_finish);
+        new_user_data.set_reward_debt(
+            get_reward_debt(
+                new_user_data.effective_balance(),
+                stake_pool.accrued_token_per_share,
+            )?
+        );
+        UserInfo::pack(new_user_data, &mut pda_new_user_state_info.data.borrow_mut())?;
+
+        // Close UserInfo of the current token-account, rent goes back to the pool wallet
+        let user_state_lamports = pda_user_state_info.lamports();
+        **pda_wallet_for_create_user_info.lamports.borrow_mut() = pda_wallet_for_create_user_info
+            .lamports()
+            .checked_add(user_state_lamports)
+            .ok_or(StakingError::Overflow)?;
+        **pda_user_state_info.lamports.borrow_mut() = 0;
+        pda_user_state_info.data.borrow_mut().fill(0);
+
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
 
         Ok(())
     }
 
-    pub fn process_update_end_block(
+    pub fn process_compound(
         accounts: &[AccountInfo],
-        end_block: u64,
     ) -> ProgramResult {
+        expect_accounts(accounts, 10)?;
         let account_info_iter = &mut accounts.iter();
 
-        let pool_owner_info = next_account_info(account_info_iter)?; // 0
-        let mint_info = next_account_info(account_info_iter)?; // 1
+        let owner_info = next_account_info(account_info_iter)?; // 0
+        let token_account_info = next_account_info(account_info_iter)?; // 1
+
+        let token_account = unpack_token_account(
+            &token_account_info.data.borrow(),
+        )?;
+
+        if token_account.owner != *owner_info.key {
+            return Err(TokenError::OwnerMismatch.into());
+        }
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
         let pda_stake_pool_info = next_account_info(account_info_iter)?; // 2
-        
-        let clock_info = next_account_info(account_info_iter)?; // 3
-        let clock = &Clock::from_account_info(clock_info)?;
+        let pda_pool_token_account_authority_info = next_account_info(account_info_iter)?; // 3
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 4
+        let pda_pool_token_account_reward_info = next_account_info(account_info_iter)?; // 5
+        let pda_user_state_info = next_account_info(account_info_iter)?; // 6
 
-        let token_program_info = next_account_info(account_info_iter)?; // 4
+        let clock_program_info = next_account_info(account_info_iter)?; // 7
+        let clock = &Clock::from_account_info(clock_program_info)?;
 
-        let reward_token_account_info = next_account_info(account_info_iter)?; // 5
+        let token_program_info = next_account_info(account_info_iter)?; // 8
+        let mint_info = next_account_info(account_info_iter)?; // 9
+
+        let (_, bump_seed_token_account_authority) = token_account_authority(&this_program_id());
+        let sign_seeds_pda_pool_token_account_authority: &[&[_]] = 
+            &[
+            ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(),
+            &[bump_seed_token_account_authority],
+            ];
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        validate_token_program(&stake_pool, token_program_info)?;
+        stake_pool.check_not_paused()?;
+
+        // Native SOL and a separate reward mint can't be staked into the pool
+        if stake_pool.reward_is_native != 0 || stake_pool.has_separate_reward_mint() {
+            StakingError::CompoundMintMismatch.print::<StakingError>();
+            return Err(StakingError::CompoundMintMismatch.into());
+        }
+        if stake_pool.mint != *mint_info.key {
+            return Err(TokenError::MintMismatch.into());
+        }
+
+        validate_reward_vault(
+            &stake_pool,
+            pda_pool_token_account_reward_info,
+        )?;
+        validate_user_state(
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
+        )?;
+
+        let mut user_data = UserInfo::from_account_info(pda_user_state_info)?;
+        user_data.check_not_frozen()?;
+
+        stake_pool.update_pool(
+            pda_pool_token_account_staked_info,
+            clock,
+        )?;
+
+        let now = now_secs(clock)?;
+        let pending = get_pending(
+            user_data.effective_balance(),
+            stake_pool.accrued_token_per_share,
+            stake_pool.precision_factor_rank,
+            user_data.reward_debt,
+        )?;
+        let pending = stake_pool.apply_whale_curve(pending, user_data.amount)?;
+        let pending = user_data.cap_claim(pending, stake_pool.max_reward_per_user)?
+            .checked_add(user_data.take_reward_owed())
+            .ok_or(StakingError::Overflow)?;
+        stake_pool.check_claim_amount(pending)?;
+        if pending > 0 && !user_data.can_claim(now) {
+            StakingError::RewardLocked.print::<StakingError>();
+            return Err(StakingError::RewardLocked.into());
+        }
+
+        transfer_tokens(
+            token_program_info,
+            pda_pool_token_account_reward_info,
+            mint_info,
+            pda_pool_token_account_staked_info,
+            pda_pool_token_account_authority_info,
+            &[sign_seeds_pda_pool_token_account_authority],
+            pending,
+            stake_pool.reward_decimals,
+        )?;
+
+        let effective_before = user_data.effective_balance();
+        let amount_before = user_data.amount;
+        user_data.accumulate_twab(now)?;
+        user_data.amount = user_data
+            .amount
+            .checked_add(pending)
+            .ok_or(StakingError::Overflow)?;
+        stake_pool.increase_total_supply(pending)?;
+        stake_pool.update_effective_total_supply(effective_before, user_data.effective_balance())?;
+        stake_pool.update_staker_count(amount_before, user_data.amount)?;
+
+        if pending > 0 {
+            user_data.set_reward_lock_finish(
+                now,
+                stake_pool.rewards_lock_duration,
+                LockPolicy::from_u8(stake_pool.lock_policy)?,
+            )?;
+        }
+        user_data.set_reward_debt(
+            get_reward_debt(
+                user_data.effective_balance(),
+                stake_pool.accrued_token_per_share,
+            )?
+        );
+
+        UserInfo::pack(user_data, &mut pda_user_state_info.data.borrow_mut())?;
+        StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    pub fn process_compound_into(
+        accounts: &[AccountInfo],
+        target_pool: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let owner_info = next_account_info(account_info_iter)?; // 0
+        let token_account_info = next_account_info(account_info_iter)?; // 1
+
+        let token_account = unpack_token_account(
+            &token_account_info.data.borrow(),
+        )?;
+
+        if token_account.owner != *owner_info.key {
+            return Err(TokenError::OwnerMismatch.into());
+        }
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mint_info = next_account_info(account_info_iter)?; // 2
+
+        let pda_stake_pool_info = next_account_info(account_info_iter)?; // 3
+        let pda_pool_token_account_authority_info = next_account_info(account_info_iter)?; // 4
+        let pda_pool_token_account_staked_info = next_account_info(account_info_iter)?; // 5
         let pda_pool_token_account_reward_info = next_account_info(account_info_iter)?; // 6
-        
-        validate_pool_token_account(
-            &pda_pool_token_account_reward_info,
+        let pda_user_state_info = next_account_info(account_info_iter)?; // 7
+
+        let pda_target_pool_info = next_account_info(account_info_iter)?; // 8
+        let pda_target_token_account_staked_info = next_account_info(account_info_iter)?; // 9
+        let pda_target_token_account_reward_info = next_account_info(account_info_iter)?; // 10
+        let pda_target_user_state_info = next_account_info(account_info_iter)?; // 11
+        let pda_wallet_for_create_user_info = next_account_info(account_info_iter)?; // 12
+
+        let rent_info = next_account_info(account_info_iter)?; // 13
+        let rent = &Rent::from_account_info(rent_info)?;
+
+        let clock_program_info = next_account_info(account_info_iter)?; // 14
+        let clock = &Clock::from_account_info(clock_program_info)?;
+
+        let system_program_info = next_account_info(account_info_iter)?; // 15
+        let token_program_info = next_account_info(account_info_iter)?; // 16
+        let allowlist_entry_info = next_account_info(account_info_iter).ok(); // 17
+
+        if *pda_target_pool_info.key != target_pool || target_pool == *pda_stake_pool_info.key {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let mut stake_pool = StakePool::from_account_info(pda_stake_pool_info)?;
+        stake_pool.validate()?;
+        validate_token_program(&stake_pool, token_program_info)?;
+
+        let mut target_stake_pool = StakePool::from_account_info(pda_target_pool_info)?;
+        target_stake_pool.validate()?;
+        validate_token_program(&target_stake_pool, token_program_info)?;
+        target_stake_pool.check_not_paused()?;
+        let target_pool_index = target_stake_pool.pool_index;
+
+        if stake_pool.reward_is_native != 0 {
+            return Err(StakingError::NativeRewardUnsupported.into());
+        }
+        // Both positions are keyed by one token-account, which also takes the target reward
+        if stake_pool.has_separate_reward_mint() || target_stake_pool.has_separate_reward_mint() {
+            StakingError::RewardMintUnsupported.print::<StakingError>();
+            return Err(StakingError::RewardMintUnsupported.into());
+        }
+        // Reward token of the source pool is the stake token of the target pool
+        if stake_pool.mint != target_stake_pool.mint || stake_pool.mint != *mint_info.key {
+            return Err(TokenError::MintMismatch.into());
+        }
+        // Amounts are copied as is, there is no scaling between decimals
+        if stake_pool.reward_decimals != target_stake_pool.reward_decimals {
+            return Err(StakingError::DecimalsMismatch.into());
+        }
+
+        // AllowlistEntry is only passed for pools with an allowlist mode
+        expect_accounts(accounts, 17 + target_stake_pool.uses_allowlist() as usize)?;
+        validate_staker(
+            &target_stake_pool,
+            pda_target_pool_info.key,
+            owner_info.key,
+            allowlist_entry_info,
         )?;
 
-        let mut stake_pool = StakePool::unpack(&pda_stake_pool_info.data.borrow_mut())
-            .expect("Failed to deserialie StakePool");
+        let (target_staked_pubkey, _bump_seed_target_staked) = staked_address(
+            &this_program_id(),
+            target_pool_index,
+        );
+        if target_staked_pubkey != *pda_target_token_account_staked_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
 
-        validate_stake_pool(
+        validate_pool_token_account(
+            pda_pool_token_account_staked_info,
+        )?;
+        // Both pending rewards are paid out of the reward vaults of their own pools
+        validate_reward_vault(
             &stake_pool,
-            pool_owner_info.key,
-            mint_info.key,
+            pda_pool_token_account_reward_info,
+        )?;
+        validate_reward_vault(
+            &target_stake_pool,
+            pda_target_token_account_reward_info,
+        )?;
+        validate_user_state(
+            pda_user_state_info,
+            pda_stake_pool_info,
+            token_account_info,
         )?;
 
-        let current_block = clock.slot;
+        if pda_target_user_state_info.data_is_empty() {
+            Self::create_user_info(
+                target_pool_index,
+                pda_target_pool_info,
+                token_account_info,
+                pda_wallet_for_create_user_info,
+                pda_target_user_state_info,
+                rent,
+                system_program_info,
+            )?;
+        }
+        validate_user_state(
+            pda_target_user_state_info,
+            pda_target_pool_info,
+            token_account_info,
+        )?;
 
-        assert!(stake_pool.end_block > current_block, "Pool already finished");
-        assert!(end_block > stake_pool.end_block, "Cannot shorten");
+        let (_, bump_seed_token_account_authority) = token_account_authority(&this_program_id());
+        let sign_seeds_pda_pool_token_account_authority: &[&[_]] = 
+            &[
+            ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(),
+            &[bump_seed_token_account_authority],
+            ];
 
-        let blocks_added = end_block - stake_pool.end_block;
+        // Settle the source pool
+        let mut user_data = UserInfo::from_account_info(pda_user_state_info)?;
+        user_data.check_not_frozen()?;
 
-        // TODO: add loop for reward tokens
-            let to_transfer = blocks_added * stake_pool.reward_per_block;
+        stake_pool.update_pool(
+            pda_pool_token_account_staked_info,
+            clock,
+        )?;
 
-            invoke(
-                &spl_token::instruction::transfer(
-                    &spl_token::id(),
-                    reward_token_account_info.key,
-                    pda_pool_token_account_reward_info.key,
-                    pool_owner_info.key,
-                    &[pool_owner_info.key],
-                    to_transfer,
-                )?,
-                &[
-                reward_token_account_info.clone(),
-                pda_pool_token_account_reward_info.clone(),
-                pool_owner_info.clone(),
-                token_program_info.clone(),
-                ],
+        let pending = get_pending(
+            user_data.effective_balance(),
+            stake_pool.accrued_token_per_share,
+            stake_pool.precision_factor_rank,
+            user_data.reward_debt,
+        )?;
+        let pending = stake_pool.apply_whale_curve(pending, user_data.amount)?;
+        let pending = user_data.cap_claim(pending, stake_pool.max_reward_per_user)?
+            .checked_add(user_data.take_reward_owed())
+            .ok_or(StakingError::Overflow)?;
+        stake_pool.check_claim_amount(pending)?;
+        let now = now_secs(clock)?;
+        if pending > 0 && !user_data.can_claim(now) {
+            StakingError::RewardLocked.print::<StakingError>();
+            return Err(StakingError::RewardLocked.into());
+        }
+
+        user_data.set_reward_debt(
+            get_reward_debt(
+                user_data.effective_balance(),
+                stake_pool.accrued_token_per_share,
+            )?
+        );
+
+        // Settle the target pool before its supply changes
+        let mut target_user_data = UserInfo::from_account_info(pda_target_user_state_info)?;
+        target_user_data.check_not_frozen()?;
+
+        target_stake_pool.start_on_first_stake(clock.slot)?;
+        target_stake_pool.update_pool(
+            pda_target_token_account_staked_info,
+            clock,
+        )?;
+
+        let target_pending = get_pending(
+            target_user_data.effective_balance(),
+            target_stake_pool.accrued_token_per_share,
+            target_stake_pool.precision_factor_rank,
+            target_user_data.reward_debt,
+        )?;
+        let target_pending = target_stake_pool.apply_whale_curve(target_pending, target_user_data.amount)?;
+        let mut target_pending = target_user_data.cap_claim(target_pending, target_stake_pool.max_reward_per_user)?;
+        // Like Withdraw, a target reward still under its lock stays with the target position
+        if !target_user_data.can_claim(now) {
+            target_user_data.owe_reward(target_pending)?;
+            target_pending = 0;
+        }
+
+        if pending > 0 {
+            transfer_tokens(
+                token_program_info,
+                pda_pool_token_account_reward_info,
+                mint_info,
+                pda_target_token_account_staked_info,
+                pda_pool_token_account_authority_info,
+                &[sign_seeds_pda_pool_token_account_authority],
+                pending,
+                stake_pool.reward_decimals,
+            )?;
+        }
+
+        if target_pending > 0 {
+            transfer_tokens(
+                token_program_info,
+                pda_target_token_account_reward_info,
+                mint_info,
+                token_account_info,
+                pda_pool_token_account_authority_info,
+                &[sign_seeds_pda_pool_token_account_authority],
+                target_pending,
+                target_stake_pool.reward_decimals,
             )?;
+        }
 
-        stake_pool.set_end_block(end_block);
+        let target_effective_before = target_user_data.effective_balance();
+        let target_amount_before = target_user_data.amount;
+        target_user_data.accumulate_twab(now)?;
+        target_user_data.amount = target_user_data
+            .amount
+            .checked_add(pending)
+            .ok_or(StakingError::Overflow)?;
+        target_stake_pool.increase_total_supply(pending)?;
+        target_stake_pool.update_effective_total_supply(
+            target_effective_before,
+            target_user_data.effective_balance(),
+        )?;
+        target_stake_pool.update_staker_count(target_amount_before, target_user_data.amount)?;
+        target_user_data.set_reward_lock_finish(
+            now,
+            target_stake_pool.rewards_lock_duration,
+            LockPolicy::from_u8(target_stake_pool.lock_policy)?,
+        )?;
+        target_user_data.set_reward_debt(
+            get_reward_debt(
+                target_user_data.effective_balance(),
+                target_stake_pool.accrued_token_per_share,
+            )?
+        );
 
-        //debug
-        msg!("StakePool after instruction is \n{:#?}", stake_pool); 
-        //
+        UserInfo::pack(user_data, &mut pda_user_state_info.data.borrow_mut())?;
+        UserInfo::pack(target_user_data, &mut pda_target_user_state_info.data.borrow_mut())?;
         StakePool::pack(stake_pool, &mut pda_stake_pool_info.data.borrow_mut())?;
+        StakePool::pack(target_stake_pool, &mut pda_target_pool_info.data.borrow_mut())?;
 
         Ok(())
     }
@@ -933,6 +4492,7 @@ impl Processor {
     pub fn process_create_master_and_authority( 
         accounts: &[AccountInfo],
     ) -> ProgramResult {
+        expect_accounts(accounts, 6 + cfg!(feature = "upgrade-authority") as usize)?;
         let account_info_iter = &mut accounts.iter();
 
         let payer_info = next_account_info(account_info_iter)?; // 0
@@ -950,10 +4510,20 @@ impl Processor {
 
         let system_program_info = next_account_info(account_info_iter)?; // 5
 
+        #[cfg(feature = "upgrade-authority")]
+        {
+            let program_data_info = next_account_info(account_info_iter)?; // 6
+            crate::utils::assert_upgrade_authority(
+                program_data_info,
+                payer_info,
+            )?;
+        }
+
+        let (_, bump_seed_token_account_authority) = token_account_authority(&this_program_id());
         let sign_seeds_pda_token_account_authority: &[&[_]] = 
             &[
             ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(),
-            &[BUMP_SEED_TOKEN_ACCOUNT_AUTHORITY],
+            &[bump_seed_token_account_authority],
             ];
 
         let instruction_create_token_account_authority = system_instruction::create_account(
@@ -972,10 +4542,11 @@ impl Processor {
 
         let minimum_balance_master = rent.minimum_balance(MASTER_STAKING_LEN);
 
+        let (_, bump_seed_master_staking) = master_staking_pda(&this_program_id());
         let sign_seeds_pda_master_staking: &[&[_]] = 
             &[
             ADD_SEED_MASTER_STAKING.as_bytes(),
-            &[BUMP_SEED_MASTER_STAKING],
+            &[bump_seed_master_staking],
             ];
         
         let instruction_create_master_staking = system_instruction::create_account(
@@ -0,0 +1,145 @@
+//! Accrual formulas of the pools, chosen by `StakePool::reward_model`
+
+use solana_program::program_error::ProgramError;
+use std::convert::TryFrom;
+
+use crate::{
+    error::StakingError,
+    state::{
+        StakePool,
+        RewardModelKind,
+        BPS_DENOMINATOR,
+    },
+    utils::simulate_update,
+};
+
+/// Raises `accrued_token_per_share` of a pool. New models only add an implementation
+/// and a `RewardModelKind`, user accounting on the share stays the same
+pub trait RewardModel {
+    /// `accrued_token_per_share` of `pool` after `elapsed_blocks` units of the model
+    /// (seconds for `Synthetix`) shared by `supply`
+    fn update(
+        &self,
+        pool: &StakePool,
+        elapsed_blocks: u128,
+        supply: u64,
+    ) -> Result<u128, ProgramError>;
+}
+
+/// Fixed `reward_per_block` funded by `reward_amount`, `elapsed_blocks` include the bonus multiplier
+pub struct BlockEmission;
+
+impl RewardModel for BlockEmission {
+    fn update(
+        &self,
+        pool: &StakePool,
+        elapsed_blocks: u128,
+        supply: u64,
+    ) -> Result<u128, ProgramError> {
+        simulate_update(
+            pool.accrued_token_per_share,
+            elapsed_blocks,
+            pool.reward_per_block,
+            supply,
+            pool.precision_factor_rank,
+        )
+    }
+}
+
+/// `simple_interest_bps` of the staked principal per block, so every staked token earns
+/// the same rate whatever the pool size. The reward token-account must then be kept funded,
+/// `reward_amount` doesn't bound it
+pub struct FixedApr;
+
+impl RewardModel for FixedApr {
+    fn update(
+        &self,
+        pool: &StakePool,
+        elapsed_blocks: u128,
+        supply: u64,
+    ) -> Result<u128, ProgramError> {
+        let reward_per_block = pool.total_supply as u128 * pool.simple_interest_bps as u128
+            / BPS_DENOMINATOR as u128;
+        let reward_per_block = u64::try_from(reward_per_block)
+            .map_err(|_| StakingError::Overflow)?;
+
+        simulate_update(
+            pool.accrued_token_per_share,
+            elapsed_blocks,
+            reward_per_block,
+            supply,
+            pool.precision_factor_rank,
+        )
+    }
+}
+
+/// StakingRewards of Synthetix: `reward_rate` per second until `period_finish`.
+/// `accrued_token_per_share` plays the role of `rewardPerTokenStored`
+pub struct Synthetix;
+
+impl RewardModel for Synthetix {
+    fn update(
+        &self,
+        pool: &StakePool,
+        elapsed_blocks: u128,
+        supply: u64,
+    ) -> Result<u128, ProgramError> {
+        simulate_update(
+            pool.accrued_token_per_share,
+            elapsed_blocks,
+            pool.reward_rate,
+            supply,
+            pool.precision_factor_rank,
+        )
+    }
+}
+
+impl RewardModelKind {
+    pub fn model(self) -> &'static dyn RewardModel {
+        match self {
+            RewardModelKind::BlockEmission => &BlockEmission,
+            RewardModelKind::FixedApr => &FixedApr,
+            RewardModelKind::Synthetix => &Synthetix,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::program_pack::Pack;
+
+    /// Pool where every model pays 1_000 per unit to 10_000 staked tokens
+    fn pool_of_1_000_per_unit() -> StakePool {
+        let mut pool = StakePool::unpack_from_slice(&[0; StakePool::LEN]).unwrap();
+        pool.precision_factor_rank = 12;
+        pool.accrued_token_per_share = 5;
+        pool.reward_per_block = 1_000;
+        pool.reward_rate = 1_000;
+        pool.simple_interest_bps = 1_000;
+        pool.total_supply = 10_000;
+        pool
+    }
+
+    #[test]
+    fn models_agree_on_the_same_emission() {
+        let pool = pool_of_1_000_per_unit();
+
+        for kind in [RewardModelKind::BlockEmission, RewardModelKind::FixedApr, RewardModelKind::Synthetix] {
+            // 10 units of 1_000 over 10_000 tokens at a precision factor of 10^12
+            assert_eq!(kind.model().update(&pool, 10, 10_000), Ok(5 + 1_000_000_000_000), "{:?}", kind);
+            assert_eq!(kind.model().update(&pool, 0, 10_000), Ok(5), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn models_read_their_own_rate() {
+        let mut pool = pool_of_1_000_per_unit();
+        pool.reward_per_block = 2_000;
+        pool.total_supply = 20_000;
+
+        assert_eq!(BlockEmission.update(&pool, 10, 10_000), Ok(5 + 2_000_000_000_000));
+        assert_eq!(FixedApr.update(&pool, 10, 10_000), Ok(5 + 2_000_000_000_000));
+        assert_eq!(Synthetix.update(&pool, 10, 10_000), Ok(5 + 1_000_000_000_000));
+    }
+}
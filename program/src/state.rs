@@ -1,5 +1,6 @@
 //! State Staking types
 
+use std::convert::TryFrom;
 use solana_program::{
    program_pack::{
       IsInitialized,
@@ -18,7 +19,6 @@ use solana_program::{
    msg,
 };
 use derivative::*;
-use spl_token::state::Account as TokenAccount;
 use arrayref::{
    array_refs,
    array_ref,
@@ -31,10 +31,50 @@ use borsh::{
    BorshSchema,
 };
 use crate::error::StakingError;
-use crate::utils::get_precision_factor;
+use crate::utils::{
+   get_precision_factor,
+   get_pending,
+   get_reward_debt,
+   now_secs,
+   linear_penalty,
+   compute_apr,
+   add,
+   sub,
+   mul,
+   state_pool_address,
+};
 
 pub const MASTER_STAKING_LEN: usize = 8;
 
+/// Denominator for all basis-point values
+pub const BPS_DENOMINATOR: u64 = 10_000;
+/// Upper bound for `StakePool::withdraw_fee_bps` (10%)
+pub const MAX_WITHDRAW_FEE_BPS: u16 = 1_000;
+/// Upper bound for `UserInfo::boost_bps` (3x)
+pub const MAX_BOOST_BPS: u16 = 30_000;
+/// Blocks after `end_block` before a pool without stake can be rearmed by `ResetPool`,
+/// leaving time for late claims (about a day of slots)
+pub const POOL_RESET_GRACE_BLOCKS: u64 = 216_000;
+/// Slots between two SetUserRewardDebt corrections of a position, about a day
+pub const REWARD_DEBT_CORRECTION_INTERVAL: u64 = 216_000;
+/// Longest `start_block..end_block` of a campaign, about a year of slots. Keeps the products
+/// of the window length with rates and multipliers far from overflowing
+pub const MAX_CAMPAIGN_BLOCKS: u64 = 63_072_000;
+/// Largest precision factor rank of a pool, `get_precision_factor` rejects the ranks above it
+pub const MAX_PRECISION_FACTOR_RANK: u8 = 12;
+/// Largest rank Initialize derived from the mint decimals before they were capped at
+/// `MAX_PRECISION_FACTOR_RANK`, 10^19 is the largest power of ten in a u64.
+/// MigratePrecisionFactor takes the pools of such ranks down to the maximum
+pub const PRECISION_FACTOR_RANK_LIMIT: u8 = 19;
+/// Compute budget bounds how many pools `CreatePools` initializes at once
+pub const MAX_POOLS_PER_BATCH: u8 = 4;
+/// Compute budget bounds how many pools `HarvestMany` claims from at once
+pub const MAX_HARVESTS_PER_BATCH: u8 = 4;
+/// Slots in a year at about 400ms a slot, for `StakePool::get_apr_bps`
+pub const SLOTS_PER_YEAR: u64 = 78_840_000;
+/// Seconds in a year of 365 days, for Synthetix pools paying `reward_rate` per second
+pub const SECONDS_PER_YEAR: u64 = 31_536_000;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, BorshSchema, BorshSerialize, BorshDeserialize)]
 pub struct MasterStaking {
@@ -88,6 +128,43 @@ pub struct StakePool {
    pub reward_amount: u64,
    pub reward_per_block: u64,
    pub accrued_token_per_share: u128, 
+   pub reward_decimals: u8,
+   pub withdraw_fee_bps: u16,
+   pub whale_threshold: u64,
+   pub whale_reward_bps: u16,
+   pub reward_starts_on_first_stake: u8,
+   pub min_claim_amount: u64,
+   pub max_reward_per_user: u64,
+   pub total_supply: u64,
+   pub max_total_supply: u64,
+   pub rewards_lock_duration: u64,
+   pub lock_policy: u8,
+   pub allowlist_mode: u8,
+   pub referral_fee_bps: u16,
+   pub staked_vault: Pubkey,
+   pub reward_is_native: u8,
+   pub rate_controller: COption<Pubkey>,
+   pub effective_total_supply: u64,
+   pub allow_zero_rate: u8,
+   pub partial_claim_on_shortfall: u8,
+   pub reward_model: u8,
+   pub simple_interest_bps: u16,
+   pub rewards_duration: u64,
+   pub reward_rate: u64,
+   pub period_finish: u64,
+   pub last_update_time: u64,
+   pub unstake_cooldown: u64,
+   pub is_paused: u8,
+   pub pending_owner: COption<Pubkey>,
+   pub min_stake: u64,
+   pub reward_mint: Pubkey,
+   pub fee_recipient: COption<Pubkey>,
+   pub early_withdraw_penalty_bps: u16,
+   pub staker_count: u64,
+   pub token_program: Pubkey,
+   /// Staked tokens of RequestUnstake not yet paid by CompleteUnstake or EmergencyWithdraw,
+   /// they stay in the staked vault outside `total_supply`
+   pub pending_unstake_supply: u64,
 }
  
 impl Sealed for StakePool {}
@@ -96,10 +173,17 @@ impl IsInitialized for StakePool {
       self.is_initialized != 0
    }
 }
+/// Bytes of StakePool used by fields. `array_refs!` fails to compile if the field sizes
+/// don't add up to it. The rest of `StakePool::LEN` is reserved for new fields and kept zeroed
+pub const STAKE_POOL_PACKED_LEN: usize = 502;
+/// Size of StakePool before the reserved space. There is no migration, such pools are refused
+/// with `LegacyAccountLayout` and have to be closed with the program version that created them
+pub const LEGACY_STAKE_POOL_LEN: usize = 321;
+
 impl Pack for StakePool {
-   const LEN: usize = 321;
+   const LEN: usize = 512;
    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-      let src = array_ref![src, 0, 159];
+      let src = array_ref![src, 0, STAKE_POOL_PACKED_LEN];
       let (
          pool_index,
          owner, 
@@ -115,7 +199,42 @@ impl Pack for StakePool {
          reward_amount,
          reward_per_block,
          accrued_token_per_share,
-      ) = array_refs![src, 8, 32, 32, 1, 1, 5, 12, 12, 8, 8, 8, 8, 8, 16];
+         reward_decimals,
+         withdraw_fee_bps,
+         whale_threshold,
+         whale_reward_bps,
+         reward_starts_on_first_stake,
+         min_claim_amount,
+         max_reward_per_user,
+         total_supply,
+         max_total_supply,
+         rewards_lock_duration,
+         lock_policy,
+         allowlist_mode,
+         referral_fee_bps,
+         staked_vault,
+         reward_is_native,
+         rate_controller,
+         effective_total_supply,
+         allow_zero_rate,
+         partial_claim_on_shortfall,
+         reward_model,
+         simple_interest_bps,
+         rewards_duration,
+         reward_rate,
+         period_finish,
+         last_update_time,
+         unstake_cooldown,
+         is_paused,
+         pending_owner,
+         min_stake,
+         reward_mint,
+         fee_recipient,
+         early_withdraw_penalty_bps,
+         staker_count,
+         token_program,
+         pending_unstake_supply,
+      ) = array_refs![src, 8, 32, 32, 1, 1, 5, 12, 12, 8, 8, 8, 8, 8, 16, 1, 2, 8, 2, 1, 8, 8, 8, 8, 8, 1, 1, 2, 32, 1, 36, 8, 1, 1, 1, 2, 8, 8, 8, 8, 8, 1, 36, 8, 32, 36, 2, 8, 32, 8];
       Ok(StakePool {
          pool_index: u64::from_le_bytes(*pool_index),
          owner: Pubkey::new_from_array(*owner),
@@ -131,10 +250,48 @@ impl Pack for StakePool {
          reward_amount: u64::from_le_bytes(*reward_amount),
          reward_per_block: u64::from_le_bytes(*reward_per_block),
          accrued_token_per_share: u128::from_le_bytes(*accrued_token_per_share), 
+         reward_decimals: u8::from_le_bytes(*reward_decimals),
+         withdraw_fee_bps: u16::from_le_bytes(*withdraw_fee_bps),
+         whale_threshold: u64::from_le_bytes(*whale_threshold),
+         whale_reward_bps: u16::from_le_bytes(*whale_reward_bps),
+         reward_starts_on_first_stake: u8::from_le_bytes(*reward_starts_on_first_stake),
+         min_claim_amount: u64::from_le_bytes(*min_claim_amount),
+         max_reward_per_user: u64::from_le_bytes(*max_reward_per_user),
+         total_supply: u64::from_le_bytes(*total_supply),
+         max_total_supply: u64::from_le_bytes(*max_total_supply),
+         rewards_lock_duration: u64::from_le_bytes(*rewards_lock_duration),
+         lock_policy: u8::from_le_bytes(*lock_policy),
+         allowlist_mode: u8::from_le_bytes(*allowlist_mode),
+         referral_fee_bps: u16::from_le_bytes(*referral_fee_bps),
+         staked_vault: Pubkey::new_from_array(*staked_vault),
+         reward_is_native: u8::from_le_bytes(*reward_is_native),
+         rate_controller: unpack_coption_key(rate_controller)?,
+         effective_total_supply: u64::from_le_bytes(*effective_total_supply),
+         allow_zero_rate: u8::from_le_bytes(*allow_zero_rate),
+         partial_claim_on_shortfall: u8::from_le_bytes(*partial_claim_on_shortfall),
+         reward_model: u8::from_le_bytes(*reward_model),
+         simple_interest_bps: u16::from_le_bytes(*simple_interest_bps),
+         rewards_duration: u64::from_le_bytes(*rewards_duration),
+         reward_rate: u64::from_le_bytes(*reward_rate),
+         period_finish: u64::from_le_bytes(*period_finish),
+         last_update_time: u64::from_le_bytes(*last_update_time),
+         unstake_cooldown: u64::from_le_bytes(*unstake_cooldown),
+         is_paused: u8::from_le_bytes(*is_paused),
+         pending_owner: unpack_coption_key(pending_owner)?,
+         min_stake: u64::from_le_bytes(*min_stake),
+         reward_mint: Pubkey::new_from_array(*reward_mint),
+         fee_recipient: unpack_coption_key(fee_recipient)?,
+         early_withdraw_penalty_bps: u16::from_le_bytes(*early_withdraw_penalty_bps),
+         staker_count: u64::from_le_bytes(*staker_count),
+         token_program: Pubkey::new_from_array(*token_program),
+         pending_unstake_supply: u64::from_le_bytes(*pending_unstake_supply),
       })
    }
+   // Layout must match unpack_from_slice field for field, `total_supply` included.
+   // The `state_pack_unpack` fuzz target round-trips every field
    fn pack_into_slice(&self, dst: &mut [u8]) {
-       let dst = array_mut_ref![dst, 0, 159];
+       dst[STAKE_POOL_PACKED_LEN..Self::LEN].fill(0);
+       let dst = array_mut_ref![dst, 0, STAKE_POOL_PACKED_LEN];
        let (
          pool_index_dst,
          owner_dst, 
@@ -150,7 +307,42 @@ impl Pack for StakePool {
          reward_amount_dst,
          reward_per_block_dst,
          accrued_token_per_share_dst,
-      ) = mut_array_refs![dst, 8, 32, 32, 1, 1, 5, 12, 12, 8, 8, 8, 8, 8, 16];
+         reward_decimals_dst,
+         withdraw_fee_bps_dst,
+         whale_threshold_dst,
+         whale_reward_bps_dst,
+         reward_starts_on_first_stake_dst,
+         min_claim_amount_dst,
+         max_reward_per_user_dst,
+         total_supply_dst,
+         max_total_supply_dst,
+         rewards_lock_duration_dst,
+         lock_policy_dst,
+         allowlist_mode_dst,
+         referral_fee_bps_dst,
+         staked_vault_dst,
+         reward_is_native_dst,
+         rate_controller_dst,
+         effective_total_supply_dst,
+         allow_zero_rate_dst,
+         partial_claim_on_shortfall_dst,
+         reward_model_dst,
+         simple_interest_bps_dst,
+         rewards_duration_dst,
+         reward_rate_dst,
+         period_finish_dst,
+         last_update_time_dst,
+         unstake_cooldown_dst,
+         is_paused_dst,
+         pending_owner_dst,
+         min_stake_dst,
+         reward_mint_dst,
+         fee_recipient_dst,
+         early_withdraw_penalty_bps_dst,
+         staker_count_dst,
+         token_program_dst,
+         pending_unstake_supply_dst,
+      ) = mut_array_refs![dst, 8, 32, 32, 1, 1, 5, 12, 12, 8, 8, 8, 8, 8, 16, 1, 2, 8, 2, 1, 8, 8, 8, 8, 8, 1, 1, 2, 32, 1, 36, 8, 1, 1, 1, 2, 8, 8, 8, 8, 8, 1, 36, 8, 32, 36, 2, 8, 32, 8];
       let &StakePool {
          pool_index,
          ref owner,
@@ -166,6 +358,41 @@ impl Pack for StakePool {
          reward_amount,
          reward_per_block,
          accrued_token_per_share,
+         reward_decimals,
+         withdraw_fee_bps,
+         whale_threshold,
+         whale_reward_bps,
+         reward_starts_on_first_stake,
+         min_claim_amount,
+         max_reward_per_user,
+         total_supply,
+         max_total_supply,
+         rewards_lock_duration,
+         lock_policy,
+         allowlist_mode,
+         referral_fee_bps,
+         ref staked_vault,
+         reward_is_native,
+         ref rate_controller,
+         effective_total_supply,
+         allow_zero_rate,
+         partial_claim_on_shortfall,
+         reward_model,
+         simple_interest_bps,
+         rewards_duration,
+         reward_rate,
+         period_finish,
+         last_update_time,
+         unstake_cooldown,
+         is_paused,
+         ref pending_owner,
+         min_stake,
+         ref reward_mint,
+         ref fee_recipient,
+         early_withdraw_penalty_bps,
+         staker_count,
+         ref token_program,
+         pending_unstake_supply,
       } = self;
       *pool_index_dst = pool_index.to_le_bytes();
       owner_dst.copy_from_slice(owner.as_ref());
@@ -181,61 +408,258 @@ impl Pack for StakePool {
       *reward_amount_dst = reward_amount.to_le_bytes();
       *reward_per_block_dst = reward_per_block.to_le_bytes();
       *accrued_token_per_share_dst = accrued_token_per_share.to_le_bytes();
+      *reward_decimals_dst = reward_decimals.to_le_bytes();
+      *withdraw_fee_bps_dst = withdraw_fee_bps.to_le_bytes();
+      *whale_threshold_dst = whale_threshold.to_le_bytes();
+      *whale_reward_bps_dst = whale_reward_bps.to_le_bytes();
+      *reward_starts_on_first_stake_dst = reward_starts_on_first_stake.to_le_bytes();
+      *min_claim_amount_dst = min_claim_amount.to_le_bytes();
+      *max_reward_per_user_dst = max_reward_per_user.to_le_bytes();
+      *total_supply_dst = total_supply.to_le_bytes();
+      *max_total_supply_dst = max_total_supply.to_le_bytes();
+      *rewards_lock_duration_dst = rewards_lock_duration.to_le_bytes();
+      *lock_policy_dst = lock_policy.to_le_bytes();
+      *allowlist_mode_dst = allowlist_mode.to_le_bytes();
+      *referral_fee_bps_dst = referral_fee_bps.to_le_bytes();
+      staked_vault_dst.copy_from_slice(staked_vault.as_ref());
+      *reward_is_native_dst = reward_is_native.to_le_bytes();
+      pack_coption_key(rate_controller, rate_controller_dst);
+      *effective_total_supply_dst = effective_total_supply.to_le_bytes();
+      *allow_zero_rate_dst = allow_zero_rate.to_le_bytes();
+      *partial_claim_on_shortfall_dst = partial_claim_on_shortfall.to_le_bytes();
+      *reward_model_dst = reward_model.to_le_bytes();
+      *simple_interest_bps_dst = simple_interest_bps.to_le_bytes();
+      *rewards_duration_dst = rewards_duration.to_le_bytes();
+      *reward_rate_dst = reward_rate.to_le_bytes();
+      *period_finish_dst = period_finish.to_le_bytes();
+      *last_update_time_dst = last_update_time.to_le_bytes();
+      *unstake_cooldown_dst = unstake_cooldown.to_le_bytes();
+      *is_paused_dst = is_paused.to_le_bytes();
+      pack_coption_key(pending_owner, pending_owner_dst);
+      *min_stake_dst = min_stake.to_le_bytes();
+      reward_mint_dst.copy_from_slice(reward_mint.as_ref());
+      pack_coption_key(fee_recipient, fee_recipient_dst);
+      *early_withdraw_penalty_bps_dst = early_withdraw_penalty_bps.to_le_bytes();
+      *staker_count_dst = staker_count.to_le_bytes();
+      token_program_dst.copy_from_slice(token_program.as_ref());
+      *pending_unstake_supply_dst = pending_unstake_supply.to_le_bytes();
    }
 }
 
 impl StakePool {
+   /// The account must be the StakePool PDA of its `pool_index`, owned by this program
+   pub fn from_account_info(
+      a: &AccountInfo
+   ) -> Result<StakePool, ProgramError> {
+      if *a.owner != crate::id() {
+         return Err(ProgramError::IncorrectProgramId);
+      }
+      let data = a.data.borrow();
+      if data.len() == LEGACY_STAKE_POOL_LEN {
+         StakingError::LegacyAccountLayout.print::<StakingError>();
+         return Err(StakingError::LegacyAccountLayout.into());
+      }
+
+      let stake_pool = StakePool::unpack(&data)?;
+      if *a.key != state_pool_address(&crate::id(), stake_pool.pool_index).0 {
+         return Err(ProgramError::InvalidSeeds);
+      }
+
+      Ok(stake_pool)
+   }
+
+   /// Checks the invariants of the pool parameters
+   pub fn validate(
+      &self,
+   ) -> ProgramResult {
+      if self.start_block > self.end_block {
+         StakingError::InvalidBlockRange.print::<StakingError>();
+         return Err(StakingError::InvalidBlockRange.into());
+      }
+      if self.last_reward_block > self.end_block {
+         StakingError::LastRewardBlockAfterEnd.print::<StakingError>();
+         return Err(StakingError::LastRewardBlockAfterEnd.into());
+      }
+      if let (COption::Some(bonus_start), COption::Some(bonus_end)) = (self.bonus_start_block, self.bonus_end_block) {
+         if bonus_start > bonus_end || bonus_end > self.end_block {
+            StakingError::InvalidBonusWindow.print::<StakingError>();
+            return Err(StakingError::InvalidBonusWindow.into());
+         }
+      }
+      get_precision_factor(self.precision_factor_rank)
+         .map_err(|_| StakingError::InvalidPrecisionFactor)?;
+
+      // Blocks already accrued may have run at another rate, only the rest of the window
+      // is priced at the current one
+      let emission_blocks = self.end_block - self.last_reward_block.max(self.start_block);
+      let emission = (self.reward_per_block as u128)
+         .checked_mul(emission_blocks as u128)
+         .ok_or(StakingError::Overflow)?;
+      if emission > self.reward_amount as u128 {
+         StakingError::InsufficientRewardAmount.print::<StakingError>();
+         return Err(StakingError::InsufficientRewardAmount.into());
+      }
+
+      Ok(())
+   }
+
+   /// Rejects campaigns longer than `MAX_CAMPAIGN_BLOCKS`, checked when the window is set or extended
+   pub fn check_campaign_window(
+      &self,
+   ) -> ProgramResult {
+      if self.end_block.saturating_sub(self.start_block) > MAX_CAMPAIGN_BLOCKS {
+         StakingError::CampaignWindowTooLong.print::<StakingError>();
+         return Err(StakingError::CampaignWindowTooLong.into());
+      }
+
+      Ok(())
+   }
+
+   /// Rearms a finished pool without stake for a new campaign, keeping its index and accounts.
+   /// Accruals start over, UserInfo of former stakers hold no amount so their debt stays valid
+   pub fn reset(
+      &mut self,
+      current_block: u64,
+      start_block: u64,
+      end_block: u64,
+      reward_per_block: u64,
+      reward_amount: u64,
+   ) -> ProgramResult {
+      let grace_end = self.end_block
+         .checked_add(POOL_RESET_GRACE_BLOCKS)
+         .ok_or(StakingError::Overflow)?;
+      if self.total_supply != 0 || current_block <= grace_end {
+         StakingError::PoolNotResettable.print::<StakingError>();
+         return Err(StakingError::PoolNotResettable.into());
+      }
+      if start_block < current_block {
+         StakingError::InvalidBlockRange.print::<StakingError>();
+         return Err(StakingError::InvalidBlockRange.into());
+      }
+
+      self.start_block = start_block;
+      self.end_block = end_block;
+      self.check_campaign_window()?;
+      self.reward_per_block = reward_per_block;
+      self.reward_amount = reward_amount;
+      self.set_last_reward_block(start_block);
+      self.accrued_token_per_share = 0;
+      self.bonus_multiplier = COption::Some(1);
+      self.bonus_start_block = COption::None;
+      self.bonus_end_block = COption::None;
+      self.reward_starts_on_first_stake = 0;
+
+      self.check_reward_rate()?;
+      self.validate()
+   }
+
+   /// Accrue reward up to the current block, or the current time for `RewardModelKind::Synthetix`.
+   /// Staked supply is `effective_total_supply`
    pub fn update_pool(
       &mut self,
-      pda_pool_token_account_staked: &TokenAccount,
+      pda_pool_token_account_staked_info: &AccountInfo,
       clock: &Clock, 
    ) -> ProgramResult {
+      // Accrual uses `effective_total_supply`, the vault is still checked so callers can't mix up pools
+      if *pda_pool_token_account_staked_info.key != self.staked_vault {
+         StakingError::StakedVaultMismatch.print::<StakingError>();
+         return Err(StakingError::StakedVaultMismatch.into());
+      }
+
+      self.accrue(clock)
+   }
+
+   /// Reward `user` would be paid at `clock` by a claim, as `update_pool` followed by the
+   /// pending, whale curve and per-user cap of Harvest, with `staked_supply` as the staked supply.
+   /// Nothing is changed, for clients and read-only instructions
+   pub fn pending_reward(
+      &self,
+      user: &UserInfo,
+      staked_supply: u64,
+      clock: &Clock,
+   ) -> Result<u64, ProgramError> {
+      let mut pool = *self;
+      pool.effective_total_supply = staked_supply;
+      pool.accrue(clock)?;
+
+      let pending = get_pending(
+         user.effective_balance(),
+         pool.accrued_token_per_share,
+         pool.precision_factor_rank,
+         user.reward_debt,
+      )?;
+      let pending = pool.apply_whale_curve(pending, user.amount)?;
+
+      let mut user = *user;
+      user.cap_claim(pending, pool.max_reward_per_user)
+   }
+
+   /// Accrual of `update_pool` once the staked vault is checked
+   fn accrue(
+      &mut self,
+      clock: &Clock,
+   ) -> ProgramResult {
+      let reward_model = RewardModelKind::from_u8(self.reward_model)?;
+      if reward_model == RewardModelKind::Synthetix {
+         return self.update_reward(clock, None);
+      }
+
       let current_block = clock.slot;
-      if current_block <= self.last_reward_block {
+      // Before the start `reset()` leaves the last reward block at `start_block`, ahead of the
+      // clock, and get_multiplier counts no block before it
+      if current_block < self.last_reward_block && self.last_reward_block <= self.start_block {
+         return Ok(());
+      }
+      // Past the start, a last reward block ahead of the clock can only come from corrupted state.
+      // Returning early would skip accrual until the clock catches up, so it is clamped instead
+      if current_block < self.last_reward_block {
+         msg!(
+            "last_reward_block {} is ahead of current block {}, clamping",
+            self.last_reward_block,
+            current_block,
+         );
+         self.set_last_reward_block(current_block);
+
+         return Ok(());
+      }
+      if current_block == self.last_reward_block {
          return Ok(());
       }
 
-      let staked_token_supply = pda_pool_token_account_staked.amount;
+      // Boosted stakers weigh more than their tokens
+      let staked_token_supply = self.effective_total_supply;
 
       if staked_token_supply == 0 { 
-         self.set_last_reward_block(current_block);
+         self.set_last_reward_block(current_block.min(self.end_block));
    
          return Ok(());
       }
 
-      let multiplier = self.get_multiplier(self.last_reward_block, current_block);
-
-      let reward = multiplier
-         .checked_mul(self.reward_per_block)
-         .ok_or(StakingError::RewardOverflow)?;
-
-      let precision_factor = get_precision_factor(
-         self.precision_factor_rank,
+      // get_multiplier clamps the blocks to the reward window, so even after a long idle gap
+      // the multiplier can't exceed the whole window at the bonus rate
+      let max_multiplier = mul(
+         self.end_block.saturating_sub(self.start_block) as u128,
+         self.bonus_multiplier.unwrap_or(1).max(1) as u128,
       )?;
+      let multiplier = self.get_multiplier(self.last_reward_block, current_block)?
+         .min(max_multiplier);
 
-      self.accrued_token_per_share = self
-         .accrued_token_per_share
-         .checked_add(
-            (reward as u128)
-            .checked_mul(precision_factor as u128)
-            .ok_or(StakingError::RewardMulPrecisionOverflow)?
-            .checked_div(staked_token_supply as u128)
-            .ok_or(StakingError::RewardMulPrecisionDivSupplyOverflow)?)
-         .ok_or(StakingError::AccuredTokenPerShareOverflow)?;
+      self.accrued_token_per_share = reward_model.model().update(
+         self,
+         multiplier,
+         staked_token_supply,
+      )?;
 
-      //debug
+      #[cfg(feature = "debug-logs")]
       msg!(
-         "multiplier: {}\n
-         reward: {}\n
-         staked_token_supply: {}\n,
-         accrued_toked: {}\n",
+         "multiplier: {}, effective_total_supply: {}, accrued_token_per_share: {}",
          multiplier,
-         reward,
          staked_token_supply,
          self.accrued_token_per_share,
       );
-      //
 
+      // Clamped to `end_block`, so claims after the campaign accrue nothing more
+      // but still pay out what accrued before
       if self.end_block > current_block {
          self.set_last_reward_block(current_block);
       } 
@@ -254,141 +678,2377 @@ impl StakePool {
       Ok(())
    }
 
-   fn get_multiplier(
+   /// Phase of the reward campaign at `current_block`. `end_block` itself is already `Ended`,
+   /// as no reward accrues after it
+   pub fn state(
       &self,
-      mut from: u64,
-      mut to: u64,
-   ) -> u64 {
-      if from < self.start_block {
-         from = self.start_block;
+      current_block: u64,
+   ) -> CampaignState {
+      if current_block < self.start_block {
+         return CampaignState::NotStarted;
       }
-      if self.end_block < to {
-         to = self.end_block;
+      if current_block >= self.end_block {
+         return CampaignState::Ended;
       }
 
-      let multiplier: u64 = self.bonus_multiplier.unwrap().into();
-      let start = match self.bonus_start_block {
-         COption::Some(v) => v,
-         COption::None => 0,
-      };
-      let end = match self.bonus_end_block {
-         COption::Some(v) => v,
-         COption::None => 0,
+      let bonus_active = match (self.bonus_start_block, self.bonus_end_block) {
+         (COption::Some(bonus_start), COption::Some(bonus_end)) => {
+            self.bonus_multiplier.unwrap_or(1) > 1 &&
+            bonus_start <= current_block &&
+            current_block < bonus_end
+         },
+         _ => false,
       };
-
-      if from < start && to > end {
-         return start - from + to - end + (end - start) * multiplier; 
+      if bonus_active {
+         CampaignState::BonusActive
+      } else {
+         CampaignState::Active
       }
-      else if from < start && to > start {
-         return start - from + (to - start) * multiplier;
+   }
+
+   /// Blocks from `from` to `to` inside the reward window, the bonus window counted
+   /// `bonus_multiplier` times. u128 so a long bonus window with a high multiplier can't overflow.
+   /// Nothing when `to` isn't after `from` once both are clamped, e.g. both before `start_block`
+   fn get_multiplier(
+      &self,
+      from: u64,
+      to: u64,
+   ) -> Result<u128, ProgramError> {
+      let from = from.max(self.start_block) as u128;
+      let to = to.min(self.end_block) as u128;
+      if to <= from {
+         return Ok(0);
       }
-      else if from < end && to > end {
-         return to - end + (end - from) * multiplier;
+
+      let multiplier: u128 = self.bonus_multiplier.unwrap_or(1).into();
+      let (start, end) = match (self.bonus_start_block, self.bonus_end_block) {
+         (COption::Some(start), COption::Some(end)) => (start as u128, end as u128),
+         _ => return Ok(to - from),
+      };
+      // validate() rejects it, but an inverted window must not wrap around here
+      if start > end {
+         StakingError::RewardOverflow.print::<StakingError>();
+         return Err(StakingError::RewardOverflow.into());
       }
-      else if from >= start && to <= end {
-         return (to - from) * multiplier;
+
+      // Blocks of `from..to` inside the bonus window, and the rest
+      let bonus_blocks = to.min(end).saturating_sub(from.max(start));
+      let plain_blocks = sub(to - from, bonus_blocks)?;
+
+      add(mul(bonus_blocks, multiplier)?, plain_blocks)
+   }
+
+   /// Fee charged on a withdrawal of `amount` staked tokens
+   pub fn get_withdraw_fee(
+      &self,
+      amount: u64,
+   ) -> Result<u64, ProgramError> {
+      let fee = (amount as u128)
+         .checked_mul(self.withdraw_fee_bps as u128)
+         .ok_or(StakingError::Overflow)?
+         .checked_div(BPS_DENOMINATOR as u128)
+         .ok_or(StakingError::Overflow)?;
+
+      Ok(fee as u64)
+   }
+
+   /// Part of `amount` kept when a position still under its reward lock is withdrawn at `now`,
+   /// `early_withdraw_penalty_bps` at the stake and falling linearly to zero at `reward_lock_finish`.
+   /// The stake is taken to be `rewards_lock_duration` before the lock end
+   pub fn get_early_withdraw_penalty(
+      &self,
+      amount: u64,
+      reward_lock_finish: u64,
+      now: u64,
+   ) -> Result<u64, ProgramError> {
+      let penalty_bps = linear_penalty(
+         now,
+         reward_lock_finish.saturating_sub(self.rewards_lock_duration),
+         reward_lock_finish,
+         self.early_withdraw_penalty_bps,
+      )?;
+      let penalty = (amount as u128)
+         .checked_mul(penalty_bps as u128)
+         .ok_or(StakingError::Overflow)?
+         .checked_div(BPS_DENOMINATOR as u128)
+         .ok_or(StakingError::Overflow)?;
+
+      Ok(penalty as u64)
+   }
+
+   /// Withdraw fee and early withdraw penalty of a withdrawal of `amount` at `now`, never above `amount`
+   pub fn get_exit_fee(
+      &self,
+      amount: u64,
+      reward_lock_finish: u64,
+      now: u64,
+   ) -> Result<u64, ProgramError> {
+      let penalty = self.get_early_withdraw_penalty(amount, reward_lock_finish, now)?;
+      if penalty > 0 {
+         msg!("Early withdraw penalty: {}", penalty);
       }
-      else {
-         return to - from;
+
+      Ok(self.get_withdraw_fee(amount)?.saturating_add(penalty).min(amount))
+   }
+
+   pub fn set_early_withdraw_penalty_bps(
+      &mut self,
+      early_withdraw_penalty_bps: u16,
+   ) -> ProgramResult {
+      if early_withdraw_penalty_bps as u64 > BPS_DENOMINATOR {
+         StakingError::PenaltyTooHigh.print::<StakingError>();
+         return Err(StakingError::PenaltyTooHigh.into());
       }
+      self.early_withdraw_penalty_bps = early_withdraw_penalty_bps;
+
+      self.check_withdraw_fee_destination()
    }
 
-   fn set_last_reward_block(
+   /// Shares `fee` between the stakers left in the pool by raising
+   /// `accrued_token_per_share`. The fee tokens must already be in the reward token-account.
+   /// If nobody is left the fee simply stays in the reward token-account.
+   /// A fee paid to `fee_recipient` isn't shared
+   pub fn distribute_withdraw_fee(
       &mut self,
-      block: u64,
-   ) {
-      self.last_reward_block = block;
+      fee: u64,
+      remaining_staked_supply: u64,
+   ) -> ProgramResult {
+      if fee == 0 || remaining_staked_supply == 0 || self.fee_recipient.is_some() {
+         return Ok(());
+      }
+
+      let precision_factor = get_precision_factor(
+         self.precision_factor_rank,
+      )?;
+
+      self.accrued_token_per_share = self
+         .accrued_token_per_share
+         .checked_add(
+            (fee as u128)
+            .checked_mul(precision_factor as u128)
+            .ok_or(StakingError::RewardMulPrecisionOverflow)?
+            .checked_div(remaining_staked_supply as u128)
+            .ok_or(StakingError::RewardMulPrecisionDivSupplyOverflow)?)
+         .ok_or(StakingError::AccuredTokenPerShareOverflow)?;
+
+      Ok(())
    }
 
-   pub fn set_end_block(
+   /// Without `fee_recipient` the withdraw fee is paid into the reward token-account, which
+   /// only takes stake tokens when the pool pays its reward in them
+   pub fn set_withdraw_fee_bps(
       &mut self,
-      block: u64,
-   ) {
-      self.end_block = block;
+      withdraw_fee_bps: u16,
+   ) -> ProgramResult {
+      if withdraw_fee_bps as u64 > BPS_DENOMINATOR {
+         StakingError::InvalidFeeBps.print::<StakingError>();
+         return Err(StakingError::InvalidFeeBps.into());
+      }
+      if withdraw_fee_bps > MAX_WITHDRAW_FEE_BPS {
+         StakingError::WithdrawFeeTooHigh.print::<StakingError>();
+         return Err(StakingError::WithdrawFeeTooHigh.into());
+      }
+      self.withdraw_fee_bps = withdraw_fee_bps;
+
+      self.check_withdraw_fee_destination()
    }
 
-   pub fn set_bonus_multiplier(
+   pub fn set_fee_recipient(
       &mut self,
-      multiplier: u8,
-   ) {
-      self.bonus_multiplier = COption::Some(multiplier);
+      fee_recipient: COption<Pubkey>,
+   ) -> ProgramResult {
+      self.fee_recipient = fee_recipient;
+
+      self.check_withdraw_fee_destination()
    }
-}
 
-pub const USER_INFO_LEN: usize = 48;
+   fn check_withdraw_fee_destination(
+      &self,
+   ) -> ProgramResult {
+      let charges_fee = self.withdraw_fee_bps != 0 || self.early_withdraw_penalty_bps != 0;
+      if charges_fee && self.fee_recipient.is_none() && self.reward_mint != self.mint {
+         StakingError::RewardMintUnsupported.print::<StakingError>();
+         return Err(StakingError::RewardMintUnsupported.into());
+      }
 
-#[repr(C)]
-#[derive(Debug, Copy, Clone, BorshSerialize, BorshDeserialize)]
-pub struct UserInfo {
-   pub token_account_id: Pubkey,
-   pub amount: u64,
-   pub reward_debt: u64,
-}
+      Ok(())
+   }
 
-impl UserInfo {
-   pub fn from_account_info(
-      a: &AccountInfo
-   ) -> Result<UserInfo, ProgramError> {
-      let user_info = UserInfo::try_from_slice(
-         &a.data.borrow_mut(),
-      );
-      let user_info = match user_info { 
-         Ok(v) => v,
-         Err(_) => {
-            StakingError::InvalidUserInfo.print::<StakingError>();
-            return Err(StakingError::InvalidUserInfo.into());
-         },
-     };
+   /// Reward actually paid for `pending` earned by a position of `user_amount`.
+   /// The part of the position above `whale_threshold` only earns `whale_reward_bps`
+   /// of the normal rate, the rest of `pending` stays in the reward token-account.
+   /// A zero `whale_threshold` disables the curve
+   pub fn apply_whale_curve(
+      &self,
+      pending: u64,
+      user_amount: u64,
+   ) -> Result<u64, ProgramError> {
+      if self.whale_threshold == 0 || user_amount <= self.whale_threshold {
+         return Ok(pending);
+      }
 
-      Ok(user_info)
+      let above_threshold = user_amount - self.whale_threshold;
+      let weighted_amount = (above_threshold as u128)
+         .checked_mul(self.whale_reward_bps as u128)
+         .ok_or(StakingError::Overflow)?
+         .checked_div(BPS_DENOMINATOR as u128)
+         .ok_or(StakingError::Overflow)?
+         .checked_add(self.whale_threshold as u128)
+         .ok_or(StakingError::Overflow)?;
+
+      let reduced = (pending as u128)
+         .checked_mul(weighted_amount)
+         .ok_or(StakingError::Overflow)?
+         .checked_div(user_amount as u128)
+         .ok_or(StakingError::Overflow)?;
+
+      Ok(reduced as u64)
    }
 
-   pub fn set_reward_debt(
+   pub fn set_whale_curve(
       &mut self,
-      value: u64,
-   ) {
-      self.reward_debt = value;
-   }
-}
+      whale_threshold: u64,
+      whale_reward_bps: u16,
+   ) -> ProgramResult {
+      if whale_reward_bps as u64 > BPS_DENOMINATOR {
+         StakingError::InvalidWhaleRewardBps.print::<StakingError>();
+         return Err(StakingError::InvalidWhaleRewardBps.into());
+      }
+      self.whale_threshold = whale_threshold;
+      self.whale_reward_bps = whale_reward_bps;
 
-fn unpack_coption_u8(src: &[u8; 5]) -> Result<COption<u8>, ProgramError> {
-   let (tag, body) = array_refs![src, 4, 1];
-   match *tag {
-      [0, 0, 0, 0] => Ok(COption::None),
-      [1, 0, 0, 0] => Ok(COption::Some(u8::from_le_bytes(*body))),
-      _ => Err(ProgramError::InvalidAccountData),
+      Ok(())
    }
-}
-fn pack_coption_u8(src: &COption<u8>, dst: &mut [u8; 5]) {
-   let (tag, body) = mut_array_refs![dst, 4, 1];
-   match src {
-      COption::Some(amount) => {
-         *tag = [1, 0, 0, 0];
-         *body = amount.to_le_bytes();
+
+   /// Rejects claims paying less than `min_claim_amount`, so they keep accruing instead.
+   /// A zero `min_claim_amount` disables the check
+   pub fn check_claim_amount(
+      &self,
+      amount: u64,
+   ) -> ProgramResult {
+      if amount < self.min_claim_amount {
+         StakingError::RewardBelowMinimum.print::<StakingError>();
+         return Err(StakingError::RewardBelowMinimum.into());
       }
-      COption::None => {
-         *tag = [0; 4];
+
+      Ok(())
+   }
+
+   pub fn set_min_claim_amount(
+      &mut self,
+      min_claim_amount: u64,
+   ) -> ProgramResult {
+      self.min_claim_amount = min_claim_amount;
+
+      Ok(())
+   }
+
+   /// A deposit of `amount` must leave the position of `staked` at `min_stake` or above,
+   /// so a top-up of an existing position may be smaller than the minimum
+   pub fn check_min_stake(
+      &self,
+      staked: u64,
+      amount: u64,
+   ) -> ProgramResult {
+      if staked.saturating_add(amount) < self.min_stake {
+         StakingError::BelowMinimumStake.print::<StakingError>();
+         return Err(StakingError::BelowMinimumStake.into());
       }
+
+      Ok(())
    }
-}
 
-fn unpack_coption_u64(src: &[u8; 12]) -> Result<COption<u64>, ProgramError> {
-   let (tag, body) = array_refs![src, 4, 8];
-   match *tag {
-      [0, 0, 0, 0] => Ok(COption::None),
-      [1, 0, 0, 0] => Ok(COption::Some(u64::from_le_bytes(*body))),
-      _ => Err(ProgramError::InvalidAccountData),
+   /// Tokens the pool still takes before `max_total_supply`, `u64::MAX` for uncapped pools
+   pub fn remaining_capacity(&self) -> u64 {
+      if self.max_total_supply == 0 {
+         return u64::MAX;
+      }
+
+      self.max_total_supply.saturating_sub(self.total_supply)
    }
-}
-fn pack_coption_u64(src: &COption<u64>, dst: &mut [u8; 12]) {
-   let (tag, body) = mut_array_refs![dst, 4, 8];
-   match src {
-      COption::Some(amount) => {
-         *tag = [1, 0, 0, 0];
-         *body = amount.to_le_bytes();
+
+   /// Every stake goes through here, so deposits and compounds respect `max_total_supply`
+   pub fn increase_total_supply(
+      &mut self,
+      amount: u64,
+   ) -> ProgramResult {
+      if amount > self.remaining_capacity() {
+         StakingError::PoolCapExceeded.print::<StakingError>();
+         return Err(StakingError::PoolCapExceeded.into());
       }
-      COption::None => {
-         *tag = [0; 4];
+      self.total_supply = self.total_supply
+         .checked_add(amount)
+         .ok_or(StakingError::Overflow)?;
+
+      Ok(())
+   }
+
+   /// A drifted `total_supply` below the unstaked amount is reported instead of wrapping
+   pub fn decrease_total_supply(
+      &mut self,
+      amount: u64,
+   ) -> ProgramResult {
+      self.total_supply = match self.total_supply.checked_sub(amount) {
+         Some(total_supply) => total_supply,
+         None => {
+            StakingError::TotalSupplyUnderflow.print::<StakingError>();
+            return Err(StakingError::TotalSupplyUnderflow.into());
+         },
+      };
+
+      Ok(())
+   }
+
+   /// Counts a position going from no stake to some stake and back, with the staked
+   /// amounts of the user before and after the change
+   pub fn update_staker_count(
+      &mut self,
+      amount_before: u64,
+      amount_after: u64,
+   ) -> ProgramResult {
+      if amount_before == 0 && amount_after > 0 {
+         self.staker_count = self.staker_count
+            .checked_add(1)
+            .ok_or(StakingError::Overflow)?;
+      } else if amount_before > 0 && amount_after == 0 {
+         self.staker_count = match self.staker_count.checked_sub(1) {
+            Some(staker_count) => staker_count,
+            None => {
+               StakingError::StakerCountUnderflow.print::<StakingError>();
+               return Err(StakingError::StakerCountUnderflow.into());
+            },
+         };
       }
+
+      Ok(())
    }
-}
\ No newline at end of file
+
+   /// Moves `effective_total_supply` by the change of a user's effective balance
+   pub fn update_effective_total_supply(
+      &mut self,
+      effective_before: u64,
+      effective_after: u64,
+   ) -> ProgramResult {
+      self.effective_total_supply = self.effective_total_supply
+         .checked_sub(effective_before)
+         .ok_or(StakingError::TotalSupplyUnderflow)?
+         .checked_add(effective_after)
+         .ok_or(StakingError::Overflow)?;
+
+      Ok(())
+   }
+
+   /// Share of `max_total_supply` that is staked, in basis points. Zero for uncapped pools
+   pub fn utilization_bps(&self) -> u16 {
+      if self.max_total_supply == 0 {
+         return 0;
+      }
+
+      let utilization = self.total_supply as u128 * BPS_DENOMINATOR as u128
+         / self.max_total_supply as u128;
+
+      utilization.min(BPS_DENOMINATOR as u128) as u16
+   }
+
+   /// `rewards_lock_duration` in seconds, zero disables the reward lock
+   pub fn set_reward_lock(
+      &mut self,
+      rewards_lock_duration: u64,
+      lock_policy: u8,
+   ) -> ProgramResult {
+      LockPolicy::from_u8(lock_policy)?;
+      self.rewards_lock_duration = rewards_lock_duration;
+      self.lock_policy = lock_policy;
+
+      Ok(())
+   }
+
+   pub fn set_allowlist_mode(
+      &mut self,
+      allowlist_mode: u8,
+   ) -> ProgramResult {
+      AllowlistMode::from_u8(allowlist_mode)?;
+      self.allowlist_mode = allowlist_mode;
+
+      Ok(())
+   }
+
+   pub fn set_referral_fee_bps(
+      &mut self,
+      referral_fee_bps: u16,
+   ) -> ProgramResult {
+      if referral_fee_bps as u64 > BPS_DENOMINATOR {
+         StakingError::InvalidReferralFeeBps.print::<StakingError>();
+         return Err(StakingError::InvalidReferralFeeBps.into());
+      }
+      self.referral_fee_bps = referral_fee_bps;
+
+      Ok(())
+   }
+
+   /// Splits a claim into the parts of the user and of the referrer
+   pub fn split_referral(
+      &self,
+      amount: u64,
+   ) -> Result<(u64, u64), ProgramError> {
+      let referrer_part = (amount as u128)
+         .checked_mul(self.referral_fee_bps as u128)
+         .ok_or(StakingError::Overflow)?
+         / BPS_DENOMINATOR as u128;
+      let referrer_part = referrer_part as u64;
+
+      Ok((amount - referrer_part, referrer_part))
+   }
+
+   /// Switches a pool without stake or reward to native SOL reward and adds `lamports`
+   /// to the budget. Pools that are already native are only topped up
+   pub fn fund_native_reward(
+      &mut self,
+      lamports: u64,
+   ) -> ProgramResult {
+      if self.reward_is_native == 0 {
+         if self.total_supply != 0 || self.reward_amount != 0 {
+            StakingError::NativeRewardUnsupported.print::<StakingError>();
+            return Err(StakingError::NativeRewardUnsupported.into());
+         }
+         self.reward_is_native = 1;
+         self.reward_per_block = lamports
+            .checked_div(
+               self.end_block
+               .checked_sub(self.start_block)
+               .ok_or(StakingError::Overflow)?)
+            .ok_or(StakingError::Overflow)?;
+      }
+
+      self.reward_amount = self.reward_amount
+         .checked_add(lamports)
+         .ok_or(StakingError::Overflow)?;
+
+      Ok(())
+   }
+
+   pub fn set_rate_controller(
+      &mut self,
+      rate_controller: COption<Pubkey>,
+   ) {
+      self.rate_controller = rate_controller;
+   }
+
+   /// First step of an ownership transfer, `owner` changes only in `accept_owner`
+   /// so a mistyped key can't take the pool
+   pub fn propose_owner(
+      &mut self,
+      new_owner: Pubkey,
+   ) -> ProgramResult {
+      if new_owner == Pubkey::default() {
+         StakingError::InvalidNewOwner.print::<StakingError>();
+         return Err(StakingError::InvalidNewOwner.into());
+      }
+      self.pending_owner = COption::Some(new_owner);
+
+      Ok(())
+   }
+
+   pub fn accept_owner(
+      &mut self,
+      signer: &Pubkey,
+   ) -> ProgramResult {
+      if self.pending_owner != COption::Some(*signer) {
+         StakingError::NotPendingOwner.print::<StakingError>();
+         return Err(StakingError::NotPendingOwner.into());
+      }
+      self.owner = *signer;
+      self.pending_owner = COption::None;
+
+      Ok(())
+   }
+
+   /// Splits a claim of `pending` into what the reward vault holding `available` pays now and
+   /// what stays owed to the user. Without `partial_claim_on_shortfall` a shortfall fails the claim
+   pub fn cover_shortfall(
+      &self,
+      pending: u64,
+      available: u64,
+   ) -> Result<(u64, u64), ProgramError> {
+      if pending <= available {
+         return Ok((pending, 0));
+      }
+      if self.partial_claim_on_shortfall == 0 {
+         StakingError::RewardVaultShortfall.print::<StakingError>();
+         return Err(StakingError::RewardVaultShortfall.into());
+      }
+
+      Ok((available, pending - available))
+   }
+
+   /// Withdraw is replaced by RequestUnstake and CompleteUnstake while `unstake_cooldown` is set
+   pub fn check_instant_unstake_allowed(
+      &self,
+   ) -> ProgramResult {
+      if self.unstake_cooldown != 0 {
+         StakingError::UnstakeCooldownRequired.print::<StakingError>();
+         return Err(StakingError::UnstakeCooldownRequired.into());
+      }
+
+      Ok(())
+   }
+
+   /// Reward in a token other than the stake token is only paid by GetReward and HarvestFor,
+   /// to a token-account of `reward_mint`
+   pub fn has_separate_reward_mint(&self) -> bool {
+      self.reward_is_native == 0 && self.reward_mint != self.mint
+   }
+
+   /// Stakes need the AllowlistEntry of the user
+   pub fn uses_allowlist(&self) -> bool {
+      self.allowlist_mode != AllowlistMode::Disabled as u8
+   }
+
+   pub fn set_paused(
+      &mut self,
+      paused: bool,
+   ) {
+      self.is_paused = paused as u8;
+   }
+
+   /// The reward window contains the slot of `clock`, `start_block <= slot < end_block`
+   pub fn is_active(
+      &self,
+      clock: &Clock,
+   ) -> bool {
+      self.start_block <= clock.slot && clock.slot < self.end_block
+   }
+
+   /// Stake outside the reward window would accrue nothing, so deposits are only taken
+   /// while the pool is active. Withdrawals, claims and EmergencyWithdraw keep working
+   pub fn check_active(
+      &self,
+      clock: &Clock,
+   ) -> ProgramResult {
+      if self.is_active(clock) {
+         return Ok(());
+      }
+
+      if clock.slot < self.start_block {
+         StakingError::PoolNotStarted.print::<StakingError>();
+         return Err(StakingError::PoolNotStarted.into());
+      }
+      StakingError::CampaignEnded.print::<StakingError>();
+      Err(StakingError::CampaignEnded.into())
+   }
+
+   /// Paused pools take no new stake, withdrawals and claims keep working
+   pub fn check_not_paused(
+      &self,
+   ) -> ProgramResult {
+      if self.is_paused != 0 {
+         StakingError::PoolPaused.print::<StakingError>();
+         return Err(StakingError::PoolPaused.into());
+      }
+
+      Ok(())
+   }
+
+   /// Takes a rank above `MAX_PRECISION_FACTOR_RANK` down to it, the share is rescaled with it.
+   /// Only without stake, no position holds a reward debt at the old scale then
+   pub fn migrate_precision_factor_rank(
+      &mut self,
+   ) -> ProgramResult {
+      if self.precision_factor_rank <= MAX_PRECISION_FACTOR_RANK {
+         return Ok(());
+      }
+      if self.precision_factor_rank > PRECISION_FACTOR_RANK_LIMIT {
+         StakingError::InvalidPrecisionFactor.print::<StakingError>();
+         return Err(StakingError::InvalidPrecisionFactor.into());
+      }
+      if self.total_supply != 0 {
+         StakingError::PoolNotEmpty.print::<StakingError>();
+         return Err(StakingError::PoolNotEmpty.into());
+      }
+
+      let scale = 10_u128.pow((self.precision_factor_rank - MAX_PRECISION_FACTOR_RANK) as u32);
+      self.accrued_token_per_share /= scale;
+      self.precision_factor_rank = MAX_PRECISION_FACTOR_RANK;
+
+      Ok(())
+   }
+
+   pub fn set_unstake_cooldown(
+      &mut self,
+      unstake_cooldown: u64,
+   ) {
+      self.unstake_cooldown = unstake_cooldown;
+   }
+
+   pub fn set_partial_claim_on_shortfall(
+      &mut self,
+      partial_claim_on_shortfall: bool,
+   ) {
+      self.partial_claim_on_shortfall = partial_claim_on_shortfall as u8;
+   }
+
+   pub fn set_max_total_supply(
+      &mut self,
+      max_total_supply: u64,
+   ) {
+      self.max_total_supply = max_total_supply;
+   }
+
+   pub fn set_max_reward_per_user(
+      &mut self,
+      max_reward_per_user: u64,
+   ) -> ProgramResult {
+      self.max_reward_per_user = max_reward_per_user;
+
+      Ok(())
+   }
+
+   /// For pools with `reward_starts_on_first_stake` moves the reward window to start
+   /// at the slot of the first stake, keeping its length. Does nothing afterwards
+   pub fn start_on_first_stake(
+      &mut self,
+      current_block: u64,
+   ) -> ProgramResult {
+      if self.reward_starts_on_first_stake == 0 {
+         return Ok(());
+      }
+
+      let duration = self.end_block - self.start_block;
+      self.start_block = current_block;
+      self.end_block = current_block
+         .checked_add(duration)
+         .ok_or(StakingError::Overflow)?;
+      self.set_last_reward_block(current_block);
+      self.reward_starts_on_first_stake = 0;
+
+      Ok(())
+   }
+
+   fn set_last_reward_block(
+      &mut self,
+      block: u64,
+   ) {
+      self.last_reward_block = block;
+   }
+
+   pub fn set_end_block(
+      &mut self,
+      block: u64,
+   ) {
+      self.end_block = block;
+   }
+
+   /// Changes the emission rate. `update_pool` must be called first (`Processor::settle_then`)
+   /// so that the blocks up to the current one are accrued at the previous rate.
+   /// `reward_amount` stays fixed, `end_block` moves to where the new rate spends what the
+   /// previous rate had left to emit
+   pub fn set_reward_per_block(
+      &mut self,
+      reward_per_block: u64,
+   ) -> ProgramResult {
+      let previous_reward_per_block = self.reward_per_block;
+      self.reward_per_block = reward_per_block;
+      self.check_reward_rate()?;
+
+      // A zero rate (`allow_zero_rate`) spends nothing, the window is kept
+      if reward_per_block == 0 {
+         return Ok(());
+      }
+
+      let from_block = self.last_reward_block.max(self.start_block);
+      // A pool still at a zero rate hasn't emitted any of `reward_amount`
+      let remaining_reward = if previous_reward_per_block == 0 {
+         self.reward_amount
+      } else {
+         previous_reward_per_block
+            .checked_mul(self.end_block.saturating_sub(from_block))
+            .ok_or(StakingError::Overflow)?
+      };
+      let end_block = from_block
+         .checked_add(remaining_reward / reward_per_block)
+         .ok_or(StakingError::Overflow)?;
+      if end_block <= from_block {
+         StakingError::RewardRateExhaustsPool.print::<StakingError>();
+         return Err(StakingError::RewardRateExhaustsPool.into());
+      }
+      self.end_block = end_block;
+
+      self.check_campaign_window()
+   }
+
+   /// Synthetix `updateReward`: stores `get_reward_per_token` and moves `last_update_time`
+   /// to `get_last_time_reward_applicable`. The reward `user` earned so far is settled into
+   /// `UserInfo::reward_owed` and its `reward_debt` reset to the new share
+   pub fn update_reward(
+      &mut self,
+      clock: &Clock,
+      user: Option<&mut UserInfo>,
+   ) -> ProgramResult {
+      let last_time_reward_applicable = self.get_last_time_reward_applicable(clock)?;
+      if last_time_reward_applicable > self.last_update_time {
+         self.accrued_token_per_share = self.get_reward_per_token(clock)?;
+         self.last_update_time = last_time_reward_applicable;
+      }
+
+      if let Some(user) = user {
+         let earned = get_pending(
+            user.effective_balance(),
+            self.accrued_token_per_share,
+            self.precision_factor_rank,
+            user.reward_debt,
+         )?;
+         user.owe_reward(earned)?;
+         user.set_reward_debt(get_reward_debt(
+            user.effective_balance(),
+            self.accrued_token_per_share,
+         )?);
+      }
+
+      Ok(())
+   }
+
+   /// Synthetix `rewardPerToken`: `accrued_token_per_share` plus the reward of
+   /// `last_update_time..get_last_time_reward_applicable` per staked token, scaled by the precision factor.
+   /// Nothing accrues while nothing is staked
+   pub fn get_reward_per_token(
+      &self,
+      clock: &Clock,
+   ) -> Result<u128, ProgramError> {
+      let staked_token_supply = self.effective_total_supply;
+      if staked_token_supply == 0 {
+         return Ok(self.accrued_token_per_share);
+      }
+
+      let elapsed = self.get_last_time_reward_applicable(clock)?
+         .saturating_sub(self.last_update_time);
+
+      RewardModelKind::Synthetix.model().update(
+         self,
+         elapsed as u128,
+         staked_token_supply,
+      )
+   }
+
+   /// `min(now, period_finish)`, reward stops accruing at the end of the period.
+   /// `clock.unix_timestamp` goes through `now_secs`, at `now == period_finish` both are the same
+   pub fn get_last_time_reward_applicable(
+      &self,
+      clock: &Clock,
+   ) -> Result<u64, ProgramError> {
+      Ok(now_secs(clock)?.min(self.period_finish))
+   }
+
+   /// Synthetix `notifyRewardAmount`: starts a period of `rewards_duration` from `now` paying `reward`
+   /// and what is left of the running period. `reward_balance` is the reward token-account after
+   /// funding, it must cover the whole new period. `update_pool` must be called first
+   pub fn notify_reward_amount(
+      &mut self,
+      reward: u64,
+      now: u64,
+      reward_balance: u64,
+   ) -> ProgramResult {
+      if RewardModelKind::from_u8(self.reward_model)? != RewardModelKind::Synthetix {
+         StakingError::InvalidRewardModel.print::<StakingError>();
+         return Err(StakingError::InvalidRewardModel.into());
+      }
+
+      let leftover = if now >= self.period_finish {
+         0
+      } else {
+         ((self.period_finish - now) as u128)
+            .checked_mul(self.reward_rate as u128)
+            .ok_or(StakingError::Overflow)?
+      };
+      let reward_rate = (reward as u128)
+         .checked_add(leftover)
+         .ok_or(StakingError::Overflow)?
+         / self.rewards_duration as u128;
+
+      let period_reward = reward_rate
+         .checked_mul(self.rewards_duration as u128)
+         .ok_or(StakingError::Overflow)?;
+      if period_reward > reward_balance as u128 {
+         StakingError::InsufficientRewardBalance.print::<StakingError>();
+         return Err(StakingError::InsufficientRewardBalance.into());
+      }
+
+      self.reward_rate = u64::try_from(reward_rate).map_err(|_| StakingError::Overflow)?;
+      self.reward_amount = self.reward_amount
+         .checked_add(reward)
+         .ok_or(StakingError::Overflow)?;
+      self.last_update_time = now;
+      self.period_finish = now
+         .checked_add(self.rewards_duration)
+         .ok_or(StakingError::Overflow)?;
+
+      Ok(())
+   }
+
+   /// Switches to `RewardModelKind::Synthetix` with periods of `rewards_duration` seconds.
+   /// Only between periods, `update_pool` must be called first (`Processor::settle_then`)
+   pub fn set_rewards_duration(
+      &mut self,
+      rewards_duration: u64,
+      now: u64,
+   ) -> ProgramResult {
+      if now < self.period_finish {
+         StakingError::RewardPeriodActive.print::<StakingError>();
+         return Err(StakingError::RewardPeriodActive.into());
+      }
+      if rewards_duration == 0 {
+         StakingError::InvalidRewardsDuration.print::<StakingError>();
+         return Err(StakingError::InvalidRewardsDuration.into());
+      }
+
+      self.rewards_duration = rewards_duration;
+      self.reward_model = RewardModelKind::Synthetix as u8;
+      self.last_update_time = now;
+
+      Ok(())
+   }
+
+   /// Switches to `RewardModelKind::FixedApr` at `simple_interest_bps` per block,
+   /// zero goes back to the fixed `reward_per_block`.
+   /// `update_pool` must be called first (`Processor::settle_then`)
+   pub fn set_simple_interest_bps(
+      &mut self,
+      simple_interest_bps: u16,
+   ) -> ProgramResult {
+      self.simple_interest_bps = simple_interest_bps;
+      self.reward_model = if simple_interest_bps == 0 {
+         RewardModelKind::BlockEmission as u8
+      } else {
+         RewardModelKind::FixedApr as u8
+      };
+
+      self.check_reward_rate()
+   }
+
+   /// Yearly reward at the current rate as bps of `total_supply`, 0 once the reward window
+   /// or the Synthetix period is over
+   pub fn get_apr_bps(
+      &self,
+      clock: &Clock,
+   ) -> Result<u64, ProgramError> {
+      match RewardModelKind::from_u8(self.reward_model)? {
+         RewardModelKind::Synthetix if now_secs(clock)? < self.period_finish => compute_apr(
+            self.reward_rate,
+            self.total_supply,
+            SECONDS_PER_YEAR,
+         ),
+         RewardModelKind::Synthetix => Ok(0),
+         _ if clock.slot >= self.end_block => Ok(0),
+         RewardModelKind::BlockEmission => compute_apr(
+            self.reward_per_block,
+            self.total_supply,
+            SLOTS_PER_YEAR,
+         ),
+         RewardModelKind::FixedApr => Ok(
+            (self.simple_interest_bps as u64)
+               .checked_mul(SLOTS_PER_YEAR)
+               .ok_or(StakingError::Overflow)?
+         ),
+      }
+   }
+
+   /// A pool without emission accrues nothing, it's only allowed when created with `allow_zero_rate`
+   pub fn check_reward_rate(&self) -> ProgramResult {
+      let rate = match RewardModelKind::from_u8(self.reward_model)? {
+         RewardModelKind::BlockEmission => self.reward_per_block,
+         RewardModelKind::FixedApr => self.simple_interest_bps as u64,
+         // `reward_rate` is only known once the reward of a period is funded
+         RewardModelKind::Synthetix => return Ok(()),
+      };
+      if rate == 0 && self.allow_zero_rate == 0 {
+         StakingError::ZeroRewardRate.print::<StakingError>();
+         return Err(StakingError::ZeroRewardRate.into());
+      }
+
+      Ok(())
+   }
+
+   pub fn set_bonus_multiplier(
+      &mut self,
+      multiplier: u8,
+   ) {
+      self.bonus_multiplier = COption::Some(multiplier);
+   }
+}
+
+/// Read-only view of a packed StakePool borrowed from the account data, it skips the copy
+/// done by `StakePool::unpack` in handlers that only read a few fields.
+/// Mirrors the layout of `StakePool::pack_into_slice`, every field is a little-endian byte array
+#[cfg(feature = "zero-copy")]
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct StakePoolZeroCopy {
+   pool_index: [u8; 8],
+   owner: [u8; 32],
+   mint: [u8; 32],
+   is_initialized: u8,
+   precision_factor_rank: u8,
+   bonus_multiplier: [u8; 5],
+   bonus_start_block: [u8; 12],
+   bonus_end_block: [u8; 12],
+   last_reward_block: [u8; 8],
+   start_block: [u8; 8],
+   end_block: [u8; 8],
+   reward_amount: [u8; 8],
+   reward_per_block: [u8; 8],
+   accrued_token_per_share: [u8; 16],
+   reward_decimals: u8,
+   withdraw_fee_bps: [u8; 2],
+   whale_threshold: [u8; 8],
+   whale_reward_bps: [u8; 2],
+   reward_starts_on_first_stake: u8,
+   min_claim_amount: [u8; 8],
+   max_reward_per_user: [u8; 8],
+   total_supply: [u8; 8],
+   max_total_supply: [u8; 8],
+   rewards_lock_duration: [u8; 8],
+   lock_policy: u8,
+   allowlist_mode: u8,
+   referral_fee_bps: [u8; 2],
+   staked_vault: [u8; 32],
+   reward_is_native: u8,
+   // bytemuck has no Pod for [u8; 36], the COption is split into tag and key
+   rate_controller_tag: [u8; 4],
+   rate_controller_key: [u8; 32],
+   effective_total_supply: [u8; 8],
+   allow_zero_rate: u8,
+   partial_claim_on_shortfall: u8,
+   reward_model: u8,
+   simple_interest_bps: [u8; 2],
+   rewards_duration: [u8; 8],
+   reward_rate: [u8; 8],
+   period_finish: [u8; 8],
+   last_update_time: [u8; 8],
+   unstake_cooldown: [u8; 8],
+   is_paused: u8,
+   pending_owner_tag: [u8; 4],
+   pending_owner_key: [u8; 32],
+   min_stake: [u8; 8],
+   reward_mint: [u8; 32],
+   fee_recipient_tag: [u8; 4],
+   fee_recipient_key: [u8; 32],
+   early_withdraw_penalty_bps: [u8; 2],
+   staker_count: [u8; 8],
+   token_program: [u8; 32],
+   pending_unstake_supply: [u8; 8],
+}
+
+#[cfg(feature = "zero-copy")]
+impl StakePoolZeroCopy {
+   pub fn load(
+      data: &[u8],
+   ) -> Result<&StakePoolZeroCopy, ProgramError> {
+      if data.len() == LEGACY_STAKE_POOL_LEN {
+         StakingError::LegacyAccountLayout.print::<StakingError>();
+         return Err(StakingError::LegacyAccountLayout.into());
+      }
+      let len = std::mem::size_of::<StakePoolZeroCopy>();
+      if data.len() < len {
+         return Err(ProgramError::InvalidAccountData);
+      }
+
+      bytemuck::try_from_bytes(&data[..len])
+         .map_err(|_| ProgramError::InvalidAccountData)
+   }
+
+   pub fn pool_index(&self) -> u64 { u64::from_le_bytes(self.pool_index) }
+   pub fn owner(&self) -> Pubkey { Pubkey::new_from_array(self.owner) }
+   pub fn mint(&self) -> Pubkey { Pubkey::new_from_array(self.mint) }
+   pub fn is_initialized(&self) -> bool { self.is_initialized != 0 }
+   pub fn precision_factor_rank(&self) -> u8 { self.precision_factor_rank }
+   pub fn bonus_multiplier(&self) -> Result<COption<u8>, ProgramError> { unpack_coption_u8(&self.bonus_multiplier) }
+   pub fn bonus_start_block(&self) -> Result<COption<u64>, ProgramError> { unpack_coption_u64(&self.bonus_start_block) }
+   pub fn bonus_end_block(&self) -> Result<COption<u64>, ProgramError> { unpack_coption_u64(&self.bonus_end_block) }
+   pub fn last_reward_block(&self) -> u64 { u64::from_le_bytes(self.last_reward_block) }
+   pub fn start_block(&self) -> u64 { u64::from_le_bytes(self.start_block) }
+   pub fn end_block(&self) -> u64 { u64::from_le_bytes(self.end_block) }
+   pub fn reward_amount(&self) -> u64 { u64::from_le_bytes(self.reward_amount) }
+   pub fn reward_per_block(&self) -> u64 { u64::from_le_bytes(self.reward_per_block) }
+   pub fn accrued_token_per_share(&self) -> u128 { u128::from_le_bytes(self.accrued_token_per_share) }
+   pub fn reward_decimals(&self) -> u8 { self.reward_decimals }
+   pub fn withdraw_fee_bps(&self) -> u16 { u16::from_le_bytes(self.withdraw_fee_bps) }
+   pub fn whale_threshold(&self) -> u64 { u64::from_le_bytes(self.whale_threshold) }
+   pub fn whale_reward_bps(&self) -> u16 { u16::from_le_bytes(self.whale_reward_bps) }
+   pub fn reward_starts_on_first_stake(&self) -> bool { self.reward_starts_on_first_stake != 0 }
+   pub fn min_claim_amount(&self) -> u64 { u64::from_le_bytes(self.min_claim_amount) }
+   pub fn max_reward_per_user(&self) -> u64 { u64::from_le_bytes(self.max_reward_per_user) }
+   pub fn total_supply(&self) -> u64 { u64::from_le_bytes(self.total_supply) }
+   pub fn max_total_supply(&self) -> u64 { u64::from_le_bytes(self.max_total_supply) }
+   pub fn rewards_lock_duration(&self) -> u64 { u64::from_le_bytes(self.rewards_lock_duration) }
+   pub fn lock_policy(&self) -> u8 { self.lock_policy }
+   pub fn allowlist_mode(&self) -> u8 { self.allowlist_mode }
+   pub fn referral_fee_bps(&self) -> u16 { u16::from_le_bytes(self.referral_fee_bps) }
+   pub fn staked_vault(&self) -> Pubkey { Pubkey::new_from_array(self.staked_vault) }
+   pub fn reward_is_native(&self) -> u8 { self.reward_is_native }
+   pub fn rate_controller(&self) -> Result<COption<Pubkey>, ProgramError> {
+      let mut rate_controller = [0; 36];
+      rate_controller[..4].copy_from_slice(&self.rate_controller_tag);
+      rate_controller[4..].copy_from_slice(&self.rate_controller_key);
+      unpack_coption_key(&rate_controller)
+   }
+   pub fn effective_total_supply(&self) -> u64 { u64::from_le_bytes(self.effective_total_supply) }
+   pub fn allow_zero_rate(&self) -> u8 { self.allow_zero_rate }
+   pub fn partial_claim_on_shortfall(&self) -> u8 { self.partial_claim_on_shortfall }
+   pub fn reward_model(&self) -> u8 { self.reward_model }
+   pub fn simple_interest_bps(&self) -> u16 { u16::from_le_bytes(self.simple_interest_bps) }
+   pub fn rewards_duration(&self) -> u64 { u64::from_le_bytes(self.rewards_duration) }
+   pub fn reward_rate(&self) -> u64 { u64::from_le_bytes(self.reward_rate) }
+   pub fn period_finish(&self) -> u64 { u64::from_le_bytes(self.period_finish) }
+   pub fn last_update_time(&self) -> u64 { u64::from_le_bytes(self.last_update_time) }
+   pub fn unstake_cooldown(&self) -> u64 { u64::from_le_bytes(self.unstake_cooldown) }
+   pub fn is_paused(&self) -> u8 { self.is_paused }
+   pub fn pending_owner(&self) -> Result<COption<Pubkey>, ProgramError> {
+      let mut pending_owner = [0; 36];
+      pending_owner[..4].copy_from_slice(&self.pending_owner_tag);
+      pending_owner[4..].copy_from_slice(&self.pending_owner_key);
+      unpack_coption_key(&pending_owner)
+   }
+   pub fn min_stake(&self) -> u64 { u64::from_le_bytes(self.min_stake) }
+   pub fn reward_mint(&self) -> Pubkey { Pubkey::new_from_array(self.reward_mint) }
+   pub fn fee_recipient(&self) -> Result<COption<Pubkey>, ProgramError> {
+      let mut fee_recipient = [0; 36];
+      fee_recipient[..4].copy_from_slice(&self.fee_recipient_tag);
+      fee_recipient[4..].copy_from_slice(&self.fee_recipient_key);
+      unpack_coption_key(&fee_recipient)
+   }
+   pub fn early_withdraw_penalty_bps(&self) -> u16 { u16::from_le_bytes(self.early_withdraw_penalty_bps) }
+   pub fn staker_count(&self) -> u64 { u64::from_le_bytes(self.staker_count) }
+   pub fn token_program(&self) -> Pubkey { Pubkey::new_from_array(self.token_program) }
+   pub fn pending_unstake_supply(&self) -> u64 { u64::from_le_bytes(self.pending_unstake_supply) }
+}
+
+/// Phase of the reward campaign of a pool, `StakePool::state`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CampaignState {
+   /// Before `start_block`
+   NotStarted,
+   /// Between `start_block` and `end_block` at the base rate
+   Active,
+   /// Inside the bonus window, rewards are multiplied by `bonus_multiplier`
+   BonusActive,
+   /// From `end_block` on
+   Ended,
+}
+
+/// How the emission of a pool is computed, `StakePool::reward_model`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RewardModelKind {
+   /// Fixed `reward_per_block` funded by `reward_amount`. Default
+   BlockEmission = 0,
+   /// `simple_interest_bps` of the staked principal per block
+   FixedApr = 1,
+   /// `reward_rate` per second until `period_finish`
+   Synthetix = 2,
+}
+
+impl RewardModelKind {
+   pub fn from_u8(
+      value: u8,
+   ) -> Result<RewardModelKind, ProgramError> {
+      match value {
+         0 => Ok(RewardModelKind::BlockEmission),
+         1 => Ok(RewardModelKind::FixedApr),
+         2 => Ok(RewardModelKind::Synthetix),
+         _ => {
+            StakingError::InvalidRewardModel.print::<StakingError>();
+            Err(StakingError::InvalidRewardModel.into())
+         },
+      }
+   }
+}
+
+/// How a stake made while the reward lock is active moves `UserInfo::reward_lock_finish`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockPolicy {
+   /// Lock restarts at the stake and lasts `rewards_lock_duration`. Default
+   Reset = 0,
+   /// `rewards_lock_duration` is added to the active lock
+   Extend = 1,
+   /// Active lock is kept, a new one starts only after it expired
+   KeepEarliest = 2,
+}
+
+impl LockPolicy {
+   pub fn from_u8(
+      value: u8,
+   ) -> Result<LockPolicy, ProgramError> {
+      match value {
+         0 => Ok(LockPolicy::Reset),
+         1 => Ok(LockPolicy::Extend),
+         2 => Ok(LockPolicy::KeepEarliest),
+         _ => {
+            StakingError::InvalidLockPolicy.print::<StakingError>();
+            Err(StakingError::InvalidLockPolicy.into())
+         },
+      }
+   }
+}
+
+/// Who may stake in a pool, `StakePool::allowlist_mode`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AllowlistMode {
+   /// Anyone. Default
+   Disabled = 0,
+   /// Only users with an allowed AllowlistEntry
+   Allowlist = 1,
+   /// Anyone except users with a disallowed AllowlistEntry
+   Denylist = 2,
+}
+
+impl AllowlistMode {
+   pub fn from_u8(
+      value: u8,
+   ) -> Result<AllowlistMode, ProgramError> {
+      match value {
+         0 => Ok(AllowlistMode::Disabled),
+         1 => Ok(AllowlistMode::Allowlist),
+         2 => Ok(AllowlistMode::Denylist),
+         _ => {
+            StakingError::InvalidAllowlistMode.print::<StakingError>();
+            Err(StakingError::InvalidAllowlistMode.into())
+         },
+      }
+   }
+}
+
+pub const ALLOWLIST_ENTRY_LEN: usize = 1;
+
+/// Owner decision for a single user of a pool. PDA from `utils::allowlist_entry_address`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, BorshSerialize, BorshDeserialize)]
+pub struct AllowlistEntry {
+   pub allowed: bool,
+}
+
+impl AllowlistEntry {
+   pub fn from_account_info(
+      a: &AccountInfo
+   ) -> Result<AllowlistEntry, ProgramError> {
+      let entry = AllowlistEntry::try_from_slice(
+         &a.data.borrow(),
+      );
+      let entry = match entry {
+         Ok(v) => v,
+         Err(_) => {
+            StakingError::InvalidAllowlistEntry.print::<StakingError>();
+            return Err(StakingError::InvalidAllowlistEntry.into());
+         },
+      };
+
+      Ok(entry)
+   }
+}
+
+/// Packed size of UserInfo, every field at a fixed offset
+pub const USER_INFO_LEN: usize = 167;
+/// Size of the Borsh UserInfo of older program versions. Such accounts are refused with
+/// `LegacyAccountLayout`, the position has to be withdrawn with the version that created it
+pub const LEGACY_USER_INFO_LEN: usize = 48;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct UserInfo {
+   pub token_account_id: Pubkey,
+   pub amount: u64,
+   /// `amount * accrued_token_per_share`, scaled by the precision factor like the share
+   pub reward_debt: u128,
+   pub total_claimed: u64,
+   pub reward_lock_finish: u64,
+   /// Token-account receiving `StakePool::referral_fee_bps` of claimed rewards
+   pub referrer: Option<Pubkey>,
+   /// Settled reward not paid yet, e.g. native SOL reward settled by Deposit or Withdraw,
+   /// or the part of a claim an under-funded reward vault couldn't cover
+   pub reward_owed: u64,
+   /// Reward share multiplier in basis points set by the pool owner, zero means no boost
+   pub boost_bps: u16,
+   /// Set by the pool owner for a legal hold: no stake, unstake or claim, rewards keep accruing
+   pub frozen: bool,
+   /// Sum of `amount * seconds` staked, for airdrops proportional to time-weighted stake.
+   /// EmergencyWithdraw takes no clock and doesn't add its last period
+   pub time_weighted_balance: u128,
+   /// Unix timestamp `time_weighted_balance` is accumulated to
+   pub twab_updated_at: u64,
+   /// Tokens requested by RequestUnstake, out of `amount` and earning nothing, paid by CompleteUnstake
+   pub pending_unstake: u64,
+   /// Unix timestamp CompleteUnstake may pay `pending_unstake` from
+   pub unstake_ready_at: u64,
+   /// Slot of the last SetUserRewardDebt correction
+   pub reward_debt_corrected_at: u64,
+}
+
+impl Sealed for UserInfo {}
+impl IsInitialized for UserInfo {
+   fn is_initialized(&self) -> bool {
+      self.token_account_id != Pubkey::default()
+   }
+}
+
+impl Pack for UserInfo {
+   const LEN: usize = USER_INFO_LEN;
+   fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+      let src = array_ref![src, 0, USER_INFO_LEN];
+      let (
+         token_account_id,
+         amount,
+         reward_debt,
+         total_claimed,
+         reward_lock_finish,
+         referrer,
+         reward_owed,
+         boost_bps,
+         frozen,
+         time_weighted_balance,
+         twab_updated_at,
+         pending_unstake,
+         unstake_ready_at,
+         reward_debt_corrected_at,
+      ) = array_refs![src, 32, 8, 16, 8, 8, 36, 8, 2, 1, 16, 8, 8, 8, 8];
+      Ok(UserInfo {
+         token_account_id: Pubkey::new_from_array(*token_account_id),
+         amount: u64::from_le_bytes(*amount),
+         reward_debt: u128::from_le_bytes(*reward_debt),
+         total_claimed: u64::from_le_bytes(*total_claimed),
+         reward_lock_finish: u64::from_le_bytes(*reward_lock_finish),
+         referrer: unpack_coption_key(referrer)?.into(),
+         reward_owed: u64::from_le_bytes(*reward_owed),
+         boost_bps: u16::from_le_bytes(*boost_bps),
+         frozen: match frozen {
+            [0] => false,
+            [1] => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+         },
+         time_weighted_balance: u128::from_le_bytes(*time_weighted_balance),
+         twab_updated_at: u64::from_le_bytes(*twab_updated_at),
+         pending_unstake: u64::from_le_bytes(*pending_unstake),
+         unstake_ready_at: u64::from_le_bytes(*unstake_ready_at),
+         reward_debt_corrected_at: u64::from_le_bytes(*reward_debt_corrected_at),
+      })
+   }
+   fn pack_into_slice(&self, dst: &mut [u8]) {
+      let dst = array_mut_ref![dst, 0, USER_INFO_LEN];
+      let (
+         token_account_id_dst,
+         amount_dst,
+         reward_debt_dst,
+         total_claimed_dst,
+         reward_lock_finish_dst,
+         referrer_dst,
+         reward_owed_dst,
+         boost_bps_dst,
+         frozen_dst,
+         time_weighted_balance_dst,
+         twab_updated_at_dst,
+         pending_unstake_dst,
+         unstake_ready_at_dst,
+         reward_debt_corrected_at_dst,
+      ) = mut_array_refs![dst, 32, 8, 16, 8, 8, 36, 8, 2, 1, 16, 8, 8, 8, 8];
+      let &UserInfo {
+         ref token_account_id,
+         amount,
+         reward_debt,
+         total_claimed,
+         reward_lock_finish,
+         referrer,
+         reward_owed,
+         boost_bps,
+         frozen,
+         time_weighted_balance,
+         twab_updated_at,
+         pending_unstake,
+         unstake_ready_at,
+         reward_debt_corrected_at,
+      } = self;
+      token_account_id_dst.copy_from_slice(token_account_id.as_ref());
+      *amount_dst = amount.to_le_bytes();
+      *reward_debt_dst = reward_debt.to_le_bytes();
+      *total_claimed_dst = total_claimed.to_le_bytes();
+      *reward_lock_finish_dst = reward_lock_finish.to_le_bytes();
+      pack_coption_key(&referrer.into(), referrer_dst);
+      *reward_owed_dst = reward_owed.to_le_bytes();
+      *boost_bps_dst = boost_bps.to_le_bytes();
+      frozen_dst[0] = frozen as u8;
+      *time_weighted_balance_dst = time_weighted_balance.to_le_bytes();
+      *twab_updated_at_dst = twab_updated_at.to_le_bytes();
+      *pending_unstake_dst = pending_unstake.to_le_bytes();
+      *unstake_ready_at_dst = unstake_ready_at.to_le_bytes();
+      *reward_debt_corrected_at_dst = reward_debt_corrected_at.to_le_bytes();
+   }
+}
+
+impl UserInfo {
+   pub fn from_account_info(
+      a: &AccountInfo
+   ) -> Result<UserInfo, ProgramError> {
+      if a.data_len() == LEGACY_USER_INFO_LEN {
+         StakingError::LegacyAccountLayout.print::<StakingError>();
+         return Err(StakingError::LegacyAccountLayout.into());
+      }
+      let user_info = UserInfo::unpack(
+         &a.data.borrow(),
+      );
+      let user_info = match user_info { 
+         Ok(v) => v,
+         Err(_) => {
+            StakingError::InvalidUserInfo.print::<StakingError>();
+            return Err(StakingError::InvalidUserInfo.into());
+         },
+     };
+
+      Ok(user_info)
+   }
+
+   pub fn set_reward_debt(
+      &mut self,
+      value: u128,
+   ) {
+      self.reward_debt = value;
+   }
+
+   /// `amount` weighted by `boost_bps`, used for the reward share instead of the raw amount
+   pub fn effective_balance(&self) -> u64 {
+      let boost_bps = if self.boost_bps == 0 { BPS_DENOMINATOR } else { self.boost_bps as u64 };
+      let effective_balance = self.amount as u128 * boost_bps as u128 / BPS_DENOMINATOR as u128;
+
+      u64::try_from(effective_balance).unwrap_or(u64::MAX)
+   }
+
+   pub fn set_boost_bps(
+      &mut self,
+      boost_bps: u16,
+   ) -> ProgramResult {
+      if boost_bps != 0 && ((boost_bps as u64) < BPS_DENOMINATOR || boost_bps > MAX_BOOST_BPS) {
+         StakingError::InvalidBoostBps.print::<StakingError>();
+         return Err(StakingError::InvalidBoostBps.into());
+      }
+      self.boost_bps = boost_bps;
+
+      Ok(())
+   }
+
+   pub fn owe_reward(
+      &mut self,
+      amount: u64,
+   ) -> ProgramResult {
+      self.reward_owed = self.reward_owed
+         .checked_add(amount)
+         .ok_or(StakingError::Overflow)?;
+
+      Ok(())
+   }
+
+   /// Returns `reward_owed` and clears it
+   pub fn take_reward_owed(
+      &mut self,
+   ) -> u64 {
+      std::mem::take(&mut self.reward_owed)
+   }
+
+   /// Moves `reward_lock_finish` after a stake at `now` according to `lock_policy`.
+   /// A zero `rewards_lock_duration` leaves it unchanged, a lock end past `u64::MAX` is
+   /// `StakingError::Overflow` instead of a panic
+   pub fn set_reward_lock_finish(
+      &mut self,
+      now: u64,
+      rewards_lock_duration: u64,
+      lock_policy: LockPolicy,
+   ) -> ProgramResult {
+      if rewards_lock_duration == 0 {
+         return Ok(());
+      }
+
+      let lock_active = self.reward_lock_finish > now;
+      let lock_from = match lock_policy {
+         LockPolicy::Reset => now,
+         LockPolicy::Extend if lock_active => self.reward_lock_finish,
+         LockPolicy::Extend => now,
+         LockPolicy::KeepEarliest if lock_active => return Ok(()),
+         LockPolicy::KeepEarliest => now,
+      };
+
+      self.reward_lock_finish = lock_from
+         .checked_add(rewards_lock_duration)
+         .ok_or(StakingError::Overflow)?;
+
+      Ok(())
+   }
+
+   /// Adds `amount` held since `twab_updated_at` to `time_weighted_balance`,
+   /// must be called before `amount` changes
+   pub fn accumulate_twab(
+      &mut self,
+      now: u64,
+   ) -> ProgramResult {
+      let elapsed = now.saturating_sub(self.twab_updated_at);
+      self.time_weighted_balance = (self.amount as u128)
+         .checked_mul(elapsed as u128)
+         .and_then(|weighted| self.time_weighted_balance.checked_add(weighted))
+         .ok_or(StakingError::Overflow)?;
+      self.twab_updated_at = now;
+
+      Ok(())
+   }
+
+   /// Moves `amount` into `pending_unstake`, a new request restarts the cooldown for the whole
+   /// pending amount
+   pub fn request_unstake(
+      &mut self,
+      amount: u64,
+      now: u64,
+      unstake_cooldown: u64,
+   ) -> ProgramResult {
+      self.amount = self.amount
+         .checked_sub(amount)
+         .ok_or(StakingError::Overflow)?;
+      self.pending_unstake = self.pending_unstake
+         .checked_add(amount)
+         .ok_or(StakingError::Overflow)?;
+      self.unstake_ready_at = now
+         .checked_add(unstake_cooldown)
+         .ok_or(StakingError::Overflow)?;
+
+      Ok(())
+   }
+
+   /// Returns `pending_unstake` and clears it once the cooldown elapsed
+   pub fn complete_unstake(
+      &mut self,
+      now: u64,
+   ) -> Result<u64, ProgramError> {
+      if self.pending_unstake == 0 {
+         StakingError::NoPendingUnstake.print::<StakingError>();
+         return Err(StakingError::NoPendingUnstake.into());
+      }
+      if now < self.unstake_ready_at {
+         StakingError::UnstakeCooldownActive.print::<StakingError>();
+         return Err(StakingError::UnstakeCooldownActive.into());
+      }
+
+      Ok(std::mem::take(&mut self.pending_unstake))
+   }
+
+   /// UserInfo can't be closed or moved while tokens wait for CompleteUnstake
+   pub fn check_no_pending_unstake(
+      &self,
+   ) -> ProgramResult {
+      if self.pending_unstake != 0 {
+         StakingError::UnstakePending.print::<StakingError>();
+         return Err(StakingError::UnstakePending.into());
+      }
+
+      Ok(())
+   }
+
+   /// Admin repair of a `reward_debt` broken by an accounting bug, once per
+   /// `REWARD_DEBT_CORRECTION_INTERVAL`. The debt can't exceed what the position
+   /// has accrued at `accrued_token_per_share`, so the next claim can't fail on it
+   pub fn correct_reward_debt(
+      &mut self,
+      reward_debt: u128,
+      accrued_token_per_share: u128,
+      slot: u64,
+   ) -> ProgramResult {
+      if self.reward_debt_corrected_at != 0 &&
+         slot < self.reward_debt_corrected_at.saturating_add(REWARD_DEBT_CORRECTION_INTERVAL) {
+         StakingError::RewardDebtCorrectionTooSoon.print::<StakingError>();
+         return Err(StakingError::RewardDebtCorrectionTooSoon.into());
+      }
+      if reward_debt > get_reward_debt(self.effective_balance(), accrued_token_per_share)? {
+         StakingError::InvalidRewardDebt.print::<StakingError>();
+         return Err(StakingError::InvalidRewardDebt.into());
+      }
+
+      self.set_reward_debt(reward_debt);
+      self.reward_debt_corrected_at = slot;
+
+      Ok(())
+   }
+
+   /// Earliest `reward_lock_finish` after `now` among `positions`, `None` when all are unlocked
+   pub fn next_unlock(
+      positions: &[UserInfo],
+      now: u64,
+   ) -> Option<u64> {
+      positions
+         .iter()
+         .map(|position| position.reward_lock_finish)
+         .filter(|&reward_lock_finish| reward_lock_finish > now)
+         .min()
+   }
+
+   pub fn set_frozen(
+      &mut self,
+      frozen: bool,
+   ) {
+      self.frozen = frozen;
+   }
+
+   pub fn check_not_frozen(&self) -> ProgramResult {
+      if self.frozen {
+         StakingError::UserFrozen.print::<StakingError>();
+         return Err(StakingError::UserFrozen.into());
+      }
+
+      Ok(())
+   }
+
+   pub fn seconds_until_unlock(
+      &self,
+      now: u64,
+   ) -> u64 {
+      self.reward_lock_finish.saturating_sub(now)
+   }
+
+   /// Reward lock of the last deposit has passed
+   pub fn can_claim(
+      &self,
+      now: u64,
+   ) -> bool {
+      self.seconds_until_unlock(now) == 0
+   }
+
+   /// Clamps `pending` to what is left of `max_reward_per_user` and adds it to `total_claimed`.
+   /// A zero `max_reward_per_user` disables the cap
+   pub fn cap_claim(
+      &mut self,
+      pending: u64,
+      max_reward_per_user: u64,
+   ) -> Result<u64, ProgramError> {
+      let claim = if max_reward_per_user == 0 {
+         pending
+      } else {
+         pending.min(max_reward_per_user.saturating_sub(self.total_claimed))
+      };
+
+      self.total_claimed = self.total_claimed
+         .checked_add(claim)
+         .ok_or(StakingError::Overflow)?;
+
+      Ok(claim)
+   }
+}
+
+/// Return data of `GetUserInfo`, everything a client needs for the claim button state
+#[derive(Debug, Copy, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct UserRewardStatus {
+   pub amount: u64,
+   /// Reward GetReward would pay now, including `reward_owed`
+   pub pending: u64,
+   pub reward_lock_finish: u64,
+   pub seconds_until_unlock: u64,
+   /// Unlocked and `pending` reaches `StakePool::min_claim_amount`
+   pub can_claim: bool,
+}
+
+/// Return data of `GetReward`, so clients see the outcome of a partial or capped claim
+/// without reading the accounts again
+#[derive(Debug, Copy, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct ClaimReceipt {
+   /// Reward sent out of the pool, the referrer share included
+   pub paid_amount: u64,
+   /// `UserInfo::reward_owed` left after the claim
+   pub remaining_pending: u64,
+   /// `UserInfo::reward_lock_finish`
+   pub next_unlock: u64,
+}
+
+/// Number of checkpoints kept by `RewardCheckpoints`
+pub const CHECKPOINTS_CAPACITY: usize = 32;
+pub const CHECKPOINT_LEN: usize = 32;
+pub const REWARD_CHECKPOINTS_LEN: usize = 16 + CHECKPOINT_LEN * CHECKPOINTS_CAPACITY;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct Checkpoint {
+   pub slot: u64,
+   pub accrued_token_per_share: u128,
+   pub total_supply: u64,
+}
+
+/// Ring buffer of pool checkpoints for computing APR over past windows
+#[repr(C)]
+#[derive(Debug, Copy, Clone, BorshSerialize, BorshDeserialize)]
+pub struct RewardCheckpoints {
+   pub pool_index: u64,
+   /// Position the next checkpoint is written to
+   pub head: u32,
+   /// Number of valid checkpoints, up to `CHECKPOINTS_CAPACITY`
+   pub count: u32,
+   pub checkpoints: [Checkpoint; CHECKPOINTS_CAPACITY],
+}
+
+impl RewardCheckpoints {
+   pub fn new(
+      pool_index: u64,
+   ) -> RewardCheckpoints {
+      RewardCheckpoints {
+         pool_index,
+         head: 0,
+         count: 0,
+         checkpoints: [Checkpoint::default(); CHECKPOINTS_CAPACITY],
+      }
+   }
+
+   pub fn from_account_info(
+      a: &AccountInfo
+   ) -> Result<RewardCheckpoints, ProgramError> {
+      let checkpoints = RewardCheckpoints::try_from_slice(
+         &a.data.borrow(),
+      );
+      let checkpoints = match checkpoints {
+         Ok(v) => v,
+         Err(_) => {
+            StakingError::InvalidRewardCheckpoints.print::<StakingError>();
+            return Err(StakingError::InvalidRewardCheckpoints.into());
+         },
+      };
+
+      Ok(checkpoints)
+   }
+
+   /// Writes a checkpoint, overwriting the oldest one once the buffer is full
+   pub fn push(
+      &mut self,
+      checkpoint: Checkpoint,
+   ) {
+      self.checkpoints[self.head as usize] = checkpoint;
+      self.head = (self.head + 1) % CHECKPOINTS_CAPACITY as u32;
+      if (self.count as usize) < CHECKPOINTS_CAPACITY {
+         self.count += 1;
+      }
+   }
+
+   /// Up to `n` most recent checkpoints, newest first
+   pub fn latest(
+      &self,
+      n: usize,
+   ) -> Vec<Checkpoint> {
+      let n = n.min(self.count as usize);
+      (1..=n)
+         .map(|i| {
+            let index = (self.head as usize + CHECKPOINTS_CAPACITY - i) % CHECKPOINTS_CAPACITY;
+            self.checkpoints[index]
+         })
+         .collect()
+   }
+}
+
+// COption tags are decoded strictly: only the canonical `[0, 0, 0, 0]` and `[1, 0, 0, 0]`
+// are accepted, anything else like `[2, 0, 0, 0]` or `[1, 0, 0, 1]` is `InvalidAccountData`
+fn unpack_coption_u8(src: &[u8; 5]) -> Result<COption<u8>, ProgramError> {
+   let (tag, body) = array_refs![src, 4, 1];
+   match *tag {
+      [0, 0, 0, 0] => Ok(COption::None),
+      [1, 0, 0, 0] => Ok(COption::Some(u8::from_le_bytes(*body))),
+      _ => Err(ProgramError::InvalidAccountData),
+   }
+}
+fn pack_coption_u8(src: &COption<u8>, dst: &mut [u8; 5]) {
+   let (tag, body) = mut_array_refs![dst, 4, 1];
+   match src {
+      COption::Some(amount) => {
+         *tag = [1, 0, 0, 0];
+         *body = amount.to_le_bytes();
+      }
+      COption::None => {
+         *tag = [0; 4];
+      }
+   }
+}
+
+fn unpack_coption_key(src: &[u8; 36]) -> Result<COption<Pubkey>, ProgramError> {
+   let (tag, body) = array_refs![src, 4, 32];
+   match *tag {
+      [0, 0, 0, 0] => Ok(COption::None),
+      [1, 0, 0, 0] => Ok(COption::Some(Pubkey::new_from_array(*body))),
+      _ => Err(ProgramError::InvalidAccountData),
+   }
+}
+fn pack_coption_key(src: &COption<Pubkey>, dst: &mut [u8; 36]) {
+   let (tag, body) = mut_array_refs![dst, 4, 32];
+   match src {
+      COption::Some(key) => {
+         *tag = [1, 0, 0, 0];
+         body.copy_from_slice(key.as_ref());
+      }
+      COption::None => {
+         *tag = [0; 4];
+      }
+   }
+}
+
+fn unpack_coption_u64(src: &[u8; 12]) -> Result<COption<u64>, ProgramError> {
+   let (tag, body) = array_refs![src, 4, 8];
+   match *tag {
+      [0, 0, 0, 0] => Ok(COption::None),
+      [1, 0, 0, 0] => Ok(COption::Some(u64::from_le_bytes(*body))),
+      _ => Err(ProgramError::InvalidAccountData),
+   }
+}
+fn pack_coption_u64(src: &COption<u64>, dst: &mut [u8; 12]) {
+   let (tag, body) = mut_array_refs![dst, 4, 8];
+   match src {
+      COption::Some(amount) => {
+         *tag = [1, 0, 0, 0];
+         *body = amount.to_le_bytes();
+      }
+      COption::None => {
+         *tag = [0; 4];
+      }
+   }
+}
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   /// Pool as created by InitializePool: 1_000 per block over slots 10 to 1_010
+   fn test_pool() -> StakePool {
+      StakePool {
+         pool_index: 0,
+         owner: Pubkey::new_unique(),
+         mint: Pubkey::new_unique(),
+         is_initialized: 1,
+         precision_factor_rank: 12,
+         bonus_multiplier: COption::Some(1),
+         bonus_start_block: COption::None,
+         bonus_end_block: COption::None,
+         last_reward_block: 0,
+         start_block: 10,
+         end_block: 1_010,
+         reward_amount: 1_000_000,
+         reward_per_block: 1_000,
+         accrued_token_per_share: 0,
+         reward_decimals: 6,
+         withdraw_fee_bps: 0,
+         whale_threshold: 0,
+         whale_reward_bps: 0,
+         reward_starts_on_first_stake: 0,
+         min_claim_amount: 0,
+         max_reward_per_user: 0,
+         total_supply: 0,
+         max_total_supply: 0,
+         rewards_lock_duration: 0,
+         lock_policy: 0,
+         allowlist_mode: 0,
+         referral_fee_bps: 0,
+         staked_vault: Pubkey::new_unique(),
+         reward_is_native: 0,
+         rate_controller: COption::None,
+         effective_total_supply: 0,
+         allow_zero_rate: 0,
+         partial_claim_on_shortfall: 0,
+         reward_model: 0,
+         simple_interest_bps: 0,
+         rewards_duration: 0,
+         reward_rate: 0,
+         period_finish: 0,
+         last_update_time: 0,
+         unstake_cooldown: 0,
+         is_paused: 0,
+         pending_owner: COption::None,
+         min_stake: 0,
+         reward_mint: Pubkey::new_unique(),
+         fee_recipient: COption::None,
+         early_withdraw_penalty_bps: 0,
+         staker_count: 0,
+         token_program: spl_token::id(),
+         pending_unstake_supply: 0,
+      }
+   }
+
+   /// `update_pool` at `slot` with the staked vault of the pool
+   fn update_pool_at(
+      stake_pool: &mut StakePool,
+      slot: u64,
+   ) -> ProgramResult {
+      let staked_vault = stake_pool.staked_vault;
+      let (mut lamports, mut data) = (0, vec![]);
+      let staked_vault_info = AccountInfo::new(
+         &staked_vault, false, false, &mut lamports, &mut data, &staked_vault, false, 0,
+      );
+      let clock = Clock {
+         slot,
+         ..Clock::default()
+      };
+
+      stake_pool.update_pool(&staked_vault_info, &clock)
+   }
+
+   #[test]
+   fn whale_curve_reduces_only_the_part_above_the_threshold() {
+      let mut stake_pool = test_pool();
+      stake_pool.set_whale_curve(1_000, 5_000).unwrap();
+
+      assert_eq!(stake_pool.apply_whale_curve(500, 500).unwrap(), 500);
+      assert_eq!(stake_pool.apply_whale_curve(1_000, 1_000).unwrap(), 1_000);
+      // 1_000 at the full rate and 1_000 at half of it
+      assert_eq!(stake_pool.apply_whale_curve(2_000, 2_000).unwrap(), 1_500);
+      assert_eq!(stake_pool.apply_whale_curve(4_000, 4_000).unwrap(), 2_500);
+   }
+
+   #[test]
+   fn whale_curve_is_disabled_by_a_zero_threshold() {
+      let mut stake_pool = test_pool();
+      stake_pool.set_whale_curve(0, 5_000).unwrap();
+
+      assert_eq!(stake_pool.apply_whale_curve(4_000, 4_000).unwrap(), 4_000);
+      assert_eq!(
+         stake_pool.set_whale_curve(1_000, 10_001),
+         Err(StakingError::InvalidWhaleRewardBps.into()),
+      );
+   }
+
+   #[test]
+   fn checkpoints_latest_is_newest_first_and_wraps_around() {
+      let mut checkpoints = RewardCheckpoints::new(0);
+      assert!(checkpoints.latest(3).is_empty());
+
+      for slot in 0..CHECKPOINTS_CAPACITY as u64 + 2 {
+         checkpoints.push(Checkpoint {
+            slot,
+            accrued_token_per_share: slot as u128 * 10,
+            total_supply: 1_000,
+         });
+      }
+
+      assert_eq!(checkpoints.count as usize, CHECKPOINTS_CAPACITY);
+      let slots: Vec<u64> = checkpoints.latest(3).iter().map(|checkpoint| checkpoint.slot).collect();
+      let newest = CHECKPOINTS_CAPACITY as u64 + 1;
+      assert_eq!(slots, vec![newest, newest - 1, newest - 2]);
+      // The two oldest were overwritten
+      assert_eq!(checkpoints.latest(usize::MAX).last().unwrap().slot, 2);
+   }
+
+   #[test]
+   fn validate_accepts_a_consistent_pool() {
+      assert_eq!(test_pool().validate(), Ok(()));
+   }
+
+   #[test]
+   fn validate_reports_each_broken_invariant() {
+      let mut stake_pool = test_pool();
+      stake_pool.start_block = 1_011;
+      assert_eq!(stake_pool.validate(), Err(StakingError::InvalidBlockRange.into()));
+
+      let mut stake_pool = test_pool();
+      stake_pool.last_reward_block = 1_011;
+      assert_eq!(stake_pool.validate(), Err(StakingError::LastRewardBlockAfterEnd.into()));
+
+      let mut stake_pool = test_pool();
+      stake_pool.bonus_start_block = COption::Some(200);
+      stake_pool.bonus_end_block = COption::Some(100);
+      assert_eq!(stake_pool.validate(), Err(StakingError::InvalidBonusWindow.into()));
+
+      let mut stake_pool = test_pool();
+      stake_pool.bonus_start_block = COption::Some(100);
+      stake_pool.bonus_end_block = COption::Some(1_011);
+      assert_eq!(stake_pool.validate(), Err(StakingError::InvalidBonusWindow.into()));
+
+      let mut stake_pool = test_pool();
+      stake_pool.precision_factor_rank = PRECISION_FACTOR_RANK_LIMIT + 1;
+      assert_eq!(stake_pool.validate(), Err(StakingError::InvalidPrecisionFactor.into()));
+
+      let mut stake_pool = test_pool();
+      stake_pool.reward_amount = 999_999;
+      assert_eq!(stake_pool.validate(), Err(StakingError::InsufficientRewardAmount.into()));
+   }
+
+   #[test]
+   fn total_supply_survives_pack_and_unpack_between_stakes() {
+      let mut stake_pool = test_pool();
+      stake_pool.increase_total_supply(1_000).unwrap();
+
+      let mut data = vec![0; StakePool::LEN];
+      StakePool::pack(stake_pool, &mut data).unwrap();
+      let mut stake_pool = StakePool::unpack(&data).unwrap();
+      assert_eq!(stake_pool.total_supply, 1_000);
+
+      stake_pool.increase_total_supply(500).unwrap();
+      StakePool::pack(stake_pool, &mut data).unwrap();
+      assert_eq!(StakePool::unpack(&data).unwrap().total_supply, 1_500);
+   }
+
+   #[test]
+   fn utilization_bps_of_an_empty_half_and_full_pool() {
+      let mut stake_pool = test_pool();
+      stake_pool.max_total_supply = 2_000;
+      assert_eq!(stake_pool.utilization_bps(), 0);
+
+      stake_pool.total_supply = 1_000;
+      assert_eq!(stake_pool.utilization_bps(), 5_000);
+
+      stake_pool.total_supply = 2_000;
+      assert_eq!(stake_pool.utilization_bps(), 10_000);
+   }
+
+   #[test]
+   fn utilization_bps_of_an_uncapped_pool_is_zero() {
+      let mut stake_pool = test_pool();
+      stake_pool.total_supply = 1_000;
+
+      assert_eq!(stake_pool.utilization_bps(), 0);
+   }
+
+   #[cfg(feature = "zero-copy")]
+   #[test]
+   fn zero_copy_reads_match_unpack() {
+      let mut stake_pool = test_pool();
+      stake_pool.pool_index = 7;
+      stake_pool.bonus_multiplier = COption::Some(3);
+      stake_pool.bonus_start_block = COption::Some(20);
+      stake_pool.bonus_end_block = COption::Some(40);
+      stake_pool.last_reward_block = 30;
+      stake_pool.accrued_token_per_share = u128::MAX / 3;
+      stake_pool.withdraw_fee_bps = 100;
+      stake_pool.whale_threshold = 5_000;
+      stake_pool.whale_reward_bps = 2_500;
+      stake_pool.min_claim_amount = 11;
+      stake_pool.max_reward_per_user = 12;
+      stake_pool.total_supply = 13;
+      stake_pool.max_total_supply = 14;
+      stake_pool.rewards_lock_duration = 15;
+      stake_pool.lock_policy = 1;
+      stake_pool.allowlist_mode = 1;
+      stake_pool.referral_fee_bps = 16;
+      stake_pool.rate_controller = COption::Some(Pubkey::new_unique());
+      stake_pool.effective_total_supply = 17;
+      stake_pool.simple_interest_bps = 18;
+      stake_pool.rewards_duration = 19;
+      stake_pool.reward_rate = 20;
+      stake_pool.period_finish = 21;
+      stake_pool.last_update_time = 22;
+      stake_pool.unstake_cooldown = 23;
+      stake_pool.is_paused = 1;
+      stake_pool.pending_owner = COption::Some(Pubkey::new_unique());
+      stake_pool.min_stake = 24;
+      stake_pool.fee_recipient = COption::Some(Pubkey::new_unique());
+      stake_pool.early_withdraw_penalty_bps = 25;
+      stake_pool.staker_count = 26;
+      stake_pool.pending_unstake_supply = 27;
+
+      let mut data = vec![0; StakePool::LEN];
+      StakePool::pack(stake_pool, &mut data).unwrap();
+      let unpacked = StakePool::unpack(&data).unwrap();
+      let zero_copy = StakePoolZeroCopy::load(&data).unwrap();
+
+      assert_eq!(zero_copy.pool_index(), unpacked.pool_index);
+      assert_eq!(zero_copy.owner(), unpacked.owner);
+      assert_eq!(zero_copy.mint(), unpacked.mint);
+      assert_eq!(zero_copy.is_initialized(), unpacked.is_initialized != 0);
+      assert_eq!(zero_copy.precision_factor_rank(), unpacked.precision_factor_rank);
+      assert_eq!(zero_copy.bonus_multiplier().unwrap(), unpacked.bonus_multiplier);
+      assert_eq!(zero_copy.bonus_start_block().unwrap(), unpacked.bonus_start_block);
+      assert_eq!(zero_copy.bonus_end_block().unwrap(), unpacked.bonus_end_block);
+      assert_eq!(zero_copy.last_reward_block(), unpacked.last_reward_block);
+      assert_eq!(zero_copy.start_block(), unpacked.start_block);
+      assert_eq!(zero_copy.end_block(), unpacked.end_block);
+      assert_eq!(zero_copy.reward_amount(), unpacked.reward_amount);
+      assert_eq!(zero_copy.reward_per_block(), unpacked.reward_per_block);
+      assert_eq!(zero_copy.accrued_token_per_share(), unpacked.accrued_token_per_share);
+      assert_eq!(zero_copy.reward_decimals(), unpacked.reward_decimals);
+      assert_eq!(zero_copy.withdraw_fee_bps(), unpacked.withdraw_fee_bps);
+      assert_eq!(zero_copy.whale_threshold(), unpacked.whale_threshold);
+      assert_eq!(zero_copy.whale_reward_bps(), unpacked.whale_reward_bps);
+      assert_eq!(zero_copy.reward_starts_on_first_stake(), unpacked.reward_starts_on_first_stake != 0);
+      assert_eq!(zero_copy.min_claim_amount(), unpacked.min_claim_amount);
+      assert_eq!(zero_copy.max_reward_per_user(), unpacked.max_reward_per_user);
+      assert_eq!(zero_copy.total_supply(), unpacked.total_supply);
+      assert_eq!(zero_copy.max_total_supply(), unpacked.max_total_supply);
+      assert_eq!(zero_copy.rewards_lock_duration(), unpacked.rewards_lock_duration);
+      assert_eq!(zero_copy.lock_policy(), unpacked.lock_policy);
+      assert_eq!(zero_copy.allowlist_mode(), unpacked.allowlist_mode);
+      assert_eq!(zero_copy.referral_fee_bps(), unpacked.referral_fee_bps);
+      assert_eq!(zero_copy.staked_vault(), unpacked.staked_vault);
+      assert_eq!(zero_copy.reward_is_native(), unpacked.reward_is_native);
+      assert_eq!(zero_copy.rate_controller().unwrap(), unpacked.rate_controller);
+      assert_eq!(zero_copy.effective_total_supply(), unpacked.effective_total_supply);
+      assert_eq!(zero_copy.allow_zero_rate(), unpacked.allow_zero_rate);
+      assert_eq!(zero_copy.partial_claim_on_shortfall(), unpacked.partial_claim_on_shortfall);
+      assert_eq!(zero_copy.reward_model(), unpacked.reward_model);
+      assert_eq!(zero_copy.simple_interest_bps(), unpacked.simple_interest_bps);
+      assert_eq!(zero_copy.rewards_duration(), unpacked.rewards_duration);
+      assert_eq!(zero_copy.reward_rate(), unpacked.reward_rate);
+      assert_eq!(zero_copy.period_finish(), unpacked.period_finish);
+      assert_eq!(zero_copy.last_update_time(), unpacked.last_update_time);
+      assert_eq!(zero_copy.unstake_cooldown(), unpacked.unstake_cooldown);
+      assert_eq!(zero_copy.is_paused(), unpacked.is_paused);
+      assert_eq!(zero_copy.pending_owner().unwrap(), unpacked.pending_owner);
+      assert_eq!(zero_copy.min_stake(), unpacked.min_stake);
+      assert_eq!(zero_copy.reward_mint(), unpacked.reward_mint);
+      assert_eq!(zero_copy.fee_recipient().unwrap(), unpacked.fee_recipient);
+      assert_eq!(zero_copy.early_withdraw_penalty_bps(), unpacked.early_withdraw_penalty_bps);
+      assert_eq!(zero_copy.staker_count(), unpacked.staker_count);
+      assert_eq!(zero_copy.token_program(), unpacked.token_program);
+      assert_eq!(zero_copy.pending_unstake_supply(), unpacked.pending_unstake_supply);
+   }
+
+   #[test]
+   fn accrual_after_a_huge_idle_gap_is_capped_to_the_window() {
+      let mut stake_pool = test_pool();
+      stake_pool.reward_per_block = u64::MAX;
+      stake_pool.last_reward_block = 10;
+      stake_pool.total_supply = u64::MAX;
+      stake_pool.effective_total_supply = u64::MAX;
+
+      // `u64::MAX * 1_000` blocks only fit in u128
+      update_pool_at(&mut stake_pool, u64::MAX).unwrap();
+
+      assert_eq!(stake_pool.accrued_token_per_share, 1_000 * 1_000_000_000_000);
+      assert_eq!(stake_pool.last_reward_block, stake_pool.end_block);
+   }
+
+   #[test]
+   fn campaign_state_follows_the_block_range() {
+      let stake_pool = test_pool();
+
+      assert_eq!(stake_pool.state(0), CampaignState::NotStarted);
+      assert_eq!(stake_pool.state(9), CampaignState::NotStarted);
+      assert_eq!(stake_pool.state(10), CampaignState::Active);
+      assert_eq!(stake_pool.state(1_009), CampaignState::Active);
+      assert_eq!(stake_pool.state(1_010), CampaignState::Ended);
+      assert_eq!(stake_pool.state(u64::MAX), CampaignState::Ended);
+   }
+
+   #[test]
+   fn campaign_state_is_bonus_active_only_inside_a_multiplying_window() {
+      let mut stake_pool = test_pool();
+      stake_pool.bonus_multiplier = COption::Some(3);
+      stake_pool.bonus_start_block = COption::Some(100);
+      stake_pool.bonus_end_block = COption::Some(200);
+
+      assert_eq!(stake_pool.state(99), CampaignState::Active);
+      assert_eq!(stake_pool.state(100), CampaignState::BonusActive);
+      assert_eq!(stake_pool.state(199), CampaignState::BonusActive);
+      assert_eq!(stake_pool.state(200), CampaignState::Active);
+
+      // A window reaching past `end_block` ends with the campaign
+      stake_pool.bonus_end_block = COption::Some(2_000);
+      assert_eq!(stake_pool.state(1_009), CampaignState::BonusActive);
+      assert_eq!(stake_pool.state(1_010), CampaignState::Ended);
+
+      stake_pool.bonus_multiplier = COption::Some(1);
+      assert_eq!(stake_pool.state(150), CampaignState::Active);
+   }
+
+   #[test]
+   fn coption_tags_other_than_none_and_some_are_refused() {
+      for tag in [[2, 0, 0, 0], [1, 0, 0, 1], [0, 0, 0, 1], [0xff; 4]] {
+         let mut coption_u8 = [0; 5];
+         coption_u8[..4].copy_from_slice(&tag);
+         assert_eq!(unpack_coption_u8(&coption_u8), Err(ProgramError::InvalidAccountData), "{:?}", tag);
+
+         let mut coption_u64 = [0; 12];
+         coption_u64[..4].copy_from_slice(&tag);
+         assert_eq!(unpack_coption_u64(&coption_u64), Err(ProgramError::InvalidAccountData), "{:?}", tag);
+      }
+
+      assert_eq!(unpack_coption_u8(&[1, 0, 0, 0, 7]), Ok(COption::Some(7)));
+      assert_eq!(unpack_coption_u64(&[0; 12]), Ok(COption::None));
+   }
+
+   #[test]
+   fn stake_pool_round_trips_every_field() {
+      let stake_pool = StakePool {
+         pool_index: 1,
+         bonus_multiplier: COption::Some(2),
+         bonus_start_block: COption::Some(3),
+         bonus_end_block: COption::Some(4),
+         last_reward_block: 5,
+         reward_per_block: 6,
+         accrued_token_per_share: u128::MAX - 7,
+         withdraw_fee_bps: 8,
+         whale_threshold: 9,
+         whale_reward_bps: 10,
+         reward_starts_on_first_stake: 1,
+         min_claim_amount: 11,
+         max_reward_per_user: 12,
+         total_supply: 13,
+         max_total_supply: 14,
+         rewards_lock_duration: 15,
+         lock_policy: 2,
+         allowlist_mode: 1,
+         referral_fee_bps: 16,
+         reward_is_native: 1,
+         rate_controller: COption::Some(Pubkey::new_unique()),
+         effective_total_supply: 17,
+         allow_zero_rate: 1,
+         partial_claim_on_shortfall: 1,
+         reward_model: 1,
+         simple_interest_bps: 18,
+         rewards_duration: 19,
+         reward_rate: 20,
+         period_finish: 21,
+         last_update_time: 22,
+         unstake_cooldown: 23,
+         is_paused: 1,
+         pending_owner: COption::Some(Pubkey::new_unique()),
+         min_stake: 24,
+         fee_recipient: COption::Some(Pubkey::new_unique()),
+         early_withdraw_penalty_bps: 25,
+         staker_count: 26,
+         token_program: Pubkey::new_unique(),
+         pending_unstake_supply: 27,
+         ..test_pool()
+      };
+      let mut data = vec![0; StakePool::LEN];
+      StakePool::pack(stake_pool, &mut data).unwrap();
+
+      let unpacked = StakePool::unpack(&data).unwrap();
+      assert_eq!(unpacked.total_supply, 13);
+      assert_eq!(format!("{:?}", unpacked), format!("{:?}", stake_pool));
+   }
+
+   #[test]
+   fn stake_pool_packed_len_is_the_sum_of_its_field_sizes() {
+      // One entry per StakePool field, in the order of `array_refs!`
+      let field_sizes = [
+         8, 32, 32, 1, 1, 5, 12, 12, 8, 8, 8, 8, 8, 16, 1, 2, 8, 2, 1, 8, 8, 8, 8, 8,
+         1, 1, 2, 32, 1, 36, 8, 1, 1, 1, 2, 8, 8, 8, 8, 8, 1, 36, 8, 32, 36, 2, 8, 32, 8,
+      ];
+      assert_eq!(field_sizes.iter().sum::<usize>(), STAKE_POOL_PACKED_LEN);
+      // Reserved for new fields
+      assert_eq!(StakePool::LEN - STAKE_POOL_PACKED_LEN, 10);
+   }
+
+   #[test]
+   fn stake_pool_reserved_space_is_zeroed_by_pack() {
+      let mut data = vec![0xff; StakePool::LEN];
+      StakePool::pack(test_pool(), &mut data).unwrap();
+
+      assert!(data[STAKE_POOL_PACKED_LEN..].iter().all(|byte| *byte == 0));
+   }
+
+   /// Synthetix pool paying 1_000 per second, last updated at second 100 of a period ending at 200
+   fn synthetix_pool() -> StakePool {
+      let mut stake_pool = test_pool();
+      stake_pool.reward_model = RewardModelKind::Synthetix as u8;
+      stake_pool.reward_rate = 1_000;
+      stake_pool.last_update_time = 100;
+      stake_pool.period_finish = 200;
+      stake_pool.accrued_token_per_share = 7;
+      stake_pool
+   }
+
+   fn clock_at_time(
+      unix_timestamp: i64,
+   ) -> Clock {
+      Clock {
+         unix_timestamp,
+         ..Clock::default()
+      }
+   }
+
+   #[test]
+   fn reward_per_token_without_stake_stays_the_stored_one() {
+      let stake_pool = synthetix_pool();
+
+      assert_eq!(stake_pool.get_reward_per_token(&clock_at_time(150)), Ok(7));
+   }
+
+   #[test]
+   fn reward_per_token_adds_the_reward_since_the_last_update() {
+      let mut stake_pool = synthetix_pool();
+      stake_pool.effective_total_supply = 1_000;
+
+      // 50 seconds of 1_000 over 1_000 staked tokens at a precision factor of 10^12
+      assert_eq!(stake_pool.get_reward_per_token(&clock_at_time(150)), Ok(7 + 50 * 1_000_000_000_000));
+      // Nothing accrues after the end of the period
+      assert_eq!(stake_pool.get_reward_per_token(&clock_at_time(500)), Ok(7 + 100 * 1_000_000_000_000));
+   }
+
+   #[test]
+   fn bonus_of_a_long_window_and_a_high_multiplier_accrues_exactly() {
+      let mut stake_pool = test_pool();
+      stake_pool.start_block = 0;
+      stake_pool.end_block = u64::MAX;
+      stake_pool.bonus_multiplier = COption::Some(u8::MAX);
+      stake_pool.bonus_start_block = COption::Some(0);
+      stake_pool.bonus_end_block = COption::Some(u64::MAX);
+      stake_pool.reward_per_block = 1;
+      stake_pool.total_supply = 1;
+      stake_pool.effective_total_supply = 1;
+
+      // Past u64, the bonus blocks only fit in u128
+      let bonus_blocks = u64::MAX as u128 * u8::MAX as u128;
+      assert_eq!(stake_pool.get_multiplier(0, u64::MAX), Ok(bonus_blocks));
+
+      update_pool_at(&mut stake_pool, u64::MAX).unwrap();
+      assert_eq!(stake_pool.accrued_token_per_share, bonus_blocks * 1_000_000_000_000);
+   }
+
+   #[test]
+   fn last_time_reward_applicable_is_the_earlier_of_now_and_period_finish() {
+      let stake_pool = synthetix_pool();
+
+      assert_eq!(stake_pool.get_last_time_reward_applicable(&clock_at_time(150)), Ok(150));
+      assert_eq!(stake_pool.get_last_time_reward_applicable(&clock_at_time(200)), Ok(200));
+      assert_eq!(stake_pool.get_last_time_reward_applicable(&clock_at_time(250)), Ok(200));
+   }
+
+   /// UserInfo of a new position staking `amount`
+   fn test_user_info(
+      amount: u64,
+   ) -> UserInfo {
+      UserInfo {
+         token_account_id: Pubkey::new_unique(),
+         amount,
+         reward_debt: 0,
+         total_claimed: 0,
+         reward_lock_finish: 0,
+         referrer: None,
+         reward_owed: 0,
+         boost_bps: 0,
+         frozen: false,
+         time_weighted_balance: 0,
+         twab_updated_at: 0,
+         pending_unstake: 0,
+         unstake_ready_at: 0,
+         reward_debt_corrected_at: 0,
+      }
+   }
+
+   #[test]
+   fn reward_lock_finish_past_u64_is_an_overflow() {
+      let mut user_info = test_user_info(1_000);
+
+      for lock_policy in [LockPolicy::Reset, LockPolicy::Extend, LockPolicy::KeepEarliest] {
+         assert_eq!(
+            user_info.set_reward_lock_finish(u64::MAX - 10, 11, lock_policy),
+            Err(StakingError::Overflow.into()),
+         );
+      }
+      assert_eq!(user_info.reward_lock_finish, 0);
+
+      user_info.set_reward_lock_finish(u64::MAX - 10, 10, LockPolicy::Reset).unwrap();
+      assert_eq!(user_info.reward_lock_finish, u64::MAX);
+   }
+
+   #[test]
+   fn update_reward_advances_the_share_and_settles_the_user() {
+      let mut stake_pool = synthetix_pool();
+      stake_pool.effective_total_supply = 1_000;
+      let mut user_info = test_user_info(500);
+      user_info.reward_debt = get_reward_debt(500, stake_pool.accrued_token_per_share).unwrap();
+
+      stake_pool.update_reward(&clock_at_time(150), Some(&mut user_info)).unwrap();
+      let after_first = stake_pool.accrued_token_per_share;
+      assert_eq!(after_first, 7 + 50 * 1_000_000_000_000);
+      assert_eq!(stake_pool.last_update_time, 150);
+      assert_eq!(user_info.reward_owed, 25_000);
+      assert_eq!(user_info.reward_debt, get_reward_debt(500, after_first).unwrap());
+
+      stake_pool.update_reward(&clock_at_time(151), Some(&mut user_info)).unwrap();
+      assert!(stake_pool.accrued_token_per_share > after_first);
+      assert_eq!(stake_pool.last_update_time, 151);
+      assert_eq!(user_info.reward_owed, 25_500);
+
+      // Past period_finish nothing accrues any more
+      stake_pool.update_reward(&clock_at_time(300), None).unwrap();
+      let at_finish = stake_pool.accrued_token_per_share;
+      stake_pool.update_reward(&clock_at_time(301), None).unwrap();
+      assert_eq!(stake_pool.accrued_token_per_share, at_finish);
+      assert_eq!(stake_pool.last_update_time, 200);
+   }
+
+   #[test]
+   fn multiplier_of_an_inverted_bonus_window_is_an_error() {
+      let mut stake_pool = test_pool();
+      stake_pool.bonus_multiplier = COption::Some(3);
+      stake_pool.bonus_start_block = COption::Some(200);
+      stake_pool.bonus_end_block = COption::Some(100);
+
+      assert_eq!(stake_pool.get_multiplier(10, 1_010), Err(StakingError::RewardOverflow.into()));
+   }
+
+   #[test]
+   fn multiplier_of_an_empty_range_is_zero() {
+      let mut stake_pool = test_pool();
+      stake_pool.bonus_multiplier = COption::Some(3);
+      stake_pool.bonus_start_block = COption::Some(100);
+      stake_pool.bonus_end_block = COption::Some(200);
+
+      for block in [0, 10, 150, 1_010, 2_000] {
+         assert_eq!(stake_pool.get_multiplier(block, block), Ok(0));
+      }
+      // `to` before `from`, or both outside of the campaign
+      assert_eq!(stake_pool.get_multiplier(150, 120), Ok(0));
+      assert_eq!(stake_pool.get_multiplier(1_500, 2_000), Ok(0));
+      assert_eq!(stake_pool.get_multiplier(140, 160), Ok(60));
+   }
+
+   #[test]
+   fn user_info_round_trips_every_field() {
+      let user_info = UserInfo {
+         token_account_id: Pubkey::new_unique(),
+         amount: 1,
+         reward_debt: u128::MAX - 2,
+         total_claimed: 3,
+         reward_lock_finish: 4,
+         referrer: Some(Pubkey::new_unique()),
+         reward_owed: 5,
+         boost_bps: 6,
+         frozen: true,
+         time_weighted_balance: u128::MAX - 7,
+         twab_updated_at: 8,
+         pending_unstake: 9,
+         unstake_ready_at: 10,
+         reward_debt_corrected_at: 11,
+      };
+      let mut data = vec![0; UserInfo::LEN];
+      UserInfo::pack(user_info, &mut data).unwrap();
+
+      let unpacked = UserInfo::unpack(&data).unwrap();
+      assert_eq!(format!("{:?}", unpacked), format!("{:?}", user_info));
+   }
+
+   #[test]
+   fn user_info_len_is_the_sum_of_its_field_sizes() {
+      // One entry per UserInfo field, in the order of `array_refs!`
+      let field_sizes = [32, 8, 16, 8, 8, 36, 8, 2, 1, 16, 8, 8, 8, 8];
+      assert_eq!(field_sizes.iter().sum::<usize>(), USER_INFO_LEN);
+      assert_eq!(UserInfo::LEN, USER_INFO_LEN);
+   }
+
+   /// Goes through the accrual log, `cargo test --features debug-logs` builds it and runs the
+   /// same accrual as the default features
+   #[test]
+   fn update_pool_accrues_the_same_with_the_debug_logs() {
+      let mut stake_pool = test_pool();
+      stake_pool.effective_total_supply = 1_000;
+      stake_pool.last_reward_block = 10;
+
+      update_pool_at(&mut stake_pool, 20).unwrap();
+      assert_eq!(stake_pool.accrued_token_per_share, 10 * 1_000 * 1_000_000_000_000 / 1_000);
+      assert_eq!(stake_pool.last_reward_block, 20);
+   }
+
+   #[test]
+   fn update_pool_before_the_start_keeps_the_start_block() {
+      let mut stake_pool = test_pool();
+      stake_pool.effective_total_supply = 1_000;
+      stake_pool.last_reward_block = stake_pool.start_block;
+
+      update_pool_at(&mut stake_pool, 5).unwrap();
+      assert_eq!(stake_pool.last_reward_block, 10);
+      assert_eq!(stake_pool.accrued_token_per_share, 0);
+
+      update_pool_at(&mut stake_pool, 20).unwrap();
+      assert_eq!(stake_pool.accrued_token_per_share, 10 * 1_000 * 1_000_000_000_000 / 1_000);
+   }
+
+   #[test]
+   fn pool_is_active_from_the_start_block_until_before_the_end_block() {
+      let stake_pool = test_pool();
+      let is_active_at = |slot| stake_pool.is_active(&Clock { slot, ..Clock::default() });
+
+      assert!(!is_active_at(0));
+      assert!(!is_active_at(9));
+      assert!(is_active_at(10));
+      assert!(is_active_at(1_009));
+      assert!(!is_active_at(1_010));
+      assert!(!is_active_at(u64::MAX));
+   }
+}
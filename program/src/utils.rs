@@ -3,24 +3,419 @@ use solana_program::{
     account_info::{
         AccountInfo
     }, 
+    program::{
+        invoke,
+        invoke_signed,
+        get_return_data,
+    },
+    instruction::{
+        AccountMeta,
+        Instruction,
+    },
     program_error::{
         PrintProgramError,
+        ProgramError,
     },
+    bpf_loader_upgradeable,
+    clock::Clock,
+    rent::Rent,
+    sysvar::Sysvar,
     entrypoint::ProgramResult, 
     program_pack::Pack, 
     pubkey::Pubkey, 
+    program_option::COption,
 };
 use spl_token::{
     state::Account as TokenAccount,
+    state::Mint as TokenMint,
+    error::TokenError,
 };
 use crate::{
-    state::StakePool,
+    state::{
+        StakePool,
+        AllowlistEntry,
+        AllowlistMode,
+        MAX_PRECISION_FACTOR_RANK,
+        BPS_DENOMINATOR,
+    },
     error::StakingError, 
     id as this_program_id,
+    check_token_program_account,
     ADD_SEED_TOKEN_ACCOUNT_AUTHORITY,
-    BUMP_SEED_TOKEN_ACCOUNT_AUTHORITY,
+    ADD_SEED_STATE_POOL,
+    ADD_SEED_WALLET_POOL,
+    ADD_SEED_STAKED,
+    ADD_SEED_MASTER_STAKING,
+    ADD_SEED_ALLOWLIST,
+    ADD_SEED_NATIVE_REWARD,
+    ADD_SEED_REWARD,
 };
 
+/// Checks that `signer_info` signed and is the upgrade authority stored in
+/// `program_data_info`, the ProgramData account of this program
+pub fn assert_upgrade_authority(
+    program_data_info: &AccountInfo,
+    signer_info: &AccountInfo,
+) -> ProgramResult {
+    let (program_data_pubkey, _bump_seed) = Pubkey::find_program_address(
+        &[this_program_id().as_ref()],
+        &bpf_loader_upgradeable::id(),
+    );
+    if *program_data_info.key != program_data_pubkey ||
+       *program_data_info.owner != bpf_loader_upgradeable::id() {
+        StakingError::InvalidUpgradeAuthority.print::<StakingError>();
+        return Err(StakingError::InvalidUpgradeAuthority.into());
+    }
+    if !signer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // bincode UpgradeableLoaderState::ProgramData: u32 tag 3, u64 slot, Option<Pubkey>
+    let data = program_data_info.data.borrow();
+    if data.len() < 45 ||
+       data[0..4] != 3u32.to_le_bytes() ||
+       data[12] != 1 ||
+       data[13..45] != signer_info.key.to_bytes() {
+        StakingError::InvalidUpgradeAuthority.print::<StakingError>();
+        return Err(StakingError::InvalidUpgradeAuthority.into());
+    }
+
+    Ok(())
+}
+
+/// `spl_token` checked transfer of `amount` tokens signed with `signer_seeds`
+/// (empty when `authority_info` signed the transaction). Zero amount makes no CPI
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_tokens<'a>(
+    token_program_info: &AccountInfo<'a>,
+    source_info: &AccountInfo<'a>,
+    mint_info: &AccountInfo<'a>,
+    destination_info: &AccountInfo<'a>,
+    authority_info: &AccountInfo<'a>,
+    signer_seeds: &[&[&[u8]]],
+    amount: u64,
+    decimals: u8,
+) -> ProgramResult {
+    if amount == 0 {
+        return Ok(());
+    }
+    // Signed transfers move pool token-accounts, the authority must be the PDA the seeds derive
+    if !signer_seeds.is_empty() {
+        validate_token_account_authority(authority_info)?;
+    }
+
+    invoke_signed(
+        &for_token_program(
+            spl_token::instruction::transfer_checked(
+                &spl_token::id(),
+                source_info.key,
+                mint_info.key,
+                destination_info.key,
+                authority_info.key,
+                &[authority_info.key],
+                amount,
+                decimals,
+            )?,
+            token_program_info.key,
+        )?,
+        &[
+        source_info.clone(),
+        mint_info.clone(),
+        destination_info.clone(),
+        authority_info.clone(),
+        token_program_info.clone(),
+        ],
+        signer_seeds,
+    )
+}
+
+/// Sends an spl-token `instruction` to `token_program_id`, SPL Token or SPL Token-2022
+pub fn for_token_program(
+    mut instruction: Instruction,
+    token_program_id: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    check_token_program_account(token_program_id)?;
+    instruction.program_id = *token_program_id;
+
+    Ok(instruction)
+}
+
+/// Token-accounts of a pool live under the token program recorded at its initialization
+pub fn validate_token_program(
+    stake_pool: &StakePool,
+    token_program_info: &AccountInfo,
+) -> ProgramResult {
+    if *token_program_info.key != stake_pool.token_program {
+        StakingError::TokenProgramMismatch.print::<StakingError>();
+        return Err(StakingError::TokenProgramMismatch.into());
+    }
+
+    Ok(())
+}
+
+/// Address of the MasterStaking state holding the pool counter
+pub fn master_staking_pda(
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[ADD_SEED_MASTER_STAKING.as_bytes()],
+        program_id,
+    )
+}
+
+/// Address and canonical bump of the PDA owning the pool token-accounts
+pub fn token_account_authority(
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes()],
+        program_id,
+    )
+}
+
+/// Checks that `authority_info` is the token-account authority PDA instead of an account
+/// substituted by the caller
+pub fn validate_token_account_authority(
+    authority_info: &AccountInfo,
+) -> ProgramResult {
+    if *authority_info.key != token_account_authority(&this_program_id()).0 {
+        StakingError::InvalidTokenAccountAuthority.print::<StakingError>();
+        return Err(StakingError::InvalidTokenAccountAuthority.into());
+    }
+
+    Ok(())
+}
+
+/// Checks that `master_staking_info` is the MasterStaking PDA of this program
+pub fn validate_master_staking(
+    master_staking_info: &AccountInfo,
+) -> ProgramResult {
+    if *master_staking_info.key != master_staking_pda(&this_program_id()).0 ||
+       *master_staking_info.owner != this_program_id() {
+        StakingError::InvalidMasterStaking.print::<StakingError>();
+        return Err(StakingError::InvalidMasterStaking.into());
+    }
+
+    Ok(())
+}
+
+/// Address of the StakePool state with index `pool_index`.
+/// Pools are numbered from 0 to `MasterStaking::pool_counter - 1`
+pub fn state_pool_address(
+    program_id: &Pubkey,
+    pool_index: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[&pool_index.to_le_bytes(), ADD_SEED_STATE_POOL.as_bytes()],
+        program_id,
+    )
+}
+
+/// Address of the pool wallet paying for the UserInfo accounts of pool `pool_index`
+pub fn wallet_pool_address(
+    program_id: &Pubkey,
+    pool_index: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[&pool_index.to_le_bytes(), ADD_SEED_WALLET_POOL.as_bytes()],
+        program_id,
+    )
+}
+
+/// Address of the PDA token-account with staked tokens of pool `pool_index`
+pub fn staked_address(
+    program_id: &Pubkey,
+    pool_index: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[&pool_index.to_le_bytes(), ADD_SEED_STAKED.as_bytes()],
+        program_id,
+    )
+}
+
+/// Asks the rate controller of the pool for the current reward per block.
+/// The controller gets `pool_index` as little-endian instruction data and the StakePool
+/// as its only readonly account, and must set exactly 8 bytes of little-endian u64 return data
+pub fn fetch_controller_rate<'a>(
+    stake_pool: &StakePool,
+    pda_stake_pool_info: &AccountInfo<'a>,
+    rate_controller_info: &AccountInfo<'a>,
+) -> Result<u64, ProgramError> {
+    if stake_pool.rate_controller != COption::Some(*rate_controller_info.key) {
+        StakingError::RateControllerMismatch.print::<StakingError>();
+        return Err(StakingError::RateControllerMismatch.into());
+    }
+
+    invoke(
+        &Instruction {
+            program_id: *rate_controller_info.key,
+            accounts: vec![AccountMeta::new_readonly(*pda_stake_pool_info.key, false)],
+            data: stake_pool.pool_index.to_le_bytes().to_vec(),
+        },
+        &[pda_stake_pool_info.clone(), rate_controller_info.clone()],
+    )?;
+
+    match get_return_data() {
+        Some((program_id, data)) if program_id == *rate_controller_info.key => {
+            let rate = <[u8; 8]>::try_from(data.as_slice())
+                .map_err(|_| StakingError::InvalidRateControllerData)?;
+
+            Ok(u64::from_le_bytes(rate))
+        },
+        _ => {
+            StakingError::InvalidRateControllerData.print::<StakingError>();
+            Err(StakingError::InvalidRateControllerData.into())
+        },
+    }
+}
+
+/// Address of the PDA token-account with reward tokens of a pool, it never
+/// coincides with the staked one derived with `ADD_SEED_STAKED`
+pub fn reward_pda(
+    program_id: &Pubkey,
+    pool_index: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[&pool_index.to_le_bytes(), ADD_SEED_REWARD.as_bytes()],
+        program_id,
+    )
+}
+
+pub fn validate_reward_vault(
+    stake_pool: &StakePool,
+    pda_pool_token_account_reward_info: &AccountInfo,
+) -> ProgramResult {
+    if reward_pda(&this_program_id(), stake_pool.pool_index).0 != *pda_pool_token_account_reward_info.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    Ok(())
+}
+
+/// Associated token-account of `wallet` for `mint` under `token_program`. Same seeds as
+/// `get_associated_token_address_with_program_id` of spl-associated-token-account 1.1,
+/// which needs a newer solana-program than this crate builds with
+pub fn associated_token_address(
+    wallet: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &spl_associated_token_account::id(),
+    ).0
+}
+
+/// Address of the lamport-holding reward account of a pool with native SOL reward
+pub fn native_reward_address(
+    program_id: &Pubkey,
+    pool_index: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[&pool_index.to_le_bytes(), ADD_SEED_NATIVE_REWARD.as_bytes()],
+        program_id,
+    )
+}
+
+/// Moves `amount` lamports of native reward to `destination_info`. The reward account is
+/// owned by this program and keeps its rent-exempt minimum. Zero amount does nothing
+pub fn transfer_native_reward(
+    native_reward_info: &AccountInfo,
+    destination_info: &AccountInfo,
+    amount: u64,
+) -> ProgramResult {
+    if amount == 0 {
+        return Ok(());
+    }
+    if *native_reward_info.owner != this_program_id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let min_balance = Rent::get()?.minimum_balance(native_reward_info.data_len());
+    let available = native_reward_info.lamports().saturating_sub(min_balance);
+    if amount > available {
+        StakingError::InsufficientNativeReward.print::<StakingError>();
+        return Err(StakingError::InsufficientNativeReward.into());
+    }
+
+    **native_reward_info.lamports.borrow_mut() = native_reward_info
+        .lamports()
+        .checked_sub(amount)
+        .ok_or(StakingError::Overflow)?;
+    **destination_info.lamports.borrow_mut() = destination_info
+        .lamports()
+        .checked_add(amount)
+        .ok_or(StakingError::Overflow)?;
+
+    Ok(())
+}
+
+/// Address of the AllowlistEntry of `user` in the pool `stake_pool`
+pub fn allowlist_entry_address(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    user: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[stake_pool.as_ref(), user.as_ref(), ADD_SEED_ALLOWLIST.as_bytes()],
+        program_id,
+    )
+}
+
+/// Checks that `user` may stake in the pool. Pools with an allowlist mode need the
+/// AllowlistEntry PDA of the user, it may be empty when no entry was set
+pub fn validate_staker(
+    stake_pool: &StakePool,
+    stake_pool_key: &Pubkey,
+    user: &Pubkey,
+    allowlist_entry_info: Option<&AccountInfo>,
+) -> ProgramResult {
+    let mode = AllowlistMode::from_u8(stake_pool.allowlist_mode)?;
+    if mode == AllowlistMode::Disabled {
+        return Ok(());
+    }
+
+    let allowlist_entry_info = allowlist_entry_info.ok_or(ProgramError::NotEnoughAccountKeys)?;
+    if *allowlist_entry_info.key != allowlist_entry_address(&this_program_id(), stake_pool_key, user).0 {
+        StakingError::InvalidAllowlistEntry.print::<StakingError>();
+        return Err(StakingError::InvalidAllowlistEntry.into());
+    }
+
+    let allowed = if allowlist_entry_info.data_is_empty() {
+        None
+    } else {
+        if *allowlist_entry_info.owner != this_program_id() {
+            StakingError::InvalidAllowlistEntry.print::<StakingError>();
+            return Err(StakingError::InvalidAllowlistEntry.into());
+        }
+        Some(AllowlistEntry::from_account_info(allowlist_entry_info)?.allowed)
+    };
+
+    let is_allowed = match mode {
+        AllowlistMode::Allowlist => allowed == Some(true),
+        _ => allowed != Some(false),
+    };
+    if !is_allowed {
+        StakingError::NotAllowlisted.print::<StakingError>();
+        return Err(StakingError::NotAllowlisted.into());
+    }
+
+    Ok(())
+}
+
+/// Owner-gated instructions must be signed by the owner of the pool
+pub fn assert_pool_owner(
+    stake_pool: &StakePool,
+    signer: &AccountInfo,
+) -> ProgramResult {
+    if !signer.is_signer || *signer.key != stake_pool.owner {
+        StakingError::Unauthorized.print::<StakingError>();
+        return Err(StakingError::Unauthorized.into());
+    }
+
+    Ok(())
+}
+
 pub fn validate_stake_pool(
     stake_pool: &StakePool,
     owner_key: &Pubkey,
@@ -35,16 +430,81 @@ pub fn validate_stake_pool(
     Ok(())
 }
 
+/// Like `validate_stake_pool` for instructions moving reward tokens, `reward_mint_key`
+/// must be the reward mint of the pool
+pub fn validate_pool_reward_mint(
+    stake_pool: &StakePool,
+    owner_key: &Pubkey,
+    reward_mint_key: &Pubkey,
+) -> ProgramResult {
+    if stake_pool.owner != *owner_key || 
+       stake_pool.reward_mint != *reward_mint_key {
+            StakingError::StakePoolMissmatch.print::<StakingError>();
+            return Err(StakingError::StakePoolMissmatch.into());
+    }
+
+    Ok(())
+}
+
+/// Token-account in the SPL Token layout. A Token-2022 token-account with extensions is longer
+/// and refused by name, instead of the generic `InvalidAccountData` of `Pack`
+pub fn unpack_token_account(
+    data: &[u8],
+) -> Result<TokenAccount, ProgramError> {
+    check_no_token_extensions(data, TokenAccount::LEN)?;
+
+    TokenAccount::unpack(data)
+}
+
+/// Mint in the SPL Token layout, unchecked like `Pack::unpack_unchecked`. A Token-2022 mint with
+/// extensions is refused, they can change the amount a transfer moves, e.g. transfer fees
+pub fn unpack_token_mint(
+    data: &[u8],
+) -> Result<TokenMint, ProgramError> {
+    check_no_token_extensions(data, TokenMint::LEN)?;
+
+    TokenMint::unpack_unchecked(data)
+}
+
+/// Token-2022 appends its extensions after the SPL Token layout of `len` bytes
+fn check_no_token_extensions(
+    data: &[u8],
+    len: usize,
+) -> ProgramResult {
+    if data.len() > len {
+        StakingError::TokenExtensionsUnsupported.print::<StakingError>();
+        return Err(StakingError::TokenExtensionsUnsupported.into());
+    }
+
+    Ok(())
+}
+
+/// Checks that `reward_token_account_info` is a token-account of `owner_key` for the reward mint of the pool
+pub fn validate_reward_token_account(
+    stake_pool: &StakePool,
+    reward_token_account_info: &AccountInfo,
+    owner_key: &Pubkey,
+) -> ProgramResult {
+    let reward_token_account = unpack_token_account(
+        &reward_token_account_info.data.borrow(),
+    )?;
+    if reward_token_account.mint != stake_pool.reward_mint {
+        return Err(TokenError::MintMismatch.into());
+    }
+    if reward_token_account.owner != *owner_key {
+        return Err(TokenError::OwnerMismatch.into());
+    }
+
+    Ok(())
+}
+
 pub fn validate_pool_token_account(
     pool_token_account_info: &AccountInfo,
 ) -> ProgramResult {
-    let pool_token_account = TokenAccount::unpack(
+    let pool_token_account = unpack_token_account(
         &pool_token_account_info.data.borrow(),
     )?;
-    let pool_token_account_authority_pubkey = Pubkey::create_program_address(
-        &[ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(), &[BUMP_SEED_TOKEN_ACCOUNT_AUTHORITY]],
-        &this_program_id(),
-    )?;
+    let (pool_token_account_authority_pubkey, _) = token_account_authority(&this_program_id());
 
     if pool_token_account.owner != pool_token_account_authority_pubkey {
         StakingError::PoolTokenAccountMissmatch.print::<StakingError>();
@@ -72,20 +532,67 @@ pub fn validate_user_state(
     Ok(())
 }
 
+/// Integers the checked math helpers below work on
+pub trait CheckedMath: Sized {
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+    fn checked_sub(self, rhs: Self) -> Option<Self>;
+    fn checked_mul(self, rhs: Self) -> Option<Self>;
+    fn checked_div(self, rhs: Self) -> Option<Self>;
+}
+
+macro_rules! impl_checked_math {
+    ($($t:ty),*) => {
+        $(
+            impl CheckedMath for $t {
+                fn checked_add(self, rhs: Self) -> Option<Self> { <$t>::checked_add(self, rhs) }
+                fn checked_sub(self, rhs: Self) -> Option<Self> { <$t>::checked_sub(self, rhs) }
+                fn checked_mul(self, rhs: Self) -> Option<Self> { <$t>::checked_mul(self, rhs) }
+                fn checked_div(self, rhs: Self) -> Option<Self> { <$t>::checked_div(self, rhs) }
+            }
+        )*
+    };
+}
+
+impl_checked_math!(u64, u128);
+
+/// `a + b`, `StakingError::MathOverflow` when it overflows
+pub fn add<T: CheckedMath>(a: T, b: T) -> Result<T, ProgramError> {
+    a.checked_add(b).ok_or_else(|| StakingError::MathOverflow.into())
+}
+
+/// `a - b`, `StakingError::MathOverflow` when `b` is larger
+pub fn sub<T: CheckedMath>(a: T, b: T) -> Result<T, ProgramError> {
+    a.checked_sub(b).ok_or_else(|| StakingError::MathOverflow.into())
+}
+
+/// `a * b`, `StakingError::MathOverflow` when it overflows
+pub fn mul<T: CheckedMath>(a: T, b: T) -> Result<T, ProgramError> {
+    a.checked_mul(b).ok_or_else(|| StakingError::MathOverflow.into())
+}
+
+/// `a / b`, `StakingError::MathOverflow` when `b` is zero
+pub fn div<T: CheckedMath>(a: T, b: T) -> Result<T, ProgramError> {
+    a.checked_div(b).ok_or_else(|| StakingError::MathOverflow.into())
+}
+
+/// Reward earned since `reward_debt` was taken. The debt is kept at the scale of
+/// `accrued_token_per_share` and the result is only truncated here, when it is paid.
+/// The share and the pending amount both round down, so what a campaign pays out never
+/// exceeds what was funded; the rounding dust stays in the reward token-account
 pub fn get_pending(
     current_amount: u64,
     accrued_token_per_share: u128,
     precision_factor_rank: u8,
-    reward_debt: u64,
+    reward_debt: u128,
 ) -> Result<u64, StakingError> {
     let precision_factor = get_precision_factor(precision_factor_rank)?;
 
     let pending = (current_amount as u128) 
         .checked_mul(accrued_token_per_share)
         .ok_or(StakingError::Overflow)?
-        .checked_div(precision_factor as u128)
+        .checked_sub(reward_debt)
         .ok_or(StakingError::Overflow)?
-        .checked_sub(reward_debt as u128)
+        .checked_div(precision_factor as u128)
         .ok_or(StakingError::Overflow)?;
     
     match u64::try_from(pending) {
@@ -94,28 +601,460 @@ pub fn get_pending(
     }
 }
 
+/// Debt of `user_amount` at `accrued_token_per_share`, not divided by the precision factor
+/// so that no rounding accumulates over stake and claim cycles
 pub fn get_reward_debt(
     user_amount: u64,
     accrued_token_per_share: u128,
+) -> Result<u128, StakingError> {
+    let reward_debt = (user_amount as u128)
+        .checked_mul(accrued_token_per_share)
+        .ok_or(StakingError::Overflow)?;
+
+    Ok(reward_debt)
+}
+
+/// Debt of an unchanged `user_amount` once its pending reward is paid. The part of the
+/// share below one token the claim truncated stays owed, instead of being dropped each claim
+pub fn get_reward_debt_after_claim(
+    user_amount: u64,
+    accrued_token_per_share: u128,
     precision_factor_rank: u8,
-) -> Result<u64, StakingError> {
+    reward_debt: u128,
+) -> Result<u128, StakingError> {
     let precision_factor = get_precision_factor(precision_factor_rank)?;
+    let share = get_reward_debt(user_amount, accrued_token_per_share)?;
+    let remainder = share
+        .checked_sub(reward_debt)
+        .ok_or(StakingError::Overflow)?
+        % precision_factor as u128;
 
-    let reward_debt = (user_amount as u128)
-        .checked_mul(accrued_token_per_share)
+    Ok(share - remainder)
+}
+
+/// Basis points falling linearly from `max_bps` at `lock_start` to zero at `lock_finish`
+pub fn linear_penalty(
+    now: u64,
+    lock_start: u64,
+    lock_finish: u64,
+    max_bps: u16,
+) -> Result<u16, StakingError> {
+    if now >= lock_finish {
+        return Ok(0);
+    }
+    if now <= lock_start {
+        return Ok(max_bps);
+    }
+
+    let penalty_bps = (max_bps as u128)
+        .checked_mul((lock_finish - now) as u128)
         .ok_or(StakingError::Overflow)?
-        .checked_div(precision_factor as u128)
-        .ok_or(StakingError::Overflow)? as u64;
+        / (lock_finish - lock_start) as u128;
 
-    Ok(reward_debt)
+    Ok(u16::try_from(penalty_bps)?)
 }
 
+/// `10^precision_factor_rank`, ranks above `MAX_PRECISION_FACTOR_RANK` are rejected.
+/// Pools created with a higher rank are taken down to it by MigratePrecisionFactor
 pub fn get_precision_factor(
     precision_factor_rank: u8,
 ) -> Result<u64, StakingError> {
+    if precision_factor_rank > MAX_PRECISION_FACTOR_RANK {
+        StakingError::InvalidPrecisionFactor.print::<StakingError>();
+        return Err(StakingError::InvalidPrecisionFactor);
+    }
+
     let precision_factor = 10_u64
         .checked_pow(precision_factor_rank as u32)
         .ok_or(StakingError::Overflow)?;
 
     Ok(precision_factor)
-}
\ No newline at end of file
+}
+
+/// Rank Initialize gives pools of a mint with `decimals`: `21 - decimals`, at most
+/// `MAX_PRECISION_FACTOR_RANK`
+pub fn precision_factor_rank_for_decimals(
+    decimals: u8,
+) -> Result<u8, StakingError> {
+    let precision_factor_rank = 21_u8
+        .checked_sub(decimals)
+        .ok_or(StakingError::Overflow)?;
+
+    Ok(precision_factor_rank.min(MAX_PRECISION_FACTOR_RANK))
+}
+
+/// `accrued_token_per_share` after `multiplier` reward blocks at `reward_per_block` are
+/// shared by `staked_supply`. The math of `StakePool::update_pool` without the pool state,
+/// also run by `SelfTestAccrual`
+pub fn simulate_update(
+    accrued_token_per_share: u128,
+    multiplier: u128,
+    reward_per_block: u64,
+    staked_supply: u64,
+    precision_factor_rank: u8,
+) -> Result<u128, ProgramError> {
+    let reward = mul(multiplier, reward_per_block as u128)?;
+
+    let precision_factor = get_precision_factor(precision_factor_rank)?;
+
+    add(
+        accrued_token_per_share,
+        div(mul(reward, precision_factor as u128)?, staked_supply as u128)?,
+    )
+}
+
+/// Handlers take exactly `expected` accounts, extra or missing ones are rejected up front
+pub fn expect_accounts(
+    accounts: &[AccountInfo],
+    expected: usize,
+) -> ProgramResult {
+    if accounts.len() != expected {
+        StakingError::UnexpectedAccountCount.print::<StakingError>();
+        return Err(StakingError::UnexpectedAccountCount.into());
+    }
+
+    Ok(())
+}
+
+/// Current unix timestamp as u64. Local test validators can report a non-positive
+/// `unix_timestamp`, which is rejected instead of wrapping around
+pub fn now_secs(
+    clock: &Clock,
+) -> Result<u64, ProgramError> {
+    if clock.unix_timestamp <= 0 {
+        StakingError::InvalidClock.print::<StakingError>();
+        return Err(StakingError::InvalidClock.into());
+    }
+
+    Ok(clock.unix_timestamp as u64)
+}
+
+/// Reward tokens a campaign emits from `start_block` to `end_block`, blocks of the
+/// bonus window inside it are counted `bonus_multiplier` times.
+/// `bonus_multiplier` of 0 or 1, or an empty bonus window, means no bonus
+pub fn required_reward_funding(
+    reward_per_block: u64,
+    start_block: u64,
+    end_block: u64,
+    bonus_multiplier: u8,
+    bonus_start: u64,
+    bonus_end: u64,
+) -> Result<u64, ProgramError> {
+    let blocks = end_block
+        .checked_sub(start_block)
+        .ok_or(StakingError::InvalidBlockRange)? as u128;
+
+    let bonus_blocks = end_block
+        .min(bonus_end)
+        .saturating_sub(start_block.max(bonus_start)) as u128;
+    let extra_bonus_blocks = bonus_blocks
+        .checked_mul(bonus_multiplier.saturating_sub(1) as u128)
+        .ok_or(StakingError::Overflow)?;
+
+    let funding = blocks
+        .checked_add(extra_bonus_blocks)
+        .ok_or(StakingError::Overflow)?
+        .checked_mul(reward_per_block as u128)
+        .ok_or(StakingError::Overflow)?;
+
+    Ok(u64::try_from(funding).map_err(StakingError::from)?)
+}
+
+/// Yearly reward of `reward_per_block` over `slots_per_year` as bps of `total_supply`.
+/// The reward and the staked token have the same decimals, so the ratio needs no scaling.
+/// A rate per second is passed with the seconds of a year. 0 when nothing is staked
+pub fn compute_apr(
+    reward_per_block: u64,
+    total_supply: u64,
+    slots_per_year: u64,
+) -> Result<u64, ProgramError> {
+    if total_supply == 0 {
+        return Ok(0);
+    }
+
+    let apr = (reward_per_block as u128)
+        .checked_mul(slots_per_year as u128)
+        .ok_or(StakingError::Overflow)?
+        .checked_mul(BPS_DENOMINATOR as u128)
+        .ok_or(StakingError::Overflow)?
+        / total_supply as u128;
+
+    Ok(u64::try_from(apr).map_err(StakingError::from)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock_at(
+        unix_timestamp: i64,
+    ) -> Clock {
+        Clock {
+            unix_timestamp,
+            ..Clock::default()
+        }
+    }
+
+    #[test]
+    fn now_secs_rejects_a_non_positive_timestamp() {
+        assert_eq!(now_secs(&clock_at(-1)), Err(StakingError::InvalidClock.into()));
+        assert_eq!(now_secs(&clock_at(i64::MIN)), Err(StakingError::InvalidClock.into()));
+        assert_eq!(now_secs(&clock_at(0)), Err(StakingError::InvalidClock.into()));
+        assert_eq!(now_secs(&clock_at(1_650_000_000)), Ok(1_650_000_000));
+    }
+
+    /// bincode `UpgradeableLoaderState::ProgramData` with `upgrade_authority`
+    fn program_data(
+        upgrade_authority: Option<&Pubkey>,
+    ) -> Vec<u8> {
+        let mut data = 3u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&42u64.to_le_bytes());
+        match upgrade_authority {
+            Some(upgrade_authority) => {
+                data.push(1);
+                data.extend_from_slice(upgrade_authority.as_ref());
+            },
+            None => data.push(0),
+        }
+
+        data
+    }
+
+    fn check_upgrade_authority(
+        program_data_key: &Pubkey,
+        program_data_owner: &Pubkey,
+        mut data: Vec<u8>,
+        signer: &Pubkey,
+        is_signer: bool,
+    ) -> ProgramResult {
+        let (mut program_data_lamports, mut signer_lamports) = (1, 1);
+        let mut signer_data = vec![];
+        let program_data_info = AccountInfo::new(
+            program_data_key, false, false, &mut program_data_lamports, &mut data, program_data_owner, false, 0,
+        );
+        let system_program = Pubkey::default();
+        let signer_info = AccountInfo::new(
+            signer, is_signer, false, &mut signer_lamports, &mut signer_data, &system_program, false, 0,
+        );
+
+        assert_upgrade_authority(&program_data_info, &signer_info)
+    }
+
+    #[test]
+    fn upgrade_authority_must_sign_and_match_the_program_data() {
+        let (program_data_key, _) = Pubkey::find_program_address(
+            &[this_program_id().as_ref()],
+            &bpf_loader_upgradeable::id(),
+        );
+        let loader = bpf_loader_upgradeable::id();
+        let authority = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let invalid = Err(StakingError::InvalidUpgradeAuthority.into());
+
+        assert_eq!(check_upgrade_authority(&program_data_key, &loader, program_data(Some(&authority)), &authority, true), Ok(()));
+        assert_eq!(check_upgrade_authority(&program_data_key, &loader, program_data(Some(&authority)), &other, true), invalid);
+        assert_eq!(
+            check_upgrade_authority(&program_data_key, &loader, program_data(Some(&authority)), &authority, false),
+            Err(ProgramError::MissingRequiredSignature),
+        );
+        // An immutable program has no upgrade authority
+        assert_eq!(check_upgrade_authority(&program_data_key, &loader, program_data(None), &authority, true), invalid);
+        assert_eq!(check_upgrade_authority(&other, &loader, program_data(Some(&authority)), &authority, true), invalid);
+        assert_eq!(check_upgrade_authority(&program_data_key, &other, program_data(Some(&authority)), &authority, true), invalid);
+    }
+
+    /// Sum of the emission of every block, the bonus window paying `bonus_multiplier` times
+    fn emissions_per_block(
+        reward_per_block: u64,
+        start_block: u64,
+        end_block: u64,
+        bonus_multiplier: u8,
+        bonus_start: u64,
+        bonus_end: u64,
+    ) -> u64 {
+        (start_block..end_block)
+            .map(|block| {
+                if (bonus_start..bonus_end).contains(&block) {
+                    reward_per_block * bonus_multiplier.max(1) as u64
+                } else {
+                    reward_per_block
+                }
+            })
+            .sum()
+    }
+
+    #[test]
+    fn required_reward_funding_without_a_bonus_window() {
+        assert_eq!(required_reward_funding(1_000, 10, 1_010, 1, 0, 0), Ok(1_000_000));
+        assert_eq!(required_reward_funding(1_000, 10, 1_010, 3, 0, 0), Ok(emissions_per_block(1_000, 10, 1_010, 3, 0, 0)));
+        assert_eq!(required_reward_funding(1_000, 10, 1_010, 0, 100, 200), Ok(1_000_000));
+    }
+
+    #[test]
+    fn required_reward_funding_with_a_bonus_window() {
+        // Inside the campaign, overlapping its start and reaching past its end
+        for (bonus_start, bonus_end) in [(100, 200), (0, 50), (1_000, 2_000)] {
+            assert_eq!(
+                required_reward_funding(1_000, 10, 1_010, 3, bonus_start, bonus_end),
+                Ok(emissions_per_block(1_000, 10, 1_010, 3, bonus_start, bonus_end)),
+            );
+        }
+        assert_eq!(required_reward_funding(1_000, 10, 1_010, 3, 100, 200), Ok(1_200_000));
+    }
+
+    #[test]
+    fn required_reward_funding_of_an_inverted_window_is_an_error() {
+        assert_eq!(
+            required_reward_funding(1_000, 1_010, 10, 1, 0, 0),
+            Err(StakingError::InvalidBlockRange.into()),
+        );
+    }
+
+    #[test]
+    fn staked_and_reward_pdas_are_distinct_and_derived_from_their_seeds() {
+        let program_id = crate::id();
+        for pool_index in [0, 1, u64::MAX] {
+            let (staked, staked_bump) = staked_address(&program_id, pool_index);
+            let (reward, reward_bump) = reward_pda(&program_id, pool_index);
+            assert_ne!(staked, reward);
+            assert_eq!(
+                Pubkey::create_program_address(
+                    &[&pool_index.to_le_bytes(), ADD_SEED_STAKED.as_bytes(), &[staked_bump]],
+                    &program_id,
+                ),
+                Ok(staked),
+            );
+            assert_eq!(
+                Pubkey::create_program_address(
+                    &[&pool_index.to_le_bytes(), ADD_SEED_REWARD.as_bytes(), &[reward_bump]],
+                    &program_id,
+                ),
+                Ok(reward),
+            );
+        }
+        assert_ne!(reward_pda(&program_id, 0).0, reward_pda(&program_id, 1).0);
+    }
+
+    /// `bump` signs for `address` and no higher bump gives an address off the curve
+    fn assert_canonical_bump(
+        seed: &[u8],
+        (address, bump): (Pubkey, u8),
+    ) {
+        let program_id = crate::id();
+        assert_eq!(Pubkey::create_program_address(&[seed, &[bump]], &program_id), Ok(address));
+        for higher_bump in (bump..=u8::MAX).skip(1) {
+            assert!(Pubkey::create_program_address(&[seed, &[higher_bump]], &program_id).is_err());
+        }
+    }
+
+    #[test]
+    fn authority_and_master_bumps_are_the_canonical_ones_of_the_program_id() {
+        assert_canonical_bump(
+            ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes(),
+            token_account_authority(&crate::id()),
+        );
+        assert_canonical_bump(
+            ADD_SEED_MASTER_STAKING.as_bytes(),
+            master_staking_pda(&crate::id()),
+        );
+    }
+
+    #[test]
+    fn pool_pdas_of_distinct_pool_indexes_are_distinct() {
+        let program_id = crate::id();
+        let pool_pdas = |pool_index| [
+            state_pool_address(&program_id, pool_index).0,
+            wallet_pool_address(&program_id, pool_index).0,
+            staked_address(&program_id, pool_index).0,
+            reward_pda(&program_id, pool_index).0,
+            native_reward_address(&program_id, pool_index).0,
+        ];
+
+        let mut addresses: Vec<Pubkey> = [0, 1, 256, u64::MAX]
+            .iter()
+            .flat_map(|pool_index| pool_pdas(*pool_index))
+            .collect();
+        let count = addresses.len();
+        addresses.sort();
+        addresses.dedup();
+        assert_eq!(addresses.len(), count);
+    }
+
+    #[test]
+    fn precision_factor_at_the_boundary_ranks() {
+        let precision_factor = |rank| get_precision_factor(rank).map_err(ProgramError::from);
+
+        assert_eq!(precision_factor(0), Ok(1));
+        assert_eq!(precision_factor(MAX_PRECISION_FACTOR_RANK), Ok(1_000_000_000_000));
+        let invalid = Err(StakingError::InvalidPrecisionFactor.into());
+        assert_eq!(precision_factor(13), invalid);
+        assert_eq!(precision_factor(19), invalid);
+        assert_eq!(precision_factor(u8::MAX), invalid);
+    }
+
+    #[test]
+    fn precision_factor_rank_of_the_mint_decimals_is_at_most_the_maximum() {
+        let rank = |decimals| precision_factor_rank_for_decimals(decimals).map_err(ProgramError::from);
+
+        assert_eq!(rank(21), Ok(0));
+        assert_eq!(rank(9), Ok(12));
+        assert_eq!(rank(6), Ok(MAX_PRECISION_FACTOR_RANK));
+        assert_eq!(rank(0), Ok(MAX_PRECISION_FACTOR_RANK));
+        assert_eq!(rank(22), Err(StakingError::Overflow.into()));
+    }
+
+    #[test]
+    fn linear_penalty_falls_from_the_maximum_to_zero_over_the_lock() {
+        let penalty = |now| linear_penalty(now, 1_000, 1_100, 500).ok();
+
+        assert_eq!(penalty(900), Some(500));
+        assert_eq!(penalty(1_000), Some(500));
+        assert_eq!(penalty(1_050), Some(250));
+        assert_eq!(penalty(1_099), Some(5));
+        assert_eq!(penalty(1_100), Some(0));
+        assert_eq!(penalty(u64::MAX), Some(0));
+    }
+
+    #[test]
+    fn apr_of_realistic_pools() {
+        // 0.5s slots
+        let slots_per_year = 63_072_000;
+
+        // 0.001 token per slot over 1M tokens of 6 decimals
+        assert_eq!(compute_apr(1_000, 1_000_000_000_000, slots_per_year).ok(), Some(630));
+        // A year of emission equal to a tenth of the stake
+        assert_eq!(compute_apr(100_000, 63_072_000_000_000, slots_per_year).ok(), Some(1_000));
+        // Below one bps rounds down
+        assert_eq!(compute_apr(1, 1_000_000_000_000, slots_per_year).ok(), Some(0));
+        assert_eq!(compute_apr(1_000, 0, slots_per_year).ok(), Some(0));
+    }
+
+    #[test]
+    fn apr_out_of_range_is_an_error() {
+        assert!(compute_apr(u64::MAX, 1, 1).is_err());
+        assert!(compute_apr(u64::MAX, u64::MAX, u64::MAX).is_err());
+    }
+
+    #[test]
+    fn checked_math_at_the_overflow_boundary() {
+        assert_eq!(add(u64::MAX - 1, 1), Ok(u64::MAX));
+        assert_eq!(add(u64::MAX, 1), Err(StakingError::MathOverflow.into()));
+        assert_eq!(add(u128::MAX - 1, 1), Ok(u128::MAX));
+        assert_eq!(add(u128::MAX, 1), Err(StakingError::MathOverflow.into()));
+
+        assert_eq!(sub(1u64, 1), Ok(0));
+        assert_eq!(sub(0u64, 1), Err(StakingError::MathOverflow.into()));
+        assert_eq!(sub(1u128, 1), Ok(0));
+        assert_eq!(sub(0u128, 1), Err(StakingError::MathOverflow.into()));
+
+        assert_eq!(mul(u64::MAX, 1), Ok(u64::MAX));
+        assert_eq!(mul(u64::MAX / 2 + 1, 2), Err(StakingError::MathOverflow.into()));
+        assert_eq!(mul(u128::MAX, 1), Ok(u128::MAX));
+        assert_eq!(mul(u128::MAX / 2 + 1, 2), Err(StakingError::MathOverflow.into()));
+
+        assert_eq!(div(u64::MAX, 1), Ok(u64::MAX));
+        assert_eq!(div(1u64, 0), Err(StakingError::MathOverflow.into()));
+        assert_eq!(div(u128::MAX, 1), Ok(u128::MAX));
+        assert_eq!(div(1u128, 0), Err(StakingError::MathOverflow.into()));
+    }
+}
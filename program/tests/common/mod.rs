@@ -0,0 +1,1177 @@
+//! In-process harness for the processor tests. Instructions run through `Processor::process`
+//! against an account store. The syscall stubs dispatch CPIs to a minimal system program,
+//! to SPL Token for both token program ids and to mock programs registered by a test.
+//! Account creation inside a CPI resizes the account, which the builtin processor of
+//! solana-program-test doesn't support
+
+#![allow(dead_code)]
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::Once,
+};
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::{
+        ProcessInstruction,
+        ProgramResult,
+        SUCCESS,
+    },
+    bpf_loader_upgradeable,
+    instruction::{
+        AccountMeta,
+        Instruction,
+    },
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::Pack,
+    program_stubs::{
+        set_syscall_stubs,
+        SyscallStubs,
+    },
+    program_utils::limited_deserialize,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction::{
+        SystemError,
+        SystemInstruction,
+    },
+    system_program,
+    sysvar,
+};
+use solana_sdk::account::{
+    create_account_for_test,
+    Account,
+};
+use spl_token::state::{
+    Account as TokenAccount,
+    AccountState,
+    Mint,
+};
+use borsh::{
+    BorshDeserialize,
+    BorshSerialize,
+};
+use stake_reward::{
+    event::StakingEvent,
+    instruction::StakingInstruction,
+    processor::Processor,
+    spl_token_2022,
+    state::{
+        MasterStaking,
+        StakePool,
+        UserInfo,
+    },
+    utils::{
+        allowlist_entry_address,
+        master_staking_pda,
+        reward_pda,
+        staked_address,
+        state_pool_address,
+        token_account_authority,
+        wallet_pool_address,
+    },
+};
+
+/// Slot the tests start at, before the default reward window
+pub const START_SLOT: u64 = 1;
+/// Unix timestamp the tests start at
+pub const START_TIME: i64 = 1_650_000_000;
+
+/// Lamports given to every wallet created by the harness
+const WALLET_LAMPORTS: u64 = 100_000_000_000;
+
+#[derive(Default)]
+struct Runtime {
+    clock: Clock,
+    rent: Rent,
+    programs: HashMap<Pubkey, ProcessInstruction>,
+    /// Programs of the running instruction and its CPIs, the innermost last
+    callers: Vec<Pubkey>,
+    return_data: Option<(Pubkey, Vec<u8>)>,
+    events: Vec<Vec<u8>>,
+    invoked: Vec<Instruction>,
+    /// Accounts allocated by CreateAccount, written back when the instruction succeeds
+    created: HashMap<Pubkey, CreatedAccount>,
+}
+
+/// Owner and buffer of an account allocated by CreateAccount. The buffer is kept as a raw
+/// pointer, `BorshSerialize` writing through `&mut &mut [u8]` shrinks the slice of the AccountInfo
+struct CreatedAccount {
+    owner: Pubkey,
+    data: *mut u8,
+    len: usize,
+}
+
+thread_local! {
+    static RUNTIME: RefCell<Runtime> = RefCell::new(Runtime::default());
+}
+
+struct TestSyscallStubs;
+
+impl SyscallStubs for TestSyscallStubs {
+    fn sol_log(&self, _message: &str) {}
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let caller = RUNTIME.with(|runtime| *runtime.borrow().callers.last().unwrap());
+
+        let mut accounts = Vec::with_capacity(instruction.accounts.len());
+        for meta in &instruction.accounts {
+            let account_info = account_infos
+                .iter()
+                .find(|account_info| *account_info.key == meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            let signed = account_info.is_signer || signers_seeds.iter().any(|seeds| {
+                Pubkey::create_program_address(seeds, &caller) == Ok(meta.pubkey)
+            });
+            if meta.is_signer && !signed {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if meta.is_writable && !account_info.is_writable {
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            // Like in the runtime, an account given twice has the privileges of both metas
+            let metas = instruction.accounts.iter().filter(|other| other.pubkey == meta.pubkey);
+            let mut account_info = account_info.clone();
+            account_info.is_signer = metas.clone().any(|other| other.is_signer);
+            account_info.is_writable = metas.clone().any(|other| other.is_writable);
+            accounts.push(account_info);
+        }
+
+        RUNTIME.with(|runtime| runtime.borrow_mut().invoked.push(instruction.clone()));
+        invoke_program(&instruction.program_id, &accounts, &instruction.data)
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = RUNTIME.with(|runtime| runtime.borrow().clock.clone());
+        unsafe { *(var_addr as *mut Clock) = clock };
+        SUCCESS
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let rent = RUNTIME.with(|runtime| runtime.borrow().rent);
+        unsafe { *(var_addr as *mut Rent) = rent };
+        SUCCESS
+    }
+
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        RUNTIME.with(|runtime| runtime.borrow().return_data.clone())
+    }
+
+    fn sol_set_return_data(&self, data: &[u8]) {
+        RUNTIME.with(|runtime| {
+            let mut runtime = runtime.borrow_mut();
+            let program_id = *runtime.callers.last().unwrap();
+            runtime.return_data = if data.is_empty() {
+                None
+            } else {
+                Some((program_id, data.to_vec()))
+            };
+        });
+    }
+
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        RUNTIME.with(|runtime| runtime.borrow_mut().events.push(fields.concat()));
+    }
+}
+
+/// Runs `program_id` like the runtime does for an instruction or a CPI,
+/// the return data is cleared before the program starts
+fn invoke_program(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let process = RUNTIME.with(|runtime| {
+        let mut runtime = runtime.borrow_mut();
+        runtime.callers.push(*program_id);
+        runtime.return_data = None;
+        runtime.programs.get(program_id).copied()
+    });
+
+    let result = if *program_id == system_program::id() {
+        process_system_instruction(accounts, data)
+    } else if *program_id == spl_token::id() || *program_id == spl_token_2022::id() {
+        spl_token::processor::Processor::process(program_id, accounts, data)
+    } else {
+        match process {
+            Some(process) => process(program_id, accounts, data),
+            None => Err(ProgramError::IncorrectProgramId),
+        }
+    };
+
+    RUNTIME.with(|runtime| runtime.borrow_mut().callers.pop());
+    result
+}
+
+/// CreateAccount and Transfer of the system program, the only ones the staking program sends
+fn process_system_instruction(
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let instruction: SystemInstruction = limited_deserialize(data, 1232)
+        .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+    match instruction {
+        SystemInstruction::CreateAccount { lamports, space, owner } => {
+            let (from, to) = (&accounts[0], &accounts[1]);
+            if to.lamports() != 0 || !to.data_is_empty() {
+                return Err(ProgramError::Custom(SystemError::AccountAlreadyInUse as u32));
+            }
+            move_lamports(from, to, lamports)?;
+
+            let data = Box::leak(vec![0; space as usize].into_boxed_slice());
+            let created = CreatedAccount { owner, data: data.as_mut_ptr(), len: data.len() };
+            *to.data.borrow_mut() = data;
+            RUNTIME.with(|runtime| runtime.borrow_mut().created.insert(*to.key, created));
+
+            Ok(())
+        },
+        SystemInstruction::Transfer { lamports } => {
+            move_lamports(&accounts[0], &accounts[1], lamports)
+        },
+        _ => Err(ProgramError::InvalidInstructionData),
+    }
+}
+
+fn move_lamports(
+    from: &AccountInfo,
+    to: &AccountInfo,
+    lamports: u64,
+) -> ProgramResult {
+    let from_lamports = from.lamports()
+        .checked_sub(lamports)
+        .ok_or(ProgramError::Custom(SystemError::ResultWithNegativeLamports as u32))?;
+    **from.lamports.borrow_mut() = from_lamports;
+    **to.lamports.borrow_mut() += lamports;
+
+    Ok(())
+}
+
+/// Accounts of a pool created by `TestEnv::create_pool`
+#[derive(Debug, Clone, Copy)]
+pub struct Pool {
+    pub index: u64,
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub reward_mint: Pubkey,
+    pub stake_pool: Pubkey,
+    pub wallet: Pubkey,
+    pub staked: Pubkey,
+    pub reward: Pubkey,
+    /// Token-account of the owner for the reward mint, the reward is funded from it
+    pub owner_reward_account: Pubkey,
+    pub token_program: Pubkey,
+}
+
+/// Wallet, token-account of the pool mint and UserInfo PDA of a staker
+#[derive(Debug, Clone, Copy)]
+pub struct Staker {
+    pub owner: Pubkey,
+    pub token_account: Pubkey,
+    pub user_info: Pubkey,
+}
+
+/// Parameters of the InitializePool run by `TestEnv::create_pool`
+#[derive(Debug, Clone, Copy)]
+pub struct PoolConfig {
+    pub reward_amount: u64,
+    pub reward_per_block: u64,
+    pub start_block: u64,
+    pub end_block: u64,
+    pub precision_factor_rank: u8,
+    pub min_stake: u64,
+    pub separate_reward_mint: bool,
+    pub decimals: u8,
+    pub token_program: Pubkey,
+    pub withdraw_fee_bps: u16,
+    pub fee_recipient: Option<Pubkey>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            reward_amount: 1_000_000,
+            reward_per_block: 1_000,
+            start_block: 10,
+            end_block: 1_010,
+            precision_factor_rank: 12,
+            min_stake: 0,
+            separate_reward_mint: false,
+            decimals: 6,
+            token_program: spl_token::id(),
+            withdraw_fee_bps: 0,
+            fee_recipient: None,
+        }
+    }
+}
+
+pub struct TestEnv {
+    pub accounts: HashMap<Pubkey, Account>,
+    pub clock: Clock,
+    pub rent: Rent,
+    pub payer: Pubkey,
+    /// Return data of the last instruction
+    pub return_data: Option<Vec<u8>>,
+    /// `sol_log_data` fields of the last instruction
+    pub events: Vec<Vec<u8>>,
+    /// CPIs of the last instruction
+    pub invoked: Vec<Instruction>,
+}
+
+impl TestEnv {
+    /// Environment with MasterStaking and the token-account authority created
+    pub fn new() -> TestEnv {
+        static STUBS: Once = Once::new();
+        STUBS.call_once(|| {
+            set_syscall_stubs(Box::new(TestSyscallStubs));
+        });
+        Self::add_program(stake_reward::id(), Processor::process);
+
+        let mut env = TestEnv {
+            accounts: HashMap::new(),
+            clock: Clock {
+                slot: START_SLOT,
+                unix_timestamp: START_TIME,
+                ..Clock::default()
+            },
+            rent: Rent::default(),
+            payer: Pubkey::default(),
+            return_data: None,
+            events: vec![],
+            invoked: vec![],
+        };
+        env.update_sysvars();
+        env.payer = env.create_wallet();
+        // bincode `UpgradeableLoaderState::ProgramData` naming the payer as upgrade authority
+        if cfg!(feature = "upgrade-authority") {
+            let mut data = 3u32.to_le_bytes().to_vec();
+            data.extend_from_slice(&0u64.to_le_bytes());
+            data.push(1);
+            data.extend_from_slice(env.payer.as_ref());
+            let mut account = Account::new(env.rent.minimum_balance(data.len()), data.len(), &bpf_loader_upgradeable::id());
+            account.data = data;
+            env.set_account(program_data_address(), account);
+        }
+
+        env.process(create_master_and_authority(&env.payer))
+            .expect("CreateMasterAndAuthority");
+
+        env
+    }
+
+    /// Registers `process` as the program `program_id` for CPIs and instructions
+    pub fn add_program(
+        program_id: Pubkey,
+        process: ProcessInstruction,
+    ) {
+        RUNTIME.with(|runtime| runtime.borrow_mut().programs.insert(program_id, process));
+    }
+
+    /// Runs `instruction` as a transaction of its own. Accounts marked as signers are taken
+    /// as signed, nothing is written when it fails
+    pub fn process(
+        &mut self,
+        instruction: Instruction,
+    ) -> ProgramResult {
+        let mut keys: Vec<(Pubkey, bool, bool)> = vec![];
+        for meta in &instruction.accounts {
+            match keys.iter_mut().find(|(key, _, _)| *key == meta.pubkey) {
+                Some((_, is_signer, is_writable)) => {
+                    *is_signer |= meta.is_signer;
+                    *is_writable |= meta.is_writable;
+                },
+                None => keys.push((meta.pubkey, meta.is_signer, meta.is_writable)),
+            }
+        }
+
+        let mut accounts: Vec<Account> = keys
+            .iter()
+            .map(|(key, _, _)| self.accounts.get(key).cloned().unwrap_or_default())
+            .collect();
+        let account_infos: Vec<AccountInfo> = keys
+            .iter()
+            .zip(accounts.iter_mut())
+            .map(|((key, is_signer, is_writable), account)| AccountInfo::new(
+                key,
+                *is_signer,
+                *is_writable,
+                &mut account.lamports,
+                &mut account.data,
+                &account.owner,
+                account.executable,
+                account.rent_epoch,
+            ))
+            .collect();
+        let instruction_accounts: Vec<AccountInfo> = instruction.accounts
+            .iter()
+            .map(|meta| {
+                account_infos
+                    .iter()
+                    .find(|account_info| *account_info.key == meta.pubkey)
+                    .unwrap()
+                    .clone()
+            })
+            .collect();
+
+        RUNTIME.with(|runtime| {
+            let mut runtime = runtime.borrow_mut();
+            runtime.clock = self.clock.clone();
+            runtime.rent = self.rent;
+            runtime.events.clear();
+            runtime.invoked.clear();
+            runtime.created.clear();
+        });
+
+        let result = invoke_program(&instruction.program_id, &instruction_accounts, &instruction.data);
+
+        let (return_data, events, invoked, created) = RUNTIME.with(|runtime| {
+            let mut runtime = runtime.borrow_mut();
+            (
+                runtime.return_data.take(),
+                std::mem::take(&mut runtime.events),
+                std::mem::take(&mut runtime.invoked),
+                std::mem::take(&mut runtime.created),
+            )
+        });
+        self.return_data = return_data.map(|(_, data)| data);
+        self.events = events;
+        self.invoked = invoked;
+        result?;
+
+        drop(instruction_accounts);
+        drop(account_infos);
+        for ((key, _, _), mut account) in keys.into_iter().zip(accounts) {
+            if let Some(created) = created.get(&key) {
+                account.owner = created.owner;
+                account.data = unsafe { std::slice::from_raw_parts(created.data, created.len) }.to_vec();
+            }
+            // Accounts left without lamports are deleted by the runtime
+            if account.lamports == 0 {
+                self.accounts.remove(&key);
+            } else {
+                self.accounts.insert(key, account);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn update_sysvars(&mut self) {
+        self.accounts.insert(sysvar::clock::id(), create_account_for_test(&self.clock));
+        self.accounts.insert(sysvar::rent::id(), create_account_for_test(&self.rent));
+    }
+
+    /// Moves the clock to `slot`, the unix timestamp is kept
+    pub fn warp_to_slot(
+        &mut self,
+        slot: u64,
+    ) {
+        self.clock.slot = slot;
+        self.update_sysvars();
+    }
+
+    pub fn set_unix_timestamp(
+        &mut self,
+        unix_timestamp: i64,
+    ) {
+        self.clock.unix_timestamp = unix_timestamp;
+        self.update_sysvars();
+    }
+
+    pub fn set_account(
+        &mut self,
+        key: Pubkey,
+        account: Account,
+    ) {
+        self.accounts.insert(key, account);
+    }
+
+    pub fn account(
+        &self,
+        key: &Pubkey,
+    ) -> Option<&Account> {
+        self.accounts.get(key)
+    }
+
+    pub fn lamports(
+        &self,
+        key: &Pubkey,
+    ) -> u64 {
+        self.account(key).map_or(0, |account| account.lamports)
+    }
+
+    /// System account with `WALLET_LAMPORTS`
+    pub fn create_wallet(&mut self) -> Pubkey {
+        let wallet = Pubkey::new_unique();
+        self.set_account(wallet, Account::new(WALLET_LAMPORTS, 0, &system_program::id()));
+
+        wallet
+    }
+
+    pub fn create_mint(
+        &mut self,
+        decimals: u8,
+        token_program: &Pubkey,
+    ) -> Pubkey {
+        let mint = Pubkey::new_unique();
+        let mut account = Account::new(self.rent.minimum_balance(Mint::LEN), Mint::LEN, token_program);
+        Mint {
+            mint_authority: COption::Some(self.payer),
+            supply: 0,
+            decimals,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        }.pack_into_slice(&mut account.data);
+        self.set_account(mint, account);
+
+        mint
+    }
+
+    pub fn create_token_account(
+        &mut self,
+        mint: &Pubkey,
+        owner: &Pubkey,
+        amount: u64,
+    ) -> Pubkey {
+        let token_account = Pubkey::new_unique();
+        self.create_token_account_at(token_account, mint, owner, amount);
+
+        token_account
+    }
+
+    /// Token-account at `address`, e.g. an associated token-account
+    pub fn create_token_account_at(
+        &mut self,
+        address: Pubkey,
+        mint: &Pubkey,
+        owner: &Pubkey,
+        amount: u64,
+    ) {
+        let token_program = self.account(mint).unwrap().owner;
+        let mut account = Account::new(self.rent.minimum_balance(TokenAccount::LEN), TokenAccount::LEN, &token_program);
+        TokenAccount {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        }.pack_into_slice(&mut account.data);
+        self.set_account(address, account);
+
+        let mut mint_account = self.account(mint).unwrap().clone();
+        let mut mint_state = Mint::unpack(&mint_account.data).unwrap();
+        mint_state.supply += amount;
+        mint_state.pack_into_slice(&mut mint_account.data);
+        self.set_account(*mint, mint_account);
+    }
+
+    /// Mints `amount` more into `token_account`
+    pub fn mint_to(
+        &mut self,
+        token_account: &Pubkey,
+        amount: u64,
+    ) {
+        let mut account = self.account(token_account).unwrap().clone();
+        let mut token_account_state = TokenAccount::unpack(&account.data).unwrap();
+        token_account_state.amount += amount;
+        token_account_state.pack_into_slice(&mut account.data);
+        self.set_account(*token_account, account);
+
+        let mut mint_account = self.account(&token_account_state.mint).unwrap().clone();
+        let mut mint_state = Mint::unpack(&mint_account.data).unwrap();
+        mint_state.supply += amount;
+        mint_state.pack_into_slice(&mut mint_account.data);
+        self.set_account(token_account_state.mint, mint_account);
+    }
+
+    pub fn token_balance(
+        &self,
+        token_account: &Pubkey,
+    ) -> u64 {
+        TokenAccount::unpack(&self.account(token_account).unwrap().data).unwrap().amount
+    }
+
+    pub fn pool_count(&self) -> u64 {
+        let master = self.account(&master_staking_pda(&stake_reward::id()).0).unwrap();
+        MasterStaking::deserialize(&mut &master.data[..]).unwrap().pool_counter
+    }
+
+    /// InitializePool of a new mint, funded with the reward amount by a new owner
+    pub fn create_pool(
+        &mut self,
+        config: PoolConfig,
+    ) -> Pool {
+        let pool = self.new_pool(config);
+        self.process(initialize_pool(&pool, &config)).expect("InitializePool");
+
+        pool
+    }
+
+    /// Accounts of the next pool with its mints and a funded owner, without running InitializePool
+    pub fn new_pool(
+        &mut self,
+        config: PoolConfig,
+    ) -> Pool {
+        let mint = self.create_mint(config.decimals, &config.token_program);
+        self.new_pool_of_mint(config, mint)
+    }
+
+    /// InitializePool of another pool staking `mint`
+    pub fn create_pool_of_mint(
+        &mut self,
+        config: PoolConfig,
+        mint: Pubkey,
+    ) -> Pool {
+        let pool = self.new_pool_of_mint(config, mint);
+        self.process(initialize_pool(&pool, &config)).expect("InitializePool");
+
+        pool
+    }
+
+    pub fn new_pool_of_mint(
+        &mut self,
+        config: PoolConfig,
+        mint: Pubkey,
+    ) -> Pool {
+        let owner = self.create_wallet();
+        let reward_mint = if config.separate_reward_mint {
+            self.create_mint(config.decimals, &config.token_program)
+        } else {
+            mint
+        };
+        let owner_reward_account = self.create_token_account(&reward_mint, &owner, config.reward_amount);
+
+        let program_id = stake_reward::id();
+        let index = self.pool_count();
+        Pool {
+            index,
+            owner,
+            mint,
+            reward_mint,
+            stake_pool: state_pool_address(&program_id, index).0,
+            wallet: wallet_pool_address(&program_id, index).0,
+            staked: staked_address(&program_id, index).0,
+            reward: reward_pda(&program_id, index).0,
+            owner_reward_account,
+            token_program: config.token_program,
+        }
+    }
+
+    /// Same wallet and token-account as `staker`, with its UserInfo in `pool`
+    pub fn staker_in(
+        &self,
+        pool: &Pool,
+        staker: &Staker,
+    ) -> Staker {
+        Staker {
+            user_info: user_info_address(pool, &staker.token_account),
+            ..*staker
+        }
+    }
+
+    /// Wallet with a token-account of the pool mint holding `amount`
+    pub fn create_staker(
+        &mut self,
+        pool: &Pool,
+        amount: u64,
+    ) -> Staker {
+        let owner = self.create_wallet();
+        let token_account = self.create_token_account(&pool.mint, &owner, amount);
+
+        Staker {
+            owner,
+            token_account,
+            user_info: user_info_address(pool, &token_account),
+        }
+    }
+
+    pub fn stake_pool(
+        &self,
+        pool: &Pool,
+    ) -> StakePool {
+        StakePool::unpack(&self.account(&pool.stake_pool).unwrap().data).unwrap()
+    }
+
+    pub fn user_info(
+        &self,
+        staker: &Staker,
+    ) -> UserInfo {
+        UserInfo::unpack(&self.account(&staker.user_info).unwrap().data).unwrap()
+    }
+
+    /// Overwrites the StakePool account with `stake_pool`
+    pub fn set_stake_pool(
+        &mut self,
+        pool: &Pool,
+        stake_pool: StakePool,
+    ) {
+        let mut account = self.account(&pool.stake_pool).unwrap().clone();
+        StakePool::pack(stake_pool, &mut account.data).unwrap();
+        self.set_account(pool.stake_pool, account);
+    }
+
+    /// Overwrites the UserInfo account of `staker` with `user_info`
+    pub fn set_user_info(
+        &mut self,
+        staker: &Staker,
+        user_info: UserInfo,
+    ) {
+        let mut account = self.account(&staker.user_info).unwrap().clone();
+        UserInfo::pack(user_info, &mut account.data).unwrap();
+        self.set_account(staker.user_info, account);
+    }
+
+    pub fn staking_events(&self) -> Vec<StakingEvent> {
+        self.events
+            .iter()
+            .map(|event| StakingEvent::try_from_slice(event).unwrap())
+            .collect()
+    }
+}
+
+pub fn user_info_address(
+    pool: &Pool,
+    token_account: &Pubkey,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &[pool.stake_pool.as_ref(), token_account.as_ref()],
+        &stake_reward::id(),
+    ).0
+}
+
+pub fn authority() -> Pubkey {
+    token_account_authority(&stake_reward::id()).0
+}
+
+pub fn staking_instruction(
+    accounts: Vec<AccountMeta>,
+    instruction: StakingInstruction,
+) -> Instruction {
+    Instruction {
+        program_id: stake_reward::id(),
+        accounts,
+        data: instruction.try_to_vec().unwrap(),
+    }
+}
+
+pub fn create_master_and_authority(
+    payer: &Pubkey,
+) -> Instruction {
+    let program_id = stake_reward::id();
+    let mut instruction = staking_instruction(
+        vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(token_account_authority(&program_id).0, false),
+            AccountMeta::new(master_staking_pda(&program_id).0, false),
+            AccountMeta::new_readonly(program_id, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        StakingInstruction::CreateMasterAndAuthority,
+    );
+    if cfg!(feature = "upgrade-authority") {
+        instruction.accounts.push(AccountMeta::new_readonly(program_data_address(), false));
+    }
+
+    instruction
+}
+
+/// ProgramData account of the program, checked by CreateMasterAndAuthority with `upgrade-authority`
+pub fn program_data_address() -> Pubkey {
+    Pubkey::find_program_address(&[stake_reward::id().as_ref()], &bpf_loader_upgradeable::id()).0
+}
+
+/// Accounts 0 to 12 shared by Initialize and InitializePool
+pub fn initialize_accounts(
+    pool: &Pool,
+) -> Vec<AccountMeta> {
+    let program_id = stake_reward::id();
+    vec![
+        AccountMeta::new(pool.owner, true),
+        AccountMeta::new(master_staking_pda(&program_id).0, false),
+        AccountMeta::new(pool.stake_pool, false),
+        AccountMeta::new(pool.wallet, false),
+        AccountMeta::new_readonly(program_id, false),
+        AccountMeta::new_readonly(pool.mint, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(pool.token_program, false),
+        AccountMeta::new(pool.owner_reward_account, false),
+        AccountMeta::new(authority(), false),
+        AccountMeta::new(pool.staked, false),
+        AccountMeta::new(pool.reward, false),
+    ]
+}
+
+pub fn initialize_pool(
+    pool: &Pool,
+    config: &PoolConfig,
+) -> Instruction {
+    let mut accounts = initialize_accounts(pool);
+    if config.separate_reward_mint {
+        accounts.push(AccountMeta::new_readonly(pool.reward_mint, false));
+    }
+
+    staking_instruction(
+        accounts,
+        StakingInstruction::InitializePool {
+            reward_amount: config.reward_amount,
+            reward_per_block: config.reward_per_block,
+            start_block: config.start_block,
+            end_block: config.end_block,
+            precision_factor_rank: config.precision_factor_rank,
+            min_stake: config.min_stake,
+            separate_reward_mint: config.separate_reward_mint,
+            withdraw_fee_bps: config.withdraw_fee_bps,
+            fee_recipient: config.fee_recipient,
+        },
+    )
+}
+
+pub fn deposit(
+    pool: &Pool,
+    staker: &Staker,
+    amount: u64,
+) -> Instruction {
+    staking_instruction(
+        vec![
+            AccountMeta::new_readonly(staker.owner, true),
+            AccountMeta::new(staker.token_account, false),
+            AccountMeta::new_readonly(pool.mint, false),
+            AccountMeta::new(pool.stake_pool, false),
+            AccountMeta::new_readonly(authority(), false),
+            AccountMeta::new(pool.staked, false),
+            AccountMeta::new(pool.reward, false),
+            AccountMeta::new(pool.wallet, false),
+            AccountMeta::new(staker.user_info, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(pool.token_program, false),
+        ],
+        StakingInstruction::Deposit { amount },
+    )
+}
+
+/// Accounts 0 to 9 shared by Withdraw, GetReward, RequestUnstake and Compound
+pub fn position_accounts(
+    pool: &Pool,
+    staker: &Staker,
+) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(staker.owner, true),
+        AccountMeta::new(staker.token_account, false),
+        AccountMeta::new(pool.stake_pool, false),
+        AccountMeta::new_readonly(authority(), false),
+        AccountMeta::new(pool.staked, false),
+        AccountMeta::new(pool.reward, false),
+        AccountMeta::new(staker.user_info, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(pool.token_program, false),
+        AccountMeta::new_readonly(pool.reward_mint, false),
+    ]
+}
+
+pub fn withdraw(
+    pool: &Pool,
+    staker: &Staker,
+    amount: u64,
+) -> Instruction {
+    let mut accounts = position_accounts(pool, staker);
+    accounts[9] = AccountMeta::new_readonly(pool.mint, false);
+
+    staking_instruction(accounts, StakingInstruction::Withdraw { amount })
+}
+
+pub fn emergency_withdraw(
+    pool: &Pool,
+    staker: &Staker,
+) -> Instruction {
+    staking_instruction(
+        vec![
+            AccountMeta::new_readonly(staker.owner, true),
+            AccountMeta::new(staker.token_account, false),
+            AccountMeta::new_readonly(authority(), false),
+            AccountMeta::new(pool.staked, false),
+            AccountMeta::new(staker.user_info, false),
+            AccountMeta::new(pool.stake_pool, false),
+            AccountMeta::new_readonly(pool.token_program, false),
+            AccountMeta::new_readonly(pool.mint, false),
+        ],
+        StakingInstruction::EmergencyWithdraw,
+    )
+}
+
+pub fn get_reward(
+    pool: &Pool,
+    staker: &Staker,
+) -> Instruction {
+    staking_instruction(
+        position_accounts(pool, staker),
+        StakingInstruction::GetReward,
+    )
+}
+
+/// Owner-gated instruction taking the owner, the mint and the StakePool
+pub fn owner_instruction(
+    pool: &Pool,
+    signer: &Pubkey,
+    instruction: StakingInstruction,
+) -> Instruction {
+    staking_instruction(
+        vec![
+            AccountMeta::new_readonly(*signer, true),
+            AccountMeta::new_readonly(pool.mint, false),
+            AccountMeta::new(pool.stake_pool, false),
+        ],
+        instruction,
+    )
+}
+
+pub fn set_reward_per_block(
+    pool: &Pool,
+    reward_per_block: u64,
+) -> Instruction {
+    let mut instruction = owner_instruction(
+        pool,
+        &pool.owner,
+        StakingInstruction::SetRewardPerBlock { reward_per_block },
+    );
+    instruction.accounts.push(AccountMeta::new_readonly(pool.staked, false));
+    instruction.accounts.push(AccountMeta::new_readonly(sysvar::clock::id(), false));
+
+    instruction
+}
+
+pub fn set_pause(
+    pool: &Pool,
+    paused: bool,
+) -> Instruction {
+    owner_instruction(pool, &pool.owner, StakingInstruction::SetPause { paused })
+}
+
+/// HarvestFor of `staker` paying to `reward_destination`, signed by nobody
+pub fn harvest_for(
+    pool: &Pool,
+    staker: &Staker,
+    reward_destination: &Pubkey,
+) -> Instruction {
+    staking_instruction(
+        vec![
+            AccountMeta::new_readonly(staker.token_account, false),
+            AccountMeta::new(*reward_destination, false),
+            AccountMeta::new(pool.stake_pool, false),
+            AccountMeta::new_readonly(authority(), false),
+            AccountMeta::new_readonly(pool.staked, false),
+            AccountMeta::new(pool.reward, false),
+            AccountMeta::new(staker.user_info, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(pool.token_program, false),
+            AccountMeta::new_readonly(pool.reward_mint, false),
+        ],
+        StakingInstruction::HarvestFor { user: staker.owner },
+    )
+}
+
+/// Initialize, the reward per block and the precision factor are derived by the program
+pub fn initialize(
+    pool: &Pool,
+    config: &PoolConfig,
+    reward_starts_on_first_stake: bool,
+    allow_zero_rate: bool,
+) -> Instruction {
+    staking_instruction(
+        initialize_accounts(pool),
+        StakingInstruction::Initialize {
+            reward_amount: config.reward_amount,
+            start_block: config.start_block,
+            end_block: config.end_block,
+            reward_starts_on_first_stake,
+            allow_zero_rate,
+        },
+    )
+}
+
+/// TransferPosition of `staker` to the token-account of `new_owner`
+pub fn transfer_position(
+    pool: &Pool,
+    staker: &Staker,
+    new_owner: &Staker,
+) -> Instruction {
+    staking_instruction(
+        vec![
+            AccountMeta::new_readonly(staker.owner, true),
+            AccountMeta::new(staker.token_account, false),
+            AccountMeta::new_readonly(new_owner.token_account, false),
+            AccountMeta::new_readonly(pool.mint, false),
+            AccountMeta::new(pool.stake_pool, false),
+            AccountMeta::new_readonly(authority(), false),
+            AccountMeta::new_readonly(pool.staked, false),
+            AccountMeta::new(pool.reward, false),
+            AccountMeta::new(staker.user_info, false),
+            AccountMeta::new(new_owner.user_info, false),
+            AccountMeta::new(pool.wallet, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(pool.token_program, false),
+        ],
+        StakingInstruction::TransferPosition { new_owner: new_owner.token_account },
+    )
+}
+
+/// CompoundInto of the reward of `staker` in `pool` into `target_pool`
+pub fn compound_into(
+    pool: &Pool,
+    target_pool: &Pool,
+    staker: &Staker,
+) -> Instruction {
+    staking_instruction(
+        vec![
+            AccountMeta::new_readonly(staker.owner, true),
+            AccountMeta::new(staker.token_account, false),
+            AccountMeta::new_readonly(pool.mint, false),
+            AccountMeta::new(pool.stake_pool, false),
+            AccountMeta::new_readonly(authority(), false),
+            AccountMeta::new_readonly(pool.staked, false),
+            AccountMeta::new(pool.reward, false),
+            AccountMeta::new(staker.user_info, false),
+            AccountMeta::new(target_pool.stake_pool, false),
+            AccountMeta::new(target_pool.staked, false),
+            AccountMeta::new(target_pool.reward, false),
+            AccountMeta::new(user_info_address(target_pool, &staker.token_account), false),
+            AccountMeta::new(target_pool.wallet, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(pool.token_program, false),
+        ],
+        StakingInstruction::CompoundInto { target_pool: target_pool.stake_pool },
+    )
+}
+
+pub fn allowlist_entry(
+    pool: &Pool,
+    user: &Pubkey,
+) -> Pubkey {
+    allowlist_entry_address(&stake_reward::id(), &pool.stake_pool, user).0
+}
+
+pub fn set_allowlist_entry(
+    pool: &Pool,
+    user: &Pubkey,
+    allowed: bool,
+) -> Instruction {
+    staking_instruction(
+        vec![
+            AccountMeta::new(pool.owner, true),
+            AccountMeta::new_readonly(pool.mint, false),
+            AccountMeta::new_readonly(pool.stake_pool, false),
+            AccountMeta::new(allowlist_entry(pool, user), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        StakingInstruction::SetAllowlistEntry { user: *user, allowed },
+    )
+}
+
+/// Deposit into a pool with an allowlist mode, the AllowlistEntry of the staker is added
+pub fn deposit_allowlisted(
+    pool: &Pool,
+    staker: &Staker,
+    amount: u64,
+) -> Instruction {
+    let mut instruction = deposit(pool, staker, amount);
+    instruction.accounts.push(AccountMeta::new_readonly(allowlist_entry(pool, &staker.owner), false));
+
+    instruction
+}
+
+pub fn reset_pool(
+    pool: &Pool,
+    new_start: u64,
+    new_end: u64,
+    new_reward_per_block: u64,
+) -> Instruction {
+    let mut instruction = owner_instruction(
+        pool,
+        &pool.owner,
+        StakingInstruction::ResetPool { new_start, new_end, new_reward_per_block },
+    );
+    instruction.accounts[1] = AccountMeta::new_readonly(pool.reward_mint, false);
+    instruction.accounts.extend([
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(pool.token_program, false),
+        AccountMeta::new(pool.owner_reward_account, false),
+        AccountMeta::new(pool.reward, false),
+    ]);
+
+    instruction
+}
+
+pub fn extend_reward_period(
+    pool: &Pool,
+    new_end_block: u64,
+    additional_reward: u64,
+) -> Instruction {
+    staking_instruction(
+        vec![
+            AccountMeta::new_readonly(pool.owner, true),
+            AccountMeta::new_readonly(pool.reward_mint, false),
+            AccountMeta::new(pool.stake_pool, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(pool.token_program, false),
+            AccountMeta::new(pool.owner_reward_account, false),
+            AccountMeta::new(pool.reward, false),
+            AccountMeta::new_readonly(pool.staked, false),
+        ],
+        StakingInstruction::ExtendRewardPeriod { new_end_block, additional_reward },
+    )
+}
+
+pub fn close_pool(
+    pool: &Pool,
+) -> Instruction {
+    let mut instruction = owner_instruction(pool, &pool.owner, StakingInstruction::ClosePool);
+    instruction.accounts[0] = AccountMeta::new(pool.owner, true);
+    instruction.accounts.extend([
+        AccountMeta::new(pool.wallet, false),
+        AccountMeta::new_readonly(authority(), false),
+        AccountMeta::new(pool.staked, false),
+        AccountMeta::new(pool.reward, false),
+        AccountMeta::new(pool.owner_reward_account, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(pool.token_program, false),
+    ]);
+    if pool.reward_mint != pool.mint {
+        instruction.accounts.push(AccountMeta::new_readonly(pool.reward_mint, false));
+    }
+
+    instruction
+}
+
+pub fn set_referrer(
+    pool: &Pool,
+    staker: &Staker,
+    referrer: &Pubkey,
+) -> Instruction {
+    staking_instruction(
+        vec![
+            AccountMeta::new_readonly(staker.owner, true),
+            AccountMeta::new_readonly(staker.token_account, false),
+            AccountMeta::new_readonly(pool.stake_pool, false),
+            AccountMeta::new(staker.user_info, false),
+        ],
+        StakingInstruction::SetReferrer { referrer: *referrer },
+    )
+}
@@ -0,0 +1,331 @@
+mod common;
+
+use common::*;
+use borsh::BorshDeserialize;
+use solana_program::{
+    instruction::Instruction,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program,
+};
+use solana_sdk::account::Account;
+use spl_token::instruction::TokenInstruction;
+use stake_reward::{
+    error::StakingError,
+    event::StakingEvent,
+    instruction::StakingInstruction,
+    state::{
+        AllowlistMode,
+        LockPolicy,
+    },
+};
+
+#[test]
+fn total_supply_adds_up_over_deposits_of_separate_transactions() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let first = env.create_staker(&pool, 1_000);
+    let second = env.create_staker(&pool, 500);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &first, 1_000)).unwrap();
+    env.warp_to_slot(11);
+    env.process(deposit(&pool, &second, 500)).unwrap();
+
+    assert_eq!(env.stake_pool(&pool).total_supply, 1_500);
+    assert_eq!(env.token_balance(&pool.staked), 1_500);
+}
+
+#[test]
+fn zero_amount_transfer_makes_no_cpi() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 100)).unwrap();
+    assert_eq!(token_transfers(&env), vec![100]);
+
+    // Same slot, so there is no pending reward either
+    env.process(deposit(&pool, &staker, 0)).unwrap();
+    assert!(env.invoked.is_empty());
+    assert_eq!(env.token_balance(&staker.token_account), 900);
+}
+
+fn token_transfers(
+    env: &TestEnv,
+) -> Vec<u64> {
+    env.invoked
+        .iter()
+        .filter(|instruction| instruction.program_id == spl_token::id())
+        .map(|instruction| match TokenInstruction::unpack(&instruction.data).unwrap() {
+            TokenInstruction::TransferChecked { amount, .. } => amount,
+            other => panic!("unexpected token instruction {:?}", other),
+        })
+        .collect()
+}
+
+#[test]
+fn stake_during_the_reward_lock_follows_the_lock_policy() {
+    for (lock_policy, reward_lock_finish) in [
+        (LockPolicy::Reset, START_TIME + 40 + 100),
+        (LockPolicy::Extend, START_TIME + 100 + 100),
+        (LockPolicy::KeepEarliest, START_TIME + 100),
+    ] {
+        let mut env = TestEnv::new();
+        let pool = env.create_pool(PoolConfig::default());
+        env.process(owner_instruction(
+            &pool,
+            &pool.owner,
+            StakingInstruction::UpdateRewardLock { rewards_lock_duration: 100, lock_policy: lock_policy as u8 },
+        )).unwrap();
+        let staker = env.create_staker(&pool, 1_000);
+
+        env.warp_to_slot(10);
+        env.process(deposit(&pool, &staker, 500)).unwrap();
+        assert_eq!(env.user_info(&staker).reward_lock_finish, START_TIME as u64 + 100);
+
+        env.warp_to_slot(11);
+        env.set_unix_timestamp(START_TIME + 40);
+        env.process(deposit(&pool, &staker, 500)).unwrap();
+        assert_eq!(env.user_info(&staker).reward_lock_finish, reward_lock_finish as u64, "{:?}", lock_policy);
+    }
+}
+
+#[test]
+fn allowlist_mode_admits_only_allowed_users() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateAllowlistMode { allowlist_mode: AllowlistMode::Allowlist as u8 },
+    )).unwrap();
+    let allowed = env.create_staker(&pool, 1_000);
+    let unlisted = env.create_staker(&pool, 1_000);
+    let disallowed = env.create_staker(&pool, 1_000);
+    env.process(set_allowlist_entry(&pool, &allowed.owner, true)).unwrap();
+    env.process(set_allowlist_entry(&pool, &disallowed.owner, false)).unwrap();
+
+    env.warp_to_slot(10);
+    env.process(deposit_allowlisted(&pool, &allowed, 1_000)).unwrap();
+    for staker in [unlisted, disallowed] {
+        assert_eq!(
+            env.process(deposit_allowlisted(&pool, &staker, 1_000)),
+            Err(StakingError::NotAllowlisted.into()),
+        );
+    }
+    assert_eq!(env.stake_pool(&pool).total_supply, 1_000);
+}
+
+#[test]
+fn denylist_mode_refuses_only_disallowed_users() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateAllowlistMode { allowlist_mode: AllowlistMode::Denylist as u8 },
+    )).unwrap();
+    let unlisted = env.create_staker(&pool, 1_000);
+    let disallowed = env.create_staker(&pool, 1_000);
+    env.process(set_allowlist_entry(&pool, &disallowed.owner, false)).unwrap();
+
+    env.warp_to_slot(10);
+    env.process(deposit_allowlisted(&pool, &unlisted, 1_000)).unwrap();
+    assert_eq!(
+        env.process(deposit_allowlisted(&pool, &disallowed, 1_000)),
+        Err(StakingError::NotAllowlisted.into()),
+    );
+    assert_eq!(env.stake_pool(&pool).total_supply, 1_000);
+}
+
+#[test]
+fn stakers_of_the_same_average_stake_end_with_the_same_twab() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let early = env.create_staker(&pool, 1_000);
+    let late = env.create_staker(&pool, 2_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &early, 1_000)).unwrap();
+    env.warp_to_slot(11);
+    env.set_unix_timestamp(START_TIME + 50);
+    env.process(deposit(&pool, &late, 2_000)).unwrap();
+    env.warp_to_slot(12);
+    env.set_unix_timestamp(START_TIME + 100);
+    env.process(deposit(&pool, &early, 0)).unwrap();
+    env.process(deposit(&pool, &late, 0)).unwrap();
+
+    // 1_000 over 100 seconds and 2_000 over 50 seconds
+    assert_eq!(env.user_info(&early).time_weighted_balance, 100_000);
+    assert_eq!(env.user_info(&late).time_weighted_balance, 100_000);
+    assert_eq!(env.user_info(&late).twab_updated_at, START_TIME as u64 + 100);
+}
+
+#[test]
+fn pause_blocks_deposits_while_withdrawals_and_rewards_go_on() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 2_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    env.process(set_pause(&pool, true)).unwrap();
+    assert_eq!(
+        env.process(deposit(&pool, &staker, 1_000)),
+        Err(StakingError::PoolPaused.into()),
+    );
+
+    env.warp_to_slot(30);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 1_000 + 20_000);
+    env.process(withdraw(&pool, &staker, 1_000)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 2_000 + 20_000);
+
+    env.process(set_pause(&pool, false)).unwrap();
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    assert_eq!(env.stake_pool(&pool).total_supply, 1_000);
+}
+
+#[test]
+fn first_deposit_below_the_minimum_is_refused_and_a_small_top_up_accepted() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig { min_stake: 500, ..PoolConfig::default() });
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    assert_eq!(
+        env.process(deposit(&pool, &staker, 499)),
+        Err(StakingError::BelowMinimumStake.into()),
+    );
+    env.process(deposit(&pool, &staker, 500)).unwrap();
+    env.process(deposit(&pool, &staker, 1)).unwrap();
+
+    assert_eq!(env.user_info(&staker).amount, 501);
+}
+
+fn update_max_total_supply(
+    pool: &Pool,
+    max_total_supply: u64,
+) -> Instruction {
+    owner_instruction(pool, &pool.owner, StakingInstruction::UpdateMaxTotalSupply { max_total_supply })
+}
+
+#[test]
+fn deposit_up_to_the_cap_is_accepted_and_past_it_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 2_000);
+    assert_eq!(env.stake_pool(&pool).remaining_capacity(), u64::MAX);
+
+    env.process(update_max_total_supply(&pool, 1_000)).unwrap();
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 600)).unwrap();
+    assert_eq!(env.stake_pool(&pool).remaining_capacity(), 400);
+    assert_eq!(
+        env.process(deposit(&pool, &staker, 401)),
+        Err(StakingError::PoolCapExceeded.into()),
+    );
+    env.process(deposit(&pool, &staker, 400)).unwrap();
+
+    assert_eq!(env.stake_pool(&pool).total_supply, 1_000);
+    assert_eq!(env.stake_pool(&pool).remaining_capacity(), 0);
+}
+
+#[test]
+fn deposit_logs_the_deposited_event_bytes() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+
+    // Variant index of `Deposited`, then pool_index, user, amount and slot in little endian
+    let mut expected = vec![1];
+    expected.extend_from_slice(&pool.index.to_le_bytes());
+    expected.extend_from_slice(staker.owner.as_ref());
+    expected.extend_from_slice(&1_000u64.to_le_bytes());
+    expected.extend_from_slice(&10u64.to_le_bytes());
+    assert_eq!(env.events, vec![expected.clone()]);
+    assert_eq!(
+        StakingEvent::try_from_slice(&expected).unwrap(),
+        StakingEvent::Deposited { pool_index: pool.index, user: staker.owner, amount: 1_000, slot: 10 },
+    );
+}
+
+#[test]
+fn deposit_is_taken_from_the_start_block_until_before_the_end_block() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig::default();
+    let pool = env.create_pool(config);
+    let staker = env.create_staker(&pool, 2_000);
+
+    env.warp_to_slot(config.start_block - 1);
+    assert_eq!(
+        env.process(deposit(&pool, &staker, 1_000)),
+        Err(StakingError::PoolNotStarted.into()),
+    );
+    env.warp_to_slot(config.start_block);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(config.end_block - 1);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(config.end_block);
+    assert_eq!(
+        env.process(deposit(&pool, &staker, 1)),
+        Err(StakingError::CampaignEnded.into()),
+    );
+
+    // The top-up paid the reward of the first deposit
+    env.process(emergency_withdraw(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 2_000 + 999 * 1_000);
+    assert_eq!(env.token_balance(&pool.staked), 0);
+}
+
+#[test]
+fn stake_pool_other_than_the_pda_of_its_index_is_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+    env.warp_to_slot(10);
+
+    // Same state at another address
+    let copy = Pubkey::new_unique();
+    let stake_pool_account = env.account(&pool.stake_pool).unwrap().clone();
+    env.set_account(copy, stake_pool_account.clone());
+    let copied_pool = Pool { stake_pool: copy, ..pool };
+    let copied_staker = Staker { user_info: user_info_address(&copied_pool, &staker.token_account), ..staker };
+    assert_eq!(
+        env.process(deposit(&copied_pool, &copied_staker, 1_000)),
+        Err(ProgramError::InvalidSeeds),
+    );
+
+    // The pool address with an account of another program
+    env.set_account(pool.stake_pool, Account { owner: system_program::id(), ..stake_pool_account.clone() });
+    assert_eq!(
+        env.process(deposit(&pool, &staker, 1_000)),
+        Err(ProgramError::IncorrectProgramId),
+    );
+    env.set_account(pool.stake_pool, stake_pool_account);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+}
+
+#[test]
+fn deposit_into_another_token_account_position_is_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let victim = env.create_staker(&pool, 1_000);
+    let attacker = env.create_staker(&pool, 1);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &victim, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    let forged = Staker { user_info: victim.user_info, ..attacker };
+    assert_eq!(
+        env.process(deposit(&pool, &forged, 1)),
+        Err(StakingError::UserInfoMissmatch.into()),
+    );
+    assert_eq!(env.token_balance(&attacker.token_account), 1);
+}
@@ -0,0 +1,639 @@
+mod common;
+
+use common::*;
+use borsh::BorshDeserialize;
+use solana_program::{
+    instruction::{
+        AccountMeta,
+        Instruction,
+    },
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_program,
+    sysvar,
+};
+use solana_sdk::account::Account;
+use stake_reward::{
+    error::StakingError,
+    instruction::StakingInstruction,
+    state::{
+        RewardCheckpoints,
+        StakePool,
+        MAX_CAMPAIGN_BLOCKS,
+        MAX_POOLS_PER_BATCH,
+        MAX_PRECISION_FACTOR_RANK,
+        POOL_RESET_GRACE_BLOCKS,
+    },
+    utils::{
+        master_staking_pda,
+        reward_pda,
+        staked_address,
+        state_pool_address,
+        token_account_authority,
+        wallet_pool_address,
+    },
+    ADD_SEED_CHECKPOINTS,
+    ADD_SEED_TOKEN_ACCOUNT_AUTHORITY,
+};
+
+#[test]
+fn authority_derived_with_a_non_canonical_bump_is_refused() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig::default();
+    let pool = env.new_pool(config);
+
+    // The program derives its bumps, so a PDA of another bump is never its authority
+    let seeds = ADD_SEED_TOKEN_ACCOUNT_AUTHORITY.as_bytes();
+    let (_, canonical_bump) = token_account_authority(&stake_reward::id());
+    let non_canonical_authority = (0..canonical_bump)
+        .rev()
+        .find_map(|bump| Pubkey::create_program_address(&[seeds, &[bump]], &stake_reward::id()).ok())
+        .unwrap();
+    let mut instruction = initialize_pool(&pool, &config);
+    instruction.accounts[10] = AccountMeta::new(non_canonical_authority, false);
+
+    assert_eq!(
+        env.process(instruction),
+        Err(StakingError::InvalidTokenAccountAuthority.into()),
+    );
+}
+
+#[test]
+fn checkpoint_records_the_updated_pool() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+    let (checkpoints_address, _) = Pubkey::find_program_address(
+        &[&pool.index.to_le_bytes(), ADD_SEED_CHECKPOINTS.as_bytes()],
+        &stake_reward::id(),
+    );
+    let payer = env.payer;
+    let checkpoint = staking_instruction(
+        vec![
+            AccountMeta::new(payer, true),
+            AccountMeta::new(pool.stake_pool, false),
+            AccountMeta::new_readonly(pool.staked, false),
+            AccountMeta::new(checkpoints_address, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        StakingInstruction::Checkpoint,
+    );
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    for slot in [20, 30, 40] {
+        env.warp_to_slot(slot);
+        env.process(checkpoint.clone()).unwrap();
+    }
+
+    let checkpoints = RewardCheckpoints::deserialize(
+        &mut &env.account(&checkpoints_address).unwrap().data[..],
+    ).unwrap();
+    let latest: Vec<(u64, u128, u64)> = checkpoints
+        .latest(2)
+        .iter()
+        .map(|checkpoint| (checkpoint.slot, checkpoint.accrued_token_per_share, checkpoint.total_supply))
+        .collect();
+    // 1_000 per block shared by 1_000 staked, at a precision factor of 10^12
+    assert_eq!(latest, vec![
+        (40, 30 * 1_000_000_000_000, 1_000),
+        (30, 20 * 1_000_000_000_000, 1_000),
+    ]);
+}
+
+#[test]
+fn separate_reward_mint_of_other_decimals_is_refused() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig { separate_reward_mint: true, ..PoolConfig::default() };
+    let mut pool = env.new_pool(config);
+    pool.reward_mint = env.create_mint(config.decimals + 3, &config.token_program);
+    pool.owner_reward_account = env.create_token_account(&pool.reward_mint, &pool.owner, config.reward_amount);
+
+    assert_eq!(
+        env.process(initialize_pool(&pool, &config)),
+        Err(StakingError::DecimalsMismatch.into()),
+    );
+}
+
+#[test]
+fn compound_into_a_pool_of_other_reward_decimals_is_refused() {
+    let mut env = TestEnv::new();
+    let source = env.create_pool(PoolConfig::default());
+    let target = env.create_pool_of_mint(PoolConfig::default(), source.mint);
+    let staker = env.create_staker(&source, 1_000);
+    let mut target_pool = env.stake_pool(&target);
+    target_pool.reward_decimals += 3;
+    env.set_stake_pool(&target, target_pool);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&source, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    assert_eq!(
+        env.process(compound_into(&source, &target, &staker)),
+        Err(StakingError::DecimalsMismatch.into()),
+    );
+}
+
+#[test]
+fn reward_vault_aliasing_the_staked_vault_is_refused() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig::default();
+    let pool = env.new_pool(config);
+    let mut instruction = initialize_pool(&pool, &config);
+    instruction.accounts[12] = AccountMeta::new(pool.staked, false);
+
+    assert_eq!(
+        env.process(instruction),
+        Err(StakingError::VaultAliasing.into()),
+    );
+}
+
+fn create_pools(
+    env: &mut TestEnv,
+    count: u8,
+) -> (Instruction, Vec<u64>) {
+    let config = PoolConfig::default();
+    let program_id = stake_reward::id();
+    let owner = env.create_wallet();
+    let mint = env.create_mint(config.decimals, &config.token_program);
+    let reward_account = env.create_token_account(&mint, &owner, config.reward_amount * count as u64);
+
+    let mut accounts = vec![
+        AccountMeta::new(owner, true),
+        AccountMeta::new(master_staking_pda(&program_id).0, false),
+        AccountMeta::new_readonly(program_id, false),
+        AccountMeta::new_readonly(mint, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(config.token_program, false),
+        AccountMeta::new(reward_account, false),
+        AccountMeta::new(authority(), false),
+    ];
+    let indexes: Vec<u64> = (env.pool_count()..env.pool_count() + count as u64).collect();
+    for &pool_index in &indexes {
+        accounts.push(AccountMeta::new(state_pool_address(&program_id, pool_index).0, false));
+        accounts.push(AccountMeta::new(wallet_pool_address(&program_id, pool_index).0, false));
+        accounts.push(AccountMeta::new(staked_address(&program_id, pool_index).0, false));
+        accounts.push(AccountMeta::new(reward_pda(&program_id, pool_index).0, false));
+    }
+
+    let instruction = staking_instruction(
+        accounts,
+        StakingInstruction::CreatePools {
+            count,
+            reward_amount: config.reward_amount,
+            start_block: config.start_block,
+            end_block: config.end_block,
+            reward_starts_on_first_stake: false,
+            allow_zero_rate: false,
+        },
+    );
+
+    (instruction, indexes)
+}
+
+#[test]
+fn create_pools_initializes_consecutive_pools() {
+    let mut env = TestEnv::new();
+    env.create_pool(PoolConfig::default());
+    let (instruction, indexes) = create_pools(&mut env, 3);
+
+    env.process(instruction).unwrap();
+
+    assert_eq!(indexes, vec![1, 2, 3]);
+    assert_eq!(env.pool_count(), 4);
+    for pool_index in indexes {
+        let stake_pool = env.account(&state_pool_address(&stake_reward::id(), pool_index).0).unwrap();
+        let stake_pool = StakePool::unpack(&stake_pool.data).unwrap();
+        assert_eq!(stake_pool.pool_index, pool_index);
+        assert_eq!(stake_pool.reward_amount, 1_000_000);
+        assert_eq!(env.token_balance(&reward_pda(&stake_reward::id(), pool_index).0), 1_000_000);
+    }
+}
+
+#[test]
+fn create_pools_above_the_batch_limit_is_refused() {
+    let mut env = TestEnv::new();
+    let (instruction, _) = create_pools(&mut env, MAX_POOLS_PER_BATCH + 1);
+
+    assert_eq!(
+        env.process(instruction),
+        Err(StakingError::InvalidPoolBatch.into()),
+    );
+    assert_eq!(env.pool_count(), 0);
+}
+
+#[test]
+fn master_staking_other_than_the_pda_is_refused() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig::default();
+    let pool = env.new_pool(config);
+    // Even owned by the program, an account at another address isn't the master
+    let fake_master = Pubkey::new_unique();
+    let mut account = Account::new(env.rent.minimum_balance(8), 8, &stake_reward::id());
+    account.data = 5u64.to_le_bytes().to_vec();
+    env.set_account(fake_master, account);
+    let mut instruction = initialize_pool(&pool, &config);
+    instruction.accounts[1] = AccountMeta::new(fake_master, false);
+
+    assert_eq!(
+        env.process(instruction),
+        Err(StakingError::InvalidMasterStaking.into()),
+    );
+}
+
+#[test]
+fn reset_pool_runs_a_second_campaign_on_the_same_accounts() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(1_010);
+    env.process(withdraw(&pool, &staker, 1_000)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 1_000 + 1_000_000);
+
+    let reset_at = 1_010 + POOL_RESET_GRACE_BLOCKS + 1;
+    env.mint_to(&pool.owner_reward_account, 50_000);
+    assert_eq!(
+        env.process(reset_pool(&pool, reset_at, reset_at + 100, 500)),
+        Err(StakingError::PoolNotResettable.into()),
+    );
+    env.warp_to_slot(reset_at);
+    env.process(reset_pool(&pool, reset_at, reset_at + 100, 500)).unwrap();
+
+    let stake_pool = env.stake_pool(&pool);
+    assert_eq!(stake_pool.pool_index, pool.index);
+    assert_eq!((stake_pool.start_block, stake_pool.end_block), (reset_at, reset_at + 100));
+    assert_eq!(stake_pool.accrued_token_per_share, 0);
+    assert_eq!(env.token_balance(&pool.reward), 50_000);
+
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(reset_at + 100);
+    env.process(withdraw(&pool, &staker, 1_000)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 1_000 + 1_000_000 + 50_000);
+}
+
+#[test]
+fn zero_reward_per_block_is_refused_without_allow_zero_rate() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig { reward_amount: 0, reward_per_block: 0, ..PoolConfig::default() };
+    let pool = env.new_pool(config);
+
+    assert_eq!(
+        env.process(initialize(&pool, &config, false, false)),
+        Err(StakingError::ZeroRewardRate.into()),
+    );
+    let pool = env.create_pool(PoolConfig::default());
+    assert_eq!(
+        env.process(set_reward_per_block(&pool, 0)),
+        Err(StakingError::ZeroRewardRate.into()),
+    );
+    assert_eq!(env.stake_pool(&pool).reward_per_block, 1_000);
+}
+
+#[test]
+fn zero_reward_per_block_is_allowed_with_allow_zero_rate() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig { reward_amount: 0, reward_per_block: 0, ..PoolConfig::default() };
+    let pool = env.new_pool(config);
+
+    env.process(initialize(&pool, &config, false, true)).unwrap();
+    assert_eq!(env.stake_pool(&pool).reward_per_block, 0);
+    env.process(set_reward_per_block(&pool, 0)).unwrap();
+}
+
+#[test]
+fn campaign_window_up_to_the_maximum_is_accepted() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig {
+        reward_amount: MAX_CAMPAIGN_BLOCKS,
+        reward_per_block: 1,
+        start_block: 10,
+        end_block: 10 + MAX_CAMPAIGN_BLOCKS,
+        ..PoolConfig::default()
+    };
+
+    let pool = env.create_pool(config);
+    assert_eq!(env.stake_pool(&pool).end_block, 10 + MAX_CAMPAIGN_BLOCKS);
+}
+
+#[test]
+fn campaign_window_beyond_the_maximum_is_refused() {
+    let mut env = TestEnv::new();
+    for end_block in [10 + MAX_CAMPAIGN_BLOCKS + 1, u64::MAX] {
+        let config = PoolConfig {
+            reward_amount: MAX_CAMPAIGN_BLOCKS + 1,
+            reward_per_block: 1,
+            start_block: 10,
+            end_block,
+            ..PoolConfig::default()
+        };
+        let pool = env.new_pool(config);
+
+        assert_eq!(
+            env.process(initialize_pool(&pool, &config)),
+            Err(StakingError::CampaignWindowTooLong.into()),
+        );
+    }
+}
+
+#[test]
+fn reward_paid_and_swept_on_close_adds_up_to_the_funding() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig::default();
+    let pool = env.create_pool(config);
+    let owner_reward_before = env.token_balance(&pool.owner_reward_account);
+    let stakes = [1_000, 3, 7_777];
+    let stakers: Vec<Staker> = stakes
+        .iter()
+        .map(|amount| env.create_staker(&pool, *amount))
+        .collect();
+
+    // Stakes and claims on their own schedules, with blocks of no stake in between
+    env.warp_to_slot(40);
+    env.process(deposit(&pool, &stakers[0], 1_000)).unwrap();
+    env.warp_to_slot(41);
+    env.process(deposit(&pool, &stakers[1], 3)).unwrap();
+    env.warp_to_slot(333);
+    env.process(get_reward(&pool, &stakers[1])).unwrap();
+    env.process(withdraw(&pool, &stakers[0], 1_000)).unwrap();
+    env.warp_to_slot(500);
+    env.process(deposit(&pool, &stakers[2], 7_777)).unwrap();
+    env.warp_to_slot(777);
+    env.process(get_reward(&pool, &stakers[2])).unwrap();
+    env.warp_to_slot(config.end_block + 1);
+    for (staker, stake) in stakers.iter().zip(stakes) {
+        if env.user_info(staker).amount > 0 {
+            env.process(withdraw(&pool, staker, stake)).unwrap();
+        }
+    }
+    env.process(close_pool(&pool)).unwrap();
+
+    let claimed: u64 = stakers.iter().map(|staker| env.user_info(staker).total_claimed).sum();
+    let paid: u64 = stakers
+        .iter()
+        .zip(stakes)
+        .map(|(staker, stake)| env.token_balance(&staker.token_account) - stake)
+        .sum();
+    let swept = env.token_balance(&pool.owner_reward_account) - owner_reward_before;
+    assert_eq!(paid, claimed);
+    assert_eq!(paid + swept, config.reward_amount);
+    assert!(env.account(&pool.stake_pool).is_none());
+}
+
+#[test]
+fn initialize_pool_returns_the_index_of_the_new_pool() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig::default();
+
+    for pool_index in [0u64, 1] {
+        let pool = env.new_pool(config);
+        assert_eq!(pool.index, pool_index);
+        env.process(initialize_pool(&pool, &config)).unwrap();
+        assert_eq!(env.return_data, Some(pool_index.to_le_bytes().to_vec()));
+    }
+}
+
+#[test]
+fn initialize_pool_accepts_ranks_up_to_the_maximum() {
+    let mut env = TestEnv::new();
+    for precision_factor_rank in [0, MAX_PRECISION_FACTOR_RANK] {
+        let pool = env.create_pool(PoolConfig { precision_factor_rank, ..PoolConfig::default() });
+        assert_eq!(env.stake_pool(&pool).precision_factor_rank, precision_factor_rank);
+    }
+
+    let config = PoolConfig { precision_factor_rank: MAX_PRECISION_FACTOR_RANK + 1, ..PoolConfig::default() };
+    let pool = env.new_pool(config);
+    assert_eq!(
+        env.process(initialize_pool(&pool, &config)),
+        Err(StakingError::InvalidPrecisionFactor.into()),
+    );
+}
+
+#[test]
+fn pool_of_a_legacy_rank_is_migrated_once_without_stake() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    // Rank Initialize derived for a mint of 6 decimals before the cap
+    env.set_stake_pool(&pool, StakePool {
+        precision_factor_rank: 15,
+        accrued_token_per_share: 7_000_123,
+        ..env.stake_pool(&pool)
+    });
+    let migrate = |pool: &Pool| owner_instruction(pool, &pool.owner, StakingInstruction::MigratePrecisionFactor);
+
+    env.warp_to_slot(20);
+    assert_eq!(env.process(get_reward(&pool, &staker)), Err(StakingError::InvalidPrecisionFactor.into()));
+    assert_eq!(env.process(migrate(&pool)), Err(StakingError::PoolNotEmpty.into()));
+
+    env.process(emergency_withdraw(&pool, &staker)).unwrap();
+    env.process(migrate(&pool)).unwrap();
+    let stake_pool = env.stake_pool(&pool);
+    assert_eq!(stake_pool.precision_factor_rank, MAX_PRECISION_FACTOR_RANK);
+    assert_eq!(stake_pool.accrued_token_per_share, 7_000);
+
+    env.process(migrate(&pool)).unwrap();
+    assert_eq!(env.stake_pool(&pool).accrued_token_per_share, 7_000);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+}
+
+#[test]
+fn close_pool_returns_the_rent_and_the_reward_left_to_the_owner() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig::default();
+    let pool = env.create_pool(config);
+    let owner_reward_before = env.token_balance(&pool.owner_reward_account);
+    let rent: u64 = [pool.stake_pool, pool.wallet, pool.staked, pool.reward]
+        .iter()
+        .map(|address| env.lamports(address))
+        .sum();
+    let owner_before = env.lamports(&pool.owner);
+
+    env.warp_to_slot(config.end_block + 1);
+    env.process(close_pool(&pool)).unwrap();
+
+    assert_eq!(env.lamports(&pool.owner), owner_before + rent);
+    assert_eq!(env.token_balance(&pool.owner_reward_account), owner_reward_before + config.reward_amount);
+    for address in [pool.stake_pool, pool.wallet, pool.staked, pool.reward] {
+        assert!(env.account(&address).is_none());
+    }
+}
+
+#[test]
+fn close_pool_with_stake_or_before_the_end_is_refused() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig::default();
+    let pool = env.create_pool(config);
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(config.end_block - 1);
+    assert_eq!(env.process(close_pool(&pool)), Err(StakingError::CampaignNotEnded.into()));
+
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(config.end_block + 1);
+    assert_eq!(env.process(close_pool(&pool)), Err(StakingError::PoolNotEmpty.into()));
+
+    env.process(withdraw(&pool, &staker, 1_000)).unwrap();
+    env.process(close_pool(&pool)).unwrap();
+}
+
+#[test]
+fn close_pool_sweeps_staked_tokens_sent_to_the_pool_outside_deposit() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig::default();
+    let pool = env.create_pool(config);
+    let owner_reward_before = env.token_balance(&pool.owner_reward_account);
+    env.mint_to(&pool.staked, 1);
+
+    env.warp_to_slot(config.end_block + 1);
+    env.process(close_pool(&pool)).unwrap();
+
+    assert_eq!(env.token_balance(&pool.owner_reward_account), owner_reward_before + config.reward_amount + 1);
+    for address in [pool.stake_pool, pool.wallet, pool.staked, pool.reward] {
+        assert!(env.account(&address).is_none());
+    }
+
+    // With a reward mint of its own, the staked tokens go to a token-account of the staked mint
+    let config = PoolConfig { separate_reward_mint: true, ..PoolConfig::default() };
+    let pool = env.create_pool(config);
+    let owner_staked_account = env.create_token_account(&pool.mint, &pool.owner, 0);
+    env.mint_to(&pool.staked, 1);
+
+    env.warp_to_slot(config.end_block + 1);
+    let mut instruction = close_pool(&pool);
+    instruction.accounts.push(AccountMeta::new(owner_staked_account, false));
+    env.process(instruction).unwrap();
+
+    assert_eq!(env.token_balance(&owner_staked_account), 1);
+    assert!(env.account(&pool.staked).is_none());
+}
+
+#[test]
+fn close_pool_keeps_the_tokens_of_pending_unstakes() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig::default();
+    let pool = env.create_pool(config);
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateUnstakeCooldown { unstake_cooldown: 100 },
+    )).unwrap();
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.process(staking_instruction(
+        position_accounts(&pool, &staker),
+        StakingInstruction::RequestUnstake { amount: 1_000 },
+    )).unwrap();
+    assert_eq!(env.stake_pool(&pool).pending_unstake_supply, 1_000);
+    env.mint_to(&pool.staked, 1);
+
+    env.warp_to_slot(config.end_block + 1);
+    assert!(env.process(close_pool(&pool)).is_err());
+    assert_eq!(env.token_balance(&pool.staked), 1_001);
+}
+
+fn accept_pool_ownership(
+    pool: &Pool,
+    signer: &Pubkey,
+) -> Instruction {
+    staking_instruction(
+        vec![
+            AccountMeta::new_readonly(*signer, true),
+            AccountMeta::new(pool.stake_pool, false),
+        ],
+        StakingInstruction::AcceptPoolOwnership,
+    )
+}
+
+#[test]
+fn pool_ownership_moves_only_once_the_new_owner_accepts() {
+    let mut env = TestEnv::new();
+    let mut pool = env.create_pool(PoolConfig::default());
+    let new_owner = env.create_wallet();
+    let stranger = env.create_wallet();
+
+    env.process(owner_instruction(&pool, &pool.owner, StakingInstruction::TransferPoolOwnership { new_owner })).unwrap();
+    let stake_pool = env.stake_pool(&pool);
+    assert_eq!(stake_pool.owner, pool.owner);
+    assert_eq!(stake_pool.pending_owner, COption::Some(new_owner));
+
+    assert_eq!(
+        env.process(accept_pool_ownership(&pool, &stranger)),
+        Err(StakingError::NotPendingOwner.into()),
+    );
+    env.process(accept_pool_ownership(&pool, &new_owner)).unwrap();
+    let stake_pool = env.stake_pool(&pool);
+    assert_eq!(stake_pool.owner, new_owner);
+    assert_eq!(stake_pool.pending_owner, COption::None);
+
+    assert_eq!(
+        env.process(set_reward_per_block(&pool, 2_000)),
+        Err(StakingError::Unauthorized.into()),
+    );
+    pool.owner = new_owner;
+    env.process(set_reward_per_block(&pool, 2_000)).unwrap();
+}
+
+#[test]
+fn pool_ownership_transfer_to_the_default_key_is_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+
+    assert_eq!(
+        env.process(owner_instruction(
+            &pool,
+            &pool.owner,
+            StakingInstruction::TransferPoolOwnership { new_owner: Pubkey::default() },
+        )),
+        Err(StakingError::InvalidNewOwner.into()),
+    );
+    assert_eq!(env.stake_pool(&pool).pending_owner, COption::None);
+}
+
+#[test]
+fn owner_gated_instructions_refuse_another_signer() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let stranger = env.create_wallet();
+    let stranger_reward_account = env.create_token_account(&pool.reward_mint, &stranger, 100_000);
+    let as_stranger = Pool { owner: stranger, owner_reward_account: stranger_reward_account, ..pool };
+    env.mint_to(&pool.owner_reward_account, 100_000);
+
+    env.warp_to_slot(20);
+    let transfer_ownership = |pool: &Pool| owner_instruction(
+        pool,
+        &pool.owner,
+        StakingInstruction::TransferPoolOwnership { new_owner: stranger },
+    );
+    for instruction in [
+        set_pause(&as_stranger, true),
+        set_reward_per_block(&as_stranger, 2_000),
+        extend_reward_period(&as_stranger, 1_110, 100_000),
+        transfer_ownership(&as_stranger),
+        close_pool(&as_stranger),
+    ] {
+        assert_eq!(env.process(instruction), Err(StakingError::Unauthorized.into()));
+    }
+    assert_eq!(env.stake_pool(&pool).owner, pool.owner);
+
+    for instruction in [
+        set_pause(&pool, true),
+        set_reward_per_block(&pool, 1_000),
+        extend_reward_period(&pool, 1_110, 100_000),
+        transfer_ownership(&pool),
+    ] {
+        env.process(instruction).unwrap();
+    }
+    let stake_pool = env.stake_pool(&pool);
+    assert_eq!((stake_pool.is_paused, stake_pool.end_block), (1, 1_110));
+}
@@ -0,0 +1,202 @@
+mod common;
+
+use common::*;
+use solana_program::{
+    instruction::{
+        AccountMeta,
+        Instruction,
+    },
+    sysvar,
+};
+use stake_reward::{
+    error::StakingError,
+    instruction::StakingInstruction,
+};
+
+#[test]
+fn transferred_position_belongs_to_the_new_owner() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let seller = env.create_staker(&pool, 1_000);
+    let buyer = env.create_staker(&pool, 0);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &seller, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    env.process(transfer_position(&pool, &seller, &buyer)).unwrap();
+
+    // The reward up to the transfer is paid to the former owner, the stake doesn't move
+    assert_eq!(env.token_balance(&seller.token_account), 10_000);
+    assert_eq!(env.token_balance(&pool.staked), 1_000);
+    assert!(env.account(&seller.user_info).is_none());
+    assert_eq!(env.user_info(&buyer).amount, 1_000);
+    assert!(env.process(withdraw(&pool, &seller, 1_000)).is_err());
+    let seller_on_buyer_position = Staker { owner: seller.owner, ..buyer };
+    assert!(env.process(withdraw(&pool, &seller_on_buyer_position, 1_000)).is_err());
+
+    env.warp_to_slot(30);
+    env.process(withdraw(&pool, &buyer, 1_000)).unwrap();
+    assert_eq!(env.token_balance(&buyer.token_account), 1_000 + 10_000);
+}
+
+#[test]
+fn compound_into_stakes_the_reward_into_the_target_pool() {
+    let mut env = TestEnv::new();
+    let source = env.create_pool(PoolConfig::default());
+    let target = env.create_pool_of_mint(PoolConfig::default(), source.mint);
+    let staker = env.create_staker(&source, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&source, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    env.process(compound_into(&source, &target, &staker)).unwrap();
+
+    assert_eq!(env.token_balance(&source.reward), 1_000_000 - 10_000);
+    assert_eq!(env.token_balance(&target.staked), 10_000);
+    assert_eq!(env.stake_pool(&target).total_supply, 10_000);
+    assert_eq!(env.user_info(&env.staker_in(&target, &staker)).amount, 10_000);
+    assert_eq!(env.token_balance(&staker.token_account), 0);
+
+    // Both positions accrue from here
+    env.warp_to_slot(30);
+    env.process(get_reward(&source, &staker)).unwrap();
+    env.process(get_reward(&target, &env.staker_in(&target, &staker))).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 10_000 + 10_000);
+}
+
+fn set_user_frozen(
+    pool: &Pool,
+    staker: &Staker,
+    frozen: bool,
+) -> Instruction {
+    let mut instruction = owner_instruction(
+        pool,
+        &pool.owner,
+        StakingInstruction::SetUserFrozen { user: staker.token_account, frozen },
+    );
+    instruction.accounts[2].is_writable = false;
+    instruction.accounts.extend([
+        AccountMeta::new_readonly(staker.token_account, false),
+        AccountMeta::new(staker.user_info, false),
+    ]);
+    instruction
+}
+
+#[test]
+fn frozen_position_can_not_stake_unstake_or_claim_but_keeps_accruing() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 2_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.process(set_user_frozen(&pool, &staker, true)).unwrap();
+    env.warp_to_slot(20);
+    for instruction in [
+        deposit(&pool, &staker, 1_000),
+        withdraw(&pool, &staker, 1_000),
+        get_reward(&pool, &staker),
+    ] {
+        assert_eq!(env.process(instruction), Err(StakingError::UserFrozen.into()));
+    }
+
+    env.process(set_user_frozen(&pool, &staker, false)).unwrap();
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 1_000 + 10_000);
+}
+
+fn set_user_reward_debt(
+    pool: &Pool,
+    staker: &Staker,
+    reward_debt: u128,
+) -> Instruction {
+    let mut instruction = owner_instruction(
+        pool,
+        &pool.owner,
+        StakingInstruction::SetUserRewardDebt { user: staker.token_account, reward_debt },
+    );
+    instruction.accounts[2].is_writable = false;
+    instruction.accounts.extend([
+        AccountMeta::new_readonly(staker.token_account, false),
+        AccountMeta::new(staker.user_info, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ]);
+    instruction
+}
+
+#[test]
+fn corrupted_reward_debt_is_repaired_while_the_pool_is_paused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    let mut user_info = env.user_info(&staker);
+    user_info.reward_debt = 4_000 * 1_000_000_000_000;
+    env.set_user_info(&staker, user_info);
+
+    env.warp_to_slot(20);
+    assert_eq!(
+        env.process(set_user_reward_debt(&pool, &staker, 0)),
+        Err(StakingError::PoolNotPaused.into()),
+    );
+    env.process(set_pause(&pool, true)).unwrap();
+    env.process(set_user_reward_debt(&pool, &staker, 0)).unwrap();
+    assert_eq!(
+        env.process(set_user_reward_debt(&pool, &staker, 0)),
+        Err(StakingError::RewardDebtCorrectionTooSoon.into()),
+    );
+    env.process(set_pause(&pool, false)).unwrap();
+
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 10_000);
+}
+
+fn compound(
+    pool: &Pool,
+    staker: &Staker,
+) -> Instruction {
+    let mut accounts = position_accounts(pool, staker);
+    accounts[1].is_writable = false;
+    staking_instruction(accounts, StakingInstruction::Compound)
+}
+
+#[test]
+fn compounding_twice_grows_the_share_of_the_stake() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let compounding = env.create_staker(&pool, 1_000);
+    let holding = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &compounding, 1_000)).unwrap();
+    env.process(deposit(&pool, &holding, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    env.process(compound(&pool, &compounding)).unwrap();
+    assert_eq!(env.user_info(&compounding).amount, 1_000 + 5_000);
+    env.warp_to_slot(30);
+    env.process(compound(&pool, &compounding)).unwrap();
+
+    // 6_000 out of 7_000 staked earn the second 10_000
+    assert_eq!(env.user_info(&compounding).amount, 6_000 + 10_000 * 6 / 7);
+    assert_eq!(env.stake_pool(&pool).total_supply, 1_000 + 6_000 + 10_000 * 6 / 7);
+    assert_eq!(env.token_balance(&pool.staked), 1_000 + 6_000 + 10_000 * 6 / 7);
+    env.process(get_reward(&pool, &holding)).unwrap();
+    assert_eq!(env.token_balance(&holding.token_account), 5_000 + 10_000 / 7);
+}
+
+#[test]
+fn compound_of_another_reward_mint_is_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig { separate_reward_mint: true, ..PoolConfig::default() });
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    assert_eq!(
+        env.process(compound(&pool, &staker)),
+        Err(StakingError::CompoundMintMismatch.into()),
+    );
+}
@@ -0,0 +1,948 @@
+mod common;
+
+use common::*;
+use borsh::BorshDeserialize;
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{
+        AccountMeta,
+        Instruction,
+    },
+    program::set_return_data,
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_program,
+    sysvar,
+};
+use spl_token::{
+    error::TokenError,
+    instruction::TokenInstruction,
+    state::Account as TokenAccount,
+};
+use stake_reward::{
+    error::StakingError,
+    instruction::StakingInstruction,
+    state::ClaimReceipt,
+    utils::{
+        associated_token_address,
+        native_reward_address,
+    },
+};
+
+#[test]
+fn reward_is_paid_with_transfer_checked_and_the_reward_decimals() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig { decimals: 9, ..PoolConfig::default() });
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(20);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(30);
+    env.process(get_reward(&pool, &staker)).unwrap();
+
+    let transfers: Vec<(u64, u8)> = env.invoked
+        .iter()
+        .filter(|instruction| instruction.program_id == spl_token::id())
+        .map(|instruction| match TokenInstruction::unpack(&instruction.data).unwrap() {
+            TokenInstruction::TransferChecked { amount, decimals } => (amount, decimals),
+            other => panic!("unexpected token instruction {:?}", other),
+        })
+        .collect();
+    assert_eq!(transfers, vec![(10_000, 9)]);
+    assert_eq!(env.token_balance(&staker.token_account), 10_000);
+}
+
+#[test]
+fn reward_per_block_change_applies_from_the_current_block() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    env.process(set_reward_per_block(&pool, 500)).unwrap();
+    env.warp_to_slot(30);
+    env.process(get_reward(&pool, &staker)).unwrap();
+
+    assert_eq!(env.token_balance(&staker.token_account), 1_000 * 10 + 500 * 10);
+}
+
+#[test]
+fn lower_reward_per_block_stretches_what_is_left_of_the_reward() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    env.process(set_reward_per_block(&pool, 500)).unwrap();
+    // 990_000 left at 500 per block
+    assert_eq!(env.stake_pool(&pool).end_block, 20 + 1_980);
+
+    env.warp_to_slot(3_000);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 1_000_000);
+    assert_eq!(env.token_balance(&pool.reward), 0);
+}
+
+#[test]
+fn reward_per_block_spending_the_rest_in_no_block_is_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+
+    env.warp_to_slot(20);
+    assert_eq!(
+        env.process(set_reward_per_block(&pool, 990_001)),
+        Err(StakingError::RewardRateExhaustsPool.into()),
+    );
+    assert_eq!(env.stake_pool(&pool).reward_per_block, 1_000);
+}
+
+#[test]
+fn anyone_can_harvest_for_a_staker_into_the_staker_associated_token_account() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+    let staker_ata = associated_token_address(&staker.owner, &pool.reward_mint, &pool.token_program);
+    env.create_token_account_at(staker_ata, &pool.reward_mint, &staker.owner, 0);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    let instruction = harvest_for(&pool, &staker, &staker_ata);
+    assert!(instruction.accounts.iter().all(|meta| !meta.is_signer));
+    env.process(instruction).unwrap();
+
+    assert_eq!(env.token_balance(&staker_ata), 10_000);
+    assert_eq!(env.user_info(&staker).total_claimed, 10_000);
+}
+
+#[test]
+fn harvest_for_refuses_a_destination_other_than_the_associated_token_account() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+    let keeper = env.create_wallet();
+    let keeper_account = env.create_token_account(&pool.reward_mint, &keeper, 0);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+
+    assert_eq!(
+        env.process(harvest_for(&pool, &staker, &keeper_account)),
+        Err(StakingError::InvalidRewardDestination.into()),
+    );
+}
+
+#[test]
+fn reward_window_starts_on_the_first_stake() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig::default();
+    let pool = env.new_pool(config);
+    env.process(initialize(&pool, &config, true, false)).unwrap();
+    let staker = env.create_staker(&pool, 1_000);
+
+    // Nothing accrues while the pool has no stake, the window waits for it
+    env.warp_to_slot(500);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    let stake_pool = env.stake_pool(&pool);
+    assert_eq!(
+        (stake_pool.start_block, stake_pool.end_block, stake_pool.last_reward_block),
+        (500, 1_500, 500),
+    );
+    assert_eq!(stake_pool.accrued_token_per_share, 0);
+
+    env.warp_to_slot(510);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 10 * 1_000);
+}
+
+#[test]
+fn last_reward_block_ahead_of_the_clock_is_clamped_instead_of_stalling() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    let mut stake_pool = env.stake_pool(&pool);
+    stake_pool.last_reward_block = 500;
+    env.set_stake_pool(&pool, stake_pool);
+
+    env.warp_to_slot(20);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.stake_pool(&pool).last_reward_block, 20);
+    assert_eq!(env.token_balance(&staker.token_account), 0);
+
+    // Accrual goes on from the clamped block
+    env.warp_to_slot(30);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 10 * 1_000);
+}
+
+#[test]
+fn claim_below_the_minimum_is_refused_and_keeps_accruing() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateMinClaimAmount { min_claim_amount: 5_000 },
+    )).unwrap();
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(14);
+    assert_eq!(
+        env.process(get_reward(&pool, &staker)),
+        Err(StakingError::RewardBelowMinimum.into()),
+    );
+
+    env.warp_to_slot(15);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 5_000);
+}
+
+#[test]
+fn claims_are_clamped_to_the_per_user_cap() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateMaxRewardPerUser { max_reward_per_user: 15_000 },
+    )).unwrap();
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 10_000);
+
+    env.warp_to_slot(30);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 15_000);
+
+    env.warp_to_slot(40);
+    // Once the cap is reached a claim pays nothing
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 15_000);
+    assert_eq!(env.user_info(&staker).total_claimed, 15_000);
+}
+
+#[test]
+fn rate_change_settles_the_blocks_before_it_at_the_old_rate() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    let before_change = env.stake_pool(&pool);
+    env.process(set_reward_per_block(&pool, 1_500)).unwrap();
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 10 * 1_000);
+
+    // Changing the rate without settling first would have priced the same blocks at the new rate
+    let mut unsettled = before_change;
+    unsettled.reward_per_block = 1_500;
+    let (mut lamports, mut data) = (0, vec![]);
+    let staked_vault_info = AccountInfo::new(
+        &pool.staked, false, false, &mut lamports, &mut data, &pool.staked, false, 0,
+    );
+    unsettled.update_pool(&staked_vault_info, &env.clock).unwrap();
+    let unsettled_reward = unsettled.accrued_token_per_share * 1_000 / 1_000_000_000_000;
+    assert_eq!(unsettled_reward, 10 * 1_500);
+}
+
+#[test]
+fn claim_is_split_with_the_referrer() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateReferralFee { referral_fee_bps: 1_000 },
+    )).unwrap();
+    let referred = env.create_staker(&pool, 1_000);
+    let unreferred = env.create_staker(&pool, 1_000);
+    let referrer_owner = env.create_wallet();
+    let referrer = env.create_token_account(&pool.reward_mint, &referrer_owner, 0);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &referred, 1_000)).unwrap();
+    env.process(deposit(&pool, &unreferred, 1_000)).unwrap();
+    env.process(set_referrer(&pool, &referred, &referrer)).unwrap();
+    env.warp_to_slot(20);
+
+    let mut claim = get_reward(&pool, &referred);
+    claim.accounts.push(AccountMeta::new(referrer, false));
+    env.process(claim).unwrap();
+    env.process(get_reward(&pool, &unreferred)).unwrap();
+
+    // Each earned half of 10 blocks
+    assert_eq!(env.token_balance(&referred.token_account), 4_500);
+    assert_eq!(env.token_balance(&referrer), 500);
+    assert_eq!(env.token_balance(&unreferred.token_account), 5_000);
+}
+
+#[test]
+fn decoy_staked_vault_is_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+    // Owned by the pool authority like the real vault, with an inflated balance
+    let decoy = env.create_token_account(&pool.mint, &authority(), 1_000_000_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    let mut claim = get_reward(&pool, &staker);
+    claim.accounts[4] = AccountMeta::new(decoy, false);
+
+    assert_eq!(
+        env.process(claim),
+        Err(StakingError::StakedVaultMismatch.into()),
+    );
+}
+
+#[test]
+fn native_reward_is_paid_in_lamports_to_the_owner() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig { reward_amount: 0, ..PoolConfig::default() };
+    let pool = env.new_pool(config);
+    env.process(initialize(&pool, &config, false, true)).unwrap();
+    let native_reward = native_reward_address(&stake_reward::id(), pool.index).0;
+    let mut fund = owner_instruction(&pool, &pool.owner, StakingInstruction::FundNativeReward { lamports: 1_000_000 });
+    fund.accounts[0] = AccountMeta::new(pool.owner, true);
+    fund.accounts.extend([
+        AccountMeta::new(native_reward, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ]);
+    env.process(fund).unwrap();
+    assert_eq!(env.stake_pool(&pool).reward_per_block, 1_000);
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    let lamports_before = env.lamports(&staker.owner);
+    let mut claim = get_reward(&pool, &staker);
+    claim.accounts[0] = AccountMeta::new(staker.owner, true);
+    claim.accounts.push(AccountMeta::new(native_reward, false));
+    env.process(claim).unwrap();
+
+    assert_eq!(env.lamports(&staker.owner), lamports_before + 10_000);
+    assert_eq!(env.token_balance(&staker.token_account), 0);
+    assert!(env.lamports(&native_reward) >= env.rent.minimum_balance(0));
+}
+
+#[test]
+fn stakers_entering_in_the_same_slot_accrue_equally() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let first = env.create_staker(&pool, 1_000);
+    let second = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &first, 1_000)).unwrap();
+    env.process(deposit(&pool, &second, 1_000)).unwrap();
+    assert_eq!(env.user_info(&first).reward_debt, env.user_info(&second).reward_debt);
+
+    env.warp_to_slot(20);
+    env.process(get_reward(&pool, &first)).unwrap();
+    env.process(get_reward(&pool, &second)).unwrap();
+
+    assert_eq!(env.token_balance(&first.token_account), 5_000);
+    assert_eq!(env.token_balance(&second.token_account), 5_000);
+}
+
+fn rate_controller(
+    _program_id: &Pubkey,
+    _accounts: &[AccountInfo],
+    _input: &[u8],
+) -> ProgramResult {
+    set_return_data(&250u64.to_le_bytes());
+    Ok(())
+}
+
+fn malformed_rate_controller(
+    _program_id: &Pubkey,
+    _accounts: &[AccountInfo],
+    _input: &[u8],
+) -> ProgramResult {
+    set_return_data(&250u32.to_le_bytes());
+    Ok(())
+}
+
+fn poke_pool(
+    pool: &Pool,
+    controller: &Pubkey,
+) -> Instruction {
+    staking_instruction(
+        vec![
+            AccountMeta::new(pool.stake_pool, false),
+            AccountMeta::new_readonly(pool.staked, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(*controller, false),
+        ],
+        StakingInstruction::PokePool,
+    )
+}
+
+#[test]
+fn poke_pool_takes_the_reward_per_block_of_the_rate_controller() {
+    let mut env = TestEnv::new();
+    let controller = Pubkey::new_unique();
+    TestEnv::add_program(controller, rate_controller);
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::SetRateController { rate_controller: Some(controller) },
+    )).unwrap();
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    env.process(poke_pool(&pool, &controller)).unwrap();
+    assert_eq!(env.stake_pool(&pool).reward_per_block, 250);
+
+    env.warp_to_slot(30);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 1_000 * 10 + 250 * 10);
+}
+
+#[test]
+fn poke_pool_refuses_another_program_or_malformed_return_data() {
+    let mut env = TestEnv::new();
+    let controller = Pubkey::new_unique();
+    TestEnv::add_program(controller, malformed_rate_controller);
+    let impostor = Pubkey::new_unique();
+    TestEnv::add_program(impostor, rate_controller);
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::SetRateController { rate_controller: Some(controller) },
+    )).unwrap();
+
+    assert_eq!(
+        env.process(poke_pool(&pool, &impostor)),
+        Err(StakingError::RateControllerMismatch.into()),
+    );
+    assert_eq!(
+        env.process(poke_pool(&pool, &controller)),
+        Err(StakingError::InvalidRateControllerData.into()),
+    );
+    assert_eq!(env.stake_pool(&pool).reward_per_block, 1_000);
+}
+
+fn set_user_boost(
+    pool: &Pool,
+    staker: &Staker,
+    boost_bps: u16,
+) -> Instruction {
+    let mut instruction = owner_instruction(pool, &pool.owner, StakingInstruction::SetUserBoost { boost_bps });
+    instruction.accounts.extend([
+        AccountMeta::new_readonly(pool.staked, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(staker.token_account, false),
+        AccountMeta::new(staker.user_info, false),
+    ]);
+    instruction
+}
+
+#[test]
+fn boosted_staker_earns_a_share_of_its_effective_balance() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let boosted = env.create_staker(&pool, 1_000);
+    let unboosted = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &boosted, 1_000)).unwrap();
+    env.process(deposit(&pool, &unboosted, 1_000)).unwrap();
+    env.process(set_user_boost(&pool, &boosted, 30_000)).unwrap();
+    assert_eq!(env.stake_pool(&pool).effective_total_supply, 4_000);
+
+    env.warp_to_slot(30);
+    env.process(get_reward(&pool, &boosted)).unwrap();
+    env.process(get_reward(&pool, &unboosted)).unwrap();
+
+    assert_eq!(env.token_balance(&boosted.token_account), 15_000);
+    assert_eq!(env.token_balance(&unboosted.token_account), 5_000);
+}
+
+#[test]
+fn boost_outside_of_the_allowed_range_is_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+
+    assert_eq!(
+        env.process(set_user_boost(&pool, &staker, 9_999)),
+        Err(StakingError::InvalidBoostBps.into()),
+    );
+    assert_eq!(
+        env.process(set_user_boost(&pool, &staker, 30_001)),
+        Err(StakingError::InvalidBoostBps.into()),
+    );
+    assert_eq!(env.user_info(&staker).boost_bps, 0);
+}
+
+/// Leaves `amount` reward tokens in the reward token-account of `pool`
+fn drain_reward_vault(
+    env: &mut TestEnv,
+    pool: &Pool,
+    amount: u64,
+) {
+    let vault = TokenAccount::unpack(&env.account(&pool.reward).unwrap().data).unwrap();
+    env.create_token_account_at(pool.reward, &vault.mint, &vault.owner, amount);
+}
+
+#[test]
+fn claim_from_an_under_funded_vault_fails_by_default() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    drain_reward_vault(&mut env, &pool, 4_000);
+
+    assert_eq!(
+        env.process(get_reward(&pool, &staker)),
+        Err(StakingError::RewardVaultShortfall.into()),
+    );
+    assert_eq!(env.token_balance(&staker.token_account), 0);
+    assert_eq!(env.token_balance(&pool.reward), 4_000);
+}
+
+#[test]
+fn partial_claim_on_shortfall_owes_the_rest_until_a_top_up() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateShortfallPolicy { partial_claim_on_shortfall: true },
+    )).unwrap();
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    drain_reward_vault(&mut env, &pool, 4_000);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 4_000);
+    assert_eq!(env.user_info(&staker).reward_owed, 6_000);
+
+    env.mint_to(&pool.reward, 100_000);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 10_000);
+    assert_eq!(env.user_info(&staker).reward_owed, 0);
+}
+
+#[test]
+fn claiming_every_block_keeps_the_truncated_remainder_owed() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let frequent = env.create_staker(&pool, 1);
+    let patient = env.create_staker(&pool, 2);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &frequent, 1)).unwrap();
+    env.process(deposit(&pool, &patient, 2)).unwrap();
+    // 1_000 per block over 3 staked tokens leaves a remainder on every claim
+    for slot in 11..=110 {
+        env.warp_to_slot(slot);
+        env.process(get_reward(&pool, &frequent)).unwrap();
+    }
+    env.process(get_reward(&pool, &patient)).unwrap();
+
+    assert_eq!(env.token_balance(&frequent.token_account), 100 * 1_000 / 3);
+    assert_eq!(env.token_balance(&patient.token_account), 100 * 1_000 * 2 / 3);
+}
+
+#[test]
+fn reward_accrued_before_end_block_is_claimable_after_it() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(1_000);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(5_000);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 10 * 1_000);
+
+    // Accrual stopped at end_block
+    env.warp_to_slot(6_000);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 10 * 1_000);
+}
+
+#[test]
+fn simple_interest_pays_a_fixed_share_of_each_stake_per_block() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let mut set_simple_interest = owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::SetSimpleInterest { simple_interest_bps: 100 },
+    );
+    set_simple_interest.accounts.extend([
+        AccountMeta::new_readonly(pool.staked, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ]);
+    env.process(set_simple_interest).unwrap();
+    let early = env.create_staker(&pool, 1_000);
+    let late = env.create_staker(&pool, 3_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &early, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    env.process(deposit(&pool, &late, 3_000)).unwrap();
+    env.warp_to_slot(30);
+    env.process(get_reward(&pool, &early)).unwrap();
+    env.process(get_reward(&pool, &late)).unwrap();
+
+    // 1% of the stake per block, whatever else is staked
+    assert_eq!(env.token_balance(&early.token_account), 1_000 / 100 * 20);
+    assert_eq!(env.token_balance(&late.token_account), 3_000 / 100 * 10);
+}
+
+#[test]
+fn get_reward_returns_a_receipt_matching_the_accounts() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateShortfallPolicy { partial_claim_on_shortfall: true },
+    )).unwrap();
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    drain_reward_vault(&mut env, &pool, 4_000);
+    env.process(get_reward(&pool, &staker)).unwrap();
+
+    let receipt = ClaimReceipt::try_from_slice(&env.return_data.clone().unwrap()).unwrap();
+    let user_info = env.user_info(&staker);
+    assert_eq!(receipt, ClaimReceipt {
+        paid_amount: env.token_balance(&staker.token_account),
+        remaining_pending: user_info.reward_owed,
+        next_unlock: user_info.reward_lock_finish,
+    });
+    assert_eq!((receipt.paid_amount, receipt.remaining_pending), (4_000, 6_000));
+}
+
+#[test]
+fn pending_reward_matches_what_get_reward_pays() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let first = env.create_staker(&pool, 1_000);
+    let second = env.create_staker(&pool, 3_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &first, 1_000)).unwrap();
+    env.warp_to_slot(17);
+    env.process(deposit(&pool, &second, 3_000)).unwrap();
+    for slot in [23, 40, 2_000] {
+        env.warp_to_slot(slot);
+        let stake_pool = env.stake_pool(&pool);
+        let staked_supply = env.token_balance(&pool.staked);
+        let pending = stake_pool
+            .pending_reward(&env.user_info(&first), staked_supply, &env.clock)
+            .unwrap();
+
+        let balance_before = env.token_balance(&first.token_account);
+        env.process(get_reward(&pool, &first)).unwrap();
+        assert_eq!(env.token_balance(&first.token_account) - balance_before, pending, "slot {}", slot);
+    }
+    assert_eq!(env.token_balance(&first.token_account), 1_000 * 7 + 250 * 993);
+}
+
+#[test]
+fn repeated_claims_pay_the_increment_and_keep_the_stake() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    for (slot, increment) in [(13, 3_000), (20, 7_000), (20, 0), (50, 30_000)] {
+        env.warp_to_slot(slot);
+        let balance_before = env.token_balance(&staker.token_account);
+        env.process(get_reward(&pool, &staker)).unwrap();
+
+        assert_eq!(env.token_balance(&staker.token_account) - balance_before, increment, "slot {}", slot);
+        assert_eq!(env.user_info(&staker).amount, 1_000);
+        assert_eq!(env.token_balance(&pool.staked), 1_000);
+    }
+    assert_eq!(env.user_info(&staker).total_claimed, 40_000);
+}
+
+fn set_rewards_duration(
+    pool: &Pool,
+    rewards_duration: u64,
+) -> Instruction {
+    let mut instruction = owner_instruction(
+        pool,
+        &pool.owner,
+        StakingInstruction::SetRewardsDuration { rewards_duration },
+    );
+    instruction.accounts.extend([
+        AccountMeta::new_readonly(pool.staked, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ]);
+    instruction
+}
+
+fn notify_reward_amount(
+    pool: &Pool,
+    reward: u64,
+) -> Instruction {
+    staking_instruction(
+        vec![
+            AccountMeta::new_readonly(pool.owner, true),
+            AccountMeta::new_readonly(pool.reward_mint, false),
+            AccountMeta::new(pool.stake_pool, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(pool.token_program, false),
+            AccountMeta::new(pool.owner_reward_account, false),
+            AccountMeta::new(pool.reward, false),
+            AccountMeta::new_readonly(pool.staked, false),
+        ],
+        StakingInstruction::NotifyRewardAmount { reward },
+    )
+}
+
+#[test]
+fn notify_reward_amount_starts_a_fresh_period() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(set_rewards_duration(&pool, 100)).unwrap();
+    env.mint_to(&pool.owner_reward_account, 10_000);
+    let vault_before = env.token_balance(&pool.reward);
+
+    env.process(notify_reward_amount(&pool, 10_000)).unwrap();
+    let stake_pool = env.stake_pool(&pool);
+    assert_eq!(stake_pool.reward_rate, 100);
+    assert_eq!(stake_pool.last_update_time, START_TIME as u64);
+    assert_eq!(stake_pool.period_finish, START_TIME as u64 + 100);
+    assert_eq!(env.token_balance(&pool.reward), vault_before + 10_000);
+}
+
+#[test]
+fn notify_reward_amount_mid_period_adds_the_leftover() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(set_rewards_duration(&pool, 100)).unwrap();
+    env.mint_to(&pool.owner_reward_account, 16_000);
+    env.process(notify_reward_amount(&pool, 10_000)).unwrap();
+
+    env.set_unix_timestamp(START_TIME + 40);
+    env.process(notify_reward_amount(&pool, 6_000)).unwrap();
+
+    // 60 seconds of 100 were left
+    let stake_pool = env.stake_pool(&pool);
+    assert_eq!(stake_pool.reward_rate, (6_000 + 60 * 100) / 100);
+    assert_eq!(stake_pool.last_update_time, START_TIME as u64 + 40);
+    assert_eq!(stake_pool.period_finish, START_TIME as u64 + 140);
+}
+
+fn get_reward_to(
+    pool: &Pool,
+    staker: &Staker,
+    reward_token_account: &Pubkey,
+) -> Instruction {
+    let mut instruction = get_reward(pool, staker);
+    instruction.accounts.push(AccountMeta::new(*reward_token_account, false));
+
+    instruction
+}
+
+#[test]
+fn separate_reward_mint_never_pays_principal_and_reward_across() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig { separate_reward_mint: true, ..PoolConfig::default() });
+    let staker = env.create_staker(&pool, 1_000);
+    let reward_account = env.create_token_account(&pool.reward_mint, &staker.owner, 0);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    assert_eq!(
+        env.process(get_reward_to(&pool, &staker, &staker.token_account)),
+        Err(TokenError::MintMismatch.into()),
+    );
+    env.process(get_reward_to(&pool, &staker, &reward_account)).unwrap();
+    assert_eq!(env.token_balance(&reward_account), 10_000);
+    assert_eq!(env.token_balance(&staker.token_account), 0);
+    assert_eq!(env.token_balance(&pool.staked), 1_000);
+
+    env.process(withdraw(&pool, &staker, 1_000)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 1_000);
+    assert_eq!(env.token_balance(&reward_account), 10_000);
+    assert_eq!(env.token_balance(&pool.reward), 1_000_000 - 10_000);
+}
+
+#[test]
+fn extended_reward_period_accrues_past_the_old_end_block() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+    env.mint_to(&pool.owner_reward_account, 100_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(500);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 490 * 1_000);
+
+    env.process(extend_reward_period(&pool, 1_110, 100_000)).unwrap();
+    assert_eq!(env.stake_pool(&pool).end_block, 1_110);
+    assert_eq!(env.token_balance(&pool.reward), 1_000_000 - 490_000 + 100_000);
+
+    env.warp_to_slot(2_000);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 1_100 * 1_000);
+}
+
+#[test]
+fn extension_to_an_earlier_end_or_of_another_amount_is_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.mint_to(&pool.owner_reward_account, 100_000);
+
+    env.warp_to_slot(500);
+    assert_eq!(
+        env.process(extend_reward_period(&pool, 1_010, 0)),
+        Err(StakingError::InvalidEndBlock.into()),
+    );
+    assert_eq!(
+        env.process(extend_reward_period(&pool, 1_110, 99_999)),
+        Err(StakingError::RewardAmountMismatch.into()),
+    );
+    assert_eq!(env.stake_pool(&pool).end_block, 1_010);
+}
+
+fn harvest_many(
+    pools: &[(&Pool, &Staker)],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(pools[0].1.owner, true),
+        AccountMeta::new_readonly(authority(), false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    for (pool, staker) in pools {
+        accounts.extend([
+            AccountMeta::new(staker.token_account, false),
+            AccountMeta::new(pool.stake_pool, false),
+            AccountMeta::new(pool.staked, false),
+            AccountMeta::new(pool.reward, false),
+            AccountMeta::new(staker.user_info, false),
+            AccountMeta::new_readonly(pool.reward_mint, false),
+            AccountMeta::new(staker.token_account, false),
+            AccountMeta::new(staker.token_account, false),
+        ]);
+    }
+
+    staking_instruction(accounts, StakingInstruction::HarvestMany { pool_count: pools.len() as u8 })
+}
+
+/// Two pools of other mints staked by the same owner, 1_000 in the first and 2_000 in the second
+fn stake_in_two_pools(
+    env: &mut TestEnv,
+) -> [(Pool, Staker); 2] {
+    let first_pool = env.create_pool(PoolConfig::default());
+    let second_pool = env.create_pool(PoolConfig { reward_per_block: 500, ..PoolConfig::default() });
+    let first = env.create_staker(&first_pool, 1_000);
+    let token_account = env.create_token_account(&second_pool.mint, &first.owner, 2_000);
+    let second = Staker {
+        owner: first.owner,
+        token_account,
+        user_info: user_info_address(&second_pool, &token_account),
+    };
+
+    env.warp_to_slot(10);
+    env.process(deposit(&first_pool, &first, 1_000)).unwrap();
+    env.process(deposit(&second_pool, &second, 2_000)).unwrap();
+    env.warp_to_slot(30);
+
+    [(first_pool, first), (second_pool, second)]
+}
+
+#[test]
+fn harvest_many_pays_what_separate_harvests_pay() {
+    let mut separate = TestEnv::new();
+    let [(first_pool, first), (second_pool, second)] = stake_in_two_pools(&mut separate);
+    separate.process(get_reward(&first_pool, &first)).unwrap();
+    separate.process(get_reward(&second_pool, &second)).unwrap();
+
+    let mut batched = TestEnv::new();
+    let [(batched_first_pool, batched_first), (batched_second_pool, batched_second)] = stake_in_two_pools(&mut batched);
+    batched.process(harvest_many(&[
+        (&batched_first_pool, &batched_first),
+        (&batched_second_pool, &batched_second),
+    ])).unwrap();
+
+    assert_eq!(separate.token_balance(&first.token_account), 20 * 1_000);
+    assert_eq!(separate.token_balance(&second.token_account), 20 * 500);
+    assert_eq!(batched.token_balance(&batched_first.token_account), 20 * 1_000);
+    assert_eq!(batched.token_balance(&batched_second.token_account), 20 * 500);
+    assert_eq!(batched.return_data, Some((20 * 1_000 + 20 * 500u64).to_le_bytes().to_vec()));
+}
+
+#[test]
+fn harvest_many_with_fewer_account_groups_than_pools_is_refused() {
+    let mut env = TestEnv::new();
+    let [(first_pool, first), (second_pool, second)] = stake_in_two_pools(&mut env);
+    let mut instruction = harvest_many(&[(&first_pool, &first), (&second_pool, &second)]);
+    instruction.accounts.pop();
+
+    assert_eq!(env.process(instruction), Err(ProgramError::NotEnoughAccountKeys));
+}
+
+#[test]
+fn claim_of_another_token_account_position_is_refused() {
+    let mut env = TestEnv::new();
+    let [(first_pool, victim), (second_pool, victim_second)] = stake_in_two_pools(&mut env);
+    let attacker = env.create_staker(&first_pool, 0);
+    let attacker_second_account = env.create_token_account(&second_pool.mint, &attacker.owner, 0);
+    let forged = Staker { user_info: victim.user_info, ..attacker };
+    let forged_second = Staker {
+        owner: attacker.owner,
+        token_account: attacker_second_account,
+        user_info: victim_second.user_info,
+    };
+
+    assert_eq!(
+        env.process(get_reward(&first_pool, &forged)),
+        Err(StakingError::UserInfoMissmatch.into()),
+    );
+    assert_eq!(
+        env.process(harvest_many(&[(&first_pool, &forged), (&second_pool, &forged_second)])),
+        Err(StakingError::UserInfoMissmatch.into()),
+    );
+    assert_eq!(env.token_balance(&attacker.token_account), 0);
+    assert_eq!(env.token_balance(&attacker_second_account), 0);
+}
@@ -0,0 +1,191 @@
+mod common;
+
+use std::convert::TryInto;
+use common::*;
+use borsh::BorshDeserialize;
+use solana_program::{
+    instruction::{
+        AccountMeta,
+        Instruction,
+    },
+    program_pack::Pack,
+    pubkey::Pubkey,
+    sysvar,
+};
+use stake_reward::{
+    error::StakingError,
+    instruction::StakingInstruction,
+    state::{
+        LockPolicy,
+        StakePool,
+        UserRewardStatus,
+    },
+    utils::{
+        master_staking_pda,
+        state_pool_address,
+    },
+};
+
+#[test]
+fn get_pool_count_returns_the_pool_counter() {
+    let mut env = TestEnv::new();
+    let get_pool_count = staking_instruction(
+        vec![AccountMeta::new_readonly(master_staking_pda(&stake_reward::id()).0, false)],
+        StakingInstruction::GetPoolCount,
+    );
+
+    env.process(get_pool_count.clone()).unwrap();
+    assert_eq!(env.return_data, Some(0u64.to_le_bytes().to_vec()));
+
+    env.create_pool(PoolConfig::default());
+    env.create_pool(PoolConfig::default());
+    env.process(get_pool_count).unwrap();
+    let pool_count = u64::from_le_bytes(env.return_data.clone().unwrap().try_into().unwrap());
+    assert_eq!(pool_count, 2);
+
+    // Every index below the count is a pool
+    for pool_index in 0..pool_count {
+        let stake_pool = env.account(&state_pool_address(&stake_reward::id(), pool_index).0).unwrap();
+        assert_eq!(stake_pool.owner, stake_reward::id());
+        assert_eq!(StakePool::unpack(&stake_pool.data).unwrap().pool_index, pool_index);
+    }
+}
+
+fn get_user_info(
+    pool: &Pool,
+    staker: &Staker,
+) -> Instruction {
+    staking_instruction(
+        vec![
+            AccountMeta::new_readonly(pool.stake_pool, false),
+            AccountMeta::new_readonly(pool.staked, false),
+            AccountMeta::new_readonly(staker.token_account, false),
+            AccountMeta::new_readonly(staker.user_info, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        StakingInstruction::GetUserInfo,
+    )
+}
+
+#[test]
+fn get_user_info_returns_the_unlock_countdown() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateRewardLock { rewards_lock_duration: 100, lock_policy: LockPolicy::Reset as u8 },
+    )).unwrap();
+    let staker = env.create_staker(&pool, 1_000);
+    let get_user_info = get_user_info(&pool, &staker);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    env.set_unix_timestamp(START_TIME + 30);
+    env.process(get_user_info.clone()).unwrap();
+    let status = UserRewardStatus::try_from_slice(&env.return_data.clone().unwrap()).unwrap();
+    let reward_lock_finish = env.user_info(&staker).reward_lock_finish;
+    assert_eq!(status, UserRewardStatus {
+        amount: 1_000,
+        pending: 10_000,
+        reward_lock_finish,
+        seconds_until_unlock: reward_lock_finish - (START_TIME as u64 + 30),
+        can_claim: false,
+    });
+
+    env.set_unix_timestamp(START_TIME + 100);
+    env.process(get_user_info).unwrap();
+    let status = UserRewardStatus::try_from_slice(&env.return_data.clone().unwrap()).unwrap();
+    assert_eq!((status.seconds_until_unlock, status.can_claim), (0, true));
+}
+
+fn self_test_accrual(
+    mint: &Pubkey,
+    blocks: u64,
+    supply: u64,
+    rate: u64,
+) -> Instruction {
+    staking_instruction(
+        vec![AccountMeta::new_readonly(*mint, false)],
+        StakingInstruction::SelfTestAccrual { blocks, supply, rate },
+    )
+}
+
+#[cfg(feature = "self-test")]
+#[test]
+fn self_test_accrual_matches_simulate_update() {
+    let mut env = TestEnv::new();
+    let mint = env.create_mint(6, &spl_token::id());
+
+    for (blocks, supply, rate) in [(10, 1_000, 1_000), (1, u64::MAX, 1), (1_000_000, 3, 7)] {
+        env.process(self_test_accrual(&mint, blocks, supply, rate)).unwrap();
+        let accrued_token_per_share = u128::from_le_bytes(env.return_data.clone().unwrap().try_into().unwrap());
+        assert_eq!(
+            Ok(accrued_token_per_share),
+            // 21 - 6 decimals, capped at the maximum rank
+            stake_reward::utils::simulate_update(0, blocks as u128, rate, supply, stake_reward::state::MAX_PRECISION_FACTOR_RANK),
+        );
+    }
+}
+
+#[cfg(not(feature = "self-test"))]
+#[test]
+fn self_test_accrual_is_refused_without_the_feature() {
+    let mut env = TestEnv::new();
+    let mint = env.create_mint(6, &spl_token::id());
+
+    assert_eq!(
+        env.process(self_test_accrual(&mint, 10, 1_000, 1_000)),
+        Err(solana_program::program_error::ProgramError::InvalidInstructionData),
+    );
+}
+
+#[test]
+fn get_user_info_with_one_account_too_many_or_too_few_is_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+
+    let mut too_many = get_user_info(&pool, &staker);
+    too_many.accounts.push(AccountMeta::new_readonly(pool.mint, false));
+    let mut too_few = get_user_info(&pool, &staker);
+    too_few.accounts.pop();
+    for instruction in [too_many, too_few] {
+        assert_eq!(env.process(instruction), Err(StakingError::UnexpectedAccountCount.into()));
+    }
+
+    env.process(get_user_info(&pool, &staker)).unwrap();
+}
+
+#[test]
+fn get_next_unlock_returns_the_earliest_future_unlock() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateRewardLock { rewards_lock_duration: 100, lock_policy: LockPolicy::Reset as u8 },
+    )).unwrap();
+    let mut user_infos = vec![];
+    env.warp_to_slot(10);
+    // Unlocked at +100, +160 and +130
+    for staked_at in [0, 60, 30] {
+        let staker = env.create_staker(&pool, 1_000);
+        env.set_unix_timestamp(START_TIME + staked_at);
+        env.process(deposit(&pool, &staker, 1_000)).unwrap();
+        user_infos.push(AccountMeta::new_readonly(staker.user_info, false));
+    }
+    let mut accounts = vec![AccountMeta::new_readonly(sysvar::clock::id(), false)];
+    accounts.extend(user_infos);
+    let get_next_unlock = staking_instruction(accounts, StakingInstruction::GetNextUnlock);
+
+    for (now, next_unlock) in [(120, START_TIME + 130), (130, START_TIME + 160), (200, 0)] {
+        env.set_unix_timestamp(START_TIME + now);
+        env.process(get_next_unlock.clone()).unwrap();
+        let unlock = u64::from_le_bytes(env.return_data.clone().unwrap().try_into().unwrap());
+        assert_eq!(unlock, next_unlock as u64, "now +{}", now);
+    }
+}
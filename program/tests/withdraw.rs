@@ -0,0 +1,510 @@
+mod common;
+
+use common::*;
+use solana_program::{
+    instruction::{
+        AccountMeta,
+        Instruction,
+    },
+    program_option::COption,
+};
+use stake_reward::{
+    error::StakingError,
+    instruction::StakingInstruction,
+    state::{
+        LockPolicy,
+        MAX_WITHDRAW_FEE_BPS,
+    },
+    spl_token_2022,
+};
+
+#[test]
+fn withdraw_fee_is_kept_from_the_amount_and_shared_by_the_remaining_stakers() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateWithdrawFee { withdraw_fee_bps: 100 },
+    )).unwrap();
+    let leaving = env.create_staker(&pool, 1_000);
+    let staying = env.create_staker(&pool, 1_000);
+
+    // Everything happens in one slot, so the fee is the only reward
+    env.warp_to_slot(20);
+    env.process(deposit(&pool, &leaving, 1_000)).unwrap();
+    env.process(deposit(&pool, &staying, 1_000)).unwrap();
+    env.process(withdraw(&pool, &leaving, 1_000)).unwrap();
+
+    assert_eq!(env.token_balance(&leaving.token_account), 990);
+    assert_eq!(env.stake_pool(&pool).total_supply, 1_000);
+    assert_eq!(env.token_balance(&pool.staked), 1_000);
+
+    env.process(get_reward(&pool, &staying)).unwrap();
+    assert_eq!(env.token_balance(&staying.token_account), 10);
+}
+
+#[test]
+fn withdraw_fee_above_the_cap_is_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+
+    assert_eq!(
+        env.process(owner_instruction(
+            &pool,
+            &pool.owner,
+            StakingInstruction::UpdateWithdrawFee { withdraw_fee_bps: MAX_WITHDRAW_FEE_BPS + 1 },
+        )),
+        Err(StakingError::WithdrawFeeTooHigh.into()),
+    );
+    assert_eq!(
+        env.process(owner_instruction(
+            &pool,
+            &pool.owner,
+            StakingInstruction::UpdateWithdrawFee { withdraw_fee_bps: 10_001 },
+        )),
+        Err(StakingError::InvalidFeeBps.into()),
+    );
+
+    let config = PoolConfig { withdraw_fee_bps: 10_001, ..PoolConfig::default() };
+    let pool = env.new_pool(config);
+    assert_eq!(
+        env.process(initialize_pool(&pool, &config)),
+        Err(StakingError::InvalidFeeBps.into()),
+    );
+}
+
+#[test]
+fn withdraw_fee_and_recipient_given_at_initialization_split_the_withdraw() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig { withdraw_fee_bps: 250, ..PoolConfig::default() };
+    let pool = env.new_pool(config);
+    let recipient_owner = env.create_wallet();
+    let fee_recipient = env.create_token_account(&pool.mint, &recipient_owner, 0);
+    let config = PoolConfig { fee_recipient: Some(fee_recipient), ..config };
+    env.process(initialize_pool(&pool, &config)).unwrap();
+    let stake_pool = env.stake_pool(&pool);
+    assert_eq!(stake_pool.withdraw_fee_bps, 250);
+    assert_eq!(stake_pool.fee_recipient, COption::Some(fee_recipient));
+    let staker = env.create_staker(&pool, 1_001);
+
+    env.warp_to_slot(20);
+    env.process(deposit(&pool, &staker, 1_001)).unwrap();
+    let mut instruction = withdraw(&pool, &staker, 1_001);
+    instruction.accounts.push(AccountMeta::new(fee_recipient, false));
+    env.process(instruction).unwrap();
+
+    // 2.5% of 1_001 is 25.025
+    assert_eq!(env.token_balance(&fee_recipient), 25);
+    assert_eq!(env.token_balance(&staker.token_account), 976);
+    assert_eq!(env.stake_pool(&pool).total_supply, 0);
+}
+
+#[test]
+fn withdraw_from_a_drifted_total_supply_is_refused_instead_of_wrapping() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    let mut stake_pool = env.stake_pool(&pool);
+    stake_pool.total_supply = 400;
+    env.set_stake_pool(&pool, stake_pool);
+
+    env.warp_to_slot(20);
+    assert_eq!(
+        env.process(withdraw(&pool, &staker, 1_000)),
+        Err(StakingError::TotalSupplyUnderflow.into()),
+    );
+    assert_eq!(env.stake_pool(&pool).total_supply, 400);
+    assert_eq!(env.user_info(&staker).amount, 1_000);
+}
+
+fn exit_pool(
+    pool: &Pool,
+    staker: &Staker,
+    forfeit_rewards: bool,
+) -> Instruction {
+    let mut accounts = position_accounts(pool, staker);
+    accounts.insert(7, AccountMeta::new(pool.wallet, false));
+    staking_instruction(accounts, StakingInstruction::ExitPool { forfeit_rewards })
+}
+
+#[test]
+fn exit_pool_returns_the_stake_and_the_reward_and_closes_user_info() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    let user_info_rent = env.lamports(&staker.user_info);
+    let wallet_before = env.lamports(&pool.wallet);
+    env.warp_to_slot(20);
+    env.process(exit_pool(&pool, &staker, false)).unwrap();
+
+    assert_eq!(env.token_balance(&staker.token_account), 1_000 + 10_000);
+    assert!(env.account(&staker.user_info).is_none());
+    assert_eq!(env.lamports(&pool.wallet), wallet_before + user_info_rent);
+    assert_eq!(env.stake_pool(&pool).total_supply, 0);
+}
+
+#[test]
+fn exit_pool_during_the_reward_lock_needs_to_forfeit_the_reward() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateRewardLock { rewards_lock_duration: 100, lock_policy: LockPolicy::Reset as u8 },
+    )).unwrap();
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    assert_eq!(
+        env.process(exit_pool(&pool, &staker, false)),
+        Err(StakingError::RewardLocked.into()),
+    );
+
+    env.process(exit_pool(&pool, &staker, true)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 1_000);
+    assert!(env.account(&staker.user_info).is_none());
+}
+
+#[test]
+fn negative_unix_timestamp_is_refused_instead_of_wrapping() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    env.set_unix_timestamp(-1);
+    for instruction in [
+        deposit(&pool, &staker, 0),
+        withdraw(&pool, &staker, 1_000),
+        get_reward(&pool, &staker),
+    ] {
+        assert_eq!(env.process(instruction), Err(StakingError::InvalidClock.into()));
+    }
+    assert_eq!(env.user_info(&staker).amount, 1_000);
+}
+
+fn complete_unstake(
+    pool: &Pool,
+    staker: &Staker,
+) -> Instruction {
+    let mut accounts = position_accounts(pool, staker);
+    accounts.remove(5);
+    accounts[8] = AccountMeta::new_readonly(pool.mint, false);
+    staking_instruction(accounts, StakingInstruction::CompleteUnstake)
+}
+
+#[test]
+fn requested_unstake_stops_earning_and_is_paid_after_the_cooldown() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateUnstakeCooldown { unstake_cooldown: 100 },
+    )).unwrap();
+    let leaving = env.create_staker(&pool, 1_000);
+    let staying = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &leaving, 1_000)).unwrap();
+    env.process(deposit(&pool, &staying, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    assert_eq!(
+        env.process(withdraw(&pool, &leaving, 1_000)),
+        Err(StakingError::UnstakeCooldownRequired.into()),
+    );
+    env.process(staking_instruction(
+        position_accounts(&pool, &leaving),
+        StakingInstruction::RequestUnstake { amount: 1_000 },
+    )).unwrap();
+    assert_eq!(env.user_info(&leaving).pending_unstake, 1_000);
+    assert_eq!(env.user_info(&leaving).reward_owed, 5_000);
+    assert_eq!(env.stake_pool(&pool).pending_unstake_supply, 1_000);
+
+    env.warp_to_slot(30);
+    env.set_unix_timestamp(START_TIME + 99);
+    assert_eq!(
+        env.process(complete_unstake(&pool, &leaving)),
+        Err(StakingError::UnstakeCooldownActive.into()),
+    );
+
+    env.set_unix_timestamp(START_TIME + 100);
+    env.process(complete_unstake(&pool, &leaving)).unwrap();
+    assert_eq!(env.token_balance(&leaving.token_account), 1_000);
+    assert_eq!(env.user_info(&leaving).pending_unstake, 0);
+    assert_eq!(env.stake_pool(&pool).pending_unstake_supply, 0);
+    env.process(get_reward(&pool, &staying)).unwrap();
+    assert_eq!(env.token_balance(&staying.token_account), 5_000 + 10_000);
+    env.process(get_reward(&pool, &leaving)).unwrap();
+    assert_eq!(env.token_balance(&leaving.token_account), 1_000 + 5_000);
+}
+
+#[test]
+fn withdraw_during_the_reward_lock_withholds_the_reward_without_forfeiting_it() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateRewardLock { rewards_lock_duration: 100, lock_policy: LockPolicy::Reset as u8 },
+    )).unwrap();
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    env.set_unix_timestamp(START_TIME + 50);
+    env.process(withdraw(&pool, &staker, 500)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 500);
+    assert_eq!(env.user_info(&staker).reward_owed, 10_000);
+
+    env.warp_to_slot(30);
+    env.set_unix_timestamp(START_TIME + 100);
+    env.process(get_reward(&pool, &staker)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 500 + 10_000 + 10_000);
+}
+
+#[test]
+fn emergency_withdraw_returns_the_stake_when_accrual_would_overflow() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    let mut stake_pool = env.stake_pool(&pool);
+    stake_pool.accrued_token_per_share = u128::MAX - 1;
+    stake_pool.reward_per_block = u64::MAX;
+    env.set_stake_pool(&pool, stake_pool);
+
+    env.warp_to_slot(20);
+    // Accrual on the broken state fails
+    assert!(env.process(withdraw(&pool, &staker, 1_000)).is_err());
+    env.process(emergency_withdraw(&pool, &staker)).unwrap();
+
+    assert_eq!(env.token_balance(&staker.token_account), 1_000);
+    assert_eq!(env.token_balance(&pool.staked), 0);
+    let user_info = env.user_info(&staker);
+    assert_eq!((user_info.amount, user_info.reward_debt), (0, 0));
+    assert_eq!(env.stake_pool(&pool).total_supply, 0);
+}
+
+#[test]
+fn withdraw_with_another_token_account_authority_is_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+
+    let mut instruction = withdraw(&pool, &staker, 1_000);
+    instruction.accounts[3] = AccountMeta::new_readonly(staker.owner, false);
+    assert_eq!(env.process(instruction), Err(StakingError::InvalidTokenAccountAuthority.into()));
+
+    env.process(withdraw(&pool, &staker, 1_000)).unwrap();
+    assert_eq!(env.token_balance(&staker.token_account), 1_000);
+}
+
+#[test]
+fn withdraw_fee_to_the_recipient_is_rounded_down() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let recipient_owner = env.create_wallet();
+    let fee_recipient = env.create_token_account(&pool.mint, &recipient_owner, 0);
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::UpdateWithdrawFee { withdraw_fee_bps: 100 },
+    )).unwrap();
+    env.process(owner_instruction(
+        &pool,
+        &pool.owner,
+        StakingInstruction::SetFeeRecipient { fee_recipient: Some(fee_recipient) },
+    )).unwrap();
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(20);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    let mut instruction = withdraw(&pool, &staker, 999);
+    instruction.accounts.push(AccountMeta::new(fee_recipient, false));
+    env.process(instruction).unwrap();
+
+    // 1% of 999 is 9.99, the staker keeps the fraction
+    assert_eq!(env.token_balance(&fee_recipient), 9);
+    assert_eq!(env.token_balance(&staker.token_account), 990);
+    assert_eq!(env.stake_pool(&pool).total_supply, 1);
+    assert_eq!(env.token_balance(&pool.staked), 1);
+}
+
+#[test]
+fn early_withdraw_penalty_follows_the_curve_to_the_lock_end() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let recipient_owner = env.create_wallet();
+    let fee_recipient = env.create_token_account(&pool.mint, &recipient_owner, 0);
+    for instruction in [
+        StakingInstruction::UpdateRewardLock { rewards_lock_duration: 100, lock_policy: LockPolicy::Reset as u8 },
+        StakingInstruction::UpdateEarlyWithdrawPenalty { early_withdraw_penalty_bps: 1_000 },
+        StakingInstruction::SetFeeRecipient { fee_recipient: Some(fee_recipient) },
+    ] {
+        env.process(owner_instruction(&pool, &pool.owner, instruction)).unwrap();
+    }
+    let staker = env.create_staker(&pool, 300);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 300)).unwrap();
+    // 10% at the stake, 5% halfway through the lock and nothing once it ended
+    for (elapsed, penalty) in [(0, 10), (50, 5), (100, 0)] {
+        env.set_unix_timestamp(START_TIME + elapsed);
+        let fee_before = env.token_balance(&fee_recipient);
+        let mut instruction = withdraw(&pool, &staker, 100);
+        instruction.accounts.push(AccountMeta::new(fee_recipient, false));
+        env.process(instruction).unwrap();
+
+        assert_eq!(env.token_balance(&fee_recipient), fee_before + penalty, "after {}s", elapsed);
+    }
+}
+
+#[test]
+fn staker_count_follows_positions_opened_and_emptied() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let first = env.create_staker(&pool, 1_000);
+    let second = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &first, 500)).unwrap();
+    env.process(deposit(&pool, &first, 500)).unwrap();
+    env.process(deposit(&pool, &second, 1_000)).unwrap();
+    assert_eq!(env.stake_pool(&pool).staker_count, 2);
+
+    env.process(withdraw(&pool, &first, 400)).unwrap();
+    assert_eq!(env.stake_pool(&pool).staker_count, 2);
+    env.process(withdraw(&pool, &first, 600)).unwrap();
+    assert_eq!(env.stake_pool(&pool).staker_count, 1);
+    env.process(deposit(&pool, &first, 1_000)).unwrap();
+    assert_eq!(env.stake_pool(&pool).staker_count, 2);
+
+    env.process(emergency_withdraw(&pool, &second)).unwrap();
+    assert_eq!(env.stake_pool(&pool).staker_count, 1);
+}
+
+#[test]
+fn staker_count_underflow_is_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    let mut stake_pool = env.stake_pool(&pool);
+    stake_pool.staker_count = 0;
+    env.set_stake_pool(&pool, stake_pool);
+
+    assert_eq!(
+        env.process(withdraw(&pool, &staker, 1_000)),
+        Err(StakingError::StakerCountUnderflow.into()),
+    );
+}
+
+#[test]
+fn pool_of_either_token_program_withdraws_with_the_one_of_its_initialization() {
+    for (token_program, other_token_program) in [
+        (spl_token::id(), spl_token_2022::id()),
+        (spl_token_2022::id(), spl_token::id()),
+    ] {
+        let mut env = TestEnv::new();
+        let pool = env.create_pool(PoolConfig { token_program, ..PoolConfig::default() });
+        assert_eq!(env.stake_pool(&pool).token_program, token_program);
+        let staker = env.create_staker(&pool, 1_000);
+
+        env.warp_to_slot(10);
+        env.process(deposit(&pool, &staker, 1_000)).unwrap();
+        env.warp_to_slot(20);
+        let other_program_pool = Pool { token_program: other_token_program, ..pool };
+        assert_eq!(
+            env.process(withdraw(&other_program_pool, &staker, 1_000)),
+            Err(StakingError::TokenProgramMismatch.into()),
+        );
+
+        env.process(withdraw(&pool, &staker, 1_000)).unwrap();
+        assert!(!env.invoked.is_empty());
+        assert!(env.invoked.iter().all(|instruction| instruction.program_id == token_program));
+        assert_eq!(env.token_balance(&staker.token_account), 1_000 + 10_000);
+    }
+}
+
+#[test]
+fn token_2022_mint_or_token_account_with_extensions_is_refused() {
+    let mut env = TestEnv::new();
+    let config = PoolConfig { token_program: spl_token_2022::id(), ..PoolConfig::default() };
+    let pool = env.create_pool(config);
+    let staker = env.create_staker(&pool, 1_000);
+    // Account type, then the ImmutableOwner extension Token-2022 adds to associated token-accounts
+    let mut token_account = env.account(&staker.token_account).unwrap().clone();
+    token_account.data.extend([2, 7, 0, 0, 0]);
+    env.set_account(staker.token_account, token_account);
+
+    env.warp_to_slot(10);
+    assert_eq!(
+        env.process(deposit(&pool, &staker, 1_000)),
+        Err(StakingError::TokenExtensionsUnsupported.into()),
+    );
+
+    // Mints with extensions are padded to the token-account length before the account type
+    let pool = env.new_pool(config);
+    let mut mint = env.account(&pool.mint).unwrap().clone();
+    mint.data.resize(165, 0);
+    mint.data.extend([1, 1, 0, 108, 0]);
+    mint.data.resize(mint.data.len() + 108, 0);
+    env.set_account(pool.mint, mint);
+    assert_eq!(
+        env.process(initialize_pool(&pool, &config)),
+        Err(StakingError::TokenExtensionsUnsupported.into()),
+    );
+}
+
+#[test]
+fn withdraw_of_another_token_account_position_is_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let victim = env.create_staker(&pool, 1_000);
+    let attacker = env.create_staker(&pool, 0);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &victim, 1_000)).unwrap();
+    env.warp_to_slot(20);
+    let forged = Staker { user_info: victim.user_info, ..attacker };
+    assert_eq!(
+        env.process(withdraw(&pool, &forged, 1_000)),
+        Err(StakingError::UserInfoMissmatch.into()),
+    );
+    assert_eq!(env.token_balance(&attacker.token_account), 0);
+    assert_eq!(env.user_info(&victim).amount, 1_000);
+}
+
+#[test]
+fn withdraw_above_the_staked_amount_is_refused() {
+    let mut env = TestEnv::new();
+    let pool = env.create_pool(PoolConfig::default());
+    let staker = env.create_staker(&pool, 1_000);
+
+    env.warp_to_slot(10);
+    env.process(deposit(&pool, &staker, 1_000)).unwrap();
+    assert_eq!(
+        env.process(withdraw(&pool, &staker, 1_001)),
+        Err(StakingError::WithdrawAmountTooHigh.into()),
+    );
+    assert_eq!(env.user_info(&staker).amount, 1_000);
+}